@@ -0,0 +1,127 @@
+//! Compares one-shot captures with a reused `Capturer`, full-screen
+//! captures with small regions, and the backends with each other. Needs a
+//! desktop: `cargo bench --bench capture --features dxgi,winrt-capture` on
+//! Windows, or `--features x11,fbdev` on Linux. Without one, e.g. in CI,
+//! the benchmarks are skipped rather than failing, and so are backends
+//! that aren't available. Only `get_screenshot` and regions are measured
+//! elsewhere.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+#[cfg(any(all(windows, feature = "gdi"), target_os = "linux"))]
+use screenshot::CaptureOptions;
+use screenshot::{get_screenshot, get_screenshot_region, Rect};
+#[cfg(all(windows, feature = "gdi"))]
+use screenshot::{get_screenshot_into, Backend, Capturer};
+#[cfg(target_os = "linux")]
+use screenshot::{CaptureBackend, CaptureTarget, ScreenshotError};
+
+fn capture(c: &mut Criterion) {
+    if let Err(e) = get_screenshot() {
+        eprintln!("Skipping capture benchmarks, no display to capture: {}", e);
+        return;
+    }
+
+    let mut group = c.benchmark_group("capture");
+    group.sample_size(20);
+    group.bench_function("get_screenshot", |b| b.iter(|| get_screenshot().unwrap()));
+    #[cfg(all(windows, feature = "gdi"))]
+    {
+        let mut buf = Vec::new();
+        group.bench_function("get_screenshot_into", |b| {
+            b.iter(|| get_screenshot_into(&mut buf).unwrap())
+        });
+        let mut capturer = Capturer::new();
+        group.bench_function("Capturer::capture", |b| {
+            b.iter(|| capturer.capture().unwrap().len())
+        });
+        group.bench_function("Capturer::capture_into", |b| {
+            b.iter(|| capturer.capture_into(&mut buf).unwrap())
+        });
+    }
+    // a small region should cost a fraction of the full screen
+    let region = Rect {
+        x: 0,
+        y: 0,
+        width: 100,
+        height: 100,
+    };
+    group.bench_function("get_screenshot_region 100x100", |b| {
+        b.iter(|| get_screenshot_region(region).unwrap())
+    });
+    #[cfg(all(windows, feature = "gdi"))]
+    {
+        let mut capturer = Capturer::for_region(region);
+        group.bench_function("Capturer::capture 100x100", |b| {
+            b.iter(|| capturer.capture().unwrap().len())
+        });
+    }
+    group.finish();
+}
+
+/// Every backend available here, full screen, reused between frames.
+fn backends(c: &mut Criterion) {
+    if get_screenshot().is_err() {
+        return;
+    }
+    let mut group = c.benchmark_group("backends");
+    group.sample_size(20);
+    #[cfg(all(windows, feature = "gdi"))]
+    for backend in [
+        Backend::Gdi,
+        Backend::DxgiDuplication,
+        Backend::GraphicsCapture,
+    ] {
+        let options = CaptureOptions::new().backend(backend);
+        let mut capturer = match Capturer::with_options(options.clone()) {
+            Ok(capturer) => capturer,
+            Err(e) => {
+                eprintln!("Skipping {}: {}", backend.name(), e);
+                continue;
+            }
+        };
+        group.bench_function(BenchmarkId::new("Capturer::capture", backend.name()), |b| {
+            b.iter(|| capturer.capture().unwrap().len())
+        });
+        group.bench_function(
+            BenchmarkId::new("CaptureOptions::capture", backend.name()),
+            |b| b.iter(|| options.capture().unwrap()),
+        );
+    }
+    #[cfg(target_os = "linux")]
+    {
+        type Opened = Result<Box<dyn CaptureBackend>, ScreenshotError>;
+        let backends: Vec<(&str, Opened)> = vec![
+            #[cfg(feature = "x11")]
+            (
+                "x11",
+                screenshot::backend::x11::X11Backend::connect().map(|b| Box::new(b) as _),
+            ),
+            #[cfg(feature = "fbdev")]
+            (
+                "fbdev",
+                screenshot::backend::fbdev::FbdevBackend::open().map(|b| Box::new(b) as _),
+            ),
+        ];
+        let options = CaptureOptions::new();
+        for (name, backend) in backends {
+            let mut backend = match backend {
+                Ok(backend) => backend,
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", name, e);
+                    continue;
+                }
+            };
+            group.bench_function(BenchmarkId::new("capture_target", name), |b| {
+                b.iter(|| {
+                    backend
+                        .capture_target(CaptureTarget::Primary, &options)
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, capture, backends);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+//! Per-pixel conversions, comparisons and encoding of synthetic frames,
+//! which run anywhere. Compare `cargo bench --bench convert` with `cargo
+//! bench --bench convert --features rayon` to see where parallel conversion
+//! pays off; the `threshold` group, only run with it, shows why small
+//! frames aren't split. PNG encoding is measured with the `png` feature.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use screenshot::{
+    testing::{MockCapturer, MockFrame},
+    Pixel,
+};
+
+const SIZES: [(&str, usize, usize); 3] = [
+    ("360p", 640, 360),
+    ("1080p", 1920, 1080),
+    ("4K", 3840, 2160),
+];
+
+fn convert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("convert");
+    for (name, width, height) in SIZES {
+        let mut mock = MockCapturer::new(width, height, MockFrame::Gradient);
+        group.bench_function(BenchmarkId::new("MockCapturer::capture", name), |b| {
+            b.iter(|| mock.capture().unwrap())
+        });
+        let mut s = mock.capture().unwrap();
+        group.bench_function(BenchmarkId::new("swap_r_b_in_place", name), |b| {
+            b.iter(|| s.swap_r_b_in_place())
+        });
+        group.bench_function(BenchmarkId::new("to_rgba_vec", name), |b| {
+            b.iter(|| s.to_rgba_vec())
+        });
+        group.bench_function(BenchmarkId::new("to_rgb_vec", name), |b| {
+            b.iter(|| s.to_rgb_vec())
+        });
+        group.bench_function(BenchmarkId::new("set_opaque", name), |b| {
+            b.iter(|| s.set_opaque())
+        });
+        group.bench_function(BenchmarkId::new("to_grayscale", name), |b| {
+            b.iter(|| s.to_grayscale())
+        });
+        group.bench_function(BenchmarkId::new("to_bmp", name), |b| b.iter(|| s.to_bmp()));
+        #[cfg(feature = "png")]
+        group.bench_function(BenchmarkId::new("to_png", name), |b| {
+            b.iter(|| s.to_png().unwrap())
+        });
+
+        // an unchanged frame compares row by row, a changed one per pixel
+        let (a, same) = (mock.capture().unwrap(), mock.capture().unwrap());
+        group.bench_function(BenchmarkId::new("diff unchanged", name), |b| {
+            b.iter(|| a.diff(&same))
+        });
+        let (black, white) = (
+            Pixel {
+                a: 255,
+                r: 0,
+                g: 0,
+                b: 0,
+            },
+            Pixel {
+                a: 255,
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+        let checkerboard = MockFrame::Checkerboard {
+            size: 16,
+            a: black,
+            b: white,
+        };
+        let other = MockCapturer::new(width, height, checkerboard)
+            .capture()
+            .unwrap();
+        group.bench_function(BenchmarkId::new("diff changed", name), |b| {
+            b.iter(|| a.diff(&other))
+        });
+        // the gradient has no blue, so white is searched for everywhere
+        group.bench_function(BenchmarkId::new("find_color", name), |b| {
+            b.iter(|| a.find_color(white, 0))
+        });
+    }
+    group.finish();
+}
+
+/// A pass over rows of `width` pixels on the calling thread and split
+/// across rayon's pool, for frames around `PARALLEL_MIN_PIXELS` (256K
+/// pixels) in `src/convert.rs`. Below it, handing out the rows costs more
+/// than the pool saves.
+#[cfg(feature = "rayon")]
+fn threshold(c: &mut Criterion) {
+    use rayon::prelude::*;
+
+    let width = 512;
+    let mut group = c.benchmark_group("threshold");
+    for pixels in [16 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024] {
+        let mut data = vec![0u8; pixels * 4];
+        let opaque = |row: &mut [u8]| row.chunks_exact_mut(4).for_each(|px| px[3] = 255);
+        group.bench_function(BenchmarkId::new("sequential", pixels), |b| {
+            b.iter(|| data.chunks_mut(width * 4).for_each(opaque))
+        });
+        group.bench_function(BenchmarkId::new("parallel", pixels), |b| {
+            b.iter(|| data.par_chunks_mut(width * 4).for_each(opaque))
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(feature = "rayon"))]
+criterion_group!(benches, convert);
+#[cfg(feature = "rayon")]
+criterion_group!(benches, convert, threshold);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Type-checks the crate for every platform it supports, so code gated on
+# another OS doesn't rot. Needs the targets installed:
+# `rustup target add x86_64-pc-windows-msvc x86_64-apple-darwin`
+set -ex
+cargo check --all-targets --target x86_64-pc-windows-msvc
+cargo check --all-targets --target x86_64-pc-windows-msvc --no-default-features
+cargo check --all-targets --target x86_64-pc-windows-msvc --features dxgi,winrt-capture,hotkey
+cargo check --all-targets --target x86_64-unknown-linux-gnu
+cargo check --all-targets --target x86_64-unknown-linux-gnu --features x11,wayland,fbdev
+cargo check --all-targets --target x86_64-apple-darwin
@@ -0,0 +1,432 @@
+//! A screenshot tool on top of the crate, e.g.
+//!
+//! ```text
+//! cargo run --example cli -- --monitor 1 --region 0,0,800,600 -o shot.jpg
+//! cargo run --example cli -- --list-monitors --json
+//! cargo run --example cli --features config -- --profile examples/profile.toml
+//! ```
+//!
+//! Exits with 0 on success, 1 if the capture failed, 2 for invalid
+//! arguments and 3 if the screenshot couldn't be encoded, saved or copied.
+
+use std::{
+    borrow::Cow,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+use image::{codecs::jpeg::JpegEncoder, ColorType, ImageFormat};
+use screenshot::{
+    find_window_by_title, monitors, CaptureOptions, Monitor, MonitorSelector, Rect, Screenshot,
+    ScreenshotError,
+};
+#[cfg(feature = "config")]
+use {
+    screenshot::{CaptureProfile, OutputFormat},
+    std::time::SystemTime,
+};
+
+const CAPTURE_FAILED: u8 = 1;
+const USAGE: u8 = 2;
+const OUTPUT_FAILED: u8 = 3;
+
+#[derive(Parser)]
+#[command(about = "Takes a screenshot of a monitor, region or window.")]
+struct Args {
+    /// Monitor to capture, by index in --list-monitors or by (part of) its
+    /// name. The primary monitor by default.
+    #[arg(short, long, value_name = "INDEX|NAME")]
+    monitor: Option<String>,
+    /// Area to capture: relative to the monitor if one is given, in
+    /// virtual-screen coordinates otherwise.
+    #[arg(short, long, value_name = "X,Y,W,H")]
+    region: Option<RegionArg>,
+    /// Capture the first window whose title contains this.
+    #[arg(short, long, conflicts_with_all = ["monitor", "region"])]
+    window_title: Option<String>,
+    /// With --window-title, have the window draw itself, so windows on top
+    /// of it don't show.
+    #[arg(long, requires = "window_title")]
+    print_window: bool,
+    /// Draw the mouse cursor into the screenshot.
+    #[arg(long)]
+    cursor: bool,
+    /// Capture profile giving what to capture, what to redact and where to
+    /// save, in TOML, or in JSON if it ends in `.json`. See
+    /// examples/profile.toml. Needs the `config` feature.
+    #[arg(short, long, conflicts_with_all = ["monitor", "region", "window_title"])]
+    profile: Option<PathBuf>,
+    /// Seconds to wait before capturing.
+    #[arg(short, long, default_value_t = 0.0)]
+    delay: f64,
+    /// Where to save the screenshot, `screenshot.<format>` by default.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Image format; guessed from the output's extension by default.
+    #[arg(short, long)]
+    format: Option<Format>,
+    /// JPEG quality, from 1 to 100.
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+    /// Copy the screenshot to the clipboard instead of saving it, unless
+    /// --output is given as well.
+    #[arg(short, long)]
+    clipboard: bool,
+    /// List the monitors instead of capturing.
+    #[arg(short, long)]
+    list_monitors: bool,
+    /// With --list-monitors, print JSON instead of a table.
+    #[arg(long, requires = "list_monitors")]
+    json: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Png,
+    Bmp,
+    Jpeg,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(Format::Png),
+            "bmp" => Some(Format::Bmp),
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Bmp => "bmp",
+            Format::Jpeg => "jpg",
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl From<OutputFormat> for Format {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Png => Format::Png,
+            OutputFormat::Bmp => Format::Bmp,
+            OutputFormat::Jpeg => Format::Jpeg,
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Png => OutputFormat::Png,
+            Format::Bmp => OutputFormat::Bmp,
+            Format::Jpeg => OutputFormat::Jpeg,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RegionArg(Rect);
+
+impl FromStr for RegionArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [x, y, width, height] = match parts[..] {
+            [x, y, w, h] => [x, y, w, h],
+            _ => return Err("expected X,Y,W,H".into()),
+        };
+        let int = |v: &str| v.parse::<i32>().map_err(|e| format!("{}: {}", v, e));
+        let size = |v: &str| v.parse::<u32>().map_err(|e| format!("{}: {}", v, e));
+        Ok(RegionArg(Rect {
+            x: int(x)?,
+            y: int(y)?,
+            width: size(width)?,
+            height: size(height)?,
+        }))
+    }
+}
+
+/// Why the tool failed, which decides the exit code.
+enum Failure {
+    Usage(String),
+    Capture(ScreenshotError),
+    Output(String),
+}
+
+impl From<ScreenshotError> for Failure {
+    fn from(e: ScreenshotError) -> Self {
+        Failure::Capture(e)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Usage(msg) | Failure::Output(msg) => f.write_str(msg),
+            Failure::Capture(e) => write!(f, "capture failed: {}", e),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let res = if args.list_monitors {
+        list_monitors(args.json)
+    } else {
+        run(&args)
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("error: {}", failure);
+            ExitCode::from(match failure {
+                Failure::Capture(_) => CAPTURE_FAILED,
+                Failure::Usage(_) => USAGE,
+                Failure::Output(_) => OUTPUT_FAILED,
+            })
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), Failure> {
+    if let Some(path) = &args.profile {
+        return run_profile(args, path);
+    }
+    let mut options = CaptureOptions::new().include_cursor(args.cursor);
+    if let Some(monitor) = &args.monitor {
+        options = options.monitor(select_monitor(monitor)?);
+    }
+    if let Some(RegionArg(region)) = args.region {
+        options = options.region(region);
+    }
+    if let Some(title) = &args.window_title {
+        let window = find_window_by_title(title)?
+            .ok_or_else(|| Failure::Usage(format!("no window titled like {:?}", title)))?;
+        options = options.window(window.id).print_window(args.print_window);
+    }
+    let shot = capture(args, &options)?;
+    output(args, &shot, Format::Png, None)
+}
+
+/// Captures, redacts and saves as the profile at `path` says.
+#[cfg(feature = "config")]
+fn run_profile(args: &Args, path: &Path) -> Result<(), Failure> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| Failure::Usage(format!("can't read {}: {}", path.display(), e)))?;
+    let profile = if path.extension().is_some_and(|e| e == "json") {
+        CaptureProfile::from_json(&text)
+    } else {
+        CaptureProfile::from_toml(&text)
+    }
+    .map_err(|e| Failure::Usage(format!("{}: {}", path.display(), e)))?;
+
+    let mut shot = capture(args, &profile.capture)?;
+    profile.redact(&mut shot);
+
+    let mut output_profile = profile.output.clone();
+    if let Some(format) = args.format {
+        output_profile.format = format.into();
+    }
+    let directory = output_profile.directory.clone().unwrap_or_default();
+    // retention only applies to the profile's own directory
+    let into_directory = args.output.is_none() && !args.clipboard;
+    if into_directory {
+        fs::create_dir_all(&directory)
+            .map_err(|e| Failure::Output(format!("can't create {}: {}", directory.display(), e)))?;
+    }
+    let default_path = output_profile.path(SystemTime::now());
+    output(
+        args,
+        &shot,
+        output_profile.format.into(),
+        Some(default_path),
+    )?;
+    if into_directory {
+        let extension = output_profile.format.extension();
+        let dir = if directory.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &directory
+        };
+        output_profile
+            .retention
+            .prune(dir, extension)
+            .map_err(|e| Failure::Output(format!("can't prune {}: {}", dir.display(), e)))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "config"))]
+fn run_profile(_: &Args, _: &Path) -> Result<(), Failure> {
+    Err(Failure::Usage(
+        "--profile needs the `config` feature".into(),
+    ))
+}
+
+/// Validates `options`, waits for `--delay` and captures.
+fn capture(args: &Args, options: &CaptureOptions) -> Result<Screenshot, Failure> {
+    options
+        .validate()
+        .map_err(|e| Failure::Usage(e.to_string()))?;
+    if args.delay > 0.0 {
+        thread::sleep(Duration::from_secs_f64(args.delay));
+    }
+    Ok(options.capture()?)
+}
+
+/// Saves `shot` or copies it to the clipboard as the arguments say, in
+/// `default_format` to `default_path` unless they give others.
+fn output(
+    args: &Args,
+    shot: &Screenshot,
+    default_format: Format,
+    default_path: Option<PathBuf>,
+) -> Result<(), Failure> {
+    if args.clipboard {
+        copy_to_clipboard(shot)?;
+        if args.output.is_none() {
+            return Ok(());
+        }
+    }
+    let format = match (args.format, &args.output) {
+        (Some(format), _) => format,
+        (None, Some(path)) => Format::from_path(path).ok_or_else(|| {
+            Failure::Usage(format!(
+                "can't tell the format of {}, use --format",
+                path.display()
+            ))
+        })?,
+        (None, None) => default_format,
+    };
+    let path = args
+        .output
+        .clone()
+        .or(default_path)
+        .unwrap_or_else(|| PathBuf::from(format!("screenshot.{}", format.extension())));
+    save(shot, &path, format, args.quality)
+        .map_err(|e| Failure::Output(format!("can't save {}: {}", path.display(), e)))?;
+    println!("{} x {} -> {}", shot.width(), shot.height(), path.display());
+    Ok(())
+}
+
+/// A monitor by index, or the first one whose name or device name contains
+/// `arg`.
+fn select_monitor(arg: &str) -> Result<MonitorSelector, Failure> {
+    if let Ok(index) = arg.parse() {
+        return Ok(MonitorSelector::Index(index));
+    }
+    let needle = arg.to_lowercase();
+    monitors()?
+        .into_iter()
+        .find(|m| {
+            m.name
+                .iter()
+                .chain(&m.device_name)
+                .any(|name| name.to_lowercase().contains(&needle))
+        })
+        .map(MonitorSelector::Monitor)
+        .ok_or_else(|| Failure::Usage(format!("no monitor named like {:?}", arg)))
+}
+
+fn save(shot: &Screenshot, path: &Path, format: Format, quality: u8) -> Result<(), String> {
+    let (width, height) = (shot.width() as u32, shot.height() as u32);
+    match format {
+        Format::Bmp => shot.save_bmp(path).map_err(|e| e.to_string()),
+        Format::Png => image::save_buffer_with_format(
+            path,
+            &shot.to_rgba_vec(),
+            width,
+            height,
+            ColorType::Rgba8,
+            ImageFormat::Png,
+        )
+        .map_err(|e| e.to_string()),
+        // JPEG has no alpha channel
+        Format::Jpeg => {
+            let file = fs::File::create(path).map_err(|e| e.to_string())?;
+            JpegEncoder::new_with_quality(file, quality)
+                .encode(&shot.to_rgb_vec(), width, height, ColorType::Rgb8)
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn copy_to_clipboard(shot: &Screenshot) -> Result<(), Failure> {
+    let image = arboard::ImageData {
+        width: shot.width(),
+        height: shot.height(),
+        bytes: Cow::Owned(shot.to_rgba_vec()),
+    };
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_image(image))
+        .map_err(|e| Failure::Output(format!("can't copy to the clipboard: {}", e)))
+}
+
+fn list_monitors(json: bool) -> Result<(), Failure> {
+    let monitors = monitors()?;
+    if json {
+        let list: Vec<_> = monitors.iter().enumerate().map(monitor_json).collect();
+        println!("{}", serde_json::to_string_pretty(&list).unwrap());
+        return Ok(());
+    }
+    for (index, m) in monitors.iter().enumerate() {
+        let mut line = format!(
+            "{}{} {:<24} {} x {} at ({}, {}), scale {}",
+            index,
+            if m.primary { '*' } else { ' ' },
+            m.name.as_deref().unwrap_or("?"),
+            m.rect.width,
+            m.rect.height,
+            m.rect.x,
+            m.rect.y,
+            m.scale_factor,
+        );
+        if let Some(rate) = m.refresh_rate {
+            line += &format!(", {:.0} Hz", rate);
+        }
+        if let Some(bits) = m.bits_per_pixel {
+            line += &format!(", {} bpp", bits);
+        }
+        if let Some(size) = m.physical_size_mm {
+            line += &format!(", {} x {} mm", size.width, size.height);
+        }
+        if let Some(device) = m
+            .device_name
+            .as_ref()
+            .filter(|&d| Some(d) != m.name.as_ref())
+        {
+            line += &format!(", {}", device);
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn monitor_json((index, m): (usize, &Monitor)) -> serde_json::Value {
+    let rect =
+        |r: Rect| serde_json::json!({ "x": r.x, "y": r.y, "width": r.width, "height": r.height });
+    serde_json::json!({
+        "index": index,
+        "name": m.name,
+        "device_name": m.device_name,
+        "primary": m.primary,
+        "rect": rect(m.rect),
+        "work_area": rect(m.work_area),
+        "scale_factor": m.scale_factor,
+        "refresh_rate": m.refresh_rate,
+        "bits_per_pixel": m.bits_per_pixel,
+        "orientation": m.orientation.map(|o| format!("{:?}", o)),
+        "physical_size_mm": m.physical_size_mm.map(|s| [s.width, s.height]),
+    })
+}
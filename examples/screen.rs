@@ -1,16 +1,15 @@
-use std::time::Instant;
+use std::{convert::TryFrom, error::Error, time::Instant};
 
 use image::RgbaImage;
 use screenshot::get_screenshot;
 
-fn main() {
+fn main() -> Result<(), Box<dyn Error>> {
     let instant = Instant::now();
     println!("Started after: {}", instant.elapsed().as_millis()); // 50 - 60 ms
-    let s = get_screenshot().unwrap();
+    let s = get_screenshot()?;
     println!("Got screenshot after: {}", instant.elapsed().as_millis()); // 50 - 60 ms
 
-    let img2 =
-        RgbaImage::from_raw(s.width as u32, s.height as u32, s.data_r_and_b_switched).unwrap();
+    let img = RgbaImage::try_from(&s)?;
 
     // 10 - 15 ms
     println!(
@@ -18,24 +17,22 @@ fn main() {
         instant.elapsed().as_millis()
     );
 
-    img2.save_with_format("test_vec.bmp", image::ImageFormat::Bmp)
-        .unwrap();
+    img.save_with_format("test_vec.bmp", image::ImageFormat::Bmp)?;
     println!(
         "Saved second image after: {}",
         instant.elapsed().as_millis()
     ); // 25 ms
 
-    img2.save_with_format("test2_vec.png", image::ImageFormat::Png)
-        .unwrap();
+    img.save_with_format("test2_vec.png", image::ImageFormat::Png)?;
     println!("Saved first image after: {}", instant.elapsed().as_millis()); // 45 - 55 ms
 
     image::save_buffer(
         "test.png",
-        &s.data,
-        s.width as u32,
-        s.height as u32,
-        image::ColorType::Rgba8, // RGBA(8),
-    )
-    .unwrap();
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        image::ColorType::Rgba8,
+    )?;
     println!("Saved third image after: {}", instant.elapsed().as_millis()); // 45 - 55 ms
+    Ok(())
 }
@@ -0,0 +1,322 @@
+//! Combining several captures of the same area into one, to even out
+//! frame-to-frame noise such as video or temporal dithering.
+
+use crate::{
+    CancelToken, CaptureBackend, CaptureOptions, CaptureTarget, Progress, Screenshot,
+    ScreenshotError, PIXEL_WIDTH,
+};
+
+use std::{thread, time::Duration};
+
+/// How `get_screenshot_averaged_with` combines its frames, channel by
+/// channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Averaging {
+    /// The mean, rounded. Smooths noise best, but something on screen in
+    /// only one of the frames, e.g. a popup, still shows faintly.
+    #[default]
+    Mean,
+    /// The median, which ignores what shows in fewer than half of the
+    /// frames. Keeps every frame in memory until the last is captured.
+    Median,
+}
+
+/// Captures the primary display `frames` times, `interval` apart, and
+/// returns the mean of the frames.
+pub fn get_screenshot_averaged(
+    frames: u32,
+    interval: Duration,
+) -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_averaged_with(
+        frames,
+        interval,
+        Averaging::Mean,
+        &CaptureOptions::default(),
+    )
+}
+
+/// Like `get_screenshot_averaged`, capturing what `options` select and
+/// combining the frames as `averaging` says.
+pub fn get_screenshot_averaged_with(
+    frames: u32,
+    interval: Duration,
+    averaging: Averaging,
+    options: &CaptureOptions,
+) -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_averaged_cancellable(
+        frames,
+        interval,
+        averaging,
+        options,
+        &CancelToken::new(),
+        |_| {},
+    )
+}
+
+/// Like `get_screenshot_averaged_with`, failing with
+/// `ScreenshotError::Cancelled` once `cancel` is cancelled, also while
+/// waiting between frames, and calling `progress` after every frame.
+pub fn get_screenshot_averaged_cancellable(
+    frames: u32,
+    interval: Duration,
+    averaging: Averaging,
+    options: &CaptureOptions,
+    cancel: &CancelToken,
+    mut progress: impl FnMut(Progress),
+) -> Result<Screenshot, ScreenshotError> {
+    let mut backend = options.backend.open()?;
+    let target = options.target(|| backend.monitors())?;
+    average(frames, averaging, |k| {
+        if k > 0 {
+            cancel.sleep(interval)?;
+        }
+        cancel.check()?;
+        let frame = backend.capture_target(target, options)?;
+        progress(Progress {
+            done: k + 1,
+            total: Some(frames),
+        });
+        Ok(frame)
+    })
+}
+
+/// Captures `target` with `backend` `frames` times, `interval` apart, and
+/// combines the frames as `averaging` says. The result has the pixel
+/// format and metadata of the first frame, and rows without padding.
+///
+/// Fails with `ScreenshotError::DisplayChanged` if the frames differ in
+/// size, and with `ScreenshotError::InvalidOptions` if `frames` is zero.
+pub fn capture_averaged<B: CaptureBackend + ?Sized>(
+    backend: &mut B,
+    target: CaptureTarget,
+    options: &CaptureOptions,
+    frames: u32,
+    interval: Duration,
+    averaging: Averaging,
+) -> Result<Screenshot, ScreenshotError> {
+    average(frames, averaging, |k| {
+        if k > 0 {
+            thread::sleep(interval);
+        }
+        backend.capture_target(target, options)
+    })
+}
+
+/// Combines `frames` frames, the `k`th of which `next(k)` returns.
+fn average(
+    frames: u32,
+    averaging: Averaging,
+    mut next: impl FnMut(u32) -> Result<Screenshot, ScreenshotError>,
+) -> Result<Screenshot, ScreenshotError> {
+    if frames == 0 {
+        return Err(ScreenshotError::InvalidOptions(
+            "averaging needs at least one frame",
+        ));
+    }
+    let first = next(0)?;
+    let mut combiner = match averaging {
+        Averaging::Mean => Combiner::Mean(vec![0; first.width() * first.height() * PIXEL_WIDTH]),
+        Averaging::Median => Combiner::Median(Vec::with_capacity(frames as usize)),
+    };
+    combiner.add(packed(&first));
+    for k in 1..frames {
+        let mut frame = next(k)?;
+        if frame.size() != first.size() {
+            return Err(ScreenshotError::DisplayChanged {
+                before: (first.width() as i32, first.height() as i32),
+                after: (frame.width() as i32, frame.height() as i32),
+            });
+        }
+        if frame.format() != first.format() {
+            frame.swap_r_b_in_place();
+        }
+        combiner.add(packed(&frame));
+    }
+
+    let data = combiner.finish(frames);
+    let row_len = first.width() * PIXEL_WIDTH;
+    let mut averaged = Screenshot::from_bgra(data, first.width(), first.height(), row_len);
+    // the frames were all brought into the first one's channel order
+    averaged.format = first.format();
+    averaged.metadata = first.metadata;
+    Ok(averaged)
+}
+
+/// The frames combined so far, as packed pixels.
+enum Combiner {
+    /// Per-channel sums.
+    Mean(Vec<u32>),
+    Median(Vec<Vec<u8>>),
+}
+
+impl Combiner {
+    fn add(&mut self, frame: Vec<u8>) {
+        match self {
+            Combiner::Mean(sums) => {
+                for (sum, &v) in sums.iter_mut().zip(&frame) {
+                    *sum += u32::from(v);
+                }
+            }
+            Combiner::Median(frames) => frames.push(frame),
+        }
+    }
+
+    fn finish(self, count: u32) -> Vec<u8> {
+        match self {
+            Combiner::Mean(sums) => sums
+                .into_iter()
+                .map(|sum| ((sum + count / 2) / count) as u8)
+                .collect(),
+            Combiner::Median(frames) => {
+                let mut values = Vec::with_capacity(frames.len());
+                (0..frames[0].len())
+                    .map(|i| {
+                        values.clear();
+                        values.extend(frames.iter().map(|frame| frame[i]));
+                        values.sort_unstable();
+                        let mid = values.len() / 2;
+                        if values.len() % 2 == 1 {
+                            values[mid]
+                        } else {
+                            ((u16::from(values[mid - 1]) + u16::from(values[mid])) / 2) as u8
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The pixels of `frame` without row padding.
+fn packed(frame: &Screenshot) -> Vec<u8> {
+    let len = frame.width() * PIXEL_WIDTH;
+    if frame.row_len() == len {
+        return frame.data().to_vec();
+    }
+    frame
+        .data()
+        .chunks(frame.row_len().max(1))
+        .take(frame.height())
+        .flat_map(|row| &row[..len])
+        .copied()
+        .collect()
+}
+
+/// Hands out prepared frames, one per capture.
+#[cfg(test)]
+struct Replay(std::collections::VecDeque<Screenshot>);
+
+#[cfg(test)]
+impl CaptureBackend for Replay {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn monitors(&self) -> Result<Vec<crate::Monitor>, ScreenshotError> {
+        Ok(Vec::new())
+    }
+
+    fn windows(&self) -> Result<Vec<crate::Window>, ScreenshotError> {
+        Ok(Vec::new())
+    }
+
+    fn capture_target(
+        &mut self,
+        _: CaptureTarget,
+        _: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        self.0.pop_front().ok_or(ScreenshotError::NoMonitors)
+    }
+}
+
+/// A clean gradient that noisy frames are made of, from 8 to 247.
+#[cfg(test)]
+fn clean(x: usize, y: usize) -> u8 {
+    (8 + (x * 16 + y * 8) % 240) as u8
+}
+
+/// Frame `k` of `offsets.len()`, each pixel off the clean image by a
+/// different one of `offsets` in every frame, with padded rows.
+#[cfg(test)]
+fn noisy(k: usize, offsets: &[i16]) -> Screenshot {
+    let (width, height, row_len) = (12, 6, 52);
+    let mut data = vec![0; row_len * height];
+    for (y, row) in data.chunks_mut(row_len).enumerate() {
+        for (x, px) in row.chunks_exact_mut(PIXEL_WIDTH).take(width).enumerate() {
+            let offset = offsets[(x + y + k) % offsets.len()];
+            let v = (i16::from(clean(x, y)) + offset) as u8;
+            px.copy_from_slice(&[v, v / 2, 255 - v, 255]);
+        }
+    }
+    Screenshot::from_raw(data, width, height, row_len).unwrap()
+}
+
+#[cfg(test)]
+fn max_error(shot: &Screenshot) -> u8 {
+    let mut max = 0;
+    for y in 0..shot.height() {
+        for x in 0..shot.width() {
+            let (p, v) = (shot.get_pixel_xy(x, y), clean(x, y));
+            max = max
+                .max(p.b.abs_diff(v))
+                .max(p.g.abs_diff(v / 2))
+                .max(p.r.abs_diff(255 - v));
+        }
+    }
+    max
+}
+
+#[test]
+fn test_averaging() {
+    let options = CaptureOptions::default();
+    let average = |frames: Vec<Screenshot>, averaging| {
+        let count = frames.len() as u32;
+        let mut replay = Replay(frames.into());
+        capture_averaged(
+            &mut replay,
+            CaptureTarget::Primary,
+            &options,
+            count,
+            Duration::ZERO,
+            averaging,
+        )
+    };
+
+    let offsets = [-3, 3, -2, 2, 0];
+    let frames: Vec<_> = (0..5).map(|k| noisy(k, &offsets)).collect();
+    assert_eq!(max_error(&frames[0]), 3);
+    let mean = average(frames, Averaging::Mean).unwrap();
+    assert_eq!((mean.width(), mean.height(), mean.row_len()), (12, 6, 48));
+    assert!(max_error(&mean) <= 1);
+
+    // a popup in one frame, and frames in either pixel format
+    let popup = || {
+        let offsets = [-1, 1, -1, 1, 0];
+        let mut frames: Vec<_> = (0..5).map(|k| noisy(k, &offsets)).collect();
+        let mut data = frames[2].data().to_vec();
+        data[..52 * 3].fill(255);
+        frames[2] = Screenshot::from_raw(data, 12, 6, 52).unwrap();
+        frames[3].swap_r_b_in_place();
+        frames
+    };
+    let median = average(popup(), Averaging::Median).unwrap();
+    assert_eq!(median.format(), crate::PixelFormat::Bgra8);
+    assert!(max_error(&median) <= 1);
+    assert!(max_error(&average(popup(), Averaging::Mean).unwrap()) > 1);
+
+    let frames = vec![
+        noisy(0, &[0]),
+        Screenshot::from_raw(vec![0; 4], 1, 1, 4).unwrap(),
+    ];
+    assert!(matches!(
+        average(frames, Averaging::Mean),
+        Err(ScreenshotError::DisplayChanged {
+            before: (12, 6),
+            after: (1, 1)
+        })
+    ));
+    assert!(matches!(
+        average(Vec::new(), Averaging::Mean),
+        Err(ScreenshotError::InvalidOptions(_))
+    ));
+}
@@ -0,0 +1,198 @@
+//! Direct3D helpers shared by the DXGI and Windows.Graphics.Capture
+//! backends, which both get frames as textures and copy them to the CPU.
+
+use crate::Rect;
+
+use windows::{
+    core::Result,
+    Win32::Foundation::{E_POINTER, HINSTANCE},
+    Win32::Graphics::Direct3D::{D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_UNKNOWN},
+    Win32::Graphics::Direct3D11::*,
+    Win32::Graphics::Dxgi::{Common::DXGI_SAMPLE_DESC, IDXGIAdapter},
+};
+
+use std::slice;
+
+/// A device on `adapter`, or on the default hardware adapter.
+pub(crate) fn create_device(
+    adapter: Option<&IDXGIAdapter>,
+) -> Result<(ID3D11Device, ID3D11DeviceContext)> {
+    // the driver type must be unknown if the adapter is given
+    let driver_type = match adapter {
+        Some(_) => D3D_DRIVER_TYPE_UNKNOWN,
+        None => D3D_DRIVER_TYPE_HARDWARE,
+    };
+    let (mut device, mut context) = (None, None);
+    unsafe {
+        D3D11CreateDevice(
+            adapter,
+            driver_type,
+            HINSTANCE::default(),
+            D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+            None,
+            D3D11_SDK_VERSION,
+            Some(&mut device),
+            None,
+            Some(&mut context),
+        )
+    }?;
+    device.zip(context).ok_or_else(|| E_POINTER.into())
+}
+
+/// A texture like the one `desc` describes that the CPU can map.
+pub(crate) fn create_staging(
+    device: &ID3D11Device,
+    desc: &D3D11_TEXTURE2D_DESC,
+) -> Result<ID3D11Texture2D> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        MipLevels: 1,
+        ArraySize: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: D3D11_USAGE_STAGING,
+        BindFlags: D3D11_BIND_FLAG(0),
+        CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+        MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+        ..*desc
+    };
+    unsafe { device.CreateTexture2D(&desc, None) }
+}
+
+/// Copies `texture` to `staging`, which must be like it, and then `rects`
+/// of it into `image`, packed BGRA rows `width` pixels wide. The rects
+/// must lie within both.
+pub(crate) fn read_rects(
+    context: &ID3D11DeviceContext,
+    staging: &ID3D11Texture2D,
+    texture: &ID3D11Texture2D,
+    rects: &[Rect],
+    image: &mut [u8],
+    width: usize,
+) -> Result<()> {
+    let mut desc = D3D11_TEXTURE2D_DESC::default();
+    unsafe { staging.GetDesc(&mut desc) };
+    if desc.Width == 0 || desc.Height == 0 {
+        return Ok(());
+    }
+    unsafe { context.CopyResource(staging, texture) };
+    let mapped = unsafe { context.Map(staging, 0, D3D11_MAP_READ, 0) }?;
+    let stride = mapped.RowPitch as usize;
+    // SAFETY: the mapped texture has `Height` rows `stride` bytes apart,
+    // each starting with `Width` BGRA pixels.
+    let src = unsafe {
+        slice::from_raw_parts(
+            mapped.pData as *const u8,
+            stride * (desc.Height as usize - 1) + desc.Width as usize * 4,
+        )
+    };
+    for rect in rects {
+        let at = (rect.x as usize, rect.y as usize);
+        let size = (rect.width as usize, rect.height as usize);
+        copy_block(src, stride, at, image, width * 4, at, size);
+    }
+    unsafe { context.Unmap(staging, 0) };
+    Ok(())
+}
+
+/// Copies `rect`, in virtual-screen coordinates, from the monitor
+/// `images`, each packed BGRA rows of its bounds, into `dst`, packed BGRA
+/// rows of `rect`. What no image covers is black.
+pub(crate) fn compose(images: &[(Rect, &[u8])], rect: Rect, dst: &mut [u8]) {
+    let overlaps: Vec<_> = images
+        .iter()
+        .filter(|(bounds, image)| image.len() as u64 == bounds.area() * 4)
+        .filter_map(|&(bounds, image)| Some((bounds, bounds.intersect(&rect)?, image)))
+        .collect();
+    // monitors don't overlap, so their areas add up to what they cover
+    if overlaps
+        .iter()
+        .map(|(_, overlap, _)| overlap.area())
+        .sum::<u64>()
+        < rect.area()
+    {
+        dst.fill(0);
+    }
+    for (bounds, overlap, image) in overlaps {
+        copy_block(
+            image,
+            bounds.width as usize * 4,
+            (
+                (overlap.x - bounds.x) as usize,
+                (overlap.y - bounds.y) as usize,
+            ),
+            dst,
+            rect.width as usize * 4,
+            ((overlap.x - rect.x) as usize, (overlap.y - rect.y) as usize),
+            (overlap.width as usize, overlap.height as usize),
+        );
+    }
+}
+
+/// Copies a block of `(width, height)` BGRA pixels at `from` in `src` to
+/// `to` in `dst`, whose rows are the given strides apart.
+pub(crate) fn copy_block(
+    src: &[u8],
+    src_stride: usize,
+    from: (usize, usize),
+    dst: &mut [u8],
+    dst_stride: usize,
+    to: (usize, usize),
+    (width, height): (usize, usize),
+) {
+    let len = width * 4;
+    for row in 0..height {
+        let src = &src[(from.1 + row) * src_stride + from.0 * 4..][..len];
+        dst[(to.1 + row) * dst_stride + to.0 * 4..][..len].copy_from_slice(src);
+    }
+}
+
+#[test]
+fn test_copy_block() {
+    // 3x2 pixels, rows padded to 16 bytes
+    let src: Vec<u8> = (0..32).collect();
+    let mut dst = vec![0; 2 * 2 * 4];
+    copy_block(&src, 16, (1, 0), &mut dst, 8, (0, 0), (2, 2));
+    let expected: Vec<u8> = (4..12).chain(20..28).collect();
+    assert_eq!(dst, expected);
+
+    // a 1x1 block into the bottom right corner
+    let mut dst = vec![0; 2 * 2 * 4];
+    copy_block(&src, 16, (2, 1), &mut dst, 8, (1, 1), (1, 1));
+    assert_eq!(dst[..12], [0; 12]);
+    assert_eq!(dst[12..], [24, 25, 26, 27]);
+}
+
+#[test]
+fn test_compose() {
+    let monitor = |x, width, height| Rect {
+        x,
+        y: 0,
+        width,
+        height,
+    };
+    // a 2x2 monitor with a 1x1 one left of it, filled with 1s and 2s
+    let (right, left) = (vec![1; 2 * 2 * 4], vec![2; 4]);
+    let images = [
+        (monitor(0, 2, 2), &right[..]),
+        (monitor(-1, 1, 1), &left[..]),
+    ];
+
+    // the gap below the small monitor is black, even in a used buffer
+    let mut dst = vec![9; 3 * 2 * 4];
+    compose(&images, monitor(-1, 3, 2), &mut dst);
+    assert_eq!(dst[..12], [2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1]);
+    assert_eq!(dst[12..], [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+
+    // only part of a monitor
+    let mut dst = vec![9; 4];
+    compose(&images, monitor(1, 1, 1), &mut dst);
+    assert_eq!(dst, [1; 4]);
+
+    // a monitor without an image yet is skipped
+    let images = [(monitor(0, 2, 2), &[][..])];
+    let mut dst = vec![9; 4];
+    compose(&images, monitor(0, 1, 1), &mut dst);
+    assert_eq!(dst, [0; 4]);
+}
@@ -0,0 +1,577 @@
+//! The DXGI desktop duplication backend: Windows hands us each monitor's
+//! desktop image as a texture, and we copy to the CPU only the areas that
+//! changed since the previous frame. Much cheaper than GDI for repeated
+//! captures, but unavailable in remote sessions.
+//!
+//! Frames only arrive when something on the monitor changed, so the latest
+//! image of every monitor is kept, and captures in between copy from it.
+//! The sessions live in the same `State` as GDI's bitmap, so `Capturer` and
+//! everything built on it use whichever of the two the options resolve to.
+
+use super::{
+    d3d::{compose, create_device, create_staging, read_rects},
+    gdi::{monitors, top_level_windows, State},
+};
+use crate::{
+    buffer::PixelBuffer, trace::trace_event, Backend, CaptureBackend, CaptureOptions,
+    CaptureTarget, Monitor, Rect, Screenshot, ScreenshotError, Window,
+};
+
+use windows::{
+    core::Interface,
+    Win32::Foundation::{E_ACCESSDENIED, RECT},
+    Win32::Graphics::Direct3D11::*,
+    Win32::Graphics::Dxgi::{Common::*, *},
+};
+
+use std::{
+    mem::{self, size_of},
+    time::{Duration, Instant},
+};
+
+/// How long the first capture of a monitor waits for its first frame.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Captures with DXGI desktop duplication, keeping the duplication
+/// sessions and the latest image of every monitor between captures.
+///
+/// Like a `Capturer`, a `DxgiBackend` is `Send` but not `Sync`. A window
+/// is captured as it appears on screen; with `CaptureOptions::print_window`
+/// it's drawn through GDI instead. Frames come without the cursor, so
+/// `CaptureOptions::include_cursor` takes them with GDI under
+/// `Backend::Auto` and fails with any other backend option.
+pub struct DxgiBackend {
+    state: State,
+    /// Target of the latest capture, whose area `state` may have cached.
+    target: Option<CaptureTarget>,
+}
+
+impl Default for DxgiBackend {
+    fn default() -> Self {
+        let mut state = State::default();
+        state.set_backend(Backend::DxgiDuplication);
+        DxgiBackend {
+            state,
+            target: None,
+        }
+    }
+}
+
+impl DxgiBackend {
+    /// The areas of the latest screenshot that changed since the one
+    /// before, in pixels of the screenshot: all of it for the first capture
+    /// of a target, none if nothing changed.
+    pub fn last_dirty_rects(&self) -> &[Rect] {
+        self.state.dirty_rects.as_deref().unwrap_or(&[])
+    }
+}
+
+impl CaptureBackend for DxgiBackend {
+    fn name(&self) -> &'static str {
+        "dxgi"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        monitors()
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        top_level_windows()
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        if self.target.replace(target) != Some(target) {
+            self.state.cache.clear();
+        }
+        self.state.capture(target, options)?;
+        let empty = Screenshot::from_bgra(Vec::new(), 0, 0, 0);
+        Ok(mem::replace(&mut self.state.frame, empty))
+    }
+}
+
+/// Whether duplication can be set up here, or why not. Opens and drops
+/// the sessions, so it's as expensive as the first capture.
+pub(crate) fn probe() -> Result<(), String> {
+    Duplication::open().map(drop).map_err(|e| e.to_string())
+}
+
+/// The duplication sessions of all monitors.
+pub(crate) struct Duplication {
+    outputs: Vec<Output>,
+    /// The area of the previous `read_into`, which the dirty rects of the
+    /// next one are relative to.
+    previous: Option<Rect>,
+}
+
+// SAFETY: the devices are free-threaded, and the contexts and sessions are
+// only used through `&mut self`, so never from two threads at once.
+unsafe impl Send for Duplication {}
+
+impl Duplication {
+    /// Starts duplicating every monitor attached to the desktop.
+    pub(crate) fn open() -> Result<Self, ScreenshotError> {
+        let factory: IDXGIFactory1 =
+            unsafe { CreateDXGIFactory1() }.map_err(failed("CreateDXGIFactory1"))?;
+        let mut outputs = Vec::new();
+        // both enumerations end with DXGI_ERROR_NOT_FOUND
+        for adapter in (0..).map_while(|i| unsafe { factory.EnumAdapters(i) }.ok()) {
+            // adapters without monitors, e.g. the software renderer, get no
+            // device
+            let mut shared: Option<(ID3D11Device, ID3D11DeviceContext)> = None;
+            for output in (0..).map_while(|i| unsafe { adapter.EnumOutputs(i) }.ok()) {
+                let desc = unsafe { output.GetDesc() }.map_err(failed("GetDesc"))?;
+                if !desc.AttachedToDesktop.as_bool() {
+                    continue;
+                }
+                let (device, context) = match &shared {
+                    Some(pair) => pair.clone(),
+                    None => shared
+                        .insert(create_device(Some(&adapter)).map_err(failed("D3D11CreateDevice"))?)
+                        .clone(),
+                };
+                let output = output.cast().map_err(failed("QueryInterface"))?;
+                outputs.push(Output::open(output, device, context)?);
+            }
+        }
+        if outputs.is_empty() {
+            return Err(ScreenshotError::NoMonitors);
+        }
+        Ok(Duplication {
+            outputs,
+            previous: None,
+        })
+    }
+
+    /// Updates the monitors overlapping `rect`, in virtual-screen
+    /// coordinates, and copies it from their images into `buf` as packed
+    /// BGRA rows. Returns the row length and the areas that changed since
+    /// the previous call, in pixels of `rect`.
+    pub(crate) fn read_into<B: PixelBuffer>(
+        &mut self,
+        rect: Rect,
+        buf: &mut B,
+    ) -> Result<(usize, Vec<Rect>), ScreenshotError> {
+        let mut dirty = Vec::new();
+        for output in &mut self.outputs {
+            if output.rect.intersect(&rect).is_none() {
+                continue;
+            }
+            for changed in output.update()? {
+                let changed = Rect {
+                    x: changed.x + output.rect.x,
+                    y: changed.y + output.rect.y,
+                    ..changed
+                };
+                if let Some(changed) = changed.intersect(&rect) {
+                    dirty.push(Rect {
+                        x: changed.x - rect.x,
+                        y: changed.y - rect.y,
+                        ..changed
+                    });
+                }
+            }
+        }
+        // a new area has nothing to be compared with
+        if self.previous.replace(rect) != Some(rect) {
+            dirty = vec![Rect { x: 0, y: 0, ..rect }];
+        }
+        let row_len = rect.width as usize * 4;
+        buf.reset(row_len * rect.height as usize);
+        let images: Vec<_> = self
+            .outputs
+            .iter()
+            .map(|output| (output.rect, &output.image[..]))
+            .collect();
+        compose(&images, rect, buf);
+        Ok((row_len, dirty))
+    }
+}
+
+/// A monitor being duplicated, with its latest image.
+struct Output {
+    output: IDXGIOutput1,
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    /// The duplication session, None once it's lost until it's started
+    /// again.
+    duplication: Option<IDXGIOutputDuplication>,
+    /// Bounds in virtual-screen coordinates.
+    rect: Rect,
+    /// How the desktop is turned on the monitor. Frames come the way the
+    /// monitor scans out, so they're turned back into `image`.
+    rotation: DXGI_MODE_ROTATION,
+    /// The latest frame as it came, for a rotated monitor.
+    raw: Vec<u8>,
+    /// A copy of the desktop texture the CPU can read, created for the
+    /// first frame.
+    staging: Option<ID3D11Texture2D>,
+    /// The latest frame as packed BGRA rows, empty before the first one.
+    image: Vec<u8>,
+}
+
+impl Output {
+    fn open(
+        output: IDXGIOutput1,
+        device: ID3D11Device,
+        context: ID3D11DeviceContext,
+    ) -> Result<Self, ScreenshotError> {
+        let desc = unsafe { output.GetDesc() }.map_err(failed("GetDesc"))?;
+        let duplication =
+            unsafe { output.DuplicateOutput(&device) }.map_err(failed("DuplicateOutput"))?;
+        Ok(Output {
+            output,
+            device,
+            context,
+            duplication: Some(duplication),
+            rect: desc.DesktopCoordinates.into(),
+            rotation: desc.Rotation,
+            raw: Vec::new(),
+            staging: None,
+            image: Vec::new(),
+        })
+    }
+
+    /// Brings `image` up to date, returning the areas that changed, in
+    /// pixels of the monitor as the desktop is laid out on it. Waits up to `FIRST_FRAME_TIMEOUT` for the
+    /// first frame; later ones are only taken if they're ready.
+    fn update(&mut self) -> Result<Vec<Rect>, ScreenshotError> {
+        let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+        let mut restarted = false;
+        loop {
+            let wait = match self.image.is_empty() {
+                true => deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis() as u32,
+                false => 0,
+            };
+            let duplication = match &self.duplication {
+                Some(duplication) => duplication.clone(),
+                // starting it again failed last time
+                None => {
+                    self.restart()?;
+                    continue;
+                }
+            };
+            let mut info = DXGI_OUTDUPL_FRAME_INFO::default();
+            let mut resource = None;
+            match unsafe { duplication.AcquireNextFrame(wait, &mut info, &mut resource) } {
+                Ok(()) => {}
+                Err(e) if e.code() == DXGI_ERROR_WAIT_TIMEOUT => {
+                    return match self.image.is_empty() {
+                        true => Err(ScreenshotError::Timeout(FIRST_FRAME_TIMEOUT)),
+                        false => Ok(Vec::new()),
+                    };
+                }
+                // The mode changed or another desktop was switched to. A new
+                // session starts over with a full frame.
+                Err(e) if e.code() == DXGI_ERROR_ACCESS_LOST && !restarted => {
+                    trace_event!(debug, "desktop duplication lost, starting it again");
+                    // so `restart` releases the last reference
+                    drop(duplication);
+                    self.restart()?;
+                    restarted = true;
+                    continue;
+                }
+                Err(e) => return Err(failed("AcquireNextFrame")(e)),
+            }
+            let _frame = AcquiredFrame(duplication);
+            match resource {
+                Some(resource) if info.LastPresentTime != 0 => {
+                    return self.read_frame(&info, &resource)
+                }
+                // only the pointer moved, the image is as before
+                _ if self.image.is_empty() => continue,
+                _ => return Ok(Vec::new()),
+            }
+        }
+    }
+
+    /// Starts a new duplication session, which begins with a full frame,
+    /// in place of a lost one.
+    fn restart(&mut self) -> Result<(), ScreenshotError> {
+        // DXGI refuses to duplicate the output again while the lost
+        // duplication is alive
+        self.duplication = None;
+        let (output, device, context) = (
+            self.output.clone(),
+            self.device.clone(),
+            self.context.clone(),
+        );
+        *self = Output::open(output, device, context)?;
+        Ok(())
+    }
+
+    /// Copies the changed areas of the acquired frame into `image`.
+    fn read_frame(
+        &mut self,
+        info: &DXGI_OUTDUPL_FRAME_INFO,
+        resource: &IDXGIResource,
+    ) -> Result<Vec<Rect>, ScreenshotError> {
+        let texture: ID3D11Texture2D = resource.cast().map_err(failed("QueryInterface"))?;
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        if desc.Format != DXGI_FORMAT_B8G8R8A8_UNORM {
+            return Err(ScreenshotError::DxgiFailed(format!(
+                "unsupported desktop format {}",
+                desc.Format.0
+            )));
+        }
+        let (width, height) = (desc.Width as usize, desc.Height as usize);
+        if width == 0 || height == 0 {
+            return Err(ScreenshotError::EmptyDisplay {
+                width: width as i32,
+                height: height as i32,
+            });
+        }
+        let bounds = Rect {
+            x: 0,
+            y: 0,
+            width: desc.Width,
+            height: desc.Height,
+        };
+        // the first frame, or one of another size, is copied whole
+        let fresh = self.image.len() != width * height * 4;
+        let changed = match fresh {
+            true => None,
+            false => self.changed_rects(info)?,
+        };
+        let changed: Vec<_> = changed
+            .unwrap_or_else(|| vec![bounds])
+            .iter()
+            .filter_map(|rect| rect.intersect(&bounds))
+            .collect();
+        let staging = match self.staging.take() {
+            Some(staging) if !fresh => staging,
+            _ => create_staging(&self.device, &desc).map_err(failed("CreateTexture2D"))?,
+        };
+        let staging = self.staging.insert(staging);
+        self.image.resize(width * height * 4, 0);
+        if matches!(
+            self.rotation,
+            DXGI_MODE_ROTATION_IDENTITY | DXGI_MODE_ROTATION_UNSPECIFIED
+        ) {
+            read_rects(
+                &self.context,
+                staging,
+                &texture,
+                &changed,
+                &mut self.image,
+                width,
+            )
+            .map_err(failed("Map"))?;
+            return Ok(changed);
+        }
+        self.raw.resize(width * height * 4, 0);
+        read_rects(
+            &self.context,
+            staging,
+            &texture,
+            &changed,
+            &mut self.raw,
+            width,
+        )
+        .map_err(failed("Map"))?;
+        let size = (width, height);
+        Ok(changed
+            .iter()
+            .map(|&rect| {
+                rotate_block(&self.raw, size, rect, self.rotation, &mut self.image);
+                rotate_rect(size, rect, self.rotation)
+            })
+            .collect())
+    }
+
+    /// The areas the acquired frame drew or moved something to, or `None`
+    /// if it doesn't say.
+    fn changed_rects(
+        &self,
+        info: &DXGI_OUTDUPL_FRAME_INFO,
+    ) -> Result<Option<Vec<Rect>>, ScreenshotError> {
+        // moves and dirty rects share the metadata buffer
+        let size = info.TotalMetadataBufferSize as usize;
+        let duplication = match &self.duplication {
+            Some(duplication) if size != 0 => duplication,
+            _ => return Ok(None),
+        };
+        let mut moves =
+            vec![DXGI_OUTDUPL_MOVE_RECT::default(); size / size_of::<DXGI_OUTDUPL_MOVE_RECT>()];
+        let mut used = 0;
+        unsafe {
+            duplication.GetFrameMoveRects(
+                mem::size_of_val(&moves[..]) as u32,
+                moves.as_mut_ptr(),
+                &mut used,
+            )
+        }
+        .map_err(failed("GetFrameMoveRects"))?;
+        moves.truncate(used as usize / size_of::<DXGI_OUTDUPL_MOVE_RECT>());
+        let mut dirty = vec![RECT::default(); size / size_of::<RECT>()];
+        unsafe {
+            duplication.GetFrameDirtyRects(
+                mem::size_of_val(&dirty[..]) as u32,
+                dirty.as_mut_ptr(),
+                &mut used,
+            )
+        }
+        .map_err(failed("GetFrameDirtyRects"))?;
+        dirty.truncate(used as usize / size_of::<RECT>());
+        // We copy from the finished desktop image, so a move only changes
+        // its destination.
+        Ok(Some(
+            moves
+                .iter()
+                .map(|m| m.DestinationRect)
+                .chain(dirty)
+                .map(Rect::from)
+                .collect(),
+        ))
+    }
+}
+
+/// An acquired frame, released when dropped so the next one can be
+/// acquired.
+struct AcquiredFrame(IDXGIOutputDuplication);
+
+impl Drop for AcquiredFrame {
+    fn drop(&mut self) {
+        // only fails if the session was lost, which the next acquire reports
+        let _ = unsafe { self.0.ReleaseFrame() };
+    }
+}
+
+/// Whether `rotation` swaps the width and height.
+fn turned(rotation: DXGI_MODE_ROTATION) -> bool {
+    matches!(
+        rotation,
+        DXGI_MODE_ROTATION_ROTATE90 | DXGI_MODE_ROTATION_ROTATE270
+    )
+}
+
+/// Where pixel `(x, y)` of a frame of `size` pixels, as duplicated from a
+/// monitor whose desktop is turned by `rotation`, is on the desktop.
+fn rotate_point(
+    (width, height): (usize, usize),
+    (x, y): (usize, usize),
+    rotation: DXGI_MODE_ROTATION,
+) -> (usize, usize) {
+    match rotation {
+        DXGI_MODE_ROTATION_ROTATE90 => (height - 1 - y, x),
+        DXGI_MODE_ROTATION_ROTATE180 => (width - 1 - x, height - 1 - y),
+        DXGI_MODE_ROTATION_ROTATE270 => (y, width - 1 - x),
+        _ => (x, y),
+    }
+}
+
+/// Where `rect`, a non-empty area of a frame of `size` pixels, is on the
+/// desktop, see `rotate_point`.
+fn rotate_rect(size: (usize, usize), rect: Rect, rotation: DXGI_MODE_ROTATION) -> Rect {
+    let (x, y) = (rect.x as usize, rect.y as usize);
+    let (right, bottom) = (x + rect.width as usize - 1, y + rect.height as usize - 1);
+    let (x0, y0) = rotate_point(size, (x, y), rotation);
+    let (x1, y1) = rotate_point(size, (right, bottom), rotation);
+    Rect {
+        x: x0.min(x1) as i32,
+        y: y0.min(y1) as i32,
+        width: (x0.max(x1) - x0.min(x1) + 1) as u32,
+        height: (y0.max(y1) - y0.min(y1) + 1) as u32,
+    }
+}
+
+/// Copies `rect` of `src`, packed BGRA rows of a frame of `size` pixels,
+/// into `dst`, packed BGRA rows of the desktop, see `rotate_point`.
+fn rotate_block(
+    src: &[u8],
+    size: (usize, usize),
+    rect: Rect,
+    rotation: DXGI_MODE_ROTATION,
+    dst: &mut [u8],
+) {
+    let dst_width = if turned(rotation) { size.1 } else { size.0 };
+    let (x, y) = (rect.x as usize, rect.y as usize);
+    for row in y..y + rect.height as usize {
+        for col in x..x + rect.width as usize {
+            let (to_x, to_y) = rotate_point(size, (col, row), rotation);
+            let from = (row * size.0 + col) * 4;
+            let to = (to_y * dst_width + to_x) * 4;
+            dst[to..to + 4].copy_from_slice(&src[from..from + 4]);
+        }
+    }
+}
+
+/// Turns the error of a DXGI or Direct3D `call` into ours.
+fn failed(call: &'static str) -> impl Fn(windows::core::Error) -> ScreenshotError {
+    move |e| {
+        trace_event!(warn, call, error = %e, "DXGI call failed");
+        // the secure desktop, e.g. of a UAC prompt, can't be duplicated
+        if e.code() == E_ACCESSDENIED {
+            ScreenshotError::SecureDesktopActive
+        } else {
+            ScreenshotError::DxgiFailed(format!("{} failed: {}", call, e))
+        }
+    }
+}
+
+#[test]
+fn test_rotation() {
+    // a 3x2 frame, numbered by pixel
+    let size = (3, 2);
+    let src: Vec<u8> = (0..6).flat_map(|i| [i; 4]).collect();
+    let whole = Rect {
+        x: 0,
+        y: 0,
+        width: 3,
+        height: 2,
+    };
+    let rotate = |rotation| {
+        let mut dst = vec![0; src.len()];
+        rotate_block(&src, size, whole, rotation, &mut dst);
+        dst.chunks(4).map(|pixel| pixel[0]).collect::<Vec<_>>()
+    };
+    // turned clockwise, the top row of the frame is the desktop's right
+    // column
+    assert_eq!(rotate(DXGI_MODE_ROTATION_ROTATE90), [3, 0, 4, 1, 5, 2]);
+    assert_eq!(rotate(DXGI_MODE_ROTATION_ROTATE180), [5, 4, 3, 2, 1, 0]);
+    assert_eq!(rotate(DXGI_MODE_ROTATION_ROTATE270), [2, 5, 1, 4, 0, 3]);
+    assert_eq!(rotate(DXGI_MODE_ROTATION_IDENTITY), [0, 1, 2, 3, 4, 5]);
+    let turned = Rect {
+        width: 2,
+        height: 3,
+        ..whole
+    };
+    assert_eq!(
+        rotate_rect(size, whole, DXGI_MODE_ROTATION_ROTATE90),
+        turned
+    );
+    assert_eq!(
+        rotate_rect(size, whole, DXGI_MODE_ROTATION_ROTATE180),
+        whole
+    );
+
+    // a changed area lands where its pixels do
+    let changed = Rect {
+        x: 1,
+        y: 0,
+        width: 2,
+        height: 1,
+    };
+    for rotation in [
+        DXGI_MODE_ROTATION_ROTATE90,
+        DXGI_MODE_ROTATION_ROTATE180,
+        DXGI_MODE_ROTATION_ROTATE270,
+    ] {
+        let rect = rotate_rect(size, changed, rotation);
+        assert_eq!(rect.area(), 2);
+        for (x, y) in [(1, 0), (2, 0)] {
+            let (x, y) = rotate_point(size, (x, y), rotation);
+            let point = crate::Point {
+                x: x as i32,
+                y: y as i32,
+            };
+            assert!(rect.contains(point), "{:?}", rotation);
+        }
+    }
+}
@@ -0,0 +1,568 @@
+//! The Linux framebuffer backend: reads `/dev/fb0` or another fbdev device
+//! directly, for consoles and kiosks without an X server or Wayland
+//! compositor. Needs read access to the device, usually by being in the
+//! `video` group.
+//!
+//! The framebuffer is mapped once when the backend is opened; its mode is
+//! read again for every capture, so a resolution change made with e.g.
+//! `fbset` is picked up. Coordinates are pixels of the visible area, with
+//! the origin at its top left.
+
+use crate::{
+    convert, trace::trace_span, validate_region, CaptureBackend, CaptureMetadata, CaptureOptions,
+    CaptureTarget, Monitor, Orientation, Rect, Screenshot, ScreenshotError, Size, Window,
+    PIXEL_WIDTH,
+};
+
+use std::{
+    fs::File,
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    ptr, slice,
+    time::{Instant, SystemTime},
+};
+
+const FBIOGET_VSCREENINFO: libc::Ioctl = 0x4600;
+const FBIOGET_FSCREENINFO: libc::Ioctl = 0x4602;
+const FB_TYPE_PACKED_PIXELS: u32 = 0;
+const FB_VISUAL_TRUECOLOR: u32 = 2;
+const FB_VISUAL_DIRECTCOLOR: u32 = 4;
+const FB_VMODE_INTERLACED: u32 = 1;
+const FB_VMODE_DOUBLE: u32 = 2;
+const FB_VMODE_MASK: u32 = 255;
+
+/// Where a colour channel is in a pixel value, as in `<linux/fb.h>`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Bitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+/// `struct fb_var_screeninfo`: the current mode.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct VarScreenInfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: Bitfield,
+    green: Bitfield,
+    blue: Bitfield,
+    transp: Bitfield,
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
+/// `struct fb_fix_screeninfo`: what doesn't change with the mode.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct FixScreenInfo {
+    id: [u8; 16],
+    smem_start: libc::c_ulong,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: libc::c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// The framebuffer memory, mapped read-only.
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// Only read through `&self`, and the memory stays mapped until dropped.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// How the visible pixels are laid out in the mapped memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FbLayout {
+    width: usize,
+    height: usize,
+    bits_per_pixel: u32,
+    /// Bytes per row, i.e. `line_length`, which may include padding.
+    stride: usize,
+    /// Byte offset of the visible area's top left pixel, which moves when
+    /// the console pans.
+    offset: usize,
+    red: Bitfield,
+    green: Bitfield,
+    blue: Bitfield,
+}
+
+/// Captures from a Linux framebuffer device.
+pub struct FbdevBackend {
+    // unmapped before the file is closed
+    map: Mapping,
+    file: File,
+    path: PathBuf,
+    /// Number of the next frame.
+    sequence: u64,
+}
+
+impl FbdevBackend {
+    /// Opens `/dev/fb0`.
+    pub fn open() -> Result<Self, ScreenshotError> {
+        FbdevBackend::open_path("/dev/fb0")
+    }
+
+    /// Opens the framebuffer device at `path`, e.g. `/dev/fb1`. Fails with
+    /// `ScreenshotError::FramebufferAccessDenied` if we may not read it.
+    pub fn open_path(path: impl AsRef<Path>) -> Result<Self, ScreenshotError> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => {
+                ScreenshotError::FramebufferAccessDenied(path.clone())
+            }
+            _ => fb_failed(&path, "open", e),
+        })?;
+        let fix = fix_screen_info(&file, &path)?;
+        let len = fix.smem_len as usize;
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(fb_failed(&path, "mmap", io::Error::last_os_error()));
+        }
+        Ok(FbdevBackend {
+            map: Mapping { ptr, len },
+            file,
+            path,
+            sequence: 0,
+        })
+    }
+
+    /// The current mode, checked against what we can convert and the
+    /// mapped memory.
+    fn layout(&self) -> Result<FbLayout, ScreenshotError> {
+        let fix = fix_screen_info(&self.file, &self.path)?;
+        let var = var_screen_info(&self.file, &self.path)?;
+        let depth = var.red.length + var.green.length + var.blue.length;
+        let unsupported = ScreenshotError::UnsupportedPixelFormat {
+            depth,
+            bits_per_pixel: var.bits_per_pixel,
+        };
+        let truecolor = fix.visual == FB_VISUAL_TRUECOLOR || fix.visual == FB_VISUAL_DIRECTCOLOR;
+        let msb_right = [var.red, var.green, var.blue]
+            .iter()
+            .any(|field| field.msb_right != 0);
+        if fix.type_ != FB_TYPE_PACKED_PIXELS || !truecolor || var.grayscale != 0 || msb_right {
+            return Err(unsupported);
+        }
+        if !matches!(var.bits_per_pixel, 16 | 24 | 32) {
+            return Err(unsupported);
+        }
+        let bytes = var.bits_per_pixel as usize / 8;
+        let layout = FbLayout {
+            width: var.xres as usize,
+            height: var.yres as usize,
+            bits_per_pixel: var.bits_per_pixel,
+            stride: fix.line_length as usize,
+            offset: var.yoffset as usize * fix.line_length as usize + var.xoffset as usize * bytes,
+            red: var.red,
+            green: var.green,
+            blue: var.blue,
+        };
+        if layout.width == 0 || layout.height == 0 {
+            return Err(ScreenshotError::EmptyDisplay {
+                width: var.xres as i32,
+                height: var.yres as i32,
+            });
+        }
+        // e.g. a mode set after we mapped a smaller framebuffer
+        let end = layout.offset + (layout.height - 1) * layout.stride + layout.width * bytes;
+        if layout.stride < layout.width * bytes || end > self.map.len {
+            return Err(ScreenshotError::FramebufferFailed(format!(
+                "{}: mode {} x {} doesn't fit the mapped {} bytes",
+                self.path.display(),
+                layout.width,
+                layout.height,
+                self.map.len
+            )));
+        }
+        Ok(layout)
+    }
+
+    fn grab(&self, region: Rect, layout: &FbLayout) -> Result<Screenshot, ScreenshotError> {
+        let bytes = layout.bits_per_pixel as usize / 8;
+        let start = layout.offset + region.y as usize * layout.stride + region.x as usize * bytes;
+        let (width, height) = (region.width as usize, region.height as usize);
+        let captured = (Instant::now(), SystemTime::now());
+        let (data, row_len) = to_bgra(&self.map.bytes()[start..], width, height, layout);
+        let mut frame = Screenshot::from_bgra(data, width, height, row_len);
+        frame.metadata = Some(CaptureMetadata {
+            captured_at: captured.0,
+            wall_time: captured.1,
+            sequence: self.sequence,
+            source: region,
+            window: None,
+            backend: "fbdev",
+            scale_factor: None,
+            cursor: None,
+            physical_pixels: true,
+            resumed: false,
+        });
+        Ok(frame)
+    }
+}
+
+impl CaptureBackend for FbdevBackend {
+    fn name(&self) -> &'static str {
+        "fbdev"
+    }
+
+    /// The visible area, as a single monitor named after the driver, e.g.
+    /// "EFI VGA".
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        let rect = bounds(&self.layout()?);
+        let fix = fix_screen_info(&self.file, &self.path)?;
+        let var = var_screen_info(&self.file, &self.path)?;
+        let id_len = fix.id.iter().position(|&b| b == 0).unwrap_or(fix.id.len());
+        Ok(vec![Monitor {
+            rect,
+            work_area: rect,
+            primary: true,
+            scale_factor: 1.0,
+            name: Some(String::from_utf8_lossy(&fix.id[..id_len]).into_owned())
+                .filter(|id| !id.is_empty()),
+            device_name: Some(self.path.display().to_string()),
+            refresh_rate: refresh_rate(&var),
+            bits_per_pixel: Some(var.bits_per_pixel),
+            orientation: match var.rotate {
+                0 => Some(Orientation::Default),
+                1 => Some(Orientation::Rotated90),
+                2 => Some(Orientation::Rotated180),
+                3 => Some(Orientation::Rotated270),
+                _ => None,
+            },
+            // unknown sizes are 0 or -1
+            physical_size_mm: match (var.width, var.height) {
+                (0, _) | (_, 0) | (u32::MAX, _) | (_, u32::MAX) => None,
+                (width, height) => Some(Size { width, height }),
+            },
+        }])
+    }
+
+    /// Always empty: a framebuffer has no windows.
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        Ok(Vec::new())
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        trace_span!("capture", backend = "fbdev", ?target);
+        let frame = options.retry.run(|| {
+            let layout = self.layout()?;
+            let region = match target {
+                CaptureTarget::Primary => bounds(&layout),
+                CaptureTarget::Region(region) => validate_region(region, bounds(&layout))?,
+                CaptureTarget::Window(window) => return Err(ScreenshotError::NoSuchWindow(window)),
+            };
+            let max = options.max_dimension;
+            if region.width > max || region.height > max {
+                return Err(ScreenshotError::DimensionsTooLarge {
+                    width: region.width as usize,
+                    height: region.height as usize,
+                });
+            }
+            self.grab(region, &layout)
+        })?;
+        self.sequence += 1;
+        Ok(frame)
+    }
+}
+
+fn fix_screen_info(file: &File, path: &Path) -> Result<FixScreenInfo, ScreenshotError> {
+    let mut fix = FixScreenInfo::default();
+    if unsafe { libc::ioctl(file.as_raw_fd(), FBIOGET_FSCREENINFO, &mut fix) } < 0 {
+        return Err(fb_failed(
+            path,
+            "FBIOGET_FSCREENINFO",
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(fix)
+}
+
+fn var_screen_info(file: &File, path: &Path) -> Result<VarScreenInfo, ScreenshotError> {
+    let mut var = VarScreenInfo::default();
+    if unsafe { libc::ioctl(file.as_raw_fd(), FBIOGET_VSCREENINFO, &mut var) } < 0 {
+        return Err(fb_failed(
+            path,
+            "FBIOGET_VSCREENINFO",
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(var)
+}
+
+/// The refresh rate in Hz from the mode's timings, None if the driver
+/// doesn't give a pixel clock.
+fn refresh_rate(var: &VarScreenInfo) -> Option<f64> {
+    let width = var.xres + var.left_margin + var.right_margin + var.hsync_len;
+    let mut height = var.yres + var.upper_margin + var.lower_margin + var.vsync_len;
+    match var.vmode & FB_VMODE_MASK {
+        FB_VMODE_INTERLACED => height /= 2,
+        FB_VMODE_DOUBLE => height *= 2,
+        _ => {}
+    }
+    if var.pixclock == 0 || width == 0 || height == 0 {
+        return None;
+    }
+    // the pixel clock is the time per pixel, in picoseconds
+    let frame_ps = f64::from(var.pixclock) * f64::from(width) * f64::from(height);
+    Some(1e12 / frame_ps)
+}
+
+fn bounds(layout: &FbLayout) -> Rect {
+    Rect {
+        x: 0,
+        y: 0,
+        width: layout.width as u32,
+        height: layout.height as u32,
+    }
+}
+
+fn fb_failed(path: &Path, call: &str, e: io::Error) -> ScreenshotError {
+    ScreenshotError::FramebufferFailed(format!("{}: {} failed: {}", path.display(), call, e))
+}
+
+/// The channel at `field` of `value`, scaled to 8 bits.
+fn channel(value: u32, field: Bitfield) -> u8 {
+    let v = value.checked_shr(field.offset).unwrap_or(0)
+        & 1u32.checked_shl(field.length).map_or(u32::MAX, |m| m - 1);
+    match field.length {
+        0 => 0,
+        bits @ 1..=7 => (v * 255 / ((1 << bits) - 1)) as u8,
+        bits => (v >> (bits - 8)) as u8,
+    }
+}
+
+/// Converts `height` rows of `width` pixels, starting at the beginning of
+/// `data` and `layout.stride` bytes apart, into BGRA rows with
+/// opaque alpha, returning them with their row length. Values are in the
+/// CPU's byte order, as the kernel stores them; 32-bit BGRX, the common
+/// case, is copied as is.
+fn to_bgra(data: &[u8], width: usize, height: usize, layout: &FbLayout) -> (Vec<u8>, usize) {
+    trace_span!("convert");
+    let bytes = layout.bits_per_pixel as usize / 8;
+    let row_len = width * PIXEL_WIDTH;
+    let rows = data
+        .chunks(layout.stride)
+        .take(height)
+        .map(|row| &row[..width * bytes]);
+    let mut out = Vec::with_capacity(row_len * height);
+    let field = |offset| Bitfield {
+        offset,
+        length: 8,
+        msb_right: 0,
+    };
+    let bgrx = (field(16), field(8), field(0));
+    if bytes == 4
+        && (layout.red, layout.green, layout.blue) == bgrx
+        && cfg!(target_endian = "little")
+    {
+        rows.for_each(|row| out.extend_from_slice(row));
+        convert::set_opaque(&mut out, width, row_len);
+        return (out, row_len);
+    }
+    for row in rows {
+        for px in row.chunks_exact(bytes) {
+            let mut value = [0; 4];
+            if cfg!(target_endian = "little") {
+                value[..bytes].copy_from_slice(px);
+            } else {
+                value[4 - bytes..].copy_from_slice(px);
+            }
+            let value = u32::from_ne_bytes(value);
+            out.extend_from_slice(&[
+                channel(value, layout.blue),
+                channel(value, layout.green),
+                channel(value, layout.red),
+                255,
+            ]);
+        }
+    }
+    (out, row_len)
+}
+
+#[test]
+fn test_fb_to_bgra() {
+    let field = |offset, length| Bitfield {
+        offset,
+        length,
+        msb_right: 0,
+    };
+    let xrgb = FbLayout {
+        width: 2,
+        height: 2,
+        bits_per_pixel: 32,
+        stride: 12,
+        offset: 0,
+        red: field(16, 8),
+        green: field(8, 8),
+        blue: field(0, 8),
+    };
+    // rows padded to 12 bytes, which don't end up in the frame
+    let data = [
+        1, 2, 3, 0, 4, 5, 6, 0, 0xee, 0xee, 0xee, 0xee, //
+        7, 8, 9, 0, 10, 11, 12, 0, 0xee, 0xee, 0xee, 0xee,
+    ];
+    let (out, row_len) = to_bgra(&data, 2, 2, &xrgb);
+    assert_eq!(row_len, 8);
+    assert_eq!(
+        out,
+        [1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255]
+    );
+    // a region is cut from the same rows
+    let (out, _) = to_bgra(&data[4..], 1, 2, &xrgb);
+    assert_eq!(out, [4, 5, 6, 255, 10, 11, 12, 255]);
+
+    // 24 bits per pixel, red in the low byte
+    let rgb24 = FbLayout {
+        bits_per_pixel: 24,
+        stride: 6,
+        red: field(0, 8),
+        blue: field(16, 8),
+        ..xrgb
+    };
+    let (out, _) = to_bgra(&[10, 20, 30, 40, 50, 60], 2, 1, &rgb24);
+    assert_eq!(out, [30, 20, 10, 255, 60, 50, 40, 255]);
+
+    // 5-6-5, rows padded to 4 bytes
+    let rgb565 = FbLayout {
+        bits_per_pixel: 16,
+        stride: 4,
+        red: field(11, 5),
+        green: field(5, 6),
+        blue: field(0, 5),
+        ..xrgb
+    };
+    let white_then_red = 0xffffu16
+        .to_ne_bytes()
+        .iter()
+        .chain(&[0, 0])
+        .chain(&0xf800u16.to_ne_bytes())
+        .copied()
+        .collect::<Vec<u8>>();
+    let (out, row_len) = to_bgra(&white_then_red, 1, 2, &rgb565);
+    assert_eq!(row_len, 4);
+    assert_eq!(out, [255, 255, 255, 255, 0, 0, 255, 255]);
+}
+
+#[test]
+fn test_fb_refresh_rate() {
+    // the VESA 1024x768 mode at 60 Hz, with a 65 MHz pixel clock
+    let mut var = VarScreenInfo {
+        xres: 1024,
+        yres: 768,
+        pixclock: 15384,
+        left_margin: 160,
+        right_margin: 24,
+        hsync_len: 136,
+        upper_margin: 29,
+        lower_margin: 3,
+        vsync_len: 6,
+        ..VarScreenInfo::default()
+    };
+    let rate = refresh_rate(&var).unwrap();
+    assert!((rate - 60.0).abs() < 0.1, "{}", rate);
+    var.vmode = FB_VMODE_DOUBLE;
+    assert!((refresh_rate(&var).unwrap() - 30.0).abs() < 0.1);
+    var.pixclock = 0;
+    assert_eq!(refresh_rate(&var), None);
+}
+
+#[test]
+fn test_fbdev_errors() {
+    assert!(matches!(
+        FbdevBackend::open_path("/nonexistent/fb0"),
+        Err(ScreenshotError::FramebufferFailed(_))
+    ));
+    // a regular file doesn't answer the ioctls
+    let path = std::env::temp_dir().join("screenshot_not_a_framebuffer");
+    std::fs::write(&path, [0; 64]).unwrap();
+    let res = FbdevBackend::open_path(&path);
+    std::fs::remove_file(&path).unwrap();
+    match res {
+        Err(ScreenshotError::FramebufferFailed(msg)) => {
+            assert!(msg.contains("FBIOGET_FSCREENINFO"), "{}", msg)
+        }
+        _ => panic!("opened a regular file"),
+    }
+}
+
+#[test]
+fn test_fbdev_backend() {
+    let mut backend = FbdevBackend::open().unwrap();
+    let monitor = backend.monitors().unwrap().remove(0);
+    assert!(matches!(
+        monitor.bits_per_pixel,
+        Some(16) | Some(24) | Some(32)
+    ));
+    let s = backend
+        .capture_target(CaptureTarget::Primary, &CaptureOptions::default())
+        .unwrap();
+    assert_eq!(
+        (s.width(), s.height()),
+        (monitor.rect.width as usize, monitor.rect.height as usize)
+    );
+    assert_eq!(s.get_pixel(0, 0).a, 255);
+    assert_eq!(s.metadata().unwrap().backend, "fbdev");
+}
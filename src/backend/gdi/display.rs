@@ -0,0 +1,258 @@
+//! What Windows knows about monitors beyond their bounds: the current mode
+//! from `EnumDisplaySettingsW`, and names and EDIDs from the display
+//! configuration.
+
+use crate::{Monitor, Orientation, Size};
+
+use windows::{
+    core::PCWSTR,
+    Win32::Devices::Display::*,
+    Win32::Foundation::{ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS},
+    Win32::Graphics::Gdi::*,
+    Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY},
+};
+
+use std::mem::size_of;
+
+/// A monitor as the display configuration sees it.
+struct Target {
+    /// GDI device name of the source showing on it, e.g. `\\.\DISPLAY1`.
+    device: String,
+    /// Empty for monitors without an EDID, e.g. virtual ones.
+    friendly_name: String,
+    /// Device interface path, from which the EDID is found.
+    path: String,
+}
+
+/// Names `monitor` after `device`, its GDI device name, and fills in
+/// refresh rate, color depth and orientation from the device's current
+/// mode.
+pub(super) fn describe_mode(monitor: &mut Monitor, device: &[u16]) {
+    monitor.device_name = Some(from_wide(device));
+    monitor.name = monitor.device_name.clone();
+    let mut mode = DEVMODEW {
+        dmSize: size_of::<DEVMODEW>() as u16,
+        ..Default::default()
+    };
+    // SAFETY: device is null-terminated, as GetMonitorInfoW returns it.
+    let ok =
+        unsafe { EnumDisplaySettingsW(PCWSTR(device.as_ptr()), ENUM_CURRENT_SETTINGS, &mut mode) };
+    if !ok.as_bool() {
+        return;
+    }
+    // 0 and 1 stand for the hardware's default rate
+    if mode.dmDisplayFrequency > 1 {
+        monitor.refresh_rate = Some(f64::from(mode.dmDisplayFrequency));
+    }
+    if mode.dmBitsPerPel != 0 {
+        monitor.bits_per_pixel = Some(mode.dmBitsPerPel);
+    }
+    // SAFETY: display devices fill in the display half of the union.
+    monitor.orientation = match unsafe { mode.Anonymous1.Anonymous2.dmDisplayOrientation } {
+        DMDO_DEFAULT => Some(Orientation::Default),
+        DMDO_90 => Some(Orientation::Rotated90),
+        DMDO_180 => Some(Orientation::Rotated180),
+        DMDO_270 => Some(Orientation::Rotated270),
+        _ => None,
+    };
+}
+
+/// Replaces the GDI device names `describe_mode` put in `monitors` by
+/// the monitors' friendly names, and adds their physical size. Monitors the
+/// display configuration doesn't know keep what they have.
+pub(super) fn describe_targets(monitors: &mut [Monitor]) {
+    let targets = targets();
+    for monitor in monitors {
+        let target = targets
+            .iter()
+            .find(|target| Some(&target.device) == monitor.device_name.as_ref());
+        if let Some(target) = target {
+            if !target.friendly_name.is_empty() {
+                monitor.name = Some(target.friendly_name.clone());
+            }
+            monitor.physical_size_mm = read_edid(&target.path).and_then(|edid| edid_size_mm(&edid));
+        }
+    }
+}
+
+/// The active display paths' targets, or none if the display configuration
+/// can't be queried, e.g. before Windows 7.
+fn targets() -> Vec<Target> {
+    let mut paths = Vec::new();
+    let mut modes = Vec::new();
+    // the configuration may change between the two calls
+    loop {
+        let (mut path_count, mut mode_count) = (0, 0);
+        let res = unsafe {
+            GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count)
+        };
+        if res != ERROR_SUCCESS {
+            return Vec::new();
+        }
+        paths.resize(path_count as usize, DISPLAYCONFIG_PATH_INFO::default());
+        modes.resize(mode_count as usize, DISPLAYCONFIG_MODE_INFO::default());
+        let res = unsafe {
+            QueryDisplayConfig(
+                QDC_ONLY_ACTIVE_PATHS,
+                &mut path_count,
+                paths.as_mut_ptr(),
+                &mut mode_count,
+                modes.as_mut_ptr(),
+                None,
+            )
+        };
+        if res == ERROR_SUCCESS {
+            paths.truncate(path_count as usize);
+            break;
+        }
+        if res != ERROR_INSUFFICIENT_BUFFER {
+            return Vec::new();
+        }
+    }
+    paths
+        .iter()
+        .filter_map(|path| {
+            let mut source = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+                header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                    r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+                    size: size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
+                    adapterId: path.sourceInfo.adapterId,
+                    id: path.sourceInfo.id,
+                },
+                ..Default::default()
+            };
+            let mut target = DISPLAYCONFIG_TARGET_DEVICE_NAME {
+                header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                    r#type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+                    size: size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+                    adapterId: path.targetInfo.adapterId,
+                    id: path.targetInfo.id,
+                },
+                ..Default::default()
+            };
+            // SAFETY: each header says how large the packet around it is.
+            unsafe {
+                if DisplayConfigGetDeviceInfo(&mut source.header) != 0
+                    || DisplayConfigGetDeviceInfo(&mut target.header) != 0
+                {
+                    return None;
+                }
+            }
+            Some(Target {
+                device: from_wide(&source.viewGdiDeviceName),
+                friendly_name: from_wide(&target.monitorFriendlyDeviceName),
+                path: from_wide(&target.monitorDevicePath),
+            })
+        })
+        .collect()
+}
+
+/// The EDID Windows keeps in the registry for the monitor at the device
+/// interface `path`.
+fn read_edid(path: &str) -> Option<Vec<u8>> {
+    let key: Vec<u16> = edid_key(path)?.encode_utf16().chain(Some(0)).collect();
+    let value: Vec<u16> = "EDID".encode_utf16().chain(Some(0)).collect();
+    // base EDID plus extension blocks, of which there are rarely more than 3
+    let mut edid = vec![0u8; 512];
+    let mut len = edid.len() as u32;
+    let res = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(key.as_ptr()),
+            PCWSTR(value.as_ptr()),
+            RRF_RT_REG_BINARY,
+            None,
+            Some(edid.as_mut_ptr().cast()),
+            Some(&mut len),
+        )
+    };
+    if res != ERROR_SUCCESS {
+        return None;
+    }
+    edid.truncate(len as usize);
+    Some(edid)
+}
+
+/// The registry key holding the EDID of the monitor at the device
+/// interface `path`, e.g. `\\?\DISPLAY#DEL41A6#5&1b2c3d&0&UID4352#{e6f0...}`
+/// is `SYSTEM\CurrentControlSet\Enum\DISPLAY\DEL41A6\5&1b2c3d&0&UID4352\
+/// Device Parameters`.
+fn edid_key(path: &str) -> Option<String> {
+    let path = path.strip_prefix(r"\\?\")?;
+    // the last part is the interface class
+    let instance = &path[..path.rfind('#')?];
+    Some(format!(
+        r"SYSTEM\CurrentControlSet\Enum\{}\Device Parameters",
+        instance.replace('#', r"\")
+    ))
+}
+
+/// The visible area's size in millimeters: from the preferred timing if it
+/// has one, otherwise from the basic display parameters, which are in
+/// centimeters. None if the EDID is invalid or gives no size.
+fn edid_size_mm(edid: &[u8]) -> Option<Size> {
+    const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+    if edid.len() < 128 || edid[..8] != HEADER {
+        return None;
+    }
+    // a detailed timing descriptor, unless its pixel clock is 0
+    let timing = &edid[54..72];
+    if timing[0] != 0 || timing[1] != 0 {
+        let width = u32::from(timing[12]) | u32::from(timing[14] >> 4) << 8;
+        let height = u32::from(timing[13]) | u32::from(timing[14] & 0xf) << 8;
+        if width != 0 && height != 0 {
+            return Some(Size { width, height });
+        }
+    }
+    match (edid[21], edid[22]) {
+        (0, _) | (_, 0) => None,
+        (width, height) => Some(Size {
+            width: u32::from(width) * 10,
+            height: u32::from(height) * 10,
+        }),
+    }
+}
+
+fn from_wide(s: &[u16]) -> String {
+    let len = s.iter().position(|&c| c == 0).unwrap_or(s.len());
+    String::from_utf16_lossy(&s[..len])
+}
+
+#[test]
+fn test_edid_size() {
+    let mut edid = [0u8; 128];
+    edid[..8].copy_from_slice(&[0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+    assert_eq!(edid_size_mm(&edid), None);
+    // 60 x 34 cm
+    edid[21] = 60;
+    edid[22] = 34;
+    assert_eq!(
+        edid_size_mm(&edid),
+        Some(Size {
+            width: 600,
+            height: 340
+        })
+    );
+    // a preferred timing with 597 x 336 mm, i.e. 0x255 x 0x150
+    edid[54] = 0x02;
+    edid[66] = 0x55;
+    edid[67] = 0x50;
+    edid[68] = 0x21;
+    assert_eq!(
+        edid_size_mm(&edid),
+        Some(Size {
+            width: 597,
+            height: 336
+        })
+    );
+    assert_eq!(edid_size_mm(&edid[..127]), None);
+    edid[0] = 1;
+    assert_eq!(edid_size_mm(&edid), None);
+
+    assert_eq!(
+        edid_key(r"\\?\DISPLAY#DEL41A6#5&1b2c3d&0&UID4352#{e6f07b5f-ee97-4a90-b076-33f57bf4eaa7}")
+            .unwrap(),
+        r"SYSTEM\CurrentControlSet\Enum\DISPLAY\DEL41A6\5&1b2c3d&0&UID4352\Device Parameters"
+    );
+    assert_eq!(edid_key("DISPLAY1"), None);
+}
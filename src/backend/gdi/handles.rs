@@ -0,0 +1,390 @@
+//! Safe wrappers around the GDI calls used for capturing. Every handle is
+//! owned by exactly one wrapper and released when it's dropped, so early
+//! returns can't leak them.
+
+use crate::{
+    buffer::PixelBuffer, trace::trace_event, Pixel, ScreenshotError, WindowId, PIXEL_WIDTH,
+};
+
+use windows::{
+    Win32::Foundation::HWND,
+    Win32::Graphics::Gdi::*,
+    Win32::Storage::Xps::{PrintWindow, PRINT_WINDOW_FLAGS},
+    Win32::UI::HiDpi::{
+        GetAwarenessFromDpiAwarenessContext, GetThreadDpiAwarenessContext,
+        SetThreadDpiAwarenessContext, DPI_AWARENESS_CONTEXT,
+        DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+        DPI_AWARENESS_UNAWARE,
+    },
+    Win32::UI::WindowsAndMessaging::{
+        DrawIconEx, GetCursorInfo, GetDesktopWindow, GetIconInfo, CURSORINFO, CURSOR_SHOWING,
+        DI_NORMAL, HICON, ICONINFO,
+    },
+};
+
+use core::ffi::c_void;
+use std::mem::size_of;
+
+/// The device context of the desktop window, which spans the whole virtual
+/// screen.
+pub(crate) struct ScreenDc {
+    hwnd: HWND,
+    hdc: HDC,
+}
+
+impl ScreenDc {
+    pub(crate) fn acquire() -> Result<Self, ScreenshotError> {
+        // SAFETY: GetDC has no preconditions; a null DC signals failure and
+        // is never released.
+        unsafe {
+            let hwnd = GetDesktopWindow();
+            let hdc = GetDC(hwnd);
+            if hdc.0 == 0 {
+                return Err(last_error(ScreenshotError::GdiFailed("GetDC")));
+            }
+            Ok(ScreenDc { hwnd, hdc })
+        }
+    }
+
+    pub(crate) fn device_caps(&self, index: GET_DEVICE_CAPS_INDEX) -> i32 {
+        // SAFETY: the DC is valid until we're dropped.
+        unsafe { GetDeviceCaps(self.hdc, index) }
+    }
+
+    /// The colour at `x`, `y` in virtual-screen coordinates, or None if
+    /// no monitor shows that point.
+    pub(crate) fn pixel(&self, x: i32, y: i32) -> Option<Pixel> {
+        // SAFETY: the DC is valid until we're dropped.
+        let color = unsafe { GetPixel(self.hdc, x, y) }.0;
+        if color == CLR_INVALID {
+            return None;
+        }
+        // 0x00BBGGRR
+        let [r, g, b, _] = color.to_le_bytes();
+        Some(Pixel { a: 255, r, g, b })
+    }
+}
+
+impl Drop for ScreenDc {
+    fn drop(&mut self) {
+        // SAFETY: the DC was obtained from GetDC for this window, and is
+        // released exactly once.
+        unsafe {
+            ReleaseDC(self.hwnd, self.hdc);
+        }
+    }
+}
+
+/// A bitmap compatible with the screen, selected into a memory DC of its
+/// own so it can be drawn into.
+pub(crate) struct MemoryBitmap {
+    dc: CreatedHDC,
+    bitmap: HBITMAP,
+    previous: HGDIOBJ,
+    width: i32,
+    height: i32,
+}
+
+impl MemoryBitmap {
+    pub(crate) fn new(screen: &ScreenDc, width: i32, height: i32) -> Result<Self, ScreenshotError> {
+        // SAFETY: the screen DC is valid while borrowed. Each handle is
+        // checked before use and owned by the returned value, or released
+        // here if a later step fails.
+        unsafe {
+            let dc = CreateCompatibleDC(screen.hdc);
+            if dc.0 == 0 {
+                return Err(last_error(ScreenshotError::GdiFailed("CreateCompatibleDC")));
+            }
+            let bitmap = CreateCompatibleBitmap(screen.hdc, width, height);
+            if bitmap.0 == 0 {
+                let e = last_error(ScreenshotError::GdiFailed("CreateCompatibleBitmap"));
+                DeleteDC(dc);
+                return Err(e);
+            }
+            let previous = SelectObject(dc, bitmap);
+            Ok(MemoryBitmap {
+                dc,
+                bitmap,
+                previous,
+                width,
+                height,
+            })
+        }
+    }
+
+    /// Copies the screen area whose top left corner is at (`x`, `y`), in
+    /// virtual-screen coordinates, into the whole bitmap. Coordinates may be
+    /// negative for monitors left of or above the primary one.
+    pub(crate) fn blit(&self, screen: &ScreenDc, x: i32, y: i32) -> Result<(), ScreenshotError> {
+        // SAFETY: both DCs are valid while borrowed, and the destination
+        // area is exactly the bitmap.
+        let res = unsafe {
+            BitBlt(
+                self.dc,
+                0,
+                0,
+                self.width,
+                self.height,
+                screen.hdc,
+                x,
+                y,
+                ROP_CODE(SRCCOPY.0),
+            )
+        };
+        if !res.as_bool() {
+            return Err(last_error(ScreenshotError::BitBltFailed));
+        }
+        Ok(())
+    }
+
+    /// Has `window` draw itself into the bitmap, including parts covered
+    /// by other windows or off screen. The bitmap should be the size of
+    /// `window_rect`.
+    pub(crate) fn print(&self, window: WindowId) -> Result<(), ScreenshotError> {
+        // PW_RENDERFULLCONTENT, which also captures DirectComposition
+        // content, e.g. of browsers, on Windows 8.1 and later
+        let flags = PRINT_WINDOW_FLAGS(2);
+        // SAFETY: PrintWindow fails for handles that aren't windows, and the
+        // memory DC is valid while borrowed.
+        let res = unsafe { PrintWindow(HWND(window.0), self.dc, flags) };
+        if !res.as_bool() {
+            return Err(last_error(ScreenshotError::GdiFailed("PrintWindow")));
+        }
+        Ok(())
+    }
+
+    /// Draws the mouse cursor where it is on screen, given that the bitmap's
+    /// top left corner is at (`x`, `y`) in virtual-screen coordinates.
+    /// Does nothing while the cursor is hidden, or hidden from us, e.g. on
+    /// the secure desktop.
+    pub(crate) fn draw_cursor(&self, x: i32, y: i32) -> Result<(), ScreenshotError> {
+        let mut cursor = CURSORINFO {
+            cbSize: size_of::<CURSORINFO>() as u32,
+            ..Default::default()
+        };
+        // SAFETY: GetCursorInfo only writes to `cursor`, whose size is set.
+        if !unsafe { GetCursorInfo(&mut cursor) }.as_bool() || cursor.flags != CURSOR_SHOWING {
+            return Ok(());
+        }
+        let icon = HICON(cursor.hCursor.0);
+        let mut info = ICONINFO::default();
+        // SAFETY: GetIconInfo only writes to `info`. The bitmaps it creates
+        // are ours and deleted right away, as only the hotspot is needed.
+        unsafe {
+            if !GetIconInfo(icon, &mut info).as_bool() {
+                return Err(last_error(ScreenshotError::GdiFailed("GetIconInfo")));
+            }
+            DeleteObject(info.hbmMask);
+            if info.hbmColor.0 != 0 {
+                DeleteObject(info.hbmColor);
+            }
+        }
+        let left = cursor.ptScreenPos.x - info.xHotspot as i32 - x;
+        let top = cursor.ptScreenPos.y - info.yHotspot as i32 - y;
+        // SAFETY: the memory DC is valid while borrowed, and GDI clips to
+        // the bitmap.
+        let res = unsafe {
+            DrawIconEx(
+                self.dc,
+                left,
+                top,
+                icon,
+                0,
+                0,
+                0,
+                HBRUSH::default(),
+                DI_NORMAL,
+            )
+        };
+        if !res.as_bool() {
+            return Err(last_error(ScreenshotError::GdiFailed("DrawIconEx")));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Reads the bitmap as top-down 32-bit BGRA rows into `buf`, reusing its
+    /// allocation. Returns the row length and the number of rows actually
+    /// copied; `buf` is truncated to match.
+    pub(crate) fn read_dib_into<B: PixelBuffer>(
+        &self,
+        buf: &mut B,
+    ) -> Result<(usize, usize), ScreenshotError> {
+        let size = dib_stride(self.width as usize, 32)
+            .and_then(|stride| stride.checked_mul(self.height as usize))
+            .ok_or(ScreenshotError::DimensionsTooLarge {
+                width: self.width as usize,
+                height: self.height as usize,
+            })?;
+        buf.reset(size);
+
+        let mut bmi = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: self.width,
+                biHeight: -self.height, // having this reverted by -1 causes the image to be flipped to save a additional flipping step later
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB,
+                biSizeImage: 0, // as compression is set to RGB, this may be set to zero
+                biXPelsPerMeter: 0,
+                biYPelsPerMeter: 0,
+                biClrUsed: 0,
+                biClrImportant: 0,
+            },
+            bmiColors: [RGBQUAD::default()],
+        };
+
+        // Our bitmap is exactly the captured area, so reading all of its
+        // scanlines transfers no more rows than were asked for.
+        //
+        // SAFETY: `buf` holds `height` rows of the stride GDI uses for a
+        // 32-bit DIB of our width, so GetDIBits can't write past its end.
+        let lines = unsafe {
+            GetDIBits(
+                self.dc,
+                self.bitmap,
+                0,
+                self.height as u32,
+                Some(buf.as_mut_ptr() as *mut c_void),
+                &mut bmi,
+                DIB_RGB_COLORS,
+            )
+        };
+
+        // The driver may have copied fewer lines, or used another stride,
+        // than we asked for; trust the header it filled in over our guess.
+        let (row_len, rows) = dib_layout(&bmi.bmiHeader, lines, buf.len()).map_err(last_error)?;
+        buf.truncate(row_len * rows);
+        Ok((row_len, rows))
+    }
+}
+
+impl Drop for MemoryBitmap {
+    fn drop(&mut self) {
+        // SAFETY: the bitmap is deselected before it's deleted, and each
+        // handle is released exactly once.
+        unsafe {
+            SelectObject(self.dc, self.previous);
+            DeleteObject(self.bitmap);
+            DeleteDC(self.dc);
+        }
+    }
+}
+
+/// Makes the calling thread per-monitor DPI aware while it lives, and
+/// restores the thread's previous awareness when dropped.
+pub(crate) struct DpiAwareScope {
+    previous: DPI_AWARENESS_CONTEXT,
+}
+
+impl DpiAwareScope {
+    /// None if `aware` is false, or if Windows won't change the thread's
+    /// awareness, e.g. before Windows 10 1607.
+    pub(crate) fn enter(aware: bool) -> Option<Self> {
+        if !aware {
+            return None;
+        }
+        // V2 needs Windows 10 1703; a null context means it was rejected
+        let previous = [
+            DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+            DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE,
+        ]
+        .iter()
+        // SAFETY: only changes the calling thread's awareness.
+        .map(|&context| unsafe { SetThreadDpiAwarenessContext(context) })
+        .find(|previous| previous.0 != 0)?;
+        Some(DpiAwareScope { previous })
+    }
+}
+
+impl Drop for DpiAwareScope {
+    fn drop(&mut self) {
+        // SAFETY: `previous` came from SetThreadDpiAwarenessContext.
+        unsafe { SetThreadDpiAwarenessContext(self.previous) };
+    }
+}
+
+/// Whether the calling thread sees physical pixels, i.e. is DPI aware in
+/// any way. System-aware threads still see scaled sizes on monitors whose
+/// DPI differs from the primary one's.
+pub(crate) fn thread_dpi_aware() -> bool {
+    // SAFETY: both only read the calling thread's awareness.
+    unsafe {
+        GetAwarenessFromDpiAwarenessContext(GetThreadDpiAwarenessContext()) != DPI_AWARENESS_UNAWARE
+    }
+}
+
+/// `e`, for a failed GDI call, logged with the thread's last Win32 error
+/// code. Must be called before anything else that may set it.
+pub(crate) fn last_error(e: ScreenshotError) -> ScreenshotError {
+    trace_event!(
+        warn,
+        code = unsafe { windows::Win32::Foundation::GetLastError() }.0,
+        error = %e,
+        "GDI call failed"
+    );
+    e
+}
+
+/// Bytes per row of a DIB, which are padded to a multiple of 4 bytes.
+pub(crate) fn dib_stride(width: usize, bit_count: u16) -> Option<usize> {
+    let bits = width.checked_mul(bit_count as usize)?.checked_add(31)?;
+    Some(bits / 32 * 4)
+}
+
+/// Row length and row count of the pixels `GetDIBits` actually wrote, given
+/// the header it filled in, the number of lines it reported and the size of
+/// the buffer it wrote into.
+fn dib_layout(
+    header: &BITMAPINFOHEADER,
+    lines: i32,
+    allocated: usize,
+) -> Result<(usize, usize), ScreenshotError> {
+    if lines <= 0 || header.biBitCount as usize != PIXEL_WIDTH * 8 {
+        return Err(ScreenshotError::GetDIBitsFailed);
+    }
+    let width = header.biWidth.unsigned_abs() as usize;
+    let row_len = dib_stride(width, header.biBitCount).ok_or(ScreenshotError::GetDIBitsFailed)?;
+    let mut rows = (lines as usize).min(header.biHeight.unsigned_abs() as usize);
+    if header.biSizeImage != 0 && row_len != 0 {
+        rows = rows.min(header.biSizeImage as usize / row_len);
+    }
+    let fits = matches!(row_len.checked_mul(rows), Some(n) if n <= allocated);
+    if rows == 0 || !fits {
+        return Err(ScreenshotError::GetDIBitsFailed);
+    }
+    Ok((row_len, rows))
+}
+
+#[test]
+fn test_dib_layout() {
+    let header = |width: i32, height: i32, size_image: u32| BITMAPINFOHEADER {
+        biSize: size_of::<BITMAPINFOHEADER>() as u32,
+        biWidth: width,
+        biHeight: height,
+        biPlanes: 1,
+        biBitCount: 32,
+        biCompression: BI_RGB,
+        biSizeImage: size_image,
+        ..Default::default()
+    };
+
+    assert_eq!(dib_stride(3, 24), Some(12));
+    assert_eq!(dib_stride(3, 32), Some(12));
+    assert_eq!(dib_stride(5, 24), Some(16));
+
+    // everything was written
+    assert_eq!(dib_layout(&header(10, -4, 0), 4, 160).unwrap(), (40, 4));
+    // fewer lines were copied than requested
+    assert_eq!(dib_layout(&header(10, -4, 0), 3, 160).unwrap(), (40, 3));
+    // the driver reported a smaller image
+    assert_eq!(dib_layout(&header(10, -4, 80), 4, 160).unwrap(), (40, 2));
+    // nothing was copied
+    assert!(dib_layout(&header(10, -4, 0), 0, 160).is_err());
+    // more would have been written than we allocated
+    assert!(dib_layout(&header(20, -4, 0), 4, 160).is_err());
+}
@@ -0,0 +1,376 @@
+//! The GDI backend: `BitBlt` from the screen DC into a memory bitmap, then
+//! `GetDIBits` into our buffer. Works on every Windows version, including
+//! in remote sessions, but copies the whole area on the CPU every frame.
+//!
+//! The Windows GDI bitmap has its coordinate origin at the bottom left. We
+//! attempt to undo this by reordering the rows. Windows also uses ARGB pixels.
+
+mod display;
+mod handles;
+mod state;
+
+pub(crate) use handles::DpiAwareScope;
+pub(crate) use state::State;
+
+use crate::{
+    CaptureBackend, CaptureOptions, CaptureTarget, Monitor, Pixel, Point, Rect, Screenshot,
+    ScreenshotError, Window, WindowId,
+};
+
+use windows::{
+    Win32::Foundation::{BOOL, HWND, LPARAM, POINT, RECT},
+    Win32::Graphics::Gdi::*,
+    Win32::UI::HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI},
+    Win32::UI::WindowsAndMessaging::*,
+};
+
+use std::mem::{self, size_of};
+
+/// Captures with GDI, keeping the bitmap between captures of the same
+/// size.
+///
+/// Like a `Capturer`, a `GdiBackend` is `Send` but not `Sync`, and the
+/// display layout is remembered between captures of the same target until
+/// a display-related error says it changed.
+#[derive(Default)]
+pub struct GdiBackend {
+    state: State,
+    /// Target of the latest capture, whose area `state` may have cached.
+    target: Option<CaptureTarget>,
+}
+
+impl CaptureBackend for GdiBackend {
+    fn name(&self) -> &'static str {
+        "gdi"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        monitors()
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        top_level_windows()
+    }
+
+    /// A window is captured as it appears on screen, i.e. only the part on
+    /// screen, and with whatever overlaps it, unless
+    /// `CaptureOptions::print_window` is set.
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        if self.target.replace(target) != Some(target) {
+            self.state.cache.clear();
+        }
+        self.state.capture(target, options)?;
+        let empty = Screenshot::from_bgra(Vec::new(), 0, 0, 0);
+        Ok(mem::replace(&mut self.state.frame, empty))
+    }
+}
+
+/// Checks the dimensions reported by the OS before anything is allocated.
+pub(crate) fn check_dimensions(width: i32, height: i32, max: u32) -> Result<(), ScreenshotError> {
+    if width <= 0 || height <= 0 {
+        return Err(ScreenshotError::EmptyDisplay { width, height });
+    }
+    if width as u32 > max || height as u32 > max {
+        return Err(ScreenshotError::DimensionsTooLarge {
+            width: width as usize,
+            height: height as usize,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that the system metrics agree with the resolution the screen DC
+/// reports, which some mirror and virtual display drivers get wrong.
+fn check_metrics(metrics: (i32, i32), device_caps: (i32, i32)) -> Result<(), ScreenshotError> {
+    if metrics != device_caps {
+        return Err(ScreenshotError::InconsistentDisplayMetrics {
+            metrics,
+            device_caps,
+        });
+    }
+    Ok(())
+}
+
+/// Lists the monitors making up the virtual screen, with their names,
+/// modes and sizes where Windows knows them.
+pub fn monitors() -> Result<Vec<Monitor>, ScreenshotError> {
+    unsafe extern "system" fn callback(
+        h_monitor: HMONITOR,
+        _: HDC,
+        _: *mut RECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(data.0 as *mut Vec<Monitor>);
+        let mut info_ex = MONITORINFOEXW::default();
+        info_ex.monitorInfo.cbSize = size_of::<MONITORINFOEXW>() as u32;
+        if GetMonitorInfoW(h_monitor, &mut info_ex.monitorInfo).as_bool() {
+            let info = &info_ex.monitorInfo;
+            let (mut dpi, mut dpi_y) = (USER_DEFAULT_SCREEN_DPI, 0);
+            // unavailable before Windows 8.1
+            if GetDpiForMonitor(h_monitor, MDT_EFFECTIVE_DPI, &mut dpi, &mut dpi_y).is_err() {
+                dpi = USER_DEFAULT_SCREEN_DPI;
+            }
+            let mut monitor = Monitor {
+                rect: info.rcMonitor.into(),
+                work_area: info.rcWork.into(),
+                primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+                scale_factor: f64::from(dpi) / f64::from(USER_DEFAULT_SCREEN_DPI),
+                name: None,
+                device_name: None,
+                refresh_rate: None,
+                bits_per_pixel: None,
+                orientation: None,
+                physical_size_mm: None,
+            };
+            display::describe_mode(&mut monitor, &info_ex.szDevice);
+            monitors.push(monitor);
+        }
+        true.into()
+    }
+
+    let mut monitors: Vec<Monitor> = Vec::new();
+    let ok = unsafe {
+        EnumDisplayMonitors(
+            HDC::default(),
+            None,
+            Some(callback),
+            LPARAM(&mut monitors as *mut _ as isize),
+        )
+    };
+    if !ok.as_bool() || monitors.is_empty() {
+        return Err(ScreenshotError::NoMonitors);
+    }
+    display::describe_targets(&mut monitors);
+    Ok(monitors)
+}
+
+/// Lists the visible, non-minimized top-level windows that have a title,
+/// topmost first.
+pub(crate) fn top_level_windows() -> Result<Vec<Window>, ScreenshotError> {
+    unsafe extern "system" fn callback(hwnd: HWND, data: LPARAM) -> BOOL {
+        let found = &mut *(data.0 as *mut Vec<Window>);
+        if !IsWindowVisible(hwnd).as_bool() || IsIconic(hwnd).as_bool() {
+            return true.into();
+        }
+        let mut title = vec![0; GetWindowTextLengthW(hwnd).max(0) as usize + 1];
+        let len = GetWindowTextW(hwnd, &mut title);
+        let mut rect = RECT::default();
+        if len > 0 && GetWindowRect(hwnd, &mut rect).as_bool() {
+            found.push(Window {
+                id: WindowId(hwnd.0),
+                title: String::from_utf16_lossy(&title[..len as usize]),
+                rect: rect.into(),
+            });
+        }
+        true.into()
+    }
+
+    let mut found: Vec<Window> = Vec::new();
+    let ok = unsafe { EnumWindows(Some(callback), LPARAM(&mut found as *mut _ as isize)) };
+    if !ok.as_bool() {
+        return Err(handles::last_error(ScreenshotError::GdiFailed(
+            "EnumWindows",
+        )));
+    }
+    Ok(found)
+}
+
+/// The colours at `points`, in virtual-screen coordinates, read through a
+/// single screen DC.
+pub(crate) fn colors_at(points: &[Point]) -> Result<Vec<Pixel>, ScreenshotError> {
+    let screen = handles::ScreenDc::acquire()?;
+    points
+        .iter()
+        .map(|&point| {
+            screen
+                .pixel(point.x, point.y)
+                .ok_or(ScreenshotError::PointOffScreen(point))
+        })
+        .collect()
+}
+
+/// Bounds of `window` in virtual-screen coordinates, including the frame.
+pub(crate) fn window_rect(window: WindowId) -> Result<Rect, ScreenshotError> {
+    let mut rect = RECT::default();
+    // SAFETY: GetWindowRect fails for handles that aren't windows, e.g.
+    // because the window was closed.
+    let ok = unsafe { GetWindowRect(HWND(window.0), &mut rect) };
+    if !ok.as_bool() {
+        return Err(ScreenshotError::NoSuchWindow(window));
+    }
+    Ok(rect.into())
+}
+
+/// Bounds of the client area of `window` in virtual-screen coordinates.
+pub(crate) fn client_rect(window: WindowId) -> Result<Rect, ScreenshotError> {
+    let hwnd = HWND(window.0);
+    let (mut rect, mut origin) = (RECT::default(), POINT::default());
+    // SAFETY: both fail for handles that aren't windows.
+    let ok = unsafe {
+        GetClientRect(hwnd, &mut rect).as_bool() && ClientToScreen(hwnd, &mut origin).as_bool()
+    };
+    if !ok {
+        return Err(ScreenshotError::NoSuchWindow(window));
+    }
+    Ok(Rect {
+        x: origin.x,
+        y: origin.y,
+        width: rect.right.max(0) as u32,
+        height: rect.bottom.max(0) as u32,
+    })
+}
+
+/// Bounds of the virtual screen, spanning all monitors.
+pub(crate) fn virtual_screen() -> Rect {
+    unsafe {
+        Rect {
+            x: GetSystemMetrics(SM_XVIRTUALSCREEN),
+            y: GetSystemMetrics(SM_YVIRTUALSCREEN),
+            width: GetSystemMetrics(SM_CXVIRTUALSCREEN).max(0) as u32,
+            height: GetSystemMetrics(SM_CYVIRTUALSCREEN).max(0) as u32,
+        }
+    }
+}
+
+pub(crate) fn primary_size() -> (i32, i32) {
+    unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) }
+}
+
+/// Scale factor of the monitor showing the middle of `rect`, if Windows
+/// knows it.
+pub(crate) fn scale_factor_at(rect: Rect) -> Option<f64> {
+    let middle = POINT {
+        x: rect.x + (rect.width / 2) as i32,
+        y: rect.y + (rect.height / 2) as i32,
+    };
+    let (mut dpi, mut dpi_y) = (0, 0);
+    unsafe {
+        let h_monitor = MonitorFromPoint(middle, MONITOR_DEFAULTTONEAREST);
+        // unavailable before Windows 8.1
+        GetDpiForMonitor(h_monitor, MDT_EFFECTIVE_DPI, &mut dpi, &mut dpi_y).ok()?;
+    }
+    Some(f64::from(dpi) / f64::from(USER_DEFAULT_SCREEN_DPI))
+}
+
+/// Position of the mouse cursor in virtual-screen coordinates, unless
+/// hidden from us, e.g. on the secure desktop.
+pub(crate) fn cursor_position() -> Option<Point> {
+    let mut point = POINT::default();
+    // SAFETY: GetCursorPos only writes to `point`.
+    if !unsafe { GetCursorPos(&mut point) }.as_bool() {
+        return None;
+    }
+    Some(Point {
+        x: point.x,
+        y: point.y,
+    })
+}
+
+#[test]
+fn test_check_dimensions() {
+    let max = crate::DEFAULT_MAX_DIMENSION;
+    assert!(check_dimensions(1920, 1080, max).is_ok());
+    assert!(matches!(
+        check_dimensions(0, 1080, max),
+        Err(ScreenshotError::EmptyDisplay { width: 0, .. })
+    ));
+    assert!(check_dimensions(1920, -1, max).is_err());
+    assert!(check_dimensions(32768, 1, max).is_ok());
+    assert!(matches!(
+        check_dimensions(32769, 1, max),
+        Err(ScreenshotError::DimensionsTooLarge { .. })
+    ));
+    assert!(check_dimensions(7680, 4320, 4096).is_err());
+}
+
+#[test]
+fn test_check_metrics() {
+    assert!(check_metrics((1920, 1080), (1920, 1080)).is_ok());
+    assert!(matches!(
+        check_metrics((7680, 1), (1920, 1080)),
+        Err(ScreenshotError::InconsistentDisplayMetrics {
+            metrics: (7680, 1),
+            device_caps: (1920, 1080)
+        })
+    ));
+}
+
+#[test]
+fn test_gdi_backend() {
+    let mut backend = GdiBackend::default();
+    let options = CaptureOptions::default();
+    let monitors = backend.monitors().unwrap();
+    let primary = monitors.iter().find(|m| m.primary).unwrap();
+    let s = backend
+        .capture_target(CaptureTarget::Primary, &options)
+        .unwrap();
+    assert_eq!(
+        (s.width(), s.height()),
+        (primary.rect.width as usize, primary.rect.height as usize)
+    );
+
+    // a new target isn't captured with the area cached for the previous one
+    let region = Rect {
+        x: 10,
+        y: 10,
+        width: 20,
+        height: 30,
+    };
+    let s = backend
+        .capture_target(CaptureTarget::Region(region), &options)
+        .unwrap();
+    assert_eq!((s.width(), s.height()), (20, 30));
+    // the cursor is drawn into the frame, wherever it is
+    let cursor = options.clone().include_cursor(true);
+    let s = backend
+        .capture_target(CaptureTarget::Region(region), &cursor)
+        .unwrap();
+    assert_eq!((s.width(), s.height()), (20, 30));
+
+    for window in backend.windows().unwrap() {
+        assert!(!window.title.is_empty());
+        // windows may close or move off screen meanwhile
+        match backend.capture_target(CaptureTarget::Window(window.id), &options) {
+            Ok(s) => assert!(s.width() <= window.rect.width as usize),
+            Err(ScreenshotError::NoSuchWindow(_)) | Err(ScreenshotError::InvalidRegion(_)) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+    // PrintWindow draws the whole window, even where it's off screen
+    let print = options.clone().print_window(true);
+    for window in backend.windows().unwrap() {
+        match backend.capture_target(CaptureTarget::Window(window.id), &print) {
+            Ok(s) => assert!(!s.is_empty()),
+            // some windows refuse to draw themselves
+            Err(ScreenshotError::NoSuchWindow(_)) | Err(ScreenshotError::GdiFailed(_)) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+    assert!(matches!(
+        backend.capture_target(CaptureTarget::Window(WindowId(0)), &options),
+        Err(ScreenshotError::NoSuchWindow(_))
+    ));
+}
+
+#[test]
+fn test_monitors() {
+    let monitors = monitors().unwrap();
+    let primary = monitors.iter().find(|m| m.primary).unwrap();
+    assert!(!primary.name.as_deref().unwrap_or_default().is_empty());
+    // some virtual displays leave the rate to the hardware default
+    if let Some(rate) = primary.refresh_rate {
+        assert!((20.0..=500.0).contains(&rate), "{}", rate);
+    }
+    for monitor in &monitors {
+        assert!(!monitor.rect.is_empty(), "{:?}", monitor);
+        assert!(
+            matches!(monitor.bits_per_pixel, None | Some(8..=64)),
+            "{:?}",
+            monitor
+        );
+    }
+}
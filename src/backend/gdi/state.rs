@@ -0,0 +1,558 @@
+//! The GDI capture itself, with the bitmap and buffers kept between
+//! captures. With the `dxgi` and `winrt-capture` features, frames can come
+//! from desktop duplication or Windows.Graphics.Capture instead, see
+//! `State::set_backend`.
+
+use super::{
+    check_dimensions, check_metrics, cursor_position,
+    handles::{thread_dpi_aware, DpiAwareScope, MemoryBitmap, ScreenDc},
+    primary_size, scale_factor_at, virtual_screen, window_rect,
+};
+use crate::{
+    buffer::PixelBuffer,
+    buffer_len,
+    cache::DisplayCache,
+    options::NO_DUPLICATED_CURSOR,
+    secure_desktop_active,
+    trace::{trace_event, trace_span},
+    validate_region, Backend, CaptureMetadata, CaptureMetrics, CaptureOptions, CaptureTarget,
+    FaultPoint, FrameInfo, PixelFormat, Rect, Screenshot, ScreenshotError,
+};
+
+#[cfg(feature = "dxgi")]
+use crate::backend::dxgi::Duplication;
+#[cfg(feature = "winrt-capture")]
+use crate::backend::wgc::GraphicsCapture;
+
+use windows::Win32::Graphics::Gdi::{HORZRES, VERTRES};
+
+use std::{
+    convert::TryFrom,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The area to copy for `target` right now.
+fn target_rect(target: CaptureTarget, options: &CaptureOptions) -> Result<Rect, ScreenshotError> {
+    match target {
+        CaptureTarget::Primary => {
+            let (width, height) = primary_size();
+            check_dimensions(width, height, options.max_dimension)?;
+            check_metrics((width, height), primary_device_caps()?)?;
+            // The primary monitor's top left corner is the virtual
+            // screen's origin.
+            Ok(Rect {
+                x: 0,
+                y: 0,
+                width: width as u32,
+                height: height as u32,
+            })
+        }
+        CaptureTarget::Region(region) => validate_region(region, virtual_screen()),
+        // PrintWindow draws the whole window, while only the part on
+        // screen can be copied.
+        CaptureTarget::Window(window) => {
+            let rect = window_rect(window)?;
+            if options.print_window && !rect.is_empty() {
+                return Ok(rect);
+            }
+            rect.intersect(&virtual_screen())
+                .ok_or(ScreenshotError::InvalidRegion(rect))
+        }
+    }
+}
+
+/// Fails with `ScreenshotError::DisplayChanged` unless the displays still
+/// match `rect` of `target` after it was copied, given the primary size and
+/// virtual screen `after`: the primary monitor must still have `rect`'s
+/// size, and for other targets the virtual screen must still be
+/// `screen_before` and hold the region, e.g. a monitor's.
+fn check_unchanged(
+    target: CaptureTarget,
+    rect: Rect,
+    screen_before: Rect,
+    (primary, screen): ((i32, i32), Rect),
+) -> Result<(), ScreenshotError> {
+    let size = |rect: Rect| (rect.width as i32, rect.height as i32);
+    let (before, after) = match target {
+        CaptureTarget::Primary => (size(rect), primary),
+        _ => (size(screen_before), size(screen)),
+    };
+    let changed = match target {
+        CaptureTarget::Primary => before != after,
+        // a cached region may predate `screen_before`
+        CaptureTarget::Region(_) => {
+            screen != screen_before || validate_region(rect, screen).is_err()
+        }
+        CaptureTarget::Window(_) => screen != screen_before,
+    };
+    if changed {
+        return Err(ScreenshotError::DisplayChanged { before, after });
+    }
+    Ok(())
+}
+
+/// Resolution of the primary display according to its DC.
+fn primary_device_caps() -> Result<(i32, i32), ScreenshotError> {
+    let screen = ScreenDc::acquire()?;
+    Ok((screen.device_caps(HORZRES), screen.device_caps(VERTRES)))
+}
+
+/// The bitmap and frame kept between captures.
+pub(crate) struct State {
+    /// `Gdi`, or `DxgiDuplication` if set.
+    backend: Backend,
+    /// Whether captures went over to GDI after the backend `Auto` picked
+    /// failed.
+    pub(crate) fell_back: bool,
+    bitmap: Option<MemoryBitmap>,
+    #[cfg(feature = "dxgi")]
+    duplication: Option<Duplication>,
+    #[cfg(feature = "winrt-capture")]
+    graphics_capture: Option<GraphicsCapture>,
+    pub(crate) frame: Screenshot,
+    /// Timings of the latest successful capture, if they were asked for.
+    pub(crate) metrics: Option<CaptureMetrics>,
+    /// About the latest capture, if it succeeded.
+    pub(crate) metadata: Option<CaptureMetadata>,
+    /// Areas of the latest frame that changed since the one before, if the
+    /// backend reports them.
+    pub(crate) dirty_rects: Option<Vec<Rect>>,
+    /// Number of the next frame.
+    pub(crate) sequence: u64,
+    pub(crate) cache: DisplayCache,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            backend: Backend::Gdi,
+            fell_back: false,
+            bitmap: None,
+            #[cfg(feature = "dxgi")]
+            duplication: None,
+            #[cfg(feature = "winrt-capture")]
+            graphics_capture: None,
+            frame: Screenshot::from_bgra(Vec::new(), 0, 0, 0),
+            metrics: None,
+            metadata: None,
+            dirty_rects: None,
+            sequence: 0,
+            cache: DisplayCache::default(),
+        }
+    }
+}
+
+impl State {
+    /// Captures with `backend` from now on: `Gdi`, or a backend whose
+    /// feature is enabled.
+    pub(crate) fn set_backend(&mut self, backend: Backend) {
+        if backend != self.backend {
+            self.backend = backend;
+            self.fell_back = false;
+            #[cfg(feature = "dxgi")]
+            {
+                self.duplication = None;
+            }
+            #[cfg(feature = "winrt-capture")]
+            {
+                self.graphics_capture = None;
+            }
+            self.dirty_rects = None;
+        }
+    }
+
+    /// Captures `target` into `self.frame`, retrying as `options` say.
+    pub(crate) fn capture(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<(), ScreenshotError> {
+        let mut data = std::mem::take(&mut self.frame.data);
+        let res = self.capture_into(target, options, &mut data);
+        let frame = &mut self.frame;
+        frame.data = data;
+        let info = res?;
+        frame.width = info.width;
+        frame.height = info.height;
+        frame.row_len = info.stride;
+        frame.format = info.format;
+        frame.metadata = self.metadata;
+        Ok(())
+    }
+
+    /// Captures `target` into `buf`, retrying as `options` say.
+    pub(crate) fn capture_into<B: PixelBuffer>(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+        buf: &mut B,
+    ) -> Result<FrameInfo, ScreenshotError> {
+        trace_span!("capture", backend = self.backend.name(), ?target);
+        let _dpi = DpiAwareScope::enter(options.dpi_aware);
+        let mut clock = Stopwatch::start(options.collect_metrics);
+        self.metrics = None;
+        self.metadata = None;
+        self.dirty_rects = None;
+        options.check_environment()?;
+        let info = match self.capture_retrying(target, options, buf) {
+            // DXGI and Windows.Graphics.Capture break in ways GDI doesn't,
+            // e.g. with some virtual display drivers, so Auto goes on
+            // without them
+            Err(e)
+                if matches!(
+                    e,
+                    ScreenshotError::DxgiFailed(_) | ScreenshotError::GraphicsCaptureFailed(_)
+                ) && options.backend == Backend::Auto
+                    && self.backend != Backend::Gdi =>
+            {
+                trace_event!(warn, backend = self.backend.name(), error = %e, "falling back to GDI");
+                self.backend.fall_back_to_gdi();
+                self.set_backend(Backend::Gdi);
+                self.fell_back = true;
+                self.capture_retrying(target, options, buf)
+            }
+            res => res,
+        }?;
+        if let Some(metrics) = &mut self.metrics {
+            metrics.total = clock.lap();
+        }
+        Ok(info)
+    }
+
+    fn capture_retrying<B: PixelBuffer>(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+        buf: &mut B,
+    ) -> Result<FrameInfo, ScreenshotError> {
+        options.retry.run(|| {
+            // A resolution change mid-capture (rotation, projector plugged
+            // in) is usually over by the time we notice, so one retry is
+            // enough.
+            match self.capture_once(target, options, buf) {
+                Err(ScreenshotError::DisplayChanged { .. }) => {
+                    trace_event!(debug, "display changed during the capture, capturing again");
+                    self.capture_once(target, options, buf)
+                }
+                res => res,
+            }
+        })
+    }
+
+    fn capture_once<B: PixelBuffer>(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+        buf: &mut B,
+    ) -> Result<FrameInfo, ScreenshotError> {
+        let res = self.capture_frame(target, options, buf);
+        if let Err(e) = &res {
+            self.cache.invalidate_on(e);
+        }
+        res
+    }
+
+    fn capture_frame<B: PixelBuffer>(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+        buf: &mut B,
+    ) -> Result<FrameInfo, ScreenshotError> {
+        let screen_before = virtual_screen();
+        let rect = match target {
+            // windows move, so they're looked up every time
+            CaptureTarget::Window(_) => target_rect(target, options)?,
+            _ => self.cache.rect(|| target_rect(target, options))?,
+        };
+        let (width, height) = match (i32::try_from(rect.width), i32::try_from(rect.height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => {
+                return Err(ScreenshotError::DimensionsTooLarge {
+                    width: rect.width as usize,
+                    height: rect.height as usize,
+                })
+            }
+        };
+        check_dimensions(width, height, options.max_dimension)?;
+        buffer_len(width as usize, height as usize)?;
+        // During a UAC prompt BitBlt may "succeed" with a black frame.
+        if secure_desktop_active() {
+            trace_event!(warn, "secure desktop active");
+            return Err(ScreenshotError::SecureDesktopActive);
+        }
+
+        let mut clock = Stopwatch::start(options.collect_metrics);
+        let mut metrics = CaptureMetrics::default();
+        // PrintWindow is GDI's, and duplicated frames come without the
+        // cursor, so Auto takes those with GDI
+        let backend = match (target, self.backend) {
+            (CaptureTarget::Window(_), _) if options.print_window => Backend::Gdi,
+            (_, Backend::DxgiDuplication) if options.include_cursor => {
+                if options.backend != Backend::Auto {
+                    return Err(ScreenshotError::InvalidOptions(NO_DUPLICATED_CURSOR));
+                }
+                Backend::Gdi
+            }
+            (_, backend) => backend,
+        };
+        if backend != Backend::Gdi && options.inject_fault == Some(FaultPoint::Session) {
+            return Err(match backend {
+                Backend::GraphicsCapture => {
+                    ScreenshotError::GraphicsCaptureFailed("injected".into())
+                }
+                _ => ScreenshotError::DxgiFailed("injected".into()),
+            });
+        }
+        let (frame_width, row_len, rows, taken) = match backend {
+            #[cfg(feature = "dxgi")]
+            Backend::DxgiDuplication => self.duplicate(rect, buf, &mut clock, &mut metrics)?,
+            #[cfg(feature = "winrt-capture")]
+            Backend::GraphicsCapture => {
+                self.graphics_capture(target, rect, options, buf, &mut clock, &mut metrics)?
+            }
+            _ => self.blit(target, rect, options, buf, &mut clock, &mut metrics)?,
+        };
+        metrics.frame_bytes = buf.len();
+
+        // The bitmap was sized before the blit; if the display changed
+        // since, its contents are a mix of the old and new layout.
+        check_unchanged(
+            target,
+            rect,
+            screen_before,
+            (primary_size(), virtual_screen()),
+        )?;
+        if options.collect_metrics {
+            self.metrics = Some(metrics);
+        }
+        self.metadata = Some(CaptureMetadata {
+            captured_at: taken.0,
+            wall_time: taken.1,
+            sequence: self.sequence,
+            source: rect,
+            window: match target {
+                CaptureTarget::Window(window) => Some(window),
+                _ => None,
+            },
+            backend: backend.name(),
+            scale_factor: scale_factor_at(rect),
+            cursor: cursor_position(),
+            // duplication and graphics capture always see physical pixels
+            physical_pixels: backend != Backend::Gdi || thread_dpi_aware(),
+            resumed: false,
+        });
+        self.sequence += 1;
+        Ok(FrameInfo {
+            width: frame_width,
+            height: rows,
+            stride: row_len,
+            format: PixelFormat::Bgra8,
+        })
+    }
+
+    /// Copies `rect` with `BitBlt`, or draws the window with `PrintWindow`,
+    /// and reads it into `buf`. Returns the width, row length and number of
+    /// rows of the frame, and when the pixels were copied.
+    fn blit<B: PixelBuffer>(
+        &mut self,
+        target: CaptureTarget,
+        rect: Rect,
+        options: &CaptureOptions,
+        buf: &mut B,
+        clock: &mut Stopwatch,
+        metrics: &mut CaptureMetrics,
+    ) -> Result<(usize, usize, usize, (Instant, SystemTime)), ScreenshotError> {
+        let (width, height) = (rect.width as i32, rect.height as i32);
+        let screen = ScreenDc::acquire()?;
+        let bitmap = match self.bitmap.take() {
+            Some(bitmap) if bitmap.size() == (width, height) => bitmap,
+            // resolution changed, or first capture
+            _ => MemoryBitmap::new(&screen, width, height)?,
+        };
+        let bitmap = self.bitmap.insert(bitmap);
+        metrics.acquire = clock.lap();
+
+        // Monitors left of or above the primary one have negative
+        // coordinates, which BitBlt accepts as is.
+        let res = {
+            trace_span!("bitblt", x = rect.x, y = rect.y, width, height);
+            match target {
+                _ if options.inject_fault == Some(FaultPoint::BitBlt) => {
+                    Err(ScreenshotError::BitBltFailed)
+                }
+                CaptureTarget::Window(window) if options.print_window => bitmap.print(window),
+                _ => bitmap.blit(&screen, rect.x, rect.y),
+            }
+        };
+        if let Err(e) = res {
+            return Err(if secure_desktop_active() {
+                trace_event!(warn, "secure desktop active");
+                ScreenshotError::SecureDesktopActive
+            } else {
+                e
+            });
+        }
+        if options.include_cursor {
+            bitmap.draw_cursor(rect.x, rect.y)?;
+        }
+        metrics.blit = clock.lap();
+        let blitted = (Instant::now(), SystemTime::now());
+
+        if options.inject_fault == Some(FaultPoint::GetDIBits) {
+            return Err(ScreenshotError::GetDIBitsFailed);
+        }
+        let (row_len, rows) = {
+            trace_span!("get_dibits");
+            bitmap.read_dib_into(buf)?
+        };
+        metrics.read_dib = clock.lap();
+        Ok((width as usize, row_len, rows, blitted))
+    }
+
+    /// Copies `rect` from the desktop duplication, opening it if needed,
+    /// and records which areas changed. Returns like `blit`.
+    #[cfg(feature = "dxgi")]
+    fn duplicate<B: PixelBuffer>(
+        &mut self,
+        rect: Rect,
+        buf: &mut B,
+        clock: &mut Stopwatch,
+        metrics: &mut CaptureMetrics,
+    ) -> Result<(usize, usize, usize, (Instant, SystemTime)), ScreenshotError> {
+        let duplication = match self.duplication.take() {
+            Some(duplication) => duplication,
+            None => Duplication::open()?,
+        };
+        let duplication = self.duplication.insert(duplication);
+        metrics.acquire = clock.lap();
+        let res = {
+            trace_span!(
+                "duplicate",
+                x = rect.x,
+                y = rect.y,
+                width = rect.width,
+                height = rect.height
+            );
+            duplication.read_into(rect, buf)
+        };
+        let (row_len, dirty) = match res {
+            Ok(read) => read,
+            // the sessions may be lost, so the next capture starts new ones
+            Err(e) => {
+                self.duplication = None;
+                return Err(e);
+            }
+        };
+        metrics.blit = clock.lap();
+        self.dirty_rects = Some(dirty);
+        let taken = (Instant::now(), SystemTime::now());
+        Ok((rect.width as usize, row_len, rect.height as usize, taken))
+    }
+
+    /// Copies `rect` from the monitors, or the window `target`, through
+    /// Windows.Graphics.Capture, starting sessions as needed. Returns like
+    /// `blit`.
+    #[cfg(feature = "winrt-capture")]
+    fn graphics_capture<B: PixelBuffer>(
+        &mut self,
+        target: CaptureTarget,
+        rect: Rect,
+        options: &CaptureOptions,
+        buf: &mut B,
+        clock: &mut Stopwatch,
+        metrics: &mut CaptureMetrics,
+    ) -> Result<(usize, usize, usize, (Instant, SystemTime)), ScreenshotError> {
+        let capture = match self.graphics_capture.take() {
+            Some(capture) => capture,
+            None => GraphicsCapture::open()?,
+        };
+        let capture = self.graphics_capture.insert(capture);
+        capture.set_cursor(options.include_cursor);
+        metrics.acquire = clock.lap();
+        let res = {
+            trace_span!("graphics_capture", ?target);
+            match target {
+                CaptureTarget::Window(window) => capture
+                    .read_window(window, buf)
+                    .map(|(width, height)| (width, width * 4, height)),
+                _ => capture
+                    .read_region(rect, buf)
+                    .map(|row_len| (rect.width as usize, row_len, rect.height as usize)),
+            }
+        };
+        let (width, row_len, rows) = match res {
+            Ok(read) => read,
+            // the device may be lost, so the next capture starts over
+            Err(e) => {
+                self.graphics_capture = None;
+                return Err(e);
+            }
+        };
+        metrics.blit = clock.lap();
+        let taken = (Instant::now(), SystemTime::now());
+        Ok((width, row_len, rows, taken))
+    }
+}
+
+/// Measures the steps of a capture, or does nothing if metrics are off.
+struct Stopwatch(Option<Instant>);
+
+impl Stopwatch {
+    fn start(enabled: bool) -> Self {
+        Stopwatch(if enabled { Some(Instant::now()) } else { None })
+    }
+
+    /// Time since the previous lap, or since the start.
+    fn lap(&mut self) -> Duration {
+        match &mut self.0 {
+            Some(last) => {
+                let now = Instant::now();
+                let elapsed = now - *last;
+                *last = now;
+                elapsed
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+#[test]
+fn test_check_unchanged() {
+    let rect = |x, y, width, height| Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+    let hd = rect(0, 0, 1920, 1080);
+    // two monitors side by side
+    let screen = rect(-1920, 0, 3840, 1080);
+    let left = rect(-1920, 0, 1920, 1080);
+    let unchanged = ((1920, 1080), screen);
+    assert!(check_unchanged(CaptureTarget::Primary, hd, screen, unchanged).is_ok());
+    assert!(check_unchanged(CaptureTarget::Region(left), left, screen, unchanged).is_ok());
+    let window = CaptureTarget::Window(crate::WindowId(1));
+    assert!(check_unchanged(window, rect(10, 10, 5, 5), screen, unchanged).is_ok());
+
+    // the primary monitor's resolution changed
+    assert!(matches!(
+        check_unchanged(CaptureTarget::Primary, hd, screen, ((1280, 720), screen)),
+        Err(ScreenshotError::DisplayChanged {
+            before: (1920, 1080),
+            after: (1280, 720)
+        })
+    ));
+    // the left monitor's did, which the primary size doesn't show
+    let shrunk = ((1920, 1080), rect(-1280, 0, 3200, 1080));
+    for target in [CaptureTarget::Region(left), window] {
+        assert!(matches!(
+            check_unchanged(target, left, screen, shrunk),
+            Err(ScreenshotError::DisplayChanged {
+                before: (3840, 1080),
+                after: (3200, 1080)
+            })
+        ));
+    }
+    // or it was unplugged before the copy, while the region was cached
+    assert!(check_unchanged(CaptureTarget::Region(left), left, hd, ((1920, 1080), hd)).is_err());
+}
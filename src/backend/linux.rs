@@ -0,0 +1,163 @@
+//! Picking a Linux backend at runtime, as the display server is only known
+//! then.
+
+use super::{CaptureBackend, CaptureTarget, Window};
+use crate::{trace::trace_event, Backend, CaptureOptions, Monitor, Screenshot, ScreenshotError};
+
+use std::env;
+
+/// The default backend on Linux: the first that opens of
+/// `x11::X11Backend` if `$DISPLAY` is set, `wayland::WaylandBackend` if
+/// `$WAYLAND_DISPLAY` is, and `fbdev::FbdevBackend`, each with its
+/// feature. If none does, every call fails with
+/// `ScreenshotError::BackendUnavailable` giving each one's reason.
+pub struct LinuxBackend {
+    inner: Result<Box<dyn CaptureBackend + Send>, String>,
+}
+
+impl Default for LinuxBackend {
+    fn default() -> Self {
+        let servers = candidates(
+            env::var_os("DISPLAY").is_some(),
+            env::var_os("WAYLAND_DISPLAY").is_some(),
+        );
+        let mut reasons = Vec::new();
+        for server in servers {
+            match server.open() {
+                Ok(backend) => {
+                    trace_event!(debug, backend = backend.name(), "using backend");
+                    return LinuxBackend { inner: Ok(backend) };
+                }
+                Err(reason) => {
+                    trace_event!(debug, backend = server.name(), %reason, "backend unavailable");
+                    reasons.push(format!("{}: {}", server.name(), reason));
+                }
+            }
+        }
+        LinuxBackend {
+            inner: Err(reasons.join("; ")),
+        }
+    }
+}
+
+impl LinuxBackend {
+    fn inner(&self) -> Result<&(dyn CaptureBackend + Send), ScreenshotError> {
+        match &self.inner {
+            Ok(backend) => Ok(&**backend),
+            Err(reason) => Err(unavailable(reason)),
+        }
+    }
+}
+
+impl CaptureBackend for LinuxBackend {
+    fn name(&self) -> &'static str {
+        match &self.inner {
+            Ok(backend) => backend.name(),
+            Err(_) => Backend::Auto.name(),
+        }
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        self.inner()?.monitors()
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        self.inner()?.windows()
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        match &mut self.inner {
+            Ok(backend) => backend.capture_target(target, options),
+            Err(reason) => Err(unavailable(reason)),
+        }
+    }
+}
+
+fn unavailable(reason: &str) -> ScreenshotError {
+    ScreenshotError::BackendUnavailable {
+        backend: Backend::Auto,
+        reason: reason.into(),
+    }
+}
+
+/// The display servers a Linux backend can capture from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Server {
+    X11,
+    Wayland,
+    Fbdev,
+}
+
+/// The servers to try, in order, given whether `$DISPLAY` and
+/// `$WAYLAND_DISPLAY` are set.
+fn candidates(display: bool, wayland_display: bool) -> Vec<Server> {
+    let mut servers = Vec::new();
+    if display {
+        servers.push(Server::X11);
+    }
+    if wayland_display {
+        servers.push(Server::Wayland);
+    }
+    servers.push(Server::Fbdev);
+    servers
+}
+
+impl Server {
+    fn name(self) -> &'static str {
+        match self {
+            Server::X11 => "x11",
+            Server::Wayland => "wayland",
+            Server::Fbdev => "fbdev",
+        }
+    }
+
+    /// Opens the server's backend. The portal is only asked at the first
+    /// Wayland capture.
+    fn open(self) -> Result<Box<dyn CaptureBackend + Send>, String> {
+        match self {
+            #[cfg(feature = "x11")]
+            Server::X11 => match super::x11::X11Backend::connect() {
+                Ok(backend) => Ok(Box::new(backend)),
+                Err(e) => Err(e.to_string()),
+            },
+            #[cfg(not(feature = "x11"))]
+            Server::X11 => Err("the `x11` feature is disabled".into()),
+            #[cfg(feature = "wayland")]
+            Server::Wayland => Ok(Box::new(super::wayland::WaylandBackend::new())),
+            #[cfg(not(feature = "wayland"))]
+            Server::Wayland => Err("the `wayland` feature is disabled".into()),
+            #[cfg(feature = "fbdev")]
+            Server::Fbdev => match super::fbdev::FbdevBackend::open() {
+                Ok(backend) => Ok(Box::new(backend)),
+                Err(e) => Err(e.to_string()),
+            },
+            #[cfg(not(feature = "fbdev"))]
+            Server::Fbdev => Err("the `fbdev` feature is disabled".into()),
+        }
+    }
+}
+
+#[test]
+fn test_linux_backend() {
+    assert_eq!(
+        candidates(true, true),
+        [Server::X11, Server::Wayland, Server::Fbdev]
+    );
+    assert_eq!(candidates(false, true), [Server::Wayland, Server::Fbdev]);
+    assert_eq!(candidates(false, false), [Server::Fbdev]);
+
+    // Auto is never the unsupported stub: it's either a working backend or
+    // says why none opened
+    let backend = Backend::Auto.open().unwrap();
+    assert_ne!(backend.name(), "unsupported");
+    match backend.monitors() {
+        Err(ScreenshotError::BackendUnavailable { reason, .. }) => {
+            assert!(reason.contains("fbdev: "), "{}", reason)
+        }
+        res => assert!(!matches!(res, Err(ScreenshotError::UnsupportedPlatform))),
+    }
+}
@@ -0,0 +1,299 @@
+//! The macOS backend: CoreGraphics display images.
+//!
+//! Coordinates are CoreGraphics' global display coordinates, in points with
+//! the origin at the top left of the main display, so displays left of or
+//! above it have negative coordinates as on Windows. On a Retina display a
+//! point is several pixels, see `Monitor::scale_factor`; captures come out
+//! at the full pixel resolution.
+//!
+//! Since macOS 10.15 the user has to allow screen recording for the app in
+//! the system settings. Until then, captures would only show the desktop
+//! background, so they fail with `ScreenshotError::ScreenRecordingDenied`.
+
+use crate::{
+    convert, trace::trace_span, validate_region, CaptureBackend, CaptureMetadata, CaptureOptions,
+    CaptureTarget, Monitor, Orientation, Rect, Screenshot, ScreenshotError, Size, Window,
+};
+
+use core_graphics::{
+    access::ScreenCaptureAccess,
+    display::{CGDisplay, CGPoint, CGRect, CGSize},
+    image::CGImage,
+    window::{
+        create_image, kCGNullWindowID, kCGWindowImageDefault, kCGWindowListOptionOnScreenOnly,
+    },
+};
+
+use std::time::{Instant, SystemTime};
+
+/// Captures with CoreGraphics.
+#[derive(Default)]
+pub struct MacBackend {
+    /// Number of the next frame.
+    sequence: u64,
+}
+
+impl MacBackend {
+    pub fn new() -> Self {
+        MacBackend::default()
+    }
+
+    /// Asks the user to allow screen recording, unless they already did.
+    /// The prompt only shows once per app; the answer applies after a
+    /// restart of the app.
+    pub fn request_access() -> bool {
+        ScreenCaptureAccess.request()
+    }
+
+    fn grab(&self, target: CaptureTarget) -> Result<Screenshot, ScreenshotError> {
+        if !ScreenCaptureAccess.preflight() {
+            return Err(ScreenshotError::ScreenRecordingDenied);
+        }
+        let (image, source) = match target {
+            CaptureTarget::Primary => {
+                let display = CGDisplay::main();
+                let image = display
+                    .image()
+                    .ok_or(ScreenshotError::CoreGraphicsFailed("CGDisplayCreateImage"))?;
+                (image, rect_from(display.bounds()))
+            }
+            // may span displays, unlike CGDisplayCreateImageForRect
+            CaptureTarget::Region(region) => {
+                let region = validate_region(region, virtual_screen(&self.monitors()?))?;
+                let image = create_image(
+                    cg_rect(region),
+                    kCGWindowListOptionOnScreenOnly,
+                    kCGNullWindowID,
+                    kCGWindowImageDefault,
+                )
+                .ok_or(ScreenshotError::CoreGraphicsFailed(
+                    "CGWindowListCreateImage",
+                ))?;
+                (image, region)
+            }
+            CaptureTarget::Window(window) => return Err(ScreenshotError::NoSuchWindow(window)),
+        };
+        let captured = (Instant::now(), SystemTime::now());
+        let mut frame = to_screenshot(&image)?;
+        frame.metadata = Some(CaptureMetadata {
+            captured_at: captured.0,
+            wall_time: captured.1,
+            sequence: self.sequence,
+            source,
+            window: None,
+            backend: "macos",
+            scale_factor: None,
+            cursor: None,
+            physical_pixels: true,
+            resumed: false,
+        });
+        Ok(frame)
+    }
+}
+
+impl CaptureBackend for MacBackend {
+    fn name(&self) -> &'static str {
+        "macos"
+    }
+
+    /// The active displays, i.e. those drawn to, with mirrored displays
+    /// listed once. Their names are only known to AppKit, so they're None.
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        let ids = CGDisplay::active_displays()
+            .map_err(|_| ScreenshotError::CoreGraphicsFailed("CGGetActiveDisplayList"))?;
+        let monitors: Vec<Monitor> = ids
+            .into_iter()
+            .map(CGDisplay::new)
+            .filter(|display| display.mirrors_display() == 0)
+            .map(|display| {
+                let bounds = display.bounds();
+                let rect = rect_from(bounds);
+                let mode = display.display_mode();
+                // from the EDID; zero if unknown
+                let size = display.screen_size();
+                Monitor {
+                    rect,
+                    // the menu bar and Dock are only known to AppKit
+                    work_area: rect,
+                    primary: display.is_main(),
+                    scale_factor: display.pixels_wide() as f64 / bounds.size.width,
+                    name: None,
+                    device_name: Some(display.id.to_string()),
+                    // zero for most built-in displays
+                    refresh_rate: mode
+                        .as_ref()
+                        .map(|mode| mode.refresh_rate())
+                        .filter(|&rate| rate > 0.0),
+                    bits_per_pixel: mode.map(|mode| mode.bit_depth() as u32),
+                    orientation: orientation(display.rotation()),
+                    physical_size_mm: if size.width > 0.0 && size.height > 0.0 {
+                        Some(Size {
+                            width: size.width.round() as u32,
+                            height: size.height.round() as u32,
+                        })
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect();
+        if monitors.is_empty() {
+            return Err(ScreenshotError::NoMonitors);
+        }
+        Ok(monitors)
+    }
+
+    /// Always empty for now.
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        Ok(Vec::new())
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        trace_span!("capture", backend = "macos", ?target);
+        let frame = options.retry.run(|| {
+            let frame = self.grab(target)?;
+            let max = options.max_dimension as usize;
+            if frame.width() > max || frame.height() > max {
+                return Err(ScreenshotError::DimensionsTooLarge {
+                    width: frame.width(),
+                    height: frame.height(),
+                });
+            }
+            Ok(frame)
+        })?;
+        self.sequence += 1;
+        Ok(frame)
+    }
+}
+
+/// Copies a screen image, which is BGRX with rows padded for alignment,
+/// into a screenshot with the same rows and opaque alpha.
+fn to_screenshot(image: &CGImage) -> Result<Screenshot, ScreenshotError> {
+    trace_span!("convert");
+    if image.bits_per_pixel() != 32 || image.bits_per_component() != 8 {
+        return Err(ScreenshotError::UnsupportedPixelFormat {
+            depth: image.bits_per_component() as u32 * 3,
+            bits_per_pixel: image.bits_per_pixel() as u32,
+        });
+    }
+    let (width, height, row_len) = (image.width(), image.height(), image.bytes_per_row());
+    let bytes = image.data();
+    let mut data = bytes
+        .bytes()
+        .get(..row_len * height)
+        .ok_or(ScreenshotError::CoreGraphicsFailed(
+            "CGDataProviderCopyData",
+        ))?
+        .to_vec();
+    convert::set_opaque(&mut data, width, row_len);
+    Screenshot::from_raw(data, width, height, row_len)
+}
+
+/// The bounding box of `monitors`.
+fn virtual_screen(monitors: &[Monitor]) -> Rect {
+    let left = monitors.iter().map(|m| m.rect.x).min().unwrap_or(0);
+    let top = monitors.iter().map(|m| m.rect.y).min().unwrap_or(0);
+    let right = monitors
+        .iter()
+        .map(|m| i64::from(m.rect.x) + i64::from(m.rect.width))
+        .max()
+        .unwrap_or(0);
+    let bottom = monitors
+        .iter()
+        .map(|m| i64::from(m.rect.y) + i64::from(m.rect.height))
+        .max()
+        .unwrap_or(0);
+    Rect {
+        x: left,
+        y: top,
+        width: (right - i64::from(left)) as u32,
+        height: (bottom - i64::from(top)) as u32,
+    }
+}
+
+/// The orientation for a clockwise rotation in degrees.
+fn orientation(degrees: f64) -> Option<Orientation> {
+    match degrees.round() as i64 {
+        0 => Some(Orientation::Default),
+        90 => Some(Orientation::Rotated90),
+        180 => Some(Orientation::Rotated180),
+        270 => Some(Orientation::Rotated270),
+        _ => None,
+    }
+}
+
+fn rect_from(rect: CGRect) -> Rect {
+    Rect {
+        x: rect.origin.x.round() as i32,
+        y: rect.origin.y.round() as i32,
+        width: rect.size.width.round() as u32,
+        height: rect.size.height.round() as u32,
+    }
+}
+
+fn cg_rect(rect: Rect) -> CGRect {
+    CGRect::new(
+        &CGPoint::new(f64::from(rect.x), f64::from(rect.y)),
+        &CGSize::new(f64::from(rect.width), f64::from(rect.height)),
+    )
+}
+
+#[test]
+fn test_virtual_screen() {
+    let monitor = |x, y, width, height| Monitor {
+        rect: Rect {
+            x,
+            y,
+            width,
+            height,
+        },
+        work_area: Rect::default(),
+        primary: false,
+        scale_factor: 1.0,
+        name: None,
+        device_name: None,
+        refresh_rate: None,
+        bits_per_pixel: None,
+        orientation: None,
+        physical_size_mm: None,
+    };
+    let monitors = [monitor(0, 0, 1440, 900), monitor(-1920, -180, 1920, 1080)];
+    assert_eq!(
+        virtual_screen(&monitors),
+        Rect {
+            x: -1920,
+            y: -180,
+            width: 3360,
+            height: 1080,
+        }
+    );
+}
+
+#[test]
+fn test_orientation() {
+    assert_eq!(orientation(0.0), Some(Orientation::Default));
+    assert_eq!(orientation(90.0), Some(Orientation::Rotated90));
+    assert_eq!(orientation(270.0), Some(Orientation::Rotated270));
+    assert_eq!(orientation(45.0), None);
+}
+
+#[test]
+fn test_macos_backend() {
+    let mut backend = MacBackend::new();
+    let monitors = backend.monitors().unwrap();
+    let main = monitors.iter().find(|m| m.primary).unwrap();
+    let s = backend
+        .capture_target(CaptureTarget::Primary, &CaptureOptions::default())
+        .unwrap();
+    // captured in pixels, with rows padded
+    assert_eq!(
+        s.width(),
+        (f64::from(main.rect.width) * main.scale_factor) as usize
+    );
+    assert!(s.row_len() >= s.width() * 4);
+    assert_eq!(s.get_pixel(0, 0).a, 255);
+}
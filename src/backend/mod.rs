@@ -0,0 +1,167 @@
+//! Where captures come from. A backend lists what can be captured and
+//! copies it into a `Screenshot`; everything else, from pixel conversions to
+//! streaming, works the same whichever backend took the frame.
+//!
+//! `gdi` is the default backend on Windows and what the free functions such
+//! as `get_screenshot` use there, unless the `gdi` feature is disabled.
+//! With the `dxgi` feature, `dxgi::DxgiBackend` captures through desktop
+//! duplication, which `Backend::Auto` prefers where it works. With the
+//! `winrt-capture` feature, `wgc::GraphicsCaptureBackend` captures through
+//! Windows.Graphics.Capture, which also sees windows that are covered or use
+//! hardware acceleration.
+//! `testing::MockCapturer` implements the trait too, so code written against
+//! `CaptureBackend` can be tested without a display, and
+//! `testing::assert_conformance` checks that a backend behaves like the
+//! others.
+//!
+//! On macOS, `macos::MacBackend` captures with CoreGraphics and is the
+//! default. On Linux, `x11::X11Backend` captures from an X server with the
+//! `x11` feature, and `wayland::WaylandBackend` through the ScreenCast
+//! portal with the `wayland` feature. Consoles without either can be
+//! captured from the framebuffer by `fbdev::FbdevBackend`, with the `fbdev`
+//! feature. The default there, `linux::LinuxBackend`, picks one of them at
+//! runtime. Elsewhere the default is `Unsupported`, which fails every call
+//! with `ScreenshotError::UnsupportedPlatform`. Any backend can be streamed
+//! with `spawn_backend_capture`.
+
+#[cfg(all(windows, any(feature = "dxgi", feature = "winrt-capture")))]
+mod d3d;
+#[cfg(all(windows, feature = "dxgi"))]
+pub mod dxgi;
+#[cfg(all(target_os = "linux", feature = "fbdev"))]
+pub mod fbdev;
+#[cfg(all(windows, feature = "gdi"))]
+pub mod gdi;
+#[cfg(target_os = "linux")]
+pub mod linux;
+#[cfg(target_os = "macos")]
+pub mod macos;
+mod select;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub mod wayland;
+#[cfg(all(windows, feature = "winrt-capture"))]
+pub mod wgc;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub mod x11;
+
+pub use select::Backend;
+
+use crate::{CaptureOptions, Monitor, Rect, Screenshot, ScreenshotError};
+
+/// The backend used by `get_screenshot` and friends.
+#[cfg(all(windows, feature = "gdi"))]
+pub type DefaultBackend = gdi::GdiBackend;
+/// The backend used by `get_screenshot` and friends.
+#[cfg(target_os = "macos")]
+pub type DefaultBackend = macos::MacBackend;
+/// The backend used by `get_screenshot` and friends.
+#[cfg(target_os = "linux")]
+pub type DefaultBackend = linux::LinuxBackend;
+/// The backend used by `get_screenshot` and friends.
+#[cfg(not(any(
+    all(windows, feature = "gdi"),
+    target_os = "macos",
+    target_os = "linux"
+)))]
+pub type DefaultBackend = Unsupported;
+
+/// What a capture covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureTarget {
+    /// The primary monitor, at whatever resolution it has at the time.
+    Primary,
+    /// A fixed area in virtual-screen coordinates. A monitor is captured as
+    /// the region of its `Monitor::rect`.
+    Region(Rect),
+    /// A top-level window, wherever it is at the time.
+    Window(WindowId),
+}
+
+/// Identifies a top-level window, e.g. an `HWND` on Windows. Only valid
+/// while the window exists; the OS may reuse it afterwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(serde::Serialize))]
+pub struct WindowId(pub isize);
+
+/// A window that can be captured, as listed by `CaptureBackend::windows`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub id: WindowId,
+    pub title: String,
+    /// Bounds in virtual-screen coordinates; on Windows including the
+    /// frame.
+    pub rect: Rect,
+}
+
+/// A way of capturing the screen.
+///
+/// Backends may keep OS resources between captures, which is why capturing
+/// takes `&mut self`; they're released when the backend is dropped. Errors
+/// are reported as `ScreenshotError`, with the retries and checks asked for
+/// in the `CaptureOptions` already applied.
+pub trait CaptureBackend {
+    /// Short name of the backend, e.g. for logs.
+    fn name(&self) -> &'static str;
+
+    /// Lists the monitors making up the virtual screen.
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError>;
+
+    /// Lists the visible top-level windows, topmost first.
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError>;
+
+    /// Captures `target` into a new screenshot.
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError>;
+}
+
+/// The default backend of platforms without one, e.g. the BSDs. Every call
+/// fails with `ScreenshotError::UnsupportedPlatform`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unsupported;
+
+impl CaptureBackend for Unsupported {
+    fn name(&self) -> &'static str {
+        "unsupported"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        Err(ScreenshotError::UnsupportedPlatform)
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        Err(ScreenshotError::UnsupportedPlatform)
+    }
+
+    fn capture_target(
+        &mut self,
+        _: CaptureTarget,
+        _: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        Err(ScreenshotError::UnsupportedPlatform)
+    }
+}
+
+impl<B: CaptureBackend + ?Sized> CaptureBackend for Box<B> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        (**self).monitors()
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        (**self).windows()
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        (**self).capture_target(target, options)
+    }
+}
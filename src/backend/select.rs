@@ -0,0 +1,199 @@
+//! Picking a Windows capture method at runtime, see `Backend`.
+
+use crate::{trace::trace_event, CaptureBackend, ScreenshotError};
+
+use std::sync::Mutex;
+
+#[cfg(not(windows))]
+use super::DefaultBackend;
+
+/// What `Auto` resolved to for one-shot captures, see `Backend::open`.
+static AUTO: Mutex<Option<Backend>> = Mutex::new(None);
+
+/// Which capture method `Capturer` and the free functions such as
+/// `get_screenshot_with` use on Windows, see `CaptureOptions::backend`.
+/// Backends built for a specific platform, e.g. `x11::X11Backend`, are used
+/// directly instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum Backend {
+    /// The first available of `DxgiDuplication`, `GraphicsCapture` and
+    /// `Gdi`, in that order. If a capture with the first two then fails
+    /// with `ScreenshotError::DxgiFailed` or `GraphicsCaptureFailed`, it's
+    /// taken again with GDI, which later captures keep using.
+    #[default]
+    Auto,
+    /// `BitBlt` through GDI. Works everywhere, including over RDP, but
+    /// copies every frame on the CPU.
+    Gdi,
+    /// DXGI desktop duplication. Fast, but unavailable in remote sessions
+    /// and with some virtual display drivers.
+    DxgiDuplication,
+    /// `Windows.Graphics.Capture`, from Windows 10 1903 on.
+    GraphicsCapture,
+}
+
+impl Backend {
+    /// The order in which `Auto` tries the backends.
+    const PREFERENCE: [Backend; 3] = [
+        Backend::DxgiDuplication,
+        Backend::GraphicsCapture,
+        Backend::Gdi,
+    ];
+
+    /// Short name, as in `CaptureBackend::name` and
+    /// `CaptureMetadata::backend`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Auto => "auto",
+            Backend::Gdi => "gdi",
+            Backend::DxgiDuplication => "dxgi",
+            Backend::GraphicsCapture => "wgc",
+        }
+    }
+
+    /// Whether this backend can capture here, or why not. `Auto` is
+    /// available if any backend is.
+    pub fn probe(self) -> Result<(), ScreenshotError> {
+        self.resolve().map(|_| ())
+    }
+
+    /// The backend captures would use: this one if it's available, or for
+    /// `Auto` the first available one. Fails with
+    /// `ScreenshotError::BackendUnavailable` and the probe's reason
+    /// otherwise.
+    pub fn resolve(self) -> Result<Backend, ScreenshotError> {
+        if self != Backend::Auto {
+            return self.check().map(|()| self).map_err(|reason| {
+                ScreenshotError::BackendUnavailable {
+                    backend: self,
+                    reason,
+                }
+            });
+        }
+        let mut reasons = Vec::new();
+        for backend in Backend::PREFERENCE {
+            match backend.check() {
+                Ok(()) => {
+                    trace_event!(debug, backend = backend.name(), "using backend");
+                    return Ok(backend);
+                }
+                Err(reason) => {
+                    trace_event!(debug, backend = backend.name(), %reason, "backend unavailable");
+                    reasons.push(format!("{}: {}", backend.name(), reason));
+                }
+            }
+        }
+        Err(ScreenshotError::BackendUnavailable {
+            backend: Backend::Auto,
+            reason: reasons.join("; "),
+        })
+    }
+
+    /// Opens the backend a one-shot capture with this option uses. Off
+    /// Windows, `Auto` is the platform's `DefaultBackend`. On Windows it's
+    /// resolved once per process, like `Capturer` does once per capturer,
+    /// rather than starting a duplication to probe DXGI for every capture;
+    /// a failed resolution is tried again next time.
+    pub(crate) fn open(self) -> Result<Box<dyn CaptureBackend>, ScreenshotError> {
+        #[cfg(not(windows))]
+        if self == Backend::Auto {
+            return Ok(Box::new(DefaultBackend::default()));
+        }
+        match self.resolve_once()? {
+            #[cfg(all(windows, feature = "gdi"))]
+            Backend::Gdi => Ok(Box::new(super::gdi::GdiBackend::default())),
+            #[cfg(all(windows, feature = "dxgi"))]
+            Backend::DxgiDuplication => Ok(Box::new(super::dxgi::DxgiBackend::default())),
+            #[cfg(all(windows, feature = "winrt-capture"))]
+            Backend::GraphicsCapture => Ok(Box::new(super::wgc::GraphicsCaptureBackend::default())),
+            resolved => unreachable!("{:?} is available but not built", resolved),
+        }
+    }
+
+    /// Like `resolve`, but keeps what `Auto` resolves to for later calls.
+    fn resolve_once(self) -> Result<Backend, ScreenshotError> {
+        if self != Backend::Auto {
+            return self.resolve();
+        }
+        let mut auto = AUTO.lock().unwrap_or_else(|e| e.into_inner());
+        match *auto {
+            Some(resolved) => Ok(resolved),
+            None => Ok(*auto.insert(self.resolve()?)),
+        }
+    }
+
+    /// Makes one-shot captures with `Auto` use GDI from now on, if `Auto`
+    /// resolved to this backend and a capture with it failed.
+    #[cfg(all(windows, feature = "gdi"))]
+    pub(crate) fn fall_back_to_gdi(self) {
+        let mut auto = AUTO.lock().unwrap_or_else(|e| e.into_inner());
+        if *auto == Some(self) {
+            *auto = Some(Backend::Gdi);
+        }
+    }
+
+    /// Probes a concrete backend. DXGI is probed by starting duplication,
+    /// which fails e.g. in remote sessions, and Windows.Graphics.Capture by
+    /// asking the system whether it supports it.
+    fn check(self) -> Result<(), String> {
+        match self {
+            Backend::Auto => unreachable!("Auto is resolved by trying the others"),
+            _ if !cfg!(windows) => Err("only available on Windows".into()),
+            Backend::Gdi if cfg!(feature = "gdi") => Ok(()),
+            #[cfg(all(windows, feature = "dxgi"))]
+            Backend::DxgiDuplication => super::dxgi::probe(),
+            #[cfg(not(all(windows, feature = "dxgi")))]
+            Backend::DxgiDuplication => Err("the `dxgi` feature is disabled".into()),
+            #[cfg(all(windows, feature = "winrt-capture"))]
+            Backend::GraphicsCapture => super::wgc::probe(),
+            #[cfg(not(all(windows, feature = "winrt-capture")))]
+            Backend::GraphicsCapture => Err("the `winrt-capture` feature is disabled".into()),
+            Backend::Gdi => Err("the `gdi` feature is disabled".into()),
+        }
+    }
+}
+
+#[test]
+fn test_backend_resolve() {
+    let unavailable = |backend: Backend| match backend.resolve() {
+        Err(ScreenshotError::BackendUnavailable { backend: b, reason }) => {
+            assert_eq!(b, backend);
+            reason
+        }
+        res => panic!("{:?} resolved to {:?}", backend, res),
+    };
+    // forcing a backend never falls back
+    if !cfg!(all(windows, feature = "dxgi")) {
+        assert!(!unavailable(Backend::DxgiDuplication).is_empty());
+    }
+    if !cfg!(all(windows, feature = "winrt-capture")) {
+        assert!(!unavailable(Backend::GraphicsCapture).is_empty());
+    }
+    if cfg!(all(windows, feature = "gdi")) {
+        assert_eq!(Backend::Gdi.resolve().unwrap(), Backend::Gdi);
+        // DXGI only where duplication works, e.g. not over RDP
+        let expected = match (
+            Backend::DxgiDuplication.probe(),
+            Backend::GraphicsCapture.probe(),
+        ) {
+            (Ok(()), _) => Backend::DxgiDuplication,
+            (_, Ok(())) => Backend::GraphicsCapture,
+            _ => Backend::Gdi,
+        };
+        assert_eq!(Backend::Auto.resolve().unwrap(), expected);
+        // one-shot captures keep the first resolution
+        assert_eq!(Backend::Auto.resolve_once().unwrap(), expected);
+        assert_eq!(Backend::Auto.resolve_once().unwrap(), expected);
+    } else {
+        // every backend's reason is given
+        let reason = unavailable(Backend::Auto);
+        assert!(
+            reason.contains("dxgi: ") && reason.contains("gdi: "),
+            "{}",
+            reason
+        );
+        assert!(Backend::Gdi.probe().is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! The Wayland backend: a screencast session of xdg-desktop-portal, read
+//! through PipeWire. Wayland compositors don't let clients read the screen
+//! directly, so this is the way to capture under GNOME, KDE and wlroots
+//! without falling back to XWayland, which only sees X11 clients.
+//!
+//! Starting a session shows the compositor's consent dialog, where the user
+//! picks a monitor. With version 4 and up of the portal, the session also
+//! yields a restore token: pass it to the next session, e.g. in the next run
+//! of the program, and it shares the same monitor without asking again. A
+//! token can only be used once; every session hands out a new one.
+//!
+//! Coordinates are the compositor's logical ones, which may differ from the
+//! frame's pixels on a scaled monitor.
+
+mod pipewire;
+mod portal;
+
+use self::{pipewire::PipeWireStream, portal::Session};
+use crate::{
+    trace::trace_span, validate_region, CaptureBackend, CaptureMetadata, CaptureOptions,
+    CaptureTarget, Monitor, Rect, Screenshot, ScreenshotError, Window, PIXEL_WIDTH,
+};
+
+use std::time::Duration;
+
+/// How long a capture waits for the first frame of a new session, not
+/// counting the consent dialog.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A started session, with its stream.
+struct Active {
+    // dropped first, so PipeWire is done before the session closes
+    stream: PipeWireStream,
+    session: Session,
+}
+
+/// Captures through the ScreenCast portal. The session starts with the
+/// first capture, or with `start`, and lasts until `close` or until the
+/// backend is dropped, so a `Capturer`-style loop only asks the user once.
+#[derive(Default)]
+pub struct WaylandBackend {
+    active: Option<Active>,
+    restore_token: Option<String>,
+    /// Number of the next frame.
+    sequence: u64,
+}
+
+impl WaylandBackend {
+    pub fn new() -> Self {
+        WaylandBackend::default()
+    }
+
+    /// A backend whose session restores the selection of an earlier one,
+    /// see `restore_token`. If the token expired or was revoked, the user is
+    /// asked as usual.
+    pub fn with_restore_token(token: impl Into<String>) -> Self {
+        WaylandBackend {
+            restore_token: Some(token.into()),
+            ..WaylandBackend::default()
+        }
+    }
+
+    /// The token to start the next session with, to be saved for the next
+    /// run of the program. Updated by every session start; None if the
+    /// portal doesn't support restoring sessions.
+    pub fn restore_token(&self) -> Option<&str> {
+        self.restore_token.as_deref()
+    }
+
+    /// Starts a session unless one is running, asking the user to pick a
+    /// monitor unless the restore token is still good. Fails with
+    /// `ScreenshotError::NoPortal` without a portal, and with
+    /// `ScreenshotError::PortalDenied` if the user declines.
+    pub fn start(&mut self) -> Result<(), ScreenshotError> {
+        if self.active.is_some() {
+            return Ok(());
+        }
+        let session = Session::start(self.restore_token.as_deref())?;
+        if session.restore_token.is_some() {
+            self.restore_token = session.restore_token.clone();
+        }
+        let fd = session.open_pipewire_remote()?;
+        let stream = PipeWireStream::connect(fd, session.streams[0].node)?;
+        self.active = Some(Active { stream, session });
+        Ok(())
+    }
+
+    /// Ends the session, if one is running. The next capture starts a new
+    /// one with the latest restore token.
+    pub fn close(&mut self) {
+        self.active = None;
+    }
+
+    /// Captures a single frame of `target` and closes the session again,
+    /// e.g. for a screenshot tool that runs once per shot.
+    pub fn capture_once(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        let res = self.capture_target(target, options);
+        self.close();
+        res
+    }
+
+    /// The shared monitor's rect in logical coordinates; that of the frame,
+    /// at the origin, if the portal doesn't say.
+    fn bounds(active: &Active) -> Result<Rect, ScreenshotError> {
+        match active.session.streams[0].rect {
+            Some(rect) => Ok(rect),
+            None => active
+                .stream
+                .with_latest(FIRST_FRAME_TIMEOUT, |frame| Rect {
+                    x: 0,
+                    y: 0,
+                    width: frame.width as u32,
+                    height: frame.height as u32,
+                }),
+        }
+    }
+
+    fn grab(
+        &self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        let active = self.active.as_ref().ok_or(ScreenshotError::NoMonitors)?;
+        let bounds = WaylandBackend::bounds(active)?;
+        let region = match target {
+            CaptureTarget::Primary => bounds,
+            CaptureTarget::Region(region) => validate_region(region, bounds)?,
+            // the portal shares windows the user picks, not ones by id
+            CaptureTarget::Window(window) => return Err(ScreenshotError::NoSuchWindow(window)),
+        };
+        active.stream.with_latest(FIRST_FRAME_TIMEOUT, |frame| {
+            // from logical coordinates to the frame's pixels
+            let scale = |v: i64, logical: u32, pixels: usize| {
+                (v * pixels as i64 / i64::from(logical.max(1))) as u32
+            };
+            let (left, top) = (
+                i64::from(region.x - bounds.x),
+                i64::from(region.y - bounds.y),
+            );
+            let (right, bottom) = (
+                left + i64::from(region.width),
+                top + i64::from(region.height),
+            );
+            let x = scale(left, bounds.width, frame.width);
+            let y = scale(top, bounds.height, frame.height);
+            let pixels = Rect {
+                x: x as i32,
+                y: y as i32,
+                width: scale(right, bounds.width, frame.width)
+                    .saturating_sub(x)
+                    .max(1),
+                height: scale(bottom, bounds.height, frame.height)
+                    .saturating_sub(y)
+                    .max(1),
+            };
+            let max = options.max_dimension;
+            if pixels.width > max || pixels.height > max {
+                return Err(ScreenshotError::DimensionsTooLarge {
+                    width: pixels.width as usize,
+                    height: pixels.height as usize,
+                });
+            }
+            let (width, height) = (pixels.width as usize, pixels.height as usize);
+            let mut shot =
+                Screenshot::from_bgra(frame.to_bgra(pixels), width, height, width * PIXEL_WIDTH);
+            shot.metadata = Some(CaptureMetadata {
+                captured_at: frame.captured_at,
+                wall_time: frame.wall_time,
+                sequence: self.sequence,
+                source: region,
+                window: None,
+                backend: "wayland",
+                scale_factor: None,
+                cursor: None,
+                physical_pixels: true,
+                resumed: false,
+            });
+            Ok(shot)
+        })?
+    }
+}
+
+impl CaptureBackend for WaylandBackend {
+    fn name(&self) -> &'static str {
+        "wayland"
+    }
+
+    /// The monitor shared in the current session, or none before it
+    /// started.
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        match &self.active {
+            Some(active) => {
+                let rect = WaylandBackend::bounds(active)?;
+                let pixels = active
+                    .stream
+                    .with_latest(FIRST_FRAME_TIMEOUT, |frame| frame.width)?;
+                Ok(vec![Monitor {
+                    rect,
+                    work_area: rect,
+                    primary: true,
+                    scale_factor: pixels as f64 / f64::from(rect.width),
+                    name: None,
+                    device_name: None,
+                    refresh_rate: None,
+                    bits_per_pixel: None,
+                    orientation: None,
+                    physical_size_mm: None,
+                }])
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Always empty: the portal doesn't list windows.
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        Ok(Vec::new())
+    }
+
+    /// Starts a session if none is running, then converts the newest frame
+    /// the compositor sent. `CaptureTarget::Primary` is the shared monitor,
+    /// whichever the user picked, and regions must lie within it.
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        trace_span!("capture", backend = "wayland", ?target);
+        self.start()?;
+        let frame = options.retry.run(|| self.grab(target, options))?;
+        self.sequence += 1;
+        Ok(frame)
+    }
+}
+
+#[test]
+fn test_wayland_backend() {
+    let mut backend = WaylandBackend::new();
+    let options = CaptureOptions::default();
+    let s = backend
+        .capture_target(CaptureTarget::Primary, &options)
+        .unwrap();
+    let monitor = backend.monitors().unwrap().remove(0);
+    assert_eq!(s.metadata().unwrap().source, monitor.rect);
+    assert_eq!(s.get_pixel(0, 0).a, 255);
+
+    // the next session doesn't ask again
+    backend.close();
+    let token = backend.restore_token().map(str::to_string);
+    let mut backend = WaylandBackend::with_restore_token(token.unwrap());
+    let region = Rect {
+        x: monitor.rect.x,
+        y: monitor.rect.y,
+        width: 16,
+        height: 8,
+    };
+    let s = backend
+        .capture_once(CaptureTarget::Region(region), &options)
+        .unwrap();
+    assert!(s.width() >= 16 && s.height() >= 8);
+    assert!(backend.monitors().unwrap().is_empty());
+}
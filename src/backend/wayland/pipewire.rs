@@ -0,0 +1,382 @@
+//! Reading a portal stream from PipeWire. The stream runs on a thread of its
+//! own, keeping a copy of the newest frame the compositor sent; captures
+//! convert that copy.
+
+use crate::{convert, trace::trace_span, Rect, ScreenshotError, PIXEL_WIDTH};
+
+use ::pipewire as pw;
+use pw::{
+    properties,
+    spa::{
+        self,
+        param::{
+            format::{FormatProperties, MediaSubtype, MediaType},
+            format_utils,
+            video::{VideoFormat, VideoInfoRaw},
+            ParamType,
+        },
+        pod::{self, serialize::PodSerializer, Pod},
+        utils::{Fraction, Rectangle, SpaTypes},
+    },
+    stream::{Stream, StreamFlags},
+};
+
+use std::{
+    io::Cursor,
+    os::unix::io::RawFd,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Channel order of the frames we accept; the compositor picks one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Layout {
+    Bgrx,
+    Bgra,
+    Rgbx,
+    Rgba,
+}
+
+impl Layout {
+    fn from_video_format(format: VideoFormat) -> Option<Self> {
+        match format {
+            VideoFormat::BGRx => Some(Layout::Bgrx),
+            VideoFormat::BGRA => Some(Layout::Bgra),
+            VideoFormat::RGBx => Some(Layout::Rgbx),
+            VideoFormat::RGBA => Some(Layout::Rgba),
+            _ => None,
+        }
+    }
+}
+
+/// A copy of a frame, as the compositor laid it out.
+pub(super) struct RawFrame {
+    pub(super) data: Vec<u8>,
+    pub(super) width: usize,
+    pub(super) height: usize,
+    pub(super) stride: usize,
+    pub(super) layout: Layout,
+    pub(super) captured_at: Instant,
+    pub(super) wall_time: SystemTime,
+}
+
+impl RawFrame {
+    /// Converts `region`, given in pixels of the frame, into packed BGRA
+    /// rows with opaque alpha.
+    pub(super) fn to_bgra(&self, region: Rect) -> Vec<u8> {
+        trace_span!("convert");
+        let (x, width) = (region.x as usize * PIXEL_WIDTH, region.width as usize);
+        let row_len = width * PIXEL_WIDTH;
+        let mut data = Vec::with_capacity(row_len * region.height as usize);
+        for row in self
+            .data
+            .chunks(self.stride)
+            .skip(region.y as usize)
+            .take(region.height as usize)
+        {
+            data.extend_from_slice(&row[x..x + row_len]);
+        }
+        if let Layout::Rgbx | Layout::Rgba = self.layout {
+            convert::swap_r_b(&mut data, width, row_len);
+        }
+        if let Layout::Bgrx | Layout::Rgbx = self.layout {
+            convert::set_opaque(&mut data, width, row_len);
+        }
+        data
+    }
+}
+
+#[derive(Default)]
+struct Latest {
+    frame: Option<RawFrame>,
+    /// Why the stream ended, if it did.
+    ended: Option<String>,
+}
+
+#[derive(Default)]
+struct Shared {
+    latest: Mutex<Latest>,
+    /// Signalled on every frame, and when the stream ends.
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Latest> {
+        self.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn end(&self, why: String) {
+        self.lock().ended.get_or_insert(why);
+        self.changed.notify_all();
+    }
+}
+
+/// A stream of frames from PipeWire node `node`, read on a thread of its
+/// own until dropped.
+pub(super) struct PipeWireStream {
+    shared: Arc<Shared>,
+    quit: pw::channel::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PipeWireStream {
+    /// Connects to `node` through `fd`, a remote from the portal, which the
+    /// stream takes ownership of.
+    pub(super) fn connect(fd: RawFd, node: u32) -> Result<Self, ScreenshotError> {
+        let shared = Arc::new(Shared::default());
+        let (quit, quit_rx) = pw::channel::channel();
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("screenshot-pipewire".into())
+                .spawn(move || {
+                    if let Err(e) = run(fd, node, &shared, quit_rx) {
+                        shared.end(e.to_string());
+                    }
+                    shared.end("the stream ended".into());
+                })
+                .map_err(|_| ScreenshotError::CaptureThreadFailed)?
+        };
+        Ok(PipeWireStream {
+            shared,
+            quit,
+            thread: Some(thread),
+        })
+    }
+
+    /// Calls `f` with the newest frame, waiting up to `timeout` for the
+    /// first one. Fails once the stream ended, e.g. because the user stopped
+    /// sharing.
+    pub(super) fn with_latest<T>(
+        &self,
+        timeout: Duration,
+        f: impl FnOnce(&RawFrame) -> T,
+    ) -> Result<T, ScreenshotError> {
+        let deadline = Instant::now() + timeout;
+        let mut latest = self.shared.lock();
+        loop {
+            if let Some(why) = &latest.ended {
+                return Err(ScreenshotError::PipeWireFailed(why.clone()));
+            }
+            if let Some(frame) = &latest.frame {
+                return Ok(f(frame));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ScreenshotError::Timeout(timeout));
+            }
+            latest = self
+                .shared
+                .changed
+                .wait_timeout(latest, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+impl Drop for PipeWireStream {
+    fn drop(&mut self) {
+        // fails if the loop already quit
+        let _ = self.quit.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs the stream until `quit` says so or it fails.
+fn run(
+    fd: RawFd,
+    node: u32,
+    shared: &Arc<Shared>,
+    quit: pw::channel::Receiver<()>,
+) -> Result<(), pw::Error> {
+    let mainloop = pw::MainLoop::new()?;
+    let context = pw::Context::new(&mainloop)?;
+    let core = context.connect_fd(fd, None)?;
+    let stream = Stream::new(
+        &core,
+        "screenshot-rs",
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+
+    let on_frame = shared.clone();
+    let _listener = stream
+        .add_local_listener_with_user_data(VideoInfoRaw::default())
+        .param_changed(|_, id, format, param| {
+            let param = match param {
+                Some(param) if id == ParamType::Format.as_raw() => param,
+                _ => return,
+            };
+            if let Ok((media_type, media_subtype)) = format_utils::parse_format(param) {
+                if media_type == MediaType::Video && media_subtype == MediaSubtype::Raw {
+                    let _ = format.parse(param);
+                }
+            }
+        })
+        .process(move |stream, format| {
+            let mut buffer = match stream.dequeue_buffer() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let data = match buffer.datas_mut().first_mut() {
+                Some(data) => data,
+                None => return,
+            };
+            let chunk = data.chunk();
+            let (offset, size, stride) = (
+                chunk.offset() as usize,
+                chunk.size() as usize,
+                chunk.stride() as usize,
+            );
+            let (width, height) = (format.size().width as usize, format.size().height as usize);
+            let (bytes, layout) = match (data.data(), Layout::from_video_format(format.format())) {
+                (Some(bytes), Some(layout)) => (bytes, layout),
+                _ => return,
+            };
+            // e.g. a buffer without damage
+            let bytes = match bytes.get(offset..offset + size) {
+                Some(bytes) if stride >= width * PIXEL_WIDTH && size >= stride * height => bytes,
+                _ => return,
+            };
+            let mut latest = on_frame.lock();
+            // reuse the previous copy's buffer
+            let mut frame = latest.frame.take().map(|f| f.data).unwrap_or_default();
+            frame.clear();
+            frame.extend_from_slice(&bytes[..stride * height]);
+            latest.frame = Some(RawFrame {
+                data: frame,
+                width,
+                height,
+                stride,
+                layout,
+                captured_at: Instant::now(),
+                wall_time: SystemTime::now(),
+            });
+            drop(latest);
+            on_frame.changed.notify_all();
+        })
+        .register()?;
+
+    let formats = pod::object!(
+        SpaTypes::ObjectParamFormat,
+        ParamType::EnumFormat,
+        pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+        pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+        pod::property!(
+            FormatProperties::VideoFormat,
+            Choice,
+            Enum,
+            Id,
+            VideoFormat::BGRx,
+            VideoFormat::BGRx,
+            VideoFormat::BGRA,
+            VideoFormat::RGBx,
+            VideoFormat::RGBA
+        ),
+        pod::property!(
+            FormatProperties::VideoSize,
+            Choice,
+            Range,
+            Rectangle,
+            Rectangle {
+                width: 1920,
+                height: 1080
+            },
+            Rectangle {
+                width: 1,
+                height: 1
+            },
+            Rectangle {
+                width: 16384,
+                height: 16384
+            }
+        ),
+        pod::property!(
+            FormatProperties::VideoFramerate,
+            Choice,
+            Range,
+            Fraction,
+            Fraction { num: 30, denom: 1 },
+            Fraction { num: 0, denom: 1 },
+            Fraction {
+                num: 1000,
+                denom: 1
+            }
+        ),
+    );
+    let formats = PodSerializer::serialize(Cursor::new(Vec::new()), &pod::Value::Object(formats))
+        .map_err(|_| pw::Error::CreationFailed)?
+        .0
+        .into_inner();
+    let mut params = [Pod::from_bytes(&formats).ok_or(pw::Error::CreationFailed)?];
+    stream.connect(
+        spa::Direction::Input,
+        Some(node),
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    let _quit = quit.attach(&mainloop, {
+        let mainloop = mainloop.clone();
+        move |()| mainloop.quit()
+    });
+    mainloop.run();
+    Ok(())
+}
+
+#[test]
+fn test_raw_frame_to_bgra() {
+    // 3 x 2 RGBx pixels, rows padded to 16 bytes
+    let mut data = Vec::new();
+    for row in 0..2u8 {
+        for col in 0..3u8 {
+            data.extend_from_slice(&[10 * row + col, 1, 2, 0]);
+        }
+        data.extend_from_slice(&[0xee; 4]);
+    }
+    let frame = RawFrame {
+        data,
+        width: 3,
+        height: 2,
+        stride: 16,
+        layout: Layout::Rgbx,
+        captured_at: Instant::now(),
+        wall_time: SystemTime::now(),
+    };
+    let all = frame.to_bgra(Rect {
+        x: 0,
+        y: 0,
+        width: 3,
+        height: 2,
+    });
+    assert_eq!(all.len(), 3 * 2 * 4);
+    assert_eq!(&all[..4], [2, 1, 0, 255]);
+    // a region comes out packed, without the padding
+    let region = frame.to_bgra(Rect {
+        x: 1,
+        y: 1,
+        width: 2,
+        height: 1,
+    });
+    assert_eq!(region, [2, 1, 11, 255, 2, 1, 12, 255]);
+
+    let bgra = RawFrame {
+        layout: Layout::Bgra,
+        ..frame
+    };
+    let px = bgra.to_bgra(Rect {
+        x: 2,
+        y: 0,
+        width: 1,
+        height: 1,
+    });
+    // alpha is kept as sent
+    assert_eq!(px, [2, 1, 2, 0]);
+}
@@ -0,0 +1,269 @@
+//! The ScreenCast portal of xdg-desktop-portal, which asks the user what to
+//! share and hands out a PipeWire remote to read it from.
+//!
+//! Every portal call that involves the user returns a request object, whose
+//! `Response` signal carries the results. The request's path is derived from
+//! the `handle_token` we pass, so we subscribe before calling and can't miss
+//! a response that comes right away.
+
+use crate::{Rect, ScreenshotError};
+
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    os::unix::io::{IntoRawFd, RawFd},
+    process,
+    sync::Arc,
+};
+use zbus::blocking::{Connection, Proxy};
+use zvariant::{DeserializeDict, ObjectPath, OwnedObjectPath, OwnedValue, SerializeDict, Type};
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREEN_CAST: &str = "org.freedesktop.portal.ScreenCast";
+
+/// The `types` of `SelectSources` that mean monitors.
+const MONITOR: u32 = 1;
+/// The `persist_mode` that keeps the permission until it's revoked.
+const PERSIST_UNTIL_REVOKED: u32 = 2;
+/// The first version of the portal with `persist_mode` and `restore_token`.
+const PERSIST_VERSION: u32 = 4;
+
+#[derive(SerializeDict, Type)]
+#[zvariant(signature = "dict")]
+struct CreateSessionOptions {
+    handle_token: String,
+    session_handle_token: String,
+}
+
+#[derive(DeserializeDict, Type)]
+#[zvariant(signature = "dict")]
+struct CreateSessionResults {
+    session_handle: String,
+}
+
+#[derive(SerializeDict, Type)]
+#[zvariant(signature = "dict")]
+struct SelectSourcesOptions {
+    handle_token: String,
+    types: u32,
+    multiple: bool,
+    persist_mode: Option<u32>,
+    restore_token: Option<String>,
+}
+
+#[derive(SerializeDict, Type)]
+#[zvariant(signature = "dict")]
+struct StartOptions {
+    handle_token: String,
+}
+
+#[derive(DeserializeDict, Type)]
+#[zvariant(signature = "dict")]
+struct StartResults {
+    streams: Option<Vec<(u32, StreamProperties)>>,
+    restore_token: Option<String>,
+}
+
+#[derive(DeserializeDict, Type)]
+#[zvariant(signature = "dict")]
+struct StreamProperties {
+    position: Option<(i32, i32)>,
+    size: Option<(i32, i32)>,
+}
+
+/// A stream of a started session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct PortalStream {
+    /// The PipeWire node to connect to.
+    pub(super) node: u32,
+    /// Where the monitor is, in the compositor's logical coordinates, if
+    /// the portal says.
+    pub(super) rect: Option<Rect>,
+}
+
+/// A started screencast session, closed when dropped.
+pub(super) struct Session {
+    conn: Connection,
+    handle: OwnedObjectPath,
+    pub(super) streams: Vec<PortalStream>,
+    /// Restores this session's selection without asking, if the portal
+    /// supports it.
+    pub(super) restore_token: Option<String>,
+}
+
+impl Session {
+    /// Creates a session for a single monitor and starts it. That shows
+    /// the consent dialog, unless `restore_token` is from an earlier session
+    /// the user allowed and hasn't revoked since.
+    pub(super) fn start(restore_token: Option<&str>) -> Result<Self, ScreenshotError> {
+        let no_portal = |e: zbus::Error| ScreenshotError::NoPortal(e.to_string());
+        let conn = Connection::session().map_err(no_portal)?;
+        let portal = Proxy::new(&conn, DESTINATION, PATH, SCREEN_CAST).map_err(no_portal)?;
+        // fails if there's no portal, or one without screencasts
+        let version: u32 = portal
+            .get_property("version")
+            .map_err(|e| ScreenshotError::NoPortal(e.to_string()))?;
+        let mut tokens = Tokens::new(&conn);
+
+        let (token, path) = tokens.next();
+        let created: CreateSessionResults = request(&conn, &path, || {
+            let options = CreateSessionOptions {
+                handle_token: token.clone(),
+                session_handle_token: token.clone(),
+            };
+            portal.call_method("CreateSession", &(options,))
+        })?;
+        let handle = OwnedObjectPath::try_from(created.session_handle).map_err(portal_failed)?;
+        // closed from here on, should a later step fail
+        let mut session = Session {
+            conn: conn.clone(),
+            handle,
+            streams: Vec::new(),
+            restore_token: None,
+        };
+
+        let (token, path) = tokens.next();
+        let persist = version >= PERSIST_VERSION;
+        let _: HashMap<String, OwnedValue> = request(&conn, &path, || {
+            let options = SelectSourcesOptions {
+                handle_token: token.clone(),
+                types: MONITOR,
+                multiple: false,
+                persist_mode: if persist {
+                    Some(PERSIST_UNTIL_REVOKED)
+                } else {
+                    None
+                },
+                restore_token: restore_token.filter(|_| persist).map(str::to_string),
+            };
+            portal.call_method("SelectSources", &(session.path(), options))
+        })?;
+
+        let (token, path) = tokens.next();
+        let started: StartResults = request(&conn, &path, || {
+            let options = StartOptions {
+                handle_token: token.clone(),
+            };
+            // no parent window, so the dialog isn't tied to one
+            portal.call_method("Start", &(session.path(), "", options))
+        })?;
+        session.streams = started
+            .streams
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(node, props)| PortalStream {
+                node,
+                rect: match (props.position, props.size) {
+                    (position, Some((width, height))) if width > 0 && height > 0 => {
+                        let (x, y) = position.unwrap_or((0, 0));
+                        Some(Rect {
+                            x,
+                            y,
+                            width: width as u32,
+                            height: height as u32,
+                        })
+                    }
+                    _ => None,
+                },
+            })
+            .collect();
+        if session.streams.is_empty() {
+            return Err(ScreenshotError::PortalFailed(
+                "the session has no streams".into(),
+            ));
+        }
+        session.restore_token = started.restore_token;
+        Ok(session)
+    }
+
+    /// Opens a connection to PipeWire that can only see this session's
+    /// streams. The caller owns the returned descriptor.
+    pub(super) fn open_pipewire_remote(&self) -> Result<RawFd, ScreenshotError> {
+        let portal =
+            Proxy::new(&self.conn, DESTINATION, PATH, SCREEN_CAST).map_err(portal_failed)?;
+        let options: HashMap<&str, zvariant::Value<'_>> = HashMap::new();
+        let fd: zvariant::OwnedFd = portal
+            .call("OpenPipeWireRemote", &(self.path(), options))
+            .map_err(portal_failed)?;
+        Ok(fd.into_raw_fd())
+    }
+
+    fn path(&self) -> ObjectPath<'_> {
+        self.handle.as_ref()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let interface = "org.freedesktop.portal.Session";
+        // the portal closes it anyway once we disconnect
+        if let Ok(session) = Proxy::new(&self.conn, DESTINATION, self.path(), interface) {
+            let _ = session.call_method("Close", &());
+        }
+    }
+}
+
+/// Makes the `handle_token`s of a connection's requests.
+struct Tokens {
+    /// The connection's unique name as used in request paths, e.g.
+    /// `1_42` for `:1.42`.
+    sender: String,
+    next: u32,
+}
+
+impl Tokens {
+    fn new(conn: &Connection) -> Self {
+        let sender = conn
+            .unique_name()
+            .map(|name| name.trim_start_matches(':').replace('.', "_"))
+            .unwrap_or_default();
+        Tokens { sender, next: 0 }
+    }
+
+    /// A new token, along with the path of the request it'll make.
+    fn next(&mut self) -> (String, String) {
+        self.next += 1;
+        let token = format!("screenshot_rs_{}_{}", process::id(), self.next);
+        let path = format!(
+            "/org/freedesktop/portal/desktop/request/{}/{}",
+            self.sender, token
+        );
+        (token, path)
+    }
+}
+
+/// Makes a request with `call` and waits for its response. `path` is that
+/// of the request, as given by `Tokens::next` along with the `handle_token`
+/// `call` passes.
+fn request<R>(
+    conn: &Connection,
+    path: &str,
+    call: impl FnOnce() -> zbus::Result<Arc<zbus::Message>>,
+) -> Result<R, ScreenshotError>
+where
+    R: for<'d> Deserialize<'d> + Type,
+{
+    let interface = "org.freedesktop.portal.Request";
+    let request = Proxy::new(conn, DESTINATION, path, interface).map_err(portal_failed)?;
+    let mut responses = request.receive_signal("Response").map_err(portal_failed)?;
+    call().map_err(portal_failed)?;
+    let response = responses.next().ok_or_else(|| {
+        ScreenshotError::PortalFailed("the portal went away during a request".into())
+    })?;
+    let (code, results): (u32, R) = response.body().map_err(portal_failed)?;
+    match code {
+        0 => Ok(results),
+        1 => Err(ScreenshotError::PortalDenied),
+        _ => Err(ScreenshotError::PortalFailed(format!(
+            "request ended with code {}",
+            code
+        ))),
+    }
+}
+
+fn portal_failed(e: impl fmt::Display) -> ScreenshotError {
+    ScreenshotError::PortalFailed(e.to_string())
+}
@@ -0,0 +1,413 @@
+//! The Windows.Graphics.Capture backend, from Windows 10 1903 on: the
+//! compositor hands us frames of a monitor or window as textures. Unlike
+//! GDI it sees hardware-accelerated content and windows covered by others,
+//! and unlike DXGI it works in remote sessions and whichever GPU drives a
+//! monitor.
+//!
+//! Monitors are captured at their physical resolution, so on scaled
+//! displays the capturing thread must be per-monitor DPI aware for their
+//! images to line up with the virtual screen; parts that don't come out
+//! black. Windows are captured whole, as the compositor draws them, at the
+//! size of the frame rather than of their `Window::rect`.
+
+use super::{
+    d3d::{compose, create_device, create_staging, read_rects},
+    gdi::{monitors, top_level_windows, State},
+};
+use crate::{
+    buffer::PixelBuffer, trace::trace_event, Backend, CaptureBackend, CaptureOptions,
+    CaptureTarget, Monitor, Rect, Screenshot, ScreenshotError, Window, WindowId,
+};
+
+use windows::{
+    core::{factory, Interface},
+    Graphics::{
+        Capture::{Direct3D11CaptureFramePool, GraphicsCaptureItem, GraphicsCaptureSession},
+        DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
+        SizeInt32,
+    },
+    Win32::Foundation::{BOOL, HWND, LPARAM, RECT},
+    Win32::Graphics::Direct3D11::*,
+    Win32::Graphics::Dxgi::IDXGIDevice,
+    Win32::Graphics::Gdi::{EnumDisplayMonitors, HDC, HMONITOR},
+    Win32::System::WinRT::{
+        Direct3D11::{CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess},
+        Graphics::Capture::IGraphicsCaptureItemInterop,
+    },
+};
+
+use std::{
+    mem, thread,
+    time::{Duration, Instant},
+};
+
+/// How long the first capture of a monitor or window waits for its first
+/// frame.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often the first frame is checked for while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// The only format we ask for, as the others need converting.
+const FORMAT: DirectXPixelFormat = DirectXPixelFormat::B8G8R8A8UIntNormalized;
+
+/// Captures with Windows.Graphics.Capture, keeping a session for every
+/// monitor or window being captured.
+///
+/// Like a `Capturer`, a `GraphicsCaptureBackend` is `Send` but not `Sync`.
+/// With `CaptureOptions::print_window`, windows are drawn through GDI
+/// instead.
+pub struct GraphicsCaptureBackend {
+    state: State,
+    /// Target of the latest capture, whose area `state` may have cached.
+    target: Option<CaptureTarget>,
+}
+
+impl Default for GraphicsCaptureBackend {
+    fn default() -> Self {
+        let mut state = State::default();
+        state.set_backend(Backend::GraphicsCapture);
+        GraphicsCaptureBackend {
+            state,
+            target: None,
+        }
+    }
+}
+
+impl CaptureBackend for GraphicsCaptureBackend {
+    fn name(&self) -> &'static str {
+        "wgc"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        monitors()
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        top_level_windows()
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        if self.target.replace(target) != Some(target) {
+            self.state.cache.clear();
+        }
+        self.state.capture(target, options)?;
+        let empty = Screenshot::from_bgra(Vec::new(), 0, 0, 0);
+        Ok(mem::replace(&mut self.state.frame, empty))
+    }
+}
+
+/// Whether this Windows supports Windows.Graphics.Capture, or why not.
+pub(crate) fn probe() -> Result<(), String> {
+    match GraphicsCaptureSession::IsSupported() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("not supported before Windows 10 1903".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// What a session captures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    /// The monitor with these bounds, in virtual-screen coordinates.
+    Monitor(Rect),
+    Window(WindowId),
+}
+
+/// The device and the sessions of the monitors or window being captured.
+pub(crate) struct GraphicsCapture {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    /// `device` as WinRT takes it.
+    direct3d: IDirect3DDevice,
+    sessions: Vec<Session>,
+    /// Whether the sessions capture the cursor.
+    cursor: bool,
+}
+
+// SAFETY: the device and the frame pools are free-threaded, and the
+// context is only used through `&mut self`, so never from two threads at
+// once.
+unsafe impl Send for GraphicsCapture {}
+
+impl GraphicsCapture {
+    /// Creates the device frames are copied with. Sessions are started by
+    /// the captures that need them.
+    pub(crate) fn open() -> Result<Self, ScreenshotError> {
+        let (device, context) = create_device(None).map_err(failed("D3D11CreateDevice"))?;
+        let direct3d = device
+            .cast::<IDXGIDevice>()
+            .and_then(|device| unsafe { CreateDirect3D11DeviceFromDXGIDevice(&device) })
+            .and_then(|device| device.cast())
+            .map_err(failed("CreateDirect3D11DeviceFromDXGIDevice"))?;
+        Ok(GraphicsCapture {
+            device,
+            context,
+            direct3d,
+            sessions: Vec::new(),
+            cursor: false,
+        })
+    }
+
+    /// Has the sessions capture the cursor or not, from their next frame
+    /// on.
+    pub(crate) fn set_cursor(&mut self, cursor: bool) {
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            for session in &self.sessions {
+                session.set_cursor(cursor);
+            }
+        }
+    }
+
+    /// Copies `rect`, in virtual-screen coordinates, from the monitors
+    /// overlapping it into `buf` as packed BGRA rows, and returns the row
+    /// length. Sessions of other monitors and windows are closed.
+    pub(crate) fn read_region<B: PixelBuffer>(
+        &mut self,
+        rect: Rect,
+        buf: &mut B,
+    ) -> Result<usize, ScreenshotError> {
+        let monitors: Vec<_> = monitor_handles()
+            .into_iter()
+            .filter(|(_, bounds)| bounds.intersect(&rect).is_some())
+            .collect();
+        self.sessions.retain(|session| {
+            monitors
+                .iter()
+                .any(|&(_, bounds)| session.source == Source::Monitor(bounds))
+        });
+        for &(handle, bounds) in &monitors {
+            let source = Source::Monitor(bounds);
+            if !self.sessions.iter().any(|session| session.source == source) {
+                let item = unsafe { interop()?.CreateForMonitor(handle) }
+                    .map_err(failed("CreateForMonitor"))?;
+                self.sessions
+                    .push(Session::open(source, &item, &self.direct3d, self.cursor)?);
+            }
+        }
+        for session in &mut self.sessions {
+            session.update(&self.device, &self.context, &self.direct3d)?;
+        }
+
+        let row_len = rect.width as usize * 4;
+        buf.reset(row_len * rect.height as usize);
+        let images: Vec<_> = self
+            .sessions
+            .iter()
+            .filter_map(|session| match session.source {
+                Source::Monitor(bounds) => Some((bounds, &session.image[..])),
+                Source::Window(_) => None,
+            })
+            .collect();
+        compose(&images, rect, buf);
+        Ok(row_len)
+    }
+
+    /// Copies the latest frame of `window` into `buf` as packed BGRA rows,
+    /// and returns its width and height. Other sessions are closed.
+    pub(crate) fn read_window<B: PixelBuffer>(
+        &mut self,
+        window: WindowId,
+        buf: &mut B,
+    ) -> Result<(usize, usize), ScreenshotError> {
+        let source = Source::Window(window);
+        self.sessions.retain(|session| session.source == source);
+        if self.sessions.is_empty() {
+            let item = unsafe { interop()?.CreateForWindow(HWND(window.0)) }
+                .map_err(failed("CreateForWindow"))?;
+            self.sessions
+                .push(Session::open(source, &item, &self.direct3d, self.cursor)?);
+        }
+        let session = &mut self.sessions[0];
+        session.update(&self.device, &self.context, &self.direct3d)?;
+        buf.reset(session.image.len());
+        buf.copy_from_slice(&session.image);
+        Ok((session.width, session.image.len() / (session.width * 4)))
+    }
+}
+
+/// A capture session with the latest frame it delivered.
+struct Session {
+    source: Source,
+    pool: Direct3D11CaptureFramePool,
+    session: GraphicsCaptureSession,
+    /// The size the pool's buffers were made for.
+    size: SizeInt32,
+    /// A copy of the frame texture the CPU can read, created for the first
+    /// frame and whenever the buffers change size.
+    staging: Option<ID3D11Texture2D>,
+    /// The latest frame as packed BGRA rows, `width` pixels wide; empty
+    /// before the first one.
+    image: Vec<u8>,
+    width: usize,
+}
+
+impl Session {
+    fn open(
+        source: Source,
+        item: &GraphicsCaptureItem,
+        direct3d: &IDirect3DDevice,
+        cursor: bool,
+    ) -> Result<Self, ScreenshotError> {
+        let size = item.Size().map_err(failed("GraphicsCaptureItem::Size"))?;
+        // one buffer is enough, as only the latest frame is read
+        let pool = Direct3D11CaptureFramePool::CreateFreeThreaded(direct3d, FORMAT, 1, size)
+            .map_err(failed("CreateFreeThreaded"))?;
+        let session = pool
+            .CreateCaptureSession(item)
+            .map_err(failed("CreateCaptureSession"))?;
+        session.StartCapture().map_err(failed("StartCapture"))?;
+        let session = Session {
+            source,
+            pool,
+            session,
+            size,
+            staging: None,
+            image: Vec::new(),
+            width: 0,
+        };
+        session.set_cursor(cursor);
+        Ok(session)
+    }
+
+    /// Before Windows 10 2004 the cursor can't be left out, and is always
+    /// captured.
+    fn set_cursor(&self, cursor: bool) {
+        if self.session.SetIsCursorCaptureEnabled(cursor).is_err() {
+            trace_event!(debug, "the cursor will be captured");
+        }
+    }
+
+    /// Brings `image` up to date with the latest frame. Waits up to
+    /// `FIRST_FRAME_TIMEOUT` for the first one; afterwards, `image` is kept
+    /// while no new frame arrived.
+    fn update(
+        &mut self,
+        device: &ID3D11Device,
+        context: &ID3D11DeviceContext,
+        direct3d: &IDirect3DDevice,
+    ) -> Result<(), ScreenshotError> {
+        let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+        // fails when there's no new frame
+        let frame = loop {
+            match self.pool.TryGetNextFrame() {
+                Ok(frame) => break frame,
+                Err(_) if !self.image.is_empty() => return Ok(()),
+                Err(_) if Instant::now() >= deadline => {
+                    return Err(ScreenshotError::Timeout(FIRST_FRAME_TIMEOUT))
+                }
+                Err(_) => thread::sleep(POLL_INTERVAL),
+            }
+        };
+        let size = frame
+            .ContentSize()
+            .map_err(failed("Direct3D11CaptureFrame::ContentSize"))?;
+        let texture: ID3D11Texture2D = frame
+            .Surface()
+            .and_then(|surface| surface.cast::<IDirect3DDxgiInterfaceAccess>())
+            .and_then(|access| unsafe { access.GetInterface() })
+            .map_err(failed("Direct3D11CaptureFrame::Surface"))?;
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+
+        // A resized window's content doesn't fit the buffers until they're
+        // made anew for the next frame.
+        let width = (size.Width.max(0) as u32).min(desc.Width);
+        let height = (size.Height.max(0) as u32).min(desc.Height);
+        if width == 0 || height == 0 {
+            return Err(ScreenshotError::EmptyDisplay {
+                width: size.Width,
+                height: size.Height,
+            });
+        }
+        if size != self.size {
+            self.pool
+                .Recreate(direct3d, FORMAT, 1, size)
+                .map_err(failed("Recreate"))?;
+            self.size = size;
+        }
+        let staging = match self.staging.take() {
+            Some(staging) if texture_size(&staging) == (desc.Width, desc.Height) => staging,
+            _ => create_staging(device, &desc).map_err(failed("CreateTexture2D"))?,
+        };
+        let staging = self.staging.insert(staging);
+        let content = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        self.width = width as usize;
+        self.image.resize(self.width * height as usize * 4, 0);
+        read_rects(
+            context,
+            staging,
+            &texture,
+            &[content],
+            &mut self.image,
+            self.width,
+        )
+        .map_err(failed("Map"))?;
+        // hands the buffer back to the pool for the next frame
+        let _ = frame.Close();
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.session.Close();
+        let _ = self.pool.Close();
+    }
+}
+
+/// The width and height of `texture`.
+fn texture_size(texture: &ID3D11Texture2D) -> (u32, u32) {
+    let mut desc = D3D11_TEXTURE2D_DESC::default();
+    unsafe { texture.GetDesc(&mut desc) };
+    (desc.Width, desc.Height)
+}
+
+/// Creates capture items for monitors and windows.
+fn interop() -> Result<IGraphicsCaptureItemInterop, ScreenshotError> {
+    factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()
+        .map_err(failed("IGraphicsCaptureItemInterop"))
+}
+
+/// The monitors with their bounds in virtual-screen coordinates.
+fn monitor_handles() -> Vec<(HMONITOR, Rect)> {
+    unsafe extern "system" fn callback(
+        h_monitor: HMONITOR,
+        _: HDC,
+        rect: *mut RECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(data.0 as *mut Vec<(HMONITOR, Rect)>);
+        monitors.push((h_monitor, (*rect).into()));
+        BOOL(1)
+    }
+
+    let mut monitors = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            HDC::default(),
+            None,
+            Some(callback),
+            LPARAM(&mut monitors as *mut Vec<(HMONITOR, Rect)> as isize),
+        );
+    }
+    monitors
+}
+
+/// Turns the error of a WinRT or Direct3D `call` into ours.
+fn failed(call: &'static str) -> impl Fn(windows::core::Error) -> ScreenshotError {
+    move |e| {
+        trace_event!(warn, call, error = %e, "Windows.Graphics.Capture call failed");
+        ScreenshotError::GraphicsCaptureFailed(format!("{} failed: {}", call, e))
+    }
+}
@@ -0,0 +1,554 @@
+//! The X11 backend: `GetImage` of the root window, with monitors from
+//! RandR. Works under any X server, including Xvfb on headless CI machines,
+//! but not for native Wayland clients.
+//!
+//! Virtual-screen coordinates are those of the root window, whose origin is
+//! the top left corner of the whole screen rather than of the primary
+//! monitor, so they're never negative.
+
+use crate::{
+    convert, trace::trace_span, validate_region, CaptureBackend, CaptureMetadata, CaptureOptions,
+    CaptureTarget, Monitor, Rect, Screenshot, ScreenshotError, Size, Window, WindowId, PIXEL_WIDTH,
+};
+
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        randr::ConnectionExt as _,
+        xproto::{self, AtomEnum, ConnectionExt as _, ImageFormat, ImageOrder, MapState},
+    },
+    rust_connection::RustConnection,
+};
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    time::{Instant, SystemTime},
+};
+
+/// Captures from an X server.
+pub struct X11Backend {
+    conn: RustConnection,
+    screen: usize,
+    /// Number of the next frame.
+    sequence: u64,
+}
+
+impl X11Backend {
+    /// Connects to the display named by `$DISPLAY`.
+    pub fn connect() -> Result<Self, ScreenshotError> {
+        X11Backend::connect_to(None)
+    }
+
+    /// Connects to `display`, e.g. `":99"` for an Xvfb, or to `$DISPLAY`
+    /// if None.
+    pub fn connect_to(display: Option<&str>) -> Result<Self, ScreenshotError> {
+        let (conn, screen) = x11rb::connect(display).map_err(x11_failed)?;
+        Ok(X11Backend {
+            conn,
+            screen,
+            sequence: 0,
+        })
+    }
+
+    fn root(&self) -> xproto::Window {
+        self.conn.setup().roots[self.screen].root
+    }
+
+    fn atom_name(&self, atom: xproto::Atom) -> Option<String> {
+        let reply = self.conn.get_atom_name(atom).ok()?.reply().ok()?;
+        Some(String::from_utf8_lossy(&reply.name).into_owned())
+    }
+
+    /// Bounds of the root window.
+    fn bounds(&self) -> Rect {
+        let screen = &self.conn.setup().roots[self.screen];
+        Rect {
+            x: 0,
+            y: 0,
+            width: u32::from(screen.width_in_pixels),
+            height: u32::from(screen.height_in_pixels),
+        }
+    }
+
+    /// The area to copy for `target` right now.
+    fn target_rect(&self, target: CaptureTarget) -> Result<Rect, ScreenshotError> {
+        match target {
+            CaptureTarget::Primary => {
+                let monitors = self.monitors()?;
+                let primary = monitors.iter().find(|m| m.primary).unwrap_or(&monitors[0]);
+                Ok(primary.rect)
+            }
+            CaptureTarget::Region(region) => validate_region(region, self.bounds()),
+            // Only the part on screen can be copied.
+            CaptureTarget::Window(window) => {
+                let rect = self.window_rect(window)?;
+                rect.intersect(&self.bounds())
+                    .ok_or(ScreenshotError::InvalidRegion(rect))
+            }
+        }
+    }
+
+    /// Bounds of `window` in root coordinates, without the frame drawn by
+    /// the window manager.
+    fn window_rect(&self, window: WindowId) -> Result<Rect, ScreenshotError> {
+        let no_such_window = |_| ScreenshotError::NoSuchWindow(window);
+        let id = u32::try_from(window.0).map_err(no_such_window)?;
+        let geometry = self
+            .conn
+            .get_geometry(id)
+            .map_err(x11_failed)?
+            .reply()
+            .map_err(|_| ScreenshotError::NoSuchWindow(window))?;
+        let origin = self
+            .conn
+            .translate_coordinates(id, self.root(), 0, 0)
+            .map_err(x11_failed)?
+            .reply()
+            .map_err(|_| ScreenshotError::NoSuchWindow(window))?;
+        Ok(Rect {
+            x: i32::from(origin.dst_x),
+            y: i32::from(origin.dst_y),
+            width: u32::from(geometry.width),
+            height: u32::from(geometry.height),
+        })
+    }
+
+    /// The windows listed by the window manager, bottom to top, or nothing
+    /// if it doesn't keep a list.
+    fn client_list(&self) -> Result<Vec<xproto::Window>, ScreenshotError> {
+        for name in [&b"_NET_CLIENT_LIST_STACKING"[..], b"_NET_CLIENT_LIST"] {
+            let atom = self.atom(name)?;
+            if atom == x11rb::NONE {
+                continue;
+            }
+            let reply = self
+                .conn
+                .get_property(false, self.root(), atom, AtomEnum::WINDOW, 0, u32::MAX)
+                .map_err(x11_failed)?
+                .reply()
+                .map_err(x11_failed)?;
+            if let Some(windows) = reply.value32() {
+                return Ok(windows.collect());
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// The title of `window`, preferring the UTF-8 `_NET_WM_NAME`.
+    fn title(&self, window: xproto::Window) -> Result<String, ScreenshotError> {
+        let utf8 = (self.atom(b"_NET_WM_NAME")?, self.atom(b"UTF8_STRING")?);
+        for (property, type_) in [utf8, (AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())] {
+            let reply = self
+                .conn
+                .get_property(false, window, property, type_, 0, u32::MAX)
+                .map_err(x11_failed)?
+                .reply()
+                .map_err(x11_failed)?;
+            if !reply.value.is_empty() {
+                return Ok(String::from_utf8_lossy(&reply.value).into_owned());
+            }
+        }
+        Ok(String::new())
+    }
+
+    /// The atom called `name`, or `NONE` if there's none.
+    fn atom(&self, name: &[u8]) -> Result<xproto::Atom, ScreenshotError> {
+        Ok(self
+            .conn
+            .intern_atom(true, name)
+            .map_err(x11_failed)?
+            .reply()
+            .map_err(x11_failed)?
+            .atom)
+    }
+
+    /// Copies `rect` of the root window, where `target` is.
+    fn grab(&self, target: CaptureTarget, rect: Rect) -> Result<Screenshot, ScreenshotError> {
+        let invalid = |_| ScreenshotError::InvalidRegion(rect);
+        let (x, y) = (
+            i16::try_from(rect.x).map_err(invalid)?,
+            i16::try_from(rect.y).map_err(invalid)?,
+        );
+        let (width, height) = (
+            u16::try_from(rect.width).map_err(invalid)?,
+            u16::try_from(rect.height).map_err(invalid)?,
+        );
+        let image = self
+            .conn
+            .get_image(ImageFormat::Z_PIXMAP, self.root(), x, y, width, height, !0)
+            .map_err(x11_failed)?
+            .reply()
+            .map_err(x11_failed)?;
+        let captured = (Instant::now(), SystemTime::now());
+        let layout = self.layout(image.depth, image.visual, rect.width as usize)?;
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        let (data, row_len) = to_bgra(image.data, width, height, &layout)?;
+        let mut frame = Screenshot::from_bgra(data, width, height, row_len);
+        frame.metadata = Some(CaptureMetadata {
+            captured_at: captured.0,
+            wall_time: captured.1,
+            sequence: self.sequence,
+            source: rect,
+            window: match target {
+                CaptureTarget::Window(window) => Some(window),
+                _ => None,
+            },
+            backend: "x11",
+            scale_factor: None,
+            cursor: None,
+            physical_pixels: true,
+            resumed: false,
+        });
+        Ok(frame)
+    }
+
+    /// How the server lays out images of `depth` with `visual`.
+    fn layout(
+        &self,
+        depth: u8,
+        visual: xproto::Visualid,
+        width: usize,
+    ) -> Result<ImageLayout, ScreenshotError> {
+        let setup = self.conn.setup();
+        let screen = &setup.roots[self.screen];
+        let visual = if visual == x11rb::NONE {
+            screen.root_visual
+        } else {
+            visual
+        };
+        let unsupported = ScreenshotError::UnsupportedPixelFormat {
+            depth: u32::from(depth),
+            bits_per_pixel: 0,
+        };
+        let format = setup
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == depth)
+            .ok_or(unsupported)?;
+        let visual = screen
+            .allowed_depths
+            .iter()
+            .flat_map(|d| d.visuals.iter())
+            .find(|v| v.visual_id == visual)
+            .ok_or(ScreenshotError::UnsupportedPixelFormat {
+                depth: u32::from(depth),
+                bits_per_pixel: u32::from(format.bits_per_pixel),
+            })?;
+        let pad = usize::from(format.scanline_pad.max(8));
+        let bits = width * usize::from(format.bits_per_pixel);
+        Ok(ImageLayout {
+            depth,
+            bits_per_pixel: format.bits_per_pixel,
+            stride: (bits + pad - 1) / pad * pad / 8,
+            msb_first: setup.image_byte_order == ImageOrder::MSB_FIRST,
+            masks: [visual.red_mask, visual.green_mask, visual.blue_mask],
+        })
+    }
+}
+
+impl CaptureBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    /// The monitors RandR reports, named after their outputs, or a single
+    /// one covering the root window if the server doesn't support RandR
+    /// 1.5.
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        let reply = self
+            .conn
+            .randr_get_monitors(self.root(), true)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok());
+        let mut monitors: Vec<Monitor> = reply
+            .map(|reply| reply.monitors)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|m| {
+                let rect = Rect {
+                    x: i32::from(m.x),
+                    y: i32::from(m.y),
+                    width: u32::from(m.width),
+                    height: u32::from(m.height),
+                };
+                let name = self.atom_name(m.name);
+                Monitor {
+                    rect,
+                    work_area: rect,
+                    primary: m.primary,
+                    // X11 has no notion of scaling; coordinates are pixels
+                    scale_factor: 1.0,
+                    device_name: name.clone(),
+                    name,
+                    refresh_rate: None,
+                    bits_per_pixel: None,
+                    orientation: None,
+                    physical_size_mm: Some(Size {
+                        width: m.width_in_millimeters,
+                        height: m.height_in_millimeters,
+                    })
+                    .filter(|size| !size.is_empty()),
+                }
+            })
+            .collect();
+        if monitors.is_empty() {
+            monitors.push(Monitor {
+                rect: self.bounds(),
+                work_area: self.bounds(),
+                primary: true,
+                scale_factor: 1.0,
+                name: None,
+                device_name: None,
+                refresh_rate: None,
+                bits_per_pixel: None,
+                orientation: None,
+                physical_size_mm: None,
+            });
+        }
+        // Without a primary output set, the first one counts as primary.
+        if !monitors.iter().any(|m| m.primary) {
+            monitors[0].primary = true;
+        }
+        Ok(monitors)
+    }
+
+    /// The viewable windows the window manager lists, topmost first.
+    /// Without a window manager there are none.
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        let mut found = Vec::new();
+        for id in self.client_list()?.into_iter().rev() {
+            let viewable = self
+                .conn
+                .get_window_attributes(id)
+                .map_err(x11_failed)?
+                .reply()
+                .map(|attrs| attrs.map_state == MapState::VIEWABLE)
+                .unwrap_or(false);
+            if !viewable {
+                continue;
+            }
+            let window = WindowId(id as isize);
+            let title = self.title(id)?;
+            // the window may be gone by now
+            if let (false, Ok(rect)) = (title.is_empty(), self.window_rect(window)) {
+                found.push(Window {
+                    id: window,
+                    title,
+                    rect,
+                });
+            }
+        }
+        Ok(found)
+    }
+
+    /// A window is captured as it appears on screen, i.e. only the part on
+    /// screen, and with whatever overlaps it.
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        trace_span!("capture", backend = "x11", ?target);
+        let frame = options.retry.run(|| {
+            let rect = self.target_rect(target)?;
+            let max = options.max_dimension;
+            if rect.width > max || rect.height > max {
+                return Err(ScreenshotError::DimensionsTooLarge {
+                    width: rect.width as usize,
+                    height: rect.height as usize,
+                });
+            }
+            self.grab(target, rect)
+        })?;
+        self.sequence += 1;
+        Ok(frame)
+    }
+}
+
+fn x11_failed(e: impl fmt::Display) -> ScreenshotError {
+    ScreenshotError::X11Failed(e.to_string())
+}
+
+/// How the pixels of a `Z_PIXMAP` image are laid out.
+#[derive(Clone, Copy, Debug)]
+struct ImageLayout {
+    depth: u8,
+    bits_per_pixel: u8,
+    /// Bytes per row, including the padding to the scanline pad.
+    stride: usize,
+    msb_first: bool,
+    /// Red, green and blue masks of the visual.
+    masks: [u32; 3],
+}
+
+/// A colour channel of a pixel value, as given by its mask.
+#[derive(Clone, Copy)]
+struct Channel {
+    mask: u32,
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn new(mask: u32) -> Self {
+        Channel {
+            mask,
+            shift: mask.trailing_zeros().min(31),
+            bits: mask.count_ones(),
+        }
+    }
+
+    /// The channel of `value`, scaled to 8 bits.
+    fn get(&self, value: u32) -> u8 {
+        let v = (value & self.mask) >> self.shift;
+        match self.bits {
+            0 => 0,
+            bits @ 1..=7 => (v * 255 / ((1 << bits) - 1)) as u8,
+            bits => (v >> (bits - 8)) as u8,
+        }
+    }
+}
+
+/// Converts an image into BGRA rows with opaque alpha, returning them with
+/// their row length. Depth 24 and 32 images of 32 bits per pixel, which is
+/// what almost every server sends, are already laid out as BGRX and only get
+/// their padding byte set.
+fn to_bgra(
+    mut data: Vec<u8>,
+    width: usize,
+    height: usize,
+    layout: &ImageLayout,
+) -> Result<(Vec<u8>, usize), ScreenshotError> {
+    trace_span!("convert");
+    if data.len() < layout.stride * height {
+        return Err(ScreenshotError::X11Failed(format!(
+            "GetImage returned {} bytes for {} rows of {} bytes",
+            data.len(),
+            height,
+            layout.stride
+        )));
+    }
+    let bgrx = [0xff_0000, 0xff00, 0xff];
+    if layout.bits_per_pixel == 32 && !layout.msb_first && layout.masks == bgrx {
+        data.truncate(layout.stride * height);
+        convert::set_opaque(&mut data, width, layout.stride);
+        return Ok((data, layout.stride));
+    }
+
+    let bytes = match layout.bits_per_pixel {
+        16 | 24 | 32 => usize::from(layout.bits_per_pixel / 8),
+        bits_per_pixel => {
+            return Err(ScreenshotError::UnsupportedPixelFormat {
+                depth: u32::from(layout.depth),
+                bits_per_pixel: u32::from(bits_per_pixel),
+            })
+        }
+    };
+    let [red, green, blue] = [
+        Channel::new(layout.masks[0]),
+        Channel::new(layout.masks[1]),
+        Channel::new(layout.masks[2]),
+    ];
+    let row_len = width * PIXEL_WIDTH;
+    let mut out = Vec::with_capacity(row_len * height);
+    for row in data.chunks(layout.stride).take(height) {
+        for px in row[..width * bytes].chunks_exact(bytes) {
+            let value = px.iter().enumerate().fold(0, |value, (i, &byte)| {
+                let i = if layout.msb_first { bytes - 1 - i } else { i };
+                value | u32::from(byte) << (8 * i)
+            });
+            out.extend_from_slice(&[blue.get(value), green.get(value), red.get(value), 255]);
+        }
+    }
+    Ok((out, row_len))
+}
+
+#[test]
+fn test_to_bgra() {
+    let layout = ImageLayout {
+        depth: 24,
+        bits_per_pixel: 32,
+        stride: 8,
+        msb_first: false,
+        masks: [0xff_0000, 0xff00, 0xff],
+    };
+    // depth 24 padded to 32 bits: the padding byte becomes opaque alpha
+    let (data, row_len) = to_bgra(vec![1, 2, 3, 0, 4, 5, 6, 9], 2, 1, &layout).unwrap();
+    assert_eq!((data, row_len), (vec![1, 2, 3, 255, 4, 5, 6, 255], 8));
+    assert!(to_bgra(vec![0; 4], 2, 1, &layout).is_err());
+
+    // the same pixels sent most significant byte first
+    let msb = ImageLayout {
+        msb_first: true,
+        ..layout
+    };
+    let (data, _) = to_bgra(vec![0, 3, 2, 1, 9, 6, 5, 4], 2, 1, &msb).unwrap();
+    assert_eq!(data, [1, 2, 3, 255, 4, 5, 6, 255]);
+
+    // 5-6-5 at depth 16, rows padded to 32 bits
+    let rgb565 = ImageLayout {
+        depth: 16,
+        bits_per_pixel: 16,
+        stride: 4,
+        msb_first: false,
+        masks: [0xf800, 0x07e0, 0x001f],
+    };
+    let white_then_red = vec![0xff, 0xff, 0x00, 0xf8, 0x00, 0xf8, 0, 0];
+    let (data, row_len) = to_bgra(white_then_red, 1, 2, &rgb565).unwrap();
+    assert_eq!(row_len, 4);
+    assert_eq!(data, [255, 255, 255, 255, 0, 0, 255, 255]);
+
+    assert!(matches!(
+        to_bgra(
+            vec![0; 4],
+            4,
+            1,
+            &ImageLayout {
+                bits_per_pixel: 8,
+                stride: 4,
+                ..rgb565
+            }
+        ),
+        Err(ScreenshotError::UnsupportedPixelFormat {
+            bits_per_pixel: 8,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_x11_backend() {
+    let mut backend = X11Backend::connect().unwrap();
+    let options = CaptureOptions::default();
+    let monitors = backend.monitors().unwrap();
+    let primary = monitors.iter().find(|m| m.primary).unwrap();
+    let s = backend
+        .capture_target(CaptureTarget::Primary, &options)
+        .unwrap();
+    assert_eq!(
+        (s.width(), s.height()),
+        (primary.rect.width as usize, primary.rect.height as usize)
+    );
+    assert_eq!(s.get_pixel(0, 0).a, 255);
+
+    let region = Rect {
+        x: 10,
+        y: 10,
+        width: 20,
+        height: 30,
+    };
+    let s = backend
+        .capture_target(CaptureTarget::Region(region), &options)
+        .unwrap();
+    assert_eq!((s.width(), s.height(), s.row_len()), (20, 30, 80));
+    assert_eq!(s.metadata().unwrap().sequence, 1);
+    assert_eq!(s.metadata().unwrap().window, None);
+
+    // windows may close or move off screen meanwhile
+    for window in backend.windows().unwrap() {
+        if let Ok(s) = backend.capture_target(CaptureTarget::Window(window.id), &options) {
+            assert_eq!(s.metadata().unwrap().window, Some(window.id));
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Compositing one screenshot onto another, e.g. for before and after
+//! comparisons.
+
+use crate::{Point, Screenshot, ScreenshotError, PIXEL_WIDTH};
+
+/// How `Screenshot::blend` combines each channel of the pixels on top with
+/// those below, before mixing in the result by the opacity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The pixels on top, i.e. a plain cross-fade.
+    #[default]
+    Normal,
+    /// How far apart the two are, so unchanged areas turn black.
+    Difference,
+    /// The brighter of the two.
+    Lighten,
+    /// The darker of the two.
+    Darken,
+}
+
+impl BlendMode {
+    fn apply(self, top: u8, below: u8) -> u8 {
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Difference => top.abs_diff(below),
+            BlendMode::Lighten => top.max(below),
+            BlendMode::Darken => top.min(below),
+        }
+    }
+}
+
+impl Screenshot {
+    /// Composites `other` on top of this screenshot, which must be of the
+    /// same size, see `blend_at`. Fails with `ScreenshotError::SizeMismatch`
+    /// otherwise.
+    pub fn blend(
+        &self,
+        other: &Screenshot,
+        mode: BlendMode,
+        opacity: f32,
+    ) -> Result<Screenshot, ScreenshotError> {
+        if other.size() != self.size() {
+            return Err(ScreenshotError::SizeMismatch {
+                expected: self.size(),
+                actual: other.size(),
+            });
+        }
+        self.blend_at(other, Point::default(), mode, opacity)
+    }
+
+    /// Composites `other` on top of this screenshot with its top left corner
+    /// at `offset`, leaving out what falls outside. Each channel becomes
+    /// `mode`'s combination of the two pixels, mixed with the pixel below
+    /// by `opacity`, from 0 (only the pixel below) to 1 (only the
+    /// combination). The alpha channel of `other` is ignored, as captures
+    /// usually leave it undefined.
+    ///
+    /// The result has the pixel format, alpha channel and metadata of this
+    /// screenshot. Fails with `ScreenshotError::InvalidOptions` if
+    /// `opacity` isn't between 0 and 1.
+    pub fn blend_at(
+        &self,
+        other: &Screenshot,
+        offset: Point,
+        mode: BlendMode,
+        opacity: f32,
+    ) -> Result<Screenshot, ScreenshotError> {
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(ScreenshotError::InvalidOptions(
+                "the opacity must be between 0 and 1",
+            ));
+        }
+        let opacity = (opacity * 255.0).round() as u32;
+        let mut data = self.data.to_vec();
+        // channels of `other` in the order of this screenshot's
+        let order = if other.format() == self.format() {
+            [0, 1, 2]
+        } else {
+            [2, 1, 0]
+        };
+
+        let (x, y) = (i64::from(offset.x), i64::from(offset.y));
+        let clamp = |v: i64, max: usize| v.clamp(0, max as i64) as usize;
+        let (left, right) = (
+            clamp(x, self.width),
+            clamp(x + other.width as i64, self.width),
+        );
+        let (top, bottom) = (
+            clamp(y, self.height),
+            clamp(y + other.height as i64, self.height),
+        );
+        for row in top..bottom {
+            let below = &mut data[row * self.row_len..][left * PIXEL_WIDTH..right * PIXEL_WIDTH];
+            let other_row = (row as i64 - y) as usize * other.row_len;
+            let other_left = (left as i64 - x) as usize * PIXEL_WIDTH;
+            let above = &other.data[other_row + other_left..][..below.len()];
+            for (dst, src) in below
+                .chunks_exact_mut(PIXEL_WIDTH)
+                .zip(above.chunks_exact(PIXEL_WIDTH))
+            {
+                for (c, &o) in order.iter().enumerate() {
+                    let blended = u32::from(mode.apply(src[o], dst[c]));
+                    let mixed = blended * opacity + u32::from(dst[c]) * (255 - opacity);
+                    dst[c] = ((mixed + 127) / 255) as u8;
+                }
+            }
+        }
+
+        let mut blended = Screenshot::from_bgra(data, self.width, self.height, self.row_len);
+        // `data` was copied in this screenshot's channel order
+        blended.format = self.format;
+        blended.metadata = self.metadata;
+        Ok(blended)
+    }
+}
+
+#[test]
+fn test_blend_modes() {
+    let below = Screenshot::from_raw(vec![100, 50, 200, 255], 1, 1, 4).unwrap();
+    let above = Screenshot::from_raw(vec![30, 80, 200, 0], 1, 1, 4).unwrap();
+    let blend = |mode, opacity| below.blend(&above, mode, opacity).unwrap().data().to_vec();
+    assert_eq!(blend(BlendMode::Normal, 1.0), [30, 80, 200, 255]);
+    assert_eq!(blend(BlendMode::Difference, 1.0), [70, 30, 0, 255]);
+    assert_eq!(blend(BlendMode::Lighten, 1.0), [100, 80, 200, 255]);
+    assert_eq!(blend(BlendMode::Darken, 1.0), [30, 50, 200, 255]);
+    assert_eq!(blend(BlendMode::Normal, 0.5), [65, 65, 200, 255]);
+    assert_eq!(blend(BlendMode::Difference, 0.5), [85, 40, 100, 255]);
+    assert_eq!(blend(BlendMode::Lighten, 0.25), [100, 58, 200, 255]);
+    assert_eq!(blend(BlendMode::Darken, 0.0), [100, 50, 200, 255]);
+
+    // the other's pixel format is respected
+    let mut rgba = Screenshot::from_raw(vec![30, 80, 200, 0], 1, 1, 4).unwrap();
+    rgba.swap_r_b_in_place();
+    let blended = below.blend(&rgba, BlendMode::Normal, 1.0).unwrap();
+    assert_eq!(blended.data(), [30, 80, 200, 255]);
+    assert_eq!(blended.to_rgba_vec(), [200, 80, 30, 255]);
+    let blended = rgba.blend(&below, BlendMode::Normal, 1.0).unwrap();
+    assert_eq!(blended.format(), crate::PixelFormat::Rgba8);
+    assert_eq!(blended.data(), [200, 50, 100, 0]);
+
+    assert!(matches!(
+        below.blend(&above, BlendMode::Normal, 1.5),
+        Err(ScreenshotError::InvalidOptions(_))
+    ));
+    assert!(matches!(
+        below.blend(&above, BlendMode::Normal, f32::NAN),
+        Err(ScreenshotError::InvalidOptions(_))
+    ));
+}
+
+#[test]
+fn test_blend_at() {
+    // 3x3 of 10 with padded rows, and 2x2 of 250 on top
+    let below = Screenshot::from_raw([10; 4].repeat(3 * 4), 3, 3, 16).unwrap();
+    let above = Screenshot::from_raw([250; 4].repeat(2 * 2), 2, 2, 8).unwrap();
+    let green = |shot: &Screenshot| -> Vec<u8> {
+        (0..9).map(|i| shot.get_pixel_xy(i % 3, i / 3).g).collect()
+    };
+    let blended = below
+        .blend_at(&above, Point { x: 2, y: -1 }, BlendMode::Normal, 1.0)
+        .unwrap();
+    assert_eq!(green(&blended), [10, 10, 250, 10, 10, 10, 10, 10, 10]);
+    // the padding is kept as it was
+    assert_eq!(&blended.data()[12..16], &[10; 4]);
+    let blended = below
+        .blend_at(&above, Point { x: 1, y: 1 }, BlendMode::Difference, 1.0)
+        .unwrap();
+    assert_eq!(green(&blended), [10, 10, 10, 10, 240, 240, 10, 240, 240]);
+    let blended = below
+        .blend_at(&above, Point { x: 3, y: 0 }, BlendMode::Normal, 1.0)
+        .unwrap();
+    assert_eq!(blended.data(), below.data());
+
+    assert!(matches!(
+        below.blend(&above, BlendMode::Normal, 1.0),
+        Err(ScreenshotError::SizeMismatch {
+            expected: crate::Size {
+                width: 3,
+                height: 3
+            },
+            actual: crate::Size {
+                width: 2,
+                height: 2
+            },
+        })
+    ));
+}
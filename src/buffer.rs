@@ -0,0 +1,128 @@
+//! Buffers the captured pixels are read into.
+
+use std::{
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+/// Alignment of `AlignedBuf`, a cache line and the widest vector register.
+pub(crate) const ALIGN: usize = 64;
+
+/// A block the size and alignment of a cache line.
+#[repr(C, align(64))]
+#[derive(Clone, Copy)]
+struct Block([u8; ALIGN]);
+
+/// A byte buffer whose start is aligned to `ALIGN` bytes, so SIMD code can
+/// use aligned loads and the pixels can be viewed as `u32`s. Being a `Vec`
+/// of aligned blocks, it stays aligned when it grows.
+#[derive(Clone, Default)]
+pub(crate) struct AlignedBuf {
+    blocks: Vec<Block>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    /// Resizes to `len` bytes. Added bytes are zero.
+    pub(crate) fn resize(&mut self, len: usize) {
+        if len < self.len {
+            // stale bytes past the end would show up when growing again
+            let end = len.div_ceil(ALIGN) * ALIGN;
+            self.bytes_mut(end)[len..].fill(0);
+        }
+        self.blocks.resize(len.div_ceil(ALIGN), Block([0; ALIGN]));
+        self.len = len;
+    }
+
+    /// The first `len` bytes of the blocks, which may exceed `self.len`.
+    fn bytes_mut(&mut self, len: usize) -> &mut [u8] {
+        assert!(len <= self.blocks.len() * ALIGN);
+        // SAFETY: the blocks are plain bytes laid out back to back, and at
+        // least `len` of them are initialized.
+        unsafe { slice::from_raw_parts_mut(self.blocks.as_mut_ptr() as *mut u8, len) }
+    }
+}
+
+impl From<&[u8]> for AlignedBuf {
+    fn from(data: &[u8]) -> Self {
+        let mut buf = AlignedBuf::default();
+        buf.resize(data.len());
+        buf.copy_from_slice(data);
+        buf
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: as in `bytes_mut`; `len` never exceeds the blocks.
+        unsafe { slice::from_raw_parts(self.blocks.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        self.bytes_mut(len)
+    }
+}
+
+/// A growable buffer that `GetDIBits` can write into.
+#[cfg_attr(not(all(windows, feature = "gdi")), allow(dead_code))]
+pub(crate) trait PixelBuffer: DerefMut<Target = [u8]> {
+    /// Resizes to `len` bytes, reusing the allocation if it's large enough.
+    fn reset(&mut self, len: usize);
+
+    fn truncate(&mut self, len: usize);
+}
+
+impl PixelBuffer for Vec<u8> {
+    fn reset(&mut self, len: usize) {
+        self.clear();
+        self.resize(len, 0);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len);
+    }
+}
+
+impl PixelBuffer for AlignedBuf {
+    fn reset(&mut self, len: usize) {
+        self.resize(len);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.resize(len);
+        }
+    }
+}
+
+#[test]
+fn test_aligned_buf() {
+    let is_aligned = |buf: &AlignedBuf| buf.as_ptr().align_offset(ALIGN) == 0;
+
+    let mut buf = AlignedBuf::from(&[1, 2, 3][..]);
+    assert!(is_aligned(&buf));
+    assert_eq!(&buf[..], &[1, 2, 3]);
+
+    // growing reallocates, and must stay aligned and keep the contents
+    for len in [100, 4096, 1 << 20, 7] {
+        buf.resize(len);
+        assert!(is_aligned(&buf), "{} bytes", len);
+        assert_eq!(buf.len(), len);
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+    }
+
+    // bytes cut off and grown back are zero
+    buf[5] = 9;
+    PixelBuffer::truncate(&mut buf, 4);
+    buf.resize(7);
+    assert_eq!(&buf[..], &[1, 2, 3, 0, 0, 0, 0]);
+    assert_eq!(buf.to_vec(), [1, 2, 3, 0, 0, 0, 0]);
+
+    buf.reset(0);
+    assert!(buf.is_empty());
+}
@@ -0,0 +1,128 @@
+//! Display layout remembered between the captures of a `Capturer`.
+
+use crate::{trace::trace_event, Monitor, Rect, ScreenshotError};
+
+/// Display lookups reused across captures, so a capture loop doesn't ask
+/// the OS for the same layout every frame. Everything is forgotten after a
+/// display-related error, so a hotplug or resolution change costs one failed
+/// attempt, and on `Capturer::refresh`.
+#[derive(Default)]
+pub(crate) struct DisplayCache {
+    /// The validated area to capture.
+    rect: Option<Rect>,
+    monitors: Option<Vec<Monitor>>,
+}
+
+impl DisplayCache {
+    /// The cached capture area, or the result of `lookup` if there's none.
+    pub(crate) fn rect(
+        &mut self,
+        lookup: impl FnOnce() -> Result<Rect, ScreenshotError>,
+    ) -> Result<Rect, ScreenshotError> {
+        let rect = match self.rect {
+            Some(rect) => rect,
+            None => lookup()?,
+        };
+        Ok(*self.rect.insert(rect))
+    }
+
+    /// The cached monitors, or the result of `lookup` if there are none.
+    pub(crate) fn monitors(
+        &mut self,
+        lookup: impl FnOnce() -> Result<Vec<Monitor>, ScreenshotError>,
+    ) -> Result<&[Monitor], ScreenshotError> {
+        let monitors = match self.monitors.take() {
+            Some(monitors) => monitors,
+            None => lookup()?,
+        };
+        Ok(self.monitors.insert(monitors))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = DisplayCache::default();
+    }
+
+    /// Forgets everything if `e` suggests the displays changed.
+    pub(crate) fn invalidate_on(&mut self, e: &ScreenshotError) {
+        if is_display_error(e) {
+            trace_event!(debug, error = %e, "forgetting the display layout");
+            self.clear();
+        }
+    }
+}
+
+/// Whether `e` may have been caused by a stale display layout.
+fn is_display_error(e: &ScreenshotError) -> bool {
+    match e {
+        ScreenshotError::EmptyDisplay { .. }
+        | ScreenshotError::DisplayChanged { .. }
+        | ScreenshotError::InconsistentDisplayMetrics { .. }
+        | ScreenshotError::DimensionsTooLarge { .. }
+        | ScreenshotError::InvalidRegion(_)
+        | ScreenshotError::NoMonitors
+        | ScreenshotError::BitBltFailed
+        | ScreenshotError::GetDIBitsFailed
+        | ScreenshotError::GdiFailed(_) => true,
+        ScreenshotError::RetriesExhausted { last, .. } => is_display_error(last),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_display_cache() {
+    let hd = Rect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+    let uhd = Rect {
+        width: 3840,
+        height: 2160,
+        ..hd
+    };
+    let mut cache = DisplayCache::default();
+    let mut lookups = 0;
+    let mut lookup = |rect: Rect| -> Result<Rect, ScreenshotError> {
+        lookups += 1;
+        Ok(rect)
+    };
+
+    assert_eq!(cache.rect(|| lookup(hd)).unwrap(), hd);
+    // the display changed, but the cache doesn't know yet
+    assert_eq!(cache.rect(|| lookup(uhd)).unwrap(), hd);
+
+    // errors unrelated to the display keep the cache
+    cache.invalidate_on(&ScreenshotError::SecureDesktopActive);
+    assert_eq!(cache.rect(|| lookup(uhd)).unwrap(), hd);
+
+    // the capture of the stale area failed, so the next one looks again
+    cache.invalidate_on(&ScreenshotError::DisplayChanged {
+        before: (1920, 1080),
+        after: (3840, 2160),
+    });
+    assert_eq!(cache.rect(|| lookup(uhd)).unwrap(), uhd);
+    assert_eq!(cache.rect(|| lookup(hd)).unwrap(), uhd);
+    assert_eq!(lookups, 2);
+
+    // failed lookups aren't cached
+    assert!(cache.monitors(|| Err(ScreenshotError::NoMonitors)).is_err());
+    let monitor = Monitor {
+        rect: hd,
+        work_area: hd,
+        primary: true,
+        scale_factor: 1.0,
+        name: None,
+        device_name: None,
+        refresh_rate: None,
+        bits_per_pixel: None,
+        orientation: None,
+        physical_size_mm: None,
+    };
+    let monitors = cache.monitors(|| Ok(vec![monitor.clone()])).unwrap();
+    assert_eq!(monitors.len(), 1);
+    assert_eq!(cache.monitors(|| Ok(Vec::new())).unwrap(), [monitor]);
+
+    cache.clear();
+    assert!(cache.monitors(|| Ok(Vec::new())).unwrap().is_empty());
+}
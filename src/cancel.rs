@@ -0,0 +1,117 @@
+//! Cancelling long-running operations such as scrolling captures from
+//! another thread, and following how far they got.
+
+use crate::ScreenshotError;
+
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// Cancels the operations it's passed to, e.g.
+/// `capture_scrolling_cancellable`, from any thread, e.g. for a cancel
+/// button. Clones cancel the same operations.
+///
+/// Operations check the token between units of work, such as captured
+/// frames, and while waiting between them, and then fail with
+/// `ScreenshotError::Cancelled` after releasing what they hold. A cancelled
+/// token stays cancelled.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    cancelled: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Cancels the operations using this token, waking those that wait.
+    pub fn cancel(&self) {
+        *self.lock() = true;
+        self.0.changed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.lock()
+    }
+
+    /// Fails with `ScreenshotError::Cancelled` if cancelled.
+    pub(crate) fn check(&self) -> Result<(), ScreenshotError> {
+        if self.is_cancelled() {
+            return Err(ScreenshotError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Sleeps for `duration`, unless cancelled before or meanwhile, in which
+    /// case it fails with `ScreenshotError::Cancelled` right away.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<(), ScreenshotError> {
+        let (cancelled, _) = self
+            .0
+            .changed
+            .wait_timeout_while(self.lock(), duration, |cancelled| !*cancelled)
+            .unwrap_or_else(|e| e.into_inner());
+        if *cancelled {
+            return Err(ScreenshotError::Cancelled);
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, bool> {
+        // the flag is valid whatever panicked
+        self.0.cancelled.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CancelToken")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
+
+/// How far a long-running operation got, passed to its progress callback
+/// after every unit of work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Units of work done, e.g. frames captured.
+    pub done: u32,
+    /// Units of work in all, if known in advance.
+    pub total: Option<u32>,
+}
+
+#[test]
+fn test_cancel_token() {
+    use std::{thread, time::Instant};
+
+    let token = CancelToken::new();
+    assert!(token.check().is_ok());
+    assert!(token.sleep(Duration::from_millis(1)).is_ok());
+
+    // cancelling wakes a sleeper long before its time is up
+    let clone = token.clone();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        clone.cancel();
+    });
+    let started = Instant::now();
+    assert!(matches!(
+        token.sleep(Duration::from_secs(30)),
+        Err(ScreenshotError::Cancelled)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    canceller.join().unwrap();
+    assert!(token.is_cancelled());
+    assert!(matches!(token.check(), Err(ScreenshotError::Cancelled)));
+    assert!(matches!(
+        token.sleep(Duration::ZERO),
+        Err(ScreenshotError::Cancelled)
+    ));
+}
@@ -0,0 +1,504 @@
+//! Repeated captures that reuse their OS resources and buffers.
+
+use crate::{
+    backend::gdi::{DpiAwareScope, State},
+    change::ChangeDetector,
+    monitors,
+    pacing::Pacer,
+    run_with_timeout,
+    stop::StopCheck,
+    Backend, CaptureMetadata, CaptureMetrics, CaptureOptions, CaptureTarget, FrameInfo, FramePool,
+    Monitor, PacingStats, PooledFrame, Rect, Screenshot, ScreenshotError,
+};
+
+use std::{cell::Cell, marker::PhantomData, sync::mpsc, time::Duration};
+
+/// Captures the same target repeatedly, keeping the bitmap and pixel
+/// buffers alive between frames instead of allocating them every time.
+/// They're reallocated when the resolution changes, and released on drop.
+///
+/// A `Capturer` is `Send` but not `Sync`: it can be moved to a worker
+/// thread, but captures on it must not run concurrently. The screen DC is
+/// acquired and released on the capturing thread for each frame, as Windows
+/// requires.
+pub struct Capturer {
+    target: CaptureTarget,
+    options: CaptureOptions,
+    /// What `options.backend` resolved to, by the first capture.
+    backend: Option<Backend>,
+    // None before the first capture
+    state: Option<State>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl Default for Capturer {
+    fn default() -> Self {
+        Capturer::new()
+    }
+}
+
+impl Capturer {
+    /// A capturer for the primary monitor.
+    pub fn new() -> Self {
+        Capturer::for_target(CaptureTarget::Primary)
+    }
+
+    /// A capturer for `region`, in virtual-screen coordinates.
+    pub fn for_region(region: Rect) -> Self {
+        Capturer::for_target(CaptureTarget::Region(region))
+    }
+
+    /// A capturer for a single monitor.
+    pub fn for_monitor(monitor: &Monitor) -> Self {
+        Capturer::for_region(monitor.rect)
+    }
+
+    /// A capturer for any target, e.g. a window.
+    pub fn for_target(target: CaptureTarget) -> Self {
+        Capturer {
+            target,
+            options: CaptureOptions::default(),
+            backend: None,
+            state: None,
+            _not_sync: PhantomData,
+        }
+    }
+
+    /// A capturer for what `options` select, see `CaptureOptions::monitor`,
+    /// with those options. Fails if they don't go together or the backend
+    /// is unavailable, instead of at the first capture.
+    pub fn with_options(options: CaptureOptions) -> Result<Self, ScreenshotError> {
+        let mut capturer = Capturer::for_target(options.target(monitors)?);
+        capturer.set_options(options);
+        capturer.resolve_backend()?;
+        Ok(capturer)
+    }
+
+    /// Replaces the options. If they ask for another backend, the next
+    /// capture probes again, and if they change `dpi_aware`, the display
+    /// layout is looked up again.
+    pub fn set_options(&mut self, options: CaptureOptions) {
+        if options.backend != self.options.backend {
+            self.backend = None;
+            if let Some(state) = &mut self.state {
+                state.fell_back = false;
+            }
+        }
+        if options.dpi_aware != self.options.dpi_aware {
+            self.refresh();
+        }
+        self.options = options;
+    }
+
+    pub(crate) fn options(&self) -> &CaptureOptions {
+        &self.options
+    }
+
+    /// Lists the monitors like `monitors`, but only asks the OS the first
+    /// time, and again after `refresh` or a display-related error.
+    pub fn monitors(&mut self) -> Result<&[Monitor], ScreenshotError> {
+        let _dpi = DpiAwareScope::enter(self.options.dpi_aware);
+        let state = self.state.get_or_insert_with(State::default);
+        state.cache.monitors(monitors)
+    }
+
+    /// Forgets the cached display layout, e.g. after a `WM_DISPLAYCHANGE`,
+    /// so the next capture looks it up again. Display-related errors do
+    /// the same automatically.
+    pub fn refresh(&mut self) {
+        if let Some(state) = &mut self.state {
+            state.cache.clear();
+        }
+    }
+
+    /// The backend captures use, once the first capture resolved
+    /// `CaptureOptions::backend`. With `Backend::Auto` that's the first
+    /// available one, which is kept rather than probed for every frame,
+    /// and GDI once a DXGI or Windows.Graphics.Capture capture failed.
+    pub fn backend(&self) -> Option<Backend> {
+        match &self.state {
+            Some(state) if state.fell_back => Some(Backend::Gdi),
+            _ => self.backend,
+        }
+    }
+
+    /// Sets the state up for the resolved backend, and returns it along
+    /// with the target and options to capture with.
+    fn prepare(&mut self) -> Result<(&mut State, CaptureTarget, &CaptureOptions), ScreenshotError> {
+        let resolved = self.resolve_backend()?;
+        let backend = self.backend().unwrap_or(resolved);
+        let state = self.state.get_or_insert_with(State::default);
+        state.set_backend(backend);
+        Ok((state, self.target, &self.options))
+    }
+
+    /// Resolves `CaptureOptions::backend` unless that's done already,
+    /// failing with the probe's reason if the backend is unavailable.
+    fn resolve_backend(&mut self) -> Result<Backend, ScreenshotError> {
+        match self.backend {
+            Some(backend) => Ok(backend),
+            None => Ok(*self.backend.insert(self.options.backend.resolve()?)),
+        }
+    }
+
+    /// When and where the latest frame was captured, if that succeeded.
+    /// Also covers `capture_into`, whose buffer can't carry it.
+    pub fn last_metadata(&self) -> Option<CaptureMetadata> {
+        self.state.as_ref().and_then(|state| state.metadata)
+    }
+
+    /// The areas of the latest frame that changed since the frame before,
+    /// in pixels of the frame, if the backend reports them, as DXGI does.
+    /// All of the frame for the first capture.
+    pub fn last_dirty_rects(&self) -> Option<&[Rect]> {
+        self.state.as_ref()?.dirty_rects.as_deref()
+    }
+
+    /// Timings of the latest capture, if it succeeded and
+    /// `CaptureOptions::collect_metrics` is set.
+    pub fn last_metrics(&self) -> Option<CaptureMetrics> {
+        self.state.as_ref().and_then(|state| state.metrics)
+    }
+
+    /// Captures a frame. The returned screenshot is overwritten by the next
+    /// capture; clone what you need to keep.
+    pub fn capture(&mut self) -> Result<&Screenshot, ScreenshotError> {
+        self.capture_mut().map(|frame| &*frame)
+    }
+
+    pub(crate) fn capture_mut(&mut self) -> Result<&mut Screenshot, ScreenshotError> {
+        let (state, target, options) = self.prepare()?;
+        state.capture(target, options)?;
+        Ok(&mut state.frame)
+    }
+
+    /// Captures a frame every `interval`, on the calling thread. See
+    /// `Frames`.
+    pub fn frames(&mut self, interval: Duration) -> Frames<'_> {
+        Frames {
+            changes: self.options.change_detector(),
+            pacer: Pacer::new(self.options.pacing, interval),
+            stop: self.options.stop_check(),
+            capturer: self,
+        }
+    }
+
+    /// Captures a frame and takes it out of the capturer, leaving `spare`,
+    /// if any, to be captured into next time.
+    pub(crate) fn capture_swap(
+        &mut self,
+        spare: Option<Screenshot>,
+    ) -> Result<Screenshot, ScreenshotError> {
+        let (state, target, options) = self.prepare()?;
+        state.capture(target, options)?;
+        let spare = spare.unwrap_or_else(|| State::default().frame);
+        Ok(std::mem::replace(&mut state.frame, spare))
+    }
+
+    /// Captures a frame into `buf` rather than the capturer's own buffer,
+    /// growing it if needed but never shrinking its allocation.
+    pub fn capture_into(&mut self, buf: &mut Vec<u8>) -> Result<FrameInfo, ScreenshotError> {
+        let (state, target, options) = self.prepare()?;
+        state.capture_into(target, options, buf)
+    }
+
+    /// Captures a frame into a buffer from `pool`, which goes back to the
+    /// pool when the returned frame is dropped. Unlike `capture`, the frame
+    /// can outlive the next capture, e.g. while another thread encodes it.
+    pub fn capture_pooled(&mut self, pool: &FramePool) -> Result<PooledFrame, ScreenshotError> {
+        let mut buf = pool.take();
+        match self.capture_into(&mut buf) {
+            Ok(info) => {
+                pool.set_frame_len(buf.len());
+                Ok(pool.wrap(buf, info, self.last_metadata()))
+            }
+            Err(e) => {
+                pool.put(buf);
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `capture`, but gives up with `ScreenshotError::Timeout` after
+    /// `timeout`. See `capture_with_timeout` for what happens to the
+    /// abandoned capture; its buffers go with it, so the next capture
+    /// allocates new ones.
+    pub fn capture_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<&Screenshot, ScreenshotError> {
+        self.prepare()?;
+        let mut state = self.take_state();
+        let (target, options) = (self.target, self.options.clone());
+        let (tx, rx) = mpsc::sync_channel(1);
+        run_with_timeout(timeout, move || {
+            let res = state.capture(target, &options);
+            // hand the state back even if the capture failed
+            let _ = tx.send(state);
+            res
+        })?;
+        let state = rx
+            .recv()
+            .map_err(|_| ScreenshotError::CaptureThreadFailed)?;
+        Ok(&self.state.insert(state).frame)
+    }
+
+    /// Takes the state for a capture on another thread. It's replaced by a
+    /// fresh one, numbering frames after those of the taken one, which is
+    /// used if the capture times out and the state never comes back.
+    fn take_state(&mut self) -> State {
+        let state = self.state.take().unwrap_or_default();
+        self.state.get_or_insert_with(State::default).sequence = state.sequence + 1;
+        state
+    }
+
+    /// Like `capture`, but runs the capture on tokio's blocking thread pool,
+    /// see `get_screenshot_async`. If the future is dropped, the capture
+    /// still finishes on the pool and then releases the capturer's buffers
+    /// and bitmap, so the next capture allocates new ones.
+    #[cfg(feature = "tokio")]
+    pub async fn capture_async(&mut self) -> Result<&Screenshot, ScreenshotError> {
+        self.prepare()?;
+        let mut state = self.take_state();
+        let (target, options) = (self.target, self.options.clone());
+        let (state, res) = tokio::task::spawn_blocking(move || {
+            let res = state.capture(target, &options);
+            (state, res)
+        })
+        .await
+        .map_err(|_| ScreenshotError::CaptureThreadFailed)?;
+        let state = self.state.insert(state);
+        res?;
+        Ok(&state.frame)
+    }
+}
+
+/// Captures at a fixed interval, yielding every frame as a screenshot of
+/// its own. Returned by `Capturer::frames`.
+///
+/// Frames are due `interval` apart, counted from when the previous one was
+/// due rather than when it was taken, so the time spent capturing and
+/// processing doesn't make the cadence drift. If a frame is overdue, it's
+/// taken right away rather than catching up with a burst; see
+/// `CaptureOptions::pacing` for what happens to the cadence then, and for
+/// waiting more precisely.
+///
+/// With `CaptureOptions::only_on_change`, unchanged frames are captured on
+/// the same cadence but skipped, so `next` returns at the first changed one.
+///
+/// Failed captures are yielded as errors and the iteration goes on, so
+/// stop on the errors you can't recover from, or end it with
+/// `CaptureOptions::stop_when`. Everything runs on the
+/// calling thread; there's nothing to clean up when the iterator is
+/// dropped.
+pub struct Frames<'a> {
+    capturer: &'a mut Capturer,
+    pacer: Pacer,
+    changes: Option<ChangeDetector>,
+    stop: StopCheck,
+}
+
+impl Frames<'_> {
+    /// How well the frames so far kept to the interval.
+    pub fn pacing_stats(&self) -> PacingStats {
+        self.pacer.stats()
+    }
+}
+
+impl Iterator for Frames<'_> {
+    type Item = Result<Screenshot, ScreenshotError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut spare = None;
+        loop {
+            if self.stop.is_done() {
+                return None;
+            }
+            self.pacer.wait();
+            // time may be up after waiting
+            if self.stop.is_done() {
+                return None;
+            }
+            let frame = match self.capturer.capture_swap(spare.take()) {
+                Ok(frame) => frame,
+                Err(e) => return Some(Err(e)),
+            };
+            let changed = match &mut self.changes {
+                Some(changes) => changes.should_deliver_frame(&frame),
+                None => true,
+            };
+            if changed {
+                self.stop.delivered(frame.metadata());
+                return Some(Ok(frame));
+            }
+            // captured into next time
+            spare = Some(frame);
+        }
+    }
+}
+
+#[test]
+fn test_frames() {
+    use std::time::Instant;
+
+    let mut capturer = Capturer::new();
+    let interval = Duration::from_millis(100);
+    let started = Instant::now();
+    let frames: Vec<_> = capturer.frames(interval).take(3).collect();
+    // the first frame is taken right away
+    assert!(started.elapsed() >= interval * 2);
+    assert!(started.elapsed() < interval * 3);
+    let frames: Vec<_> = frames.into_iter().map(Result::unwrap).collect();
+    assert!(frames.iter().all(|frame| !frame.is_empty()));
+    // timestamps show the interval
+    let taken: Vec<_> = frames.iter().map(|f| f.captured_at().unwrap()).collect();
+    assert!(taken[0] >= started);
+    assert!(taken[1] - taken[0] >= interval / 2);
+    assert!(taken[2] > taken[1]);
+
+    // precise pacing keeps to the interval to the millisecond
+    capturer.set_options(CaptureOptions {
+        pacing: crate::Pacing::Precise,
+        ..CaptureOptions::default()
+    });
+    let mut frames = capturer.frames(interval);
+    for _ in 0..5 {
+        frames.next().unwrap().unwrap();
+    }
+    let stats = frames.pacing_stats();
+    assert_eq!(stats.frames, 5);
+    assert!((stats.fps() - 10.0).abs() < 0.1, "{}", stats.fps());
+
+    // and a stop condition ends the iteration
+    capturer.set_options(CaptureOptions {
+        stop_when: crate::StopCondition::FrameCount(2),
+        ..CaptureOptions::default()
+    });
+    assert_eq!(capturer.frames(Duration::ZERO).count(), 2);
+}
+
+#[test]
+fn test_capturer() {
+    let mut capturer = Capturer::new();
+    let (width, height) = {
+        let s = capturer.capture().unwrap();
+        (s.width(), s.height())
+    };
+    let s = capturer.capture().unwrap();
+    assert_eq!((s.width(), s.height()), (width, height));
+    let s = capturer
+        .capture_with_timeout(Duration::from_secs(10))
+        .unwrap();
+    assert_eq!((s.width(), s.height()), (width, height));
+
+    let mut buf = Vec::with_capacity(width * height * 8);
+    let capacity = buf.capacity();
+    let info = capturer.capture_into(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (width, height));
+    // frames are numbered however they're captured
+    let metadata = capturer.last_metadata().unwrap();
+    assert_eq!(metadata.sequence, 3);
+    assert_eq!(
+        metadata.source,
+        Rect {
+            x: 0,
+            y: 0,
+            width: width as u32,
+            height: height as u32,
+        }
+    );
+    // whichever backend Auto resolved to took the frames
+    let backend = capturer.backend().unwrap();
+    assert_eq!(metadata.backend, backend.name());
+    assert_eq!(backend, Backend::Auto.resolve().unwrap());
+    let next = capturer.capture().unwrap().metadata().copied().unwrap();
+    assert_eq!(next.sequence, 4);
+    assert!(next.captured_at > metadata.captured_at);
+    assert_eq!(info.format, crate::PixelFormat::Bgra8);
+    assert_eq!(buf.len(), info.stride * info.height);
+    assert_eq!(buf.capacity(), capacity);
+
+    assert!(capturer.last_metrics().is_none());
+    capturer.set_options(CaptureOptions {
+        collect_metrics: true,
+        ..CaptureOptions::default()
+    });
+    capturer.capture().unwrap();
+    let metrics = capturer.last_metrics().unwrap();
+    assert!(metrics.total >= metrics.acquire + metrics.blit + metrics.read_dib);
+    assert_eq!(metrics.frame_bytes, capturer.capture().unwrap().len());
+
+    assert!(!capturer.monitors().unwrap().is_empty());
+    capturer.refresh();
+    assert_eq!(capturer.capture().unwrap().width(), width);
+
+    let pool = FramePool::new(2);
+    let frame = capturer.capture_pooled(&pool).unwrap();
+    assert_eq!(frame.len(), frame.info().stride * height);
+    assert_eq!(
+        frame.metadata().unwrap().sequence,
+        capturer.last_metadata().unwrap().sequence
+    );
+    drop(frame);
+    assert_eq!(pool.available(), 1);
+
+    // Send, so it can move to a worker thread
+    std::thread::spawn(move || capturer.capture().map(|_| ()))
+        .join()
+        .unwrap()
+        .unwrap();
+}
+
+#[test]
+#[cfg(not(all(windows, feature = "winrt-capture")))]
+fn test_unavailable_backend() {
+    let mut capturer = Capturer::new();
+    capturer.set_options(CaptureOptions {
+        backend: Backend::GraphicsCapture,
+        ..CaptureOptions::default()
+    });
+    // fails before touching the screen, and doesn't fall back
+    assert!(matches!(
+        capturer.capture(),
+        Err(ScreenshotError::BackendUnavailable {
+            backend: Backend::GraphicsCapture,
+            ..
+        })
+    ));
+    assert!(capturer.capture_into(&mut Vec::new()).is_err());
+    assert_eq!(capturer.backend(), None);
+}
+
+#[test]
+#[cfg(feature = "dxgi")]
+fn test_fall_back_to_gdi() {
+    use crate::FaultPoint;
+
+    let broken = CaptureOptions {
+        inject_fault: Some(FaultPoint::Session),
+        ..CaptureOptions::default()
+    };
+    // as if Auto had picked DXGI, which then broke
+    let mut capturer = Capturer::new();
+    capturer.set_options(broken.clone());
+    capturer.backend = Some(Backend::DxgiDuplication);
+    let res = capturer.capture().map(|s| s.metadata().copied());
+    assert_eq!(capturer.backend(), Some(Backend::Gdi));
+    if let Ok(metadata) = res {
+        assert_eq!(metadata.unwrap().backend, "gdi");
+    }
+    // and stays with GDI
+    capturer.set_options(CaptureOptions::default());
+    let _ = capturer.capture();
+    assert_eq!(capturer.backend(), Some(Backend::Gdi));
+
+    // a backend asked for by name doesn't fall back
+    let mut capturer = Capturer::new();
+    capturer.set_options(broken.backend(Backend::DxgiDuplication));
+    capturer.backend = Some(Backend::DxgiDuplication);
+    assert!(matches!(
+        capturer.capture(),
+        Err(ScreenshotError::DxgiFailed(_))
+    ));
+    assert_eq!(capturer.backend(), Some(Backend::DxgiDuplication));
+}
@@ -0,0 +1,190 @@
+//! Telling whether a frame changed since the last delivered one, for
+//! streaming captures that only deliver changes.
+
+use crate::{fingerprint::fingerprint_rows, Rect, Screenshot, PIXEL_WIDTH};
+
+use std::time::{Duration, Instant};
+
+/// Which frames count as changed, see `CaptureOptions::only_on_change`.
+/// The default only lets through frames that differ in any pixel.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(default, deny_unknown_fields))]
+pub struct ChangeFilter {
+    /// Largest difference in any channel of a pixel that still counts as
+    /// unchanged, to ignore e.g. dithering. Zero requires equal pixels.
+    pub tolerance: u8,
+    /// Only changes in this area count, in pixels from the frame's top left
+    /// corner. The whole frame if None.
+    pub region: Option<Rect>,
+    /// Deliver a frame at least this often even if nothing changed, so
+    /// consumers can tell a still screen from a capture that died.
+    #[cfg_attr(feature = "config", serde(with = "crate::config::optional_duration"))]
+    pub max_quiet_duration: Option<Duration>,
+}
+
+/// Applies a `ChangeFilter` to a sequence of frames.
+///
+/// Frames are compared against the last delivered one rather than the
+/// previous capture, so changes creeping in below the tolerance still add
+/// up to a delivery. Fingerprints settle most frames; only frames whose
+/// fingerprint differs while a tolerance is set are compared pixel by pixel.
+pub(crate) struct ChangeDetector {
+    filter: ChangeFilter,
+    /// Fingerprint of the compared area of the last delivered frame.
+    fingerprint: u64,
+    /// The compared area of the last delivered frame, rows back to back.
+    /// Only kept if there's a tolerance.
+    pixels: Vec<u8>,
+    /// Size of the compared area of the last delivered frame.
+    size: (usize, usize),
+    /// None before the first frame.
+    delivered_at: Option<Instant>,
+}
+
+impl ChangeDetector {
+    pub(crate) fn new(filter: ChangeFilter) -> Self {
+        ChangeDetector {
+            filter,
+            fingerprint: 0,
+            pixels: Vec::new(),
+            size: (0, 0),
+            delivered_at: None,
+        }
+    }
+
+    /// Whether to deliver the frame in `data`, of `height` rows of
+    /// `row_len` bytes holding `width` pixels. Remembers it if so.
+    pub(crate) fn should_deliver(
+        &mut self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        row_len: usize,
+    ) -> bool {
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            width: width as u32,
+            height: height as u32,
+        };
+        let area = match &self.filter.region {
+            Some(region) => region.intersect(&frame).unwrap_or_default(),
+            None => frame,
+        };
+        let size = (area.width as usize, area.height as usize);
+        let rows = || area_rows(data, row_len, area);
+        let fingerprint = fingerprint_rows(size.0, size.1, rows());
+
+        let now = Instant::now();
+        if let Some(delivered_at) = self.delivered_at {
+            let quiet_too_long = matches!(self.filter.max_quiet_duration,
+                Some(max) if now - delivered_at >= max);
+            let unchanged = size == self.size
+                && (fingerprint == self.fingerprint
+                    || (self.filter.tolerance > 0 && self.within_tolerance(rows())));
+            if unchanged && !quiet_too_long {
+                return false;
+            }
+        }
+
+        self.fingerprint = fingerprint;
+        self.size = size;
+        self.delivered_at = Some(now);
+        if self.filter.tolerance > 0 {
+            self.pixels.clear();
+            rows().for_each(|row| self.pixels.extend_from_slice(row));
+        }
+        true
+    }
+
+    /// Forgets the last delivered frame, so the next one is delivered.
+    pub(crate) fn reset(&mut self) {
+        self.delivered_at = None;
+    }
+
+    /// `should_deliver` for a screenshot.
+    pub(crate) fn should_deliver_frame(&mut self, frame: &Screenshot) -> bool {
+        self.should_deliver(frame.data(), frame.width, frame.height, frame.row_len)
+    }
+
+    fn within_tolerance<'a>(&self, rows: impl Iterator<Item = &'a [u8]>) -> bool {
+        let tolerance = self.filter.tolerance;
+        let row_len = self.size.0 * PIXEL_WIDTH;
+        rows.zip(self.pixels.chunks(row_len.max(1)))
+            .all(|(row, last)| {
+                row.iter()
+                    .zip(last)
+                    .all(|(&a, &b)| a.abs_diff(b) <= tolerance)
+            })
+    }
+}
+
+/// The pixels of `area` in each row, which must lie within the frame.
+fn area_rows(data: &[u8], row_len: usize, area: Rect) -> impl Iterator<Item = &[u8]> {
+    let start = area.x as usize * PIXEL_WIDTH;
+    let end = start + area.width as usize * PIXEL_WIDTH;
+    data.chunks(row_len.max(1))
+        .skip(area.y as usize)
+        .take(area.height as usize)
+        .map(move |row| &row[start..end])
+}
+
+#[test]
+fn test_change_detector() {
+    // 4x4 frames, rows padded to 20 bytes
+    let frame = vec![100u8; 4 * 20];
+    let at = |x: usize, y: usize| y * 20 + x * PIXEL_WIDTH;
+
+    let mut exact = ChangeDetector::new(ChangeFilter::default());
+    // the first frame is always delivered, identical ones never
+    assert!(exact.should_deliver(&frame, 4, 4, 20));
+    assert!(!exact.should_deliver(&frame, 4, 4, 20));
+    // padding doesn't count
+    let mut padded = frame.clone();
+    padded[19] = 0;
+    assert!(!exact.should_deliver(&padded, 4, 4, 20));
+    let mut changed = frame.clone();
+    changed[at(3, 3)] += 1;
+    assert!(exact.should_deliver(&changed, 4, 4, 20));
+    // a new size is a change
+    assert!(exact.should_deliver(&frame[..60], 4, 3, 20));
+
+    let mut tolerant = ChangeDetector::new(ChangeFilter {
+        tolerance: 2,
+        ..ChangeFilter::default()
+    });
+    assert!(tolerant.should_deliver(&frame, 4, 4, 20));
+    let mut noisy = frame.clone();
+    noisy[at(1, 1)] += 2;
+    assert!(!tolerant.should_deliver(&noisy, 4, 4, 20));
+    // compared to the last delivered frame, so small steps add up
+    noisy[at(1, 1)] += 1;
+    assert!(tolerant.should_deliver(&noisy, 4, 4, 20));
+
+    let mut region = ChangeDetector::new(ChangeFilter {
+        region: Some(Rect {
+            x: 2,
+            y: 2,
+            width: 10,
+            height: 10,
+        }),
+        ..ChangeFilter::default()
+    });
+    assert!(region.should_deliver(&frame, 4, 4, 20));
+    let mut outside = frame.clone();
+    outside[at(1, 3)] = 0;
+    assert!(!region.should_deliver(&outside, 4, 4, 20));
+    outside[at(2, 3)] = 0;
+    assert!(region.should_deliver(&outside, 4, 4, 20));
+
+    let mut keepalive = ChangeDetector::new(ChangeFilter {
+        max_quiet_duration: Some(Duration::from_millis(20)),
+        ..ChangeFilter::default()
+    });
+    assert!(keepalive.should_deliver(&frame, 4, 4, 20));
+    assert!(!keepalive.should_deliver(&frame, 4, 4, 20));
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(keepalive.should_deliver(&frame, 4, 4, 20));
+    assert!(!keepalive.should_deliver(&frame, 4, 4, 20));
+}
@@ -0,0 +1,599 @@
+//! Capture profiles: everything a recorder needs to know, in a TOML or JSON
+//! file that can be edited without rebuilding, see `CaptureProfile`.
+
+use crate::{CaptureOptions, Pixel, QueuePolicy, Rect, Screenshot, ScreenshotError, StopCondition};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What to capture, how often, what to hide and where to keep the results.
+/// Every field is optional in the file. `examples/profile.toml` shows them
+/// all:
+///
+/// ```toml
+/// fps = 2.0
+/// queue = { drop_oldest = { capacity = 8 } }
+///
+/// [capture]
+/// monitor = { index = 1 }
+/// region = { x = 0, y = 0, width = 1280, height = 720 }
+/// stop_when = { duration = "8h" }
+///
+/// [[redact]]
+/// rect = { x = 900, y = 0, width = 380, height = 40 }
+/// style = { blur = 8 }
+///
+/// [output]
+/// directory = "captures"
+/// format = "jpeg"
+/// retention = { max_files = 1000, max_age = "7d" }
+/// ```
+///
+/// Durations are written with a unit: `ns`, `us`, `ms`, `s`, `m`, `h` or
+/// `d`, e.g. `"250ms"` or `"1.5s"`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureProfile {
+    /// What to capture and how. `window` can't be set from a profile, and
+    /// neither can `StopCondition::When`.
+    pub capture: CaptureOptions,
+    /// Frames per second for streaming captures. None leaves it to the
+    /// recorder.
+    pub fps: Option<f64>,
+    /// Queue for streaming captures, e.g. `MultiChannels::Merged`.
+    pub queue: Option<QueuePolicy>,
+    /// Areas hidden in every frame, in the order given.
+    pub redact: Vec<Redaction>,
+    pub output: OutputProfile,
+}
+
+/// An area of the frames to hide, see `CaptureProfile::redact`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Redaction {
+    /// In pixels from the top left corner of the frame.
+    pub rect: Rect,
+    #[serde(default)]
+    pub style: RedactStyle,
+}
+
+/// How a `Redaction` hides its area.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactStyle {
+    /// Paints it black.
+    #[default]
+    Fill,
+    /// `Screenshot::blur_rect` with this radius.
+    Blur(u32),
+    /// `Screenshot::pixelate_rect` with this block size.
+    Pixelate(u32),
+}
+
+/// Where and how a recorder saves its frames.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputProfile {
+    /// Directory the frames are saved into, named by `file_name`. The
+    /// current directory if None.
+    pub directory: Option<PathBuf>,
+    pub format: OutputFormat,
+    pub retention: Retention,
+}
+
+impl OutputProfile {
+    /// `screenshot-<milliseconds since the epoch>.<extension>` for a frame
+    /// captured at `captured`, so that names sort by capture time.
+    pub fn file_name(&self, captured: SystemTime) -> String {
+        let millis = captured
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        format!("screenshot-{}.{}", millis, self.format.extension())
+    }
+
+    /// Where to save a frame captured at `captured`.
+    pub fn path(&self, captured: SystemTime) -> PathBuf {
+        let name = self.file_name(captured);
+        match &self.directory {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+}
+
+/// Image format of saved frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Bmp,
+    Jpeg,
+}
+
+impl OutputFormat {
+    /// File extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Which saved frames to keep. Everything, by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    /// How many of the newest frames to keep.
+    pub max_files: Option<usize>,
+    /// How long to keep a frame after it was saved.
+    #[serde(with = "optional_duration")]
+    pub max_age: Option<Duration>,
+}
+
+impl Retention {
+    /// Deletes the files in `dir` with the given extension that aren't to be
+    /// kept, going by when they were last modified, and returns how many
+    /// were deleted. Other files and subdirectories are left alone.
+    pub fn prune(&self, dir: &Path, extension: &str) -> io::Result<usize> {
+        if self.max_files.is_none() && self.max_age.is_none() {
+            return Ok(0);
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && path.extension().is_some_and(|e| e == extension) {
+                files.push((metadata.modified()?, path));
+            }
+        }
+        // newest first
+        files.sort_unstable_by(|a, b| b.cmp(a));
+        let now = SystemTime::now();
+        let mut deleted = 0;
+        for (i, (modified, path)) in files.iter().enumerate() {
+            let too_many = self.max_files.is_some_and(|max| i >= max);
+            let too_old = self.max_age.is_some_and(|max_age| {
+                now.duration_since(*modified).is_ok_and(|age| age > max_age)
+            });
+            if too_many || too_old {
+                fs::remove_file(path)?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+impl CaptureProfile {
+    /// Reads and validates a profile in TOML. Fails with
+    /// `ScreenshotError::InvalidConfig`, saying where the file is wrong.
+    pub fn from_toml(s: &str) -> Result<Self, ScreenshotError> {
+        let profile: CaptureProfile =
+            toml::from_str(s).map_err(|e| ScreenshotError::InvalidConfig(e.to_string()))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Like `from_toml`, for a profile in JSON.
+    pub fn from_json(s: &str) -> Result<Self, ScreenshotError> {
+        let profile: CaptureProfile =
+            serde_json::from_str(s).map_err(|e| ScreenshotError::InvalidConfig(e.to_string()))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// The profile as TOML, e.g. to write out the defaults as a starting
+    /// point. Fails with `ScreenshotError::InvalidConfig` if it holds a
+    /// `StopCondition::When`.
+    pub fn to_toml(&self) -> Result<String, ScreenshotError> {
+        toml::to_string(self).map_err(|e| ScreenshotError::InvalidConfig(e.to_string()))
+    }
+
+    /// Like `to_toml`, as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, ScreenshotError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| ScreenshotError::InvalidConfig(e.to_string()))
+    }
+
+    /// Checks the values the file format can't, failing with
+    /// `ScreenshotError::InvalidConfig` naming the first offending field,
+    /// e.g. `redact[2].rect: is empty`. Done by `from_toml` and
+    /// `from_json`.
+    pub fn validate(&self) -> Result<(), ScreenshotError> {
+        let invalid = |field: String, problem: &str| -> Result<(), ScreenshotError> {
+            Err(ScreenshotError::InvalidConfig(format!(
+                "{}: {}",
+                field, problem
+            )))
+        };
+        let capture = &self.capture;
+        if capture.region.is_some_and(|r| r.is_empty()) {
+            return invalid("capture.region".into(), "is empty");
+        }
+        if capture.retry.max_attempts == 0 {
+            return invalid("capture.retry.max_attempts".into(), "must be at least 1");
+        }
+        if !(capture.retry.backoff.is_finite() && capture.retry.backoff >= 0.0) {
+            let problem = "must be a finite number, not negative";
+            return invalid("capture.retry.backoff".into(), problem);
+        }
+        if capture.max_dimension == 0 {
+            return invalid("capture.max_dimension".into(), "must be at least 1");
+        }
+        let filter = capture.only_on_change.as_ref();
+        if filter.is_some_and(|f| f.region.is_some_and(|r| r.is_empty())) {
+            return invalid("capture.only_on_change.region".into(), "is empty");
+        }
+        if let Some(problem) = stop_problem(&capture.stop_when) {
+            return invalid("capture.stop_when".into(), problem);
+        }
+        if self.fps.is_some_and(|fps| !(fps.is_finite() && fps > 0.0)) {
+            return invalid("fps".into(), "must be a positive number");
+        }
+        // the time between frames must fit a Duration
+        if self
+            .fps
+            .is_some_and(|fps| Duration::try_from_secs_f64(1.0 / fps).is_err())
+        {
+            return invalid("fps".into(), "is too low");
+        }
+        if matches!(
+            self.queue,
+            Some(
+                QueuePolicy::DropOldest { capacity: 0 }
+                    | QueuePolicy::DropNewest { capacity: 0 }
+                    | QueuePolicy::Block { capacity: 0 }
+            )
+        ) {
+            return invalid("queue.capacity".into(), "must be at least 1");
+        }
+        for (i, redaction) in self.redact.iter().enumerate() {
+            if redaction.rect.is_empty() {
+                return invalid(format!("redact[{}].rect", i), "is empty");
+            }
+            match redaction.style {
+                RedactStyle::Blur(0) => {
+                    return invalid(format!("redact[{}].style.blur", i), "must be at least 1")
+                }
+                RedactStyle::Pixelate(size) if size < 2 => {
+                    return invalid(
+                        format!("redact[{}].style.pixelate", i),
+                        "must be at least 2",
+                    )
+                }
+                _ => {}
+            }
+        }
+        if self.output.retention.max_files == Some(0) {
+            return invalid("output.retention.max_files".into(), "must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Time between frames at `fps`, if it's set. Never more than
+    /// `Duration::MAX`, and zero if `fps` isn't a positive number, which
+    /// `validate` rejects.
+    pub fn interval(&self) -> Option<Duration> {
+        self.fps
+            .map(|fps| match Duration::try_from_secs_f64(1.0 / fps) {
+                Ok(interval) => interval,
+                Err(_) if fps > 0.0 => Duration::MAX,
+                Err(_) => Duration::ZERO,
+            })
+    }
+
+    /// Hides the `redact` areas of `shot`.
+    pub fn redact(&self, shot: &mut Screenshot) {
+        for redaction in &self.redact {
+            let rect = redaction.rect;
+            match redaction.style {
+                RedactStyle::Fill => shot.fill(
+                    rect.x.into(),
+                    rect.y.into(),
+                    rect.width.into(),
+                    rect.height.into(),
+                    Pixel {
+                        a: 255,
+                        r: 0,
+                        g: 0,
+                        b: 0,
+                    },
+                ),
+                RedactStyle::Blur(radius) => shot.blur_rect(rect, radius),
+                RedactStyle::Pixelate(size) => shot.pixelate_rect(rect, size),
+            }
+        }
+    }
+}
+
+/// What's wrong with a stop condition read from a profile, if anything.
+fn stop_problem(condition: &StopCondition) -> Option<&'static str> {
+    match condition {
+        StopCondition::Duration(limit) if limit.is_zero() => Some("duration must not be zero"),
+        StopCondition::FrameCount(0) => Some("frame_count must be at least 1"),
+        StopCondition::Any(conditions) => conditions.iter().find_map(stop_problem),
+        _ => None,
+    }
+}
+
+/// Units of durations in profiles, in nanoseconds.
+const UNITS: [(&str, u64); 7] = [
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+/// Parses e.g. `"250ms"` or `"1.5h"`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = (&s[..split], s[split..].trim());
+    let nanos = match UNITS.iter().find(|(name, _)| *name == unit) {
+        Some(&(_, nanos)) => nanos,
+        None => {
+            return Err(format!(
+                "invalid duration {:?}, expected a number and a unit, e.g. \"250ms\"",
+                s
+            ))
+        }
+    };
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+    let total = (value * nanos as f64).round();
+    if total >= u64::MAX as f64 {
+        return Err(format!("duration {:?} is too long", s));
+    }
+    Ok(Duration::from_nanos(total as u64))
+}
+
+/// The largest unit that gives a whole number, e.g. `"90s"`.
+fn format_duration(duration: Duration) -> String {
+    let total = duration.as_nanos();
+    if total == 0 {
+        return "0s".into();
+    }
+    let (name, nanos) = UNITS
+        .iter()
+        .find(|(_, nanos)| total % u128::from(*nanos) == 0)
+        .expect("every duration is a whole number of nanoseconds");
+    format!("{}{}", total / u128::from(*nanos), name)
+}
+
+/// `serde(with)` for `Duration` fields, as strings like `"250ms"`.
+pub(crate) mod duration {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&super::format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        super::parse_duration(&String::deserialize(d)?).map_err(D::Error::custom)
+    }
+}
+
+/// `serde(with)` for `Option<Duration>` fields.
+pub(crate) mod optional_duration {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => s.serialize_some(&super::format_duration(*duration)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|s| super::parse_duration(&s).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+#[test]
+fn test_durations() {
+    for (s, duration) in [
+        ("250ms", Duration::from_millis(250)),
+        ("1.5s", Duration::from_millis(1500)),
+        ("0.3s", Duration::from_millis(300)),
+        ("10m", Duration::from_secs(600)),
+        ("2 h", Duration::from_secs(7200)),
+        ("7d", Duration::from_secs(7 * 86_400)),
+        ("15us", Duration::from_micros(15)),
+    ] {
+        assert_eq!(parse_duration(s), Ok(duration), "{}", s);
+    }
+    for s in ["", "5", "ms", "1.5 weeks", "-1s", "1e400s"] {
+        assert!(parse_duration(s).is_err(), "{}", s);
+    }
+    assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+    assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+    assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+    assert_eq!(format_duration(Duration::from_nanos(7)), "7ns");
+    assert_eq!(format_duration(Duration::ZERO), "0s");
+}
+
+#[test]
+fn test_profile_from_toml() {
+    use crate::MonitorSelector;
+
+    let profile = CaptureProfile::from_toml(
+        r#"
+        fps = 4.0
+        queue = { block = { capacity = 2 } }
+
+        [capture]
+        monitor = { index = 1 }
+        region = { x = 10, y = 20, width = 300, height = 200 }
+        retry = { max_attempts = 5, delay = "20ms" }
+        stop_when = { any = [{ duration = "1h" }, { frame_count = 100 }] }
+
+        [[redact]]
+        rect = { x = 0, y = 0, width = 50, height = 10 }
+
+        [[redact]]
+        rect = { x = 0, y = 100, width = 50, height = 10 }
+        style = { pixelate = 8 }
+
+        [output]
+        directory = "captures"
+        format = "jpeg"
+        retention = { max_files = 10, max_age = "7d" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(profile.capture.monitor, Some(MonitorSelector::Index(1)));
+    assert_eq!(profile.capture.region.map(|r| r.width), Some(300));
+    assert_eq!(profile.capture.retry.max_attempts, 5);
+    assert_eq!(profile.capture.retry.delay, Duration::from_millis(20));
+    assert_eq!(
+        profile.capture.stop_when.limit(),
+        Some(Duration::from_secs(3600))
+    );
+    assert_eq!(profile.interval(), Some(Duration::from_millis(250)));
+    assert_eq!(profile.queue, Some(QueuePolicy::Block { capacity: 2 }));
+    assert_eq!(profile.redact[0].style, RedactStyle::Fill);
+    assert_eq!(profile.redact[1].style, RedactStyle::Pixelate(8));
+    assert_eq!(profile.output.format, OutputFormat::Jpeg);
+    assert_eq!(
+        profile
+            .output
+            .path(UNIX_EPOCH + Duration::from_millis(1234)),
+        Path::new("captures").join("screenshot-1234.jpg")
+    );
+    assert_eq!(
+        profile.output.retention.max_age,
+        Some(Duration::from_secs(7 * 86_400))
+    );
+
+    // and back
+    let again = CaptureProfile::from_toml(&profile.to_toml().unwrap()).unwrap();
+    assert_eq!(again.redact, profile.redact);
+    assert_eq!(again.output, profile.output);
+    let again = CaptureProfile::from_json(&profile.to_json().unwrap()).unwrap();
+    assert_eq!(again.capture.region, profile.capture.region);
+
+    // nothing is required
+    let mut profile = CaptureProfile::from_toml("").unwrap();
+    assert!(profile.redact.is_empty());
+    assert_eq!(profile.capture.max_dimension, crate::DEFAULT_MAX_DIMENSION);
+    assert_eq!(profile.interval(), None);
+    // fields set directly aren't validated, but the interval is clamped
+    profile.fps = Some(1e-320);
+    assert_eq!(profile.interval(), Some(Duration::MAX));
+    profile.fps = Some(-1.0);
+    assert_eq!(profile.interval(), Some(Duration::ZERO));
+}
+
+#[test]
+fn test_profile_errors() {
+    let error = |toml: &str| match CaptureProfile::from_toml(toml) {
+        Err(ScreenshotError::InvalidConfig(msg)) => msg,
+        other => panic!("{:?} for {}", other.map(|_| ()), toml),
+    };
+    assert!(error("fsp = 4").contains("fsp"));
+    assert!(error("[capture]\nmonitor = \"secondary\"").contains("secondary"));
+    assert!(error("[capture.retry]\ndelay = \"5\"").contains("expected a number and a unit"));
+    assert_eq!(error("fps = 0.0"), "fps: must be a positive number");
+    assert_eq!(error("fps = 1e-320"), "fps: is too low");
+    assert_eq!(
+        error("[capture.retry]\nbackoff = inf"),
+        "capture.retry.backoff: must be a finite number, not negative"
+    );
+    assert_eq!(
+        error("queue = { drop_newest = { capacity = 0 } }"),
+        "queue.capacity: must be at least 1"
+    );
+    assert_eq!(
+        error("[capture]\nstop_when = { any = [\"manual\", { frame_count = 0 }] }"),
+        "capture.stop_when: frame_count must be at least 1"
+    );
+    assert_eq!(
+        error(
+            "[[redact]]\nrect = { x = 0, y = 0, width = 5, height = 5 }\n\
+             [[redact]]\nrect = { x = 0, y = 0, width = 0, height = 5 }"
+        ),
+        "redact[1].rect: is empty"
+    );
+    assert_eq!(
+        error("[[redact]]\nrect = { x = 0, y = 0, width = 5, height = 5 }\nstyle = { blur = 0 }"),
+        "redact[0].style.blur: must be at least 1"
+    );
+    assert!(matches!(
+        CaptureProfile::from_json(r#"{ "capture": { "window": 5 } }"#),
+        Err(ScreenshotError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_redact() {
+    let mut shot = Screenshot::from_raw([200, 100, 50, 255].repeat(16), 4, 4, 16).unwrap();
+    let profile = CaptureProfile {
+        redact: vec![Redaction {
+            rect: Rect {
+                x: 2,
+                y: -1,
+                width: 9,
+                height: 2,
+            },
+            style: RedactStyle::Fill,
+        }],
+        ..CaptureProfile::default()
+    };
+    profile.redact(&mut shot);
+    let black: Vec<bool> = (0..16)
+        .map(|i| shot.get_pixel_xy(i % 4, i / 4).g == 0)
+        .collect();
+    let mut expected = [false; 16];
+    expected[2] = true;
+    expected[3] = true;
+    assert_eq!(black, expected);
+}
+
+#[test]
+fn test_retention() {
+    let dir = std::env::temp_dir().join(format!("screenshot-retention-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for i in 0..5 {
+        fs::write(dir.join(format!("screenshot-{}.png", i)), [i as u8]).unwrap();
+        // mtimes must differ for the order to be known
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    fs::write(dir.join("notes.txt"), "keep").unwrap();
+    let retention = Retention {
+        max_files: Some(2),
+        max_age: None,
+    };
+    assert_eq!(retention.prune(&dir, "png").unwrap(), 3);
+    let mut left: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["notes.txt", "screenshot-3.png", "screenshot-4.png"]);
+    let retention = Retention {
+        max_files: None,
+        max_age: Some(Duration::ZERO),
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(retention.prune(&dir, "png").unwrap(), 2);
+    assert_eq!(Retention::default().prune(&dir, "txt").unwrap(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
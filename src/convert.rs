@@ -0,0 +1,303 @@
+//! Per-pixel passes over 32-bit pixel buffers. On x86 the SIMD versions are
+//! picked at runtime when the CPU supports them; other targets, and the few
+//! pixels left over at the end of a row, go through the scalar code.
+//!
+//! With the `rayon` feature, frames of at least `PARALLEL_MIN_PIXELS` are
+//! split into rows that are converted, compared or searched in parallel.
+
+use crate::PIXEL_WIDTH;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Smallest frame, in pixels, that's worth splitting across threads. Below
+/// it, e.g. for thumbnails and small regions, handing rows to the pool
+/// costs more than converting them on the calling thread. A 1080p frame is
+/// about eight times as large; the `threshold` group of `benches/convert.rs`
+/// shows where splitting starts to pay off.
+#[cfg(feature = "rayon")]
+pub(crate) const PARALLEL_MIN_PIXELS: usize = 256 * 1024;
+
+/// Switches bytes 0 and 2 of the first `width` pixels of each row, turning
+/// BGRA into RGBA or back. Padding and alpha are left alone.
+pub(crate) fn swap_r_b(data: &mut [u8], width: usize, row_len: usize) {
+    for_each_row(data, width, row_len, swap_r_b_pixels);
+}
+
+/// Sets the alpha byte of the first `width` pixels of each row to 255.
+pub(crate) fn set_opaque(data: &mut [u8], width: usize, row_len: usize) {
+    for_each_row(data, width, row_len, set_opaque_pixels);
+}
+
+/// Calls `f` with each row of `src`, without its padding, and the matching
+/// row of `dst`. Rows of `src` are `row_len` bytes long and hold `width`
+/// pixels; rows of `dst` are `dst_row_len` elements long.
+pub(crate) fn map_rows<T, F>(
+    src: &[u8],
+    width: usize,
+    row_len: usize,
+    dst: &mut [T],
+    dst_row_len: usize,
+    f: F,
+) where
+    T: Send,
+    F: Fn(&[u8], &mut [T]) + Send + Sync,
+{
+    let len = width.saturating_mul(PIXEL_WIDTH);
+    let (row_len, dst_row_len) = (row_len.max(1), dst_row_len.max(1));
+    let row = |(src, dst): (&[u8], &mut [T])| {
+        let n = len.min(src.len() / PIXEL_WIDTH * PIXEL_WIDTH);
+        f(&src[..n], dst)
+    };
+    #[cfg(feature = "rayon")]
+    {
+        if is_parallel(src, width, row_len) {
+            return src
+                .par_chunks(row_len)
+                .zip(dst.par_chunks_mut(dst_row_len))
+                .for_each(row);
+        }
+    }
+    src.chunks(row_len)
+        .zip(dst.chunks_mut(dst_row_len))
+        .for_each(row)
+}
+
+/// `(0..len).map(f)`, collected in order, for a frame of `pixels` pixels.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub(crate) fn map_range<T, F>(len: usize, pixels: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Send + Sync,
+{
+    #[cfg(feature = "rayon")]
+    {
+        if pixels >= PARALLEL_MIN_PIXELS {
+            return (0..len).into_par_iter().map(f).collect();
+        }
+    }
+    (0..len).map(f).collect()
+}
+
+/// `(0..len).find_map(f)` for a frame of `pixels` pixels.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub(crate) fn find_map_range<T, F>(len: usize, pixels: usize, f: F) -> Option<T>
+where
+    T: Send,
+    F: Fn(usize) -> Option<T> + Send + Sync,
+{
+    #[cfg(feature = "rayon")]
+    {
+        if pixels >= PARALLEL_MIN_PIXELS {
+            return (0..len).into_par_iter().find_map_first(f);
+        }
+    }
+    (0..len).find_map(f)
+}
+
+/// Whether a frame is large enough to be converted in parallel.
+#[cfg(feature = "rayon")]
+fn is_parallel(data: &[u8], width: usize, row_len: usize) -> bool {
+    width.saturating_mul(data.len() / row_len) >= PARALLEL_MIN_PIXELS
+}
+
+/// Calls `f` with the pixels of each row, without the padding.
+fn for_each_row(data: &mut [u8], width: usize, row_len: usize, f: fn(&mut [u8])) {
+    let len = width.saturating_mul(PIXEL_WIDTH);
+    #[cfg(feature = "rayon")]
+    {
+        if is_parallel(data, width, row_len.max(1)) {
+            return data.par_chunks_mut(row_len.max(1)).for_each(|row| {
+                let n = len.min(row.len() / PIXEL_WIDTH * PIXEL_WIDTH);
+                f(&mut row[..n])
+            });
+        }
+    }
+    if len != 0 && row_len == len {
+        // no padding, so all rows can go in one pass
+        let n = data.len() / PIXEL_WIDTH * PIXEL_WIDTH;
+        return f(&mut data[..n]);
+    }
+    for row in data.chunks_mut(row_len.max(1)) {
+        let n = len.min(row.len() / PIXEL_WIDTH * PIXEL_WIDTH);
+        f(&mut row[..n]);
+    }
+}
+
+fn swap_r_b_pixels(px: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports AVX2.
+            return unsafe { x86::swap_r_b_avx2(px) };
+        }
+        if is_x86_feature_detected!("ssse3") {
+            // SAFETY: the CPU supports SSSE3.
+            return unsafe { x86::swap_r_b_ssse3(px) };
+        }
+    }
+    swap_r_b_scalar(px)
+}
+
+fn set_opaque_pixels(px: &mut [u8]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports AVX2.
+            return unsafe { x86::set_opaque_avx2(px) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            // SAFETY: the CPU supports SSE2.
+            return unsafe { x86::set_opaque_sse2(px) };
+        }
+    }
+    set_opaque_scalar(px)
+}
+
+fn swap_r_b_scalar(px: &mut [u8]) {
+    for px in px.chunks_exact_mut(PIXEL_WIDTH) {
+        px.swap(0, 2);
+    }
+}
+
+fn set_opaque_scalar(px: &mut [u8]) {
+    for px in px.chunks_exact_mut(PIXEL_WIDTH) {
+        px[3] = 255;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    /// Moves byte 2 of each pixel to 0 and back, within a 16-byte lane.
+    const SWAP_R_B: [i8; 16] = [2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15];
+    const ALPHA: i32 = 0xff00_0000_u32 as i32;
+
+    // The loads and stores below are unaligned, so any `chunks_exact_mut`
+    // chunk of the right size can be used as a vector.
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn swap_r_b_avx2(px: &mut [u8]) {
+        let lane = _mm_loadu_si128(SWAP_R_B.as_ptr() as *const __m128i);
+        let mask = _mm256_broadcastsi128_si256(lane);
+        let mut chunks = px.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            let p = chunk.as_mut_ptr() as *mut __m256i;
+            _mm256_storeu_si256(p, _mm256_shuffle_epi8(_mm256_loadu_si256(p), mask));
+        }
+        swap_r_b_ssse3(chunks.into_remainder());
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn swap_r_b_ssse3(px: &mut [u8]) {
+        let mask = _mm_loadu_si128(SWAP_R_B.as_ptr() as *const __m128i);
+        let mut chunks = px.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let p = chunk.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(p, _mm_shuffle_epi8(_mm_loadu_si128(p), mask));
+        }
+        super::swap_r_b_scalar(chunks.into_remainder());
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn set_opaque_avx2(px: &mut [u8]) {
+        let alpha = _mm256_set1_epi32(ALPHA);
+        let mut chunks = px.chunks_exact_mut(32);
+        for chunk in &mut chunks {
+            let p = chunk.as_mut_ptr() as *mut __m256i;
+            _mm256_storeu_si256(p, _mm256_or_si256(_mm256_loadu_si256(p), alpha));
+        }
+        set_opaque_sse2(chunks.into_remainder());
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn set_opaque_sse2(px: &mut [u8]) {
+        let alpha = _mm_set1_epi32(ALPHA);
+        let mut chunks = px.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let p = chunk.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(p, _mm_or_si128(_mm_loadu_si128(p), alpha));
+        }
+        super::set_opaque_scalar(chunks.into_remainder());
+    }
+}
+
+#[test]
+fn test_swap_r_b_rows() {
+    // 2 pixels per row, padded to 12 bytes
+    let mut data: Vec<u8> = (0..24).collect();
+    swap_r_b(&mut data, 2, 12);
+    assert_eq!(
+        data,
+        [2, 1, 0, 3, 6, 5, 4, 7, 8, 9, 10, 11, 14, 13, 12, 15, 18, 17, 16, 19, 20, 21, 22, 23]
+    );
+    set_opaque(&mut data, 2, 12);
+    assert_eq!(&data[..12], &[2, 1, 0, 255, 6, 5, 4, 255, 8, 9, 10, 11]);
+}
+
+#[test]
+fn test_map_rows() {
+    // both below and above the parallel threshold
+    for (width, height) in [(3, 2), (1024, 300)] {
+        let row_len = width * PIXEL_WIDTH + 4;
+        let src: Vec<u8> = (0..row_len * height).map(|i| i as u8).collect();
+        let mut dst = vec![0; width * height];
+        map_rows(&src, width, row_len, &mut dst, width, |src, dst| {
+            for (d, px) in dst.iter_mut().zip(src.chunks_exact(PIXEL_WIDTH)) {
+                *d = px[1];
+            }
+        });
+        let expected: Vec<u8> = src
+            .chunks(row_len)
+            .flat_map(|row| {
+                row[..width * PIXEL_WIDTH]
+                    .chunks(PIXEL_WIDTH)
+                    .map(|px| px[1])
+            })
+            .collect();
+        assert_eq!(dst, expected);
+
+        let mut data = src.clone();
+        swap_r_b(&mut data, width, row_len);
+        swap_r_b(&mut data, width, row_len);
+        assert_eq!(data, src);
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[test]
+fn test_simd_matches_scalar() {
+    // lengths around and between the vector widths, so both the vector
+    // loops and the scalar tails are exercised
+    for pixels in (0..20).chain([63, 64, 65, 1000, 1001]) {
+        let original: Vec<u8> = (0..pixels * PIXEL_WIDTH)
+            .map(|i| (i * 7 + i / 5) as u8)
+            .collect();
+        let run = |f: unsafe fn(&mut [u8])| {
+            let mut data = original.clone();
+            // SAFETY: only called after checking the CPU supports `f`.
+            unsafe { f(&mut data) };
+            data
+        };
+
+        let mut swapped = original.clone();
+        swap_r_b_scalar(&mut swapped);
+        let mut opaque = original.clone();
+        set_opaque_scalar(&mut opaque);
+
+        if is_x86_feature_detected!("ssse3") {
+            assert_eq!(run(x86::swap_r_b_ssse3), swapped, "{} pixels", pixels);
+        }
+        if is_x86_feature_detected!("sse2") {
+            assert_eq!(run(x86::set_opaque_sse2), opaque, "{} pixels", pixels);
+        }
+        if is_x86_feature_detected!("avx2") {
+            assert_eq!(run(x86::swap_r_b_avx2), swapped, "{} pixels", pixels);
+            assert_eq!(run(x86::set_opaque_avx2), opaque, "{} pixels", pixels);
+        }
+    }
+}
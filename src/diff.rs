@@ -0,0 +1,293 @@
+//! Finding what changed between two screenshots, e.g. to skip encoding
+//! frames that didn't change or to only send the parts that did.
+
+use crate::{convert::map_range, Rect, Screenshot, PIXEL_WIDTH};
+
+use std::ops::Range;
+
+/// How `Screenshot::diff_with` compares screenshots. The default finds
+/// every pixel whose colour differs, without a region list.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// How much each of red, green and blue may differ for a pixel to
+    /// still count as unchanged. Alpha isn't compared, as GDI leaves it
+    /// undefined.
+    pub tolerance: u8,
+    /// Size of the square tiles `DiffResult::regions` is made of. No
+    /// regions are listed if None.
+    pub tile_size: Option<u32>,
+}
+
+/// What changed between two screenshots, see `Screenshot::diff`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffResult {
+    /// Number of changed pixels.
+    pub changed_pixels: u64,
+    /// The smallest rectangle holding every changed pixel, in pixels from
+    /// the top left corner. None if nothing changed.
+    pub bounds: Option<Rect>,
+    /// The tiles of `DiffOptions::tile_size` holding changed pixels, with
+    /// neighbours in a row of tiles merged, top to bottom and left to
+    /// right. Clipped to the screenshots. Empty without a tile size.
+    pub regions: Vec<Rect>,
+}
+
+impl DiffResult {
+    /// Whether any pixel changed.
+    pub fn is_changed(&self) -> bool {
+        self.changed_pixels != 0
+    }
+}
+
+impl Screenshot {
+    /// The pixels whose colour differs between this screenshot and `other`,
+    /// see `diff_with`.
+    pub fn diff(&self, other: &Screenshot) -> DiffResult {
+        self.diff_with(other, &DiffOptions::default())
+    }
+
+    /// The pixels whose red, green or blue differ by more than
+    /// `options.tolerance` between this screenshot and `other`, which may
+    /// have another pixel format. Screenshots of different sizes are
+    /// compared where they overlap, and the pixels only one of them has
+    /// count as changed.
+    pub fn diff_with(&self, other: &Screenshot, options: &DiffOptions) -> DiffResult {
+        let (width, height) = (self.width.max(other.width), self.height.max(other.height));
+        let comparison = Comparison {
+            a_rows: self.rows().collect(),
+            b_rows: other.rows().collect(),
+            width,
+            tile: options.tile_size.map(|size| size.max(1) as usize),
+            offsets: (self.red_blue_offsets(), other.red_blue_offsets()),
+            // rows can be compared as bytes first
+            exact: self.format == other.format && options.tolerance == 0,
+            tolerance: options.tolerance,
+        };
+        // a row of tiles, or a row without them, is compared on its own
+        let band = comparison.tile.unwrap_or(1);
+        let bands = map_range(height.div_ceil(band), width.saturating_mul(height), |i| {
+            comparison.band(i * band..height.min((i + 1) * band))
+        });
+
+        let mut result = DiffResult::default();
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+        for band in bands {
+            result.changed_pixels += band.changed_pixels;
+            left = left.min(band.left);
+            top = top.min(band.top);
+            right = right.max(band.right);
+            bottom = bottom.max(band.bottom);
+            result.regions.extend(band.regions);
+        }
+        if result.changed_pixels != 0 {
+            result.bounds = Some(Rect {
+                x: left as i32,
+                y: top as i32,
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+            });
+        }
+        result
+    }
+}
+
+/// Two screenshots being compared by `Screenshot::diff_with`.
+struct Comparison<'a> {
+    a_rows: Vec<&'a [u8]>,
+    b_rows: Vec<&'a [u8]>,
+    /// The larger width of the two.
+    width: usize,
+    tile: Option<usize>,
+    /// Red and blue offsets of each.
+    offsets: ((usize, usize), (usize, usize)),
+    exact: bool,
+    tolerance: u8,
+}
+
+/// What changed in some rows, with the bounds as edges, empty if nothing
+/// did.
+struct Band {
+    changed_pixels: u64,
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+    regions: Vec<Rect>,
+}
+
+impl Comparison<'_> {
+    /// Compares `rows`, which are a row of tiles if there are tiles.
+    fn band(&self, rows: Range<usize>) -> Band {
+        let mut band = Band {
+            changed_pixels: 0,
+            left: usize::MAX,
+            top: usize::MAX,
+            right: 0,
+            bottom: 0,
+            regions: Vec::new(),
+        };
+        let mut tiles = vec![false; self.tile.map_or(0, |tile| self.width.div_ceil(tile))];
+        for y in rows.clone() {
+            let (a, b) = (
+                self.a_rows.get(y).copied().unwrap_or_default(),
+                self.b_rows.get(y).copied().unwrap_or_default(),
+            );
+            let overlap = a.len().min(b.len()) / PIXEL_WIDTH;
+            let mut mark = |x: usize| {
+                band.changed_pixels += 1;
+                band.left = band.left.min(x);
+                band.right = band.right.max(x + 1);
+                band.top = band.top.min(y);
+                band.bottom = y + 1;
+                if let Some(tile) = self.tile {
+                    tiles[x / tile] = true;
+                }
+            };
+            if !(self.exact && a[..overlap * PIXEL_WIDTH] == b[..overlap * PIXEL_WIDTH]) {
+                let pixels = a.chunks_exact(PIXEL_WIDTH).zip(b.chunks_exact(PIXEL_WIDTH));
+                for (x, (a, b)) in pixels.enumerate() {
+                    if !same_rgb(a, b, self.offsets, self.tolerance) {
+                        mark(x);
+                    }
+                }
+            }
+            // only one of them has the rest of the row
+            (overlap..a.len().max(b.len()) / PIXEL_WIDTH).for_each(mark);
+        }
+        if let Some(tile) = self.tile {
+            let (top, height) = (rows.start, rows.len());
+            push_runs(
+                &mut tiles,
+                tile,
+                (top, height),
+                self.width,
+                &mut band.regions,
+            );
+        }
+        band
+    }
+}
+
+/// Whether red, green and blue of pixels `a` and `b`, with the given red
+/// and blue offsets, differ by at most `tolerance`.
+fn same_rgb(
+    a: &[u8],
+    b: &[u8],
+    ((ar, ab), (br, bb)): ((usize, usize), (usize, usize)),
+    tolerance: u8,
+) -> bool {
+    a[ar].abs_diff(b[br]) <= tolerance
+        && a[1].abs_diff(b[1]) <= tolerance
+        && a[ab].abs_diff(b[bb]) <= tolerance
+}
+
+/// Appends the runs of changed `tiles` in a row of tiles at `top` that's
+/// `height` pixels high to `regions`, clipped to `width`, and clears them.
+fn push_runs(
+    tiles: &mut [bool],
+    tile: usize,
+    (top, height): (usize, usize),
+    width: usize,
+    regions: &mut Vec<Rect>,
+) {
+    let mut start = None;
+    for i in 0..=tiles.len() {
+        match (start, tiles.get(i).copied().unwrap_or(false)) {
+            (None, true) => start = Some(i),
+            (Some(first), false) => {
+                let x = first * tile;
+                regions.push(Rect {
+                    x: x as i32,
+                    y: top as i32,
+                    width: ((i * tile).min(width) - x) as u32,
+                    height: height as u32,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tiles.fill(false);
+}
+
+#[test]
+fn test_diff() {
+    let rect = |x, y, width, height| Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+    // 5x4 grey, rows padded to 24 bytes
+    let grey = Screenshot::from_raw([100; 24].repeat(4), 5, 4, 24).unwrap();
+    assert_eq!(grey.diff(&grey), DiffResult::default());
+    assert!(!grey.diff(&grey).is_changed());
+
+    // two pixels, one barely
+    let mut data = [100; 24].repeat(4);
+    data[24 + 4..][..3].copy_from_slice(&[0, 0, 0]);
+    data[3 * 24 + 3 * 4] = 103;
+    let changed = Screenshot::from_raw(data, 5, 4, 24).unwrap();
+    let result = grey.diff(&changed);
+    assert_eq!(result.changed_pixels, 2);
+    assert_eq!(result.bounds, Some(rect(1, 1, 3, 3)));
+    assert!(result.regions.is_empty());
+
+    let options = DiffOptions {
+        tolerance: 3,
+        tile_size: Some(2),
+    };
+    let result = grey.diff_with(&changed, &options);
+    assert_eq!(result.changed_pixels, 1);
+    assert_eq!(result.bounds, Some(rect(1, 1, 1, 1)));
+    assert_eq!(result.regions, [rect(0, 0, 2, 2)]);
+    let options = DiffOptions {
+        tolerance: 0,
+        ..options
+    };
+    assert_eq!(
+        grey.diff_with(&changed, &options).regions,
+        [rect(0, 0, 2, 2), rect(2, 2, 2, 2)]
+    );
+
+    // neighbouring tiles merge, and the last ones are clipped
+    let options = DiffOptions {
+        tolerance: 0,
+        tile_size: Some(3),
+    };
+    let white = Screenshot::from_raw([255; 24].repeat(4), 5, 4, 24).unwrap();
+    assert_eq!(
+        grey.diff_with(&white, &options).regions,
+        [rect(0, 0, 5, 3), rect(0, 3, 5, 1)]
+    );
+
+    // the same pixels in another format are unchanged
+    let mut swapped = Screenshot::from_raw(changed.data().to_vec(), 5, 4, 24).unwrap();
+    swapped.swap_r_b_in_place();
+    assert_eq!(changed.diff(&swapped), DiffResult::default());
+
+    // a larger screenshot differs where the smaller one has no pixels
+    let wide = Screenshot::from_raw([100; 28].repeat(4), 7, 4, 28).unwrap();
+    let result = grey.diff(&wide);
+    assert_eq!(result.changed_pixels, 2 * 4);
+    assert_eq!(result.bounds, Some(rect(5, 0, 2, 4)));
+    assert_eq!(wide.diff(&grey), result);
+
+    // frames large enough to be compared in parallel add up the same way
+    let (width, height) = (1024, 512);
+    let black =
+        Screenshot::from_raw(vec![0; width * height * 4], width, height, width * 4).unwrap();
+    let mut data = vec![0; width * height * 4];
+    for (x, y) in [(10, 10), (1000, 500)] {
+        data[(y * width + x) * 4] = 255;
+    }
+    let dotted = Screenshot::from_raw(data, width, height, width * 4).unwrap();
+    let options = DiffOptions {
+        tolerance: 0,
+        tile_size: Some(64),
+    };
+    let result = black.diff_with(&dotted, &options);
+    assert_eq!(result.changed_pixels, 2);
+    assert_eq!(result.bounds, Some(rect(10, 10, 991, 491)));
+    assert_eq!(result.regions, [rect(0, 0, 64, 64), rect(960, 448, 64, 64)]);
+}
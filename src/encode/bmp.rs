@@ -0,0 +1,94 @@
+//! Writing screenshots as 24-bit BMP files, which need no dependencies.
+
+use crate::Screenshot;
+
+use std::{fs, io, path::Path};
+
+const FILE_HEADER_LEN: usize = 14;
+const INFO_HEADER_LEN: usize = 40;
+
+impl Screenshot {
+    /// Encodes the screenshot as a 24-bit BMP image.
+    pub fn to_bmp(&self) -> Vec<u8> {
+        // BMP rows are padded to 4 bytes, which matters at odd widths.
+        let row_len = (self.width * 3).div_ceil(4) * 4;
+        let image_len = row_len * self.height;
+        let offset = FILE_HEADER_LEN + INFO_HEADER_LEN;
+
+        let mut out = Vec::with_capacity(offset + image_len);
+        out.extend_from_slice(b"BM");
+        out.extend_from_slice(&((offset + image_len) as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(offset as u32).to_le_bytes());
+
+        out.extend_from_slice(&(INFO_HEADER_LEN as u32).to_le_bytes());
+        out.extend_from_slice(&(self.width as i32).to_le_bytes());
+        // negative height: rows are stored top to bottom, like ours
+        out.extend_from_slice(&(-(self.height as i32)).to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes()); // planes
+        out.extend_from_slice(&24u16.to_le_bytes()); // bits per pixel
+        out.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+        out.extend_from_slice(&(image_len as u32).to_le_bytes());
+        out.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI
+        out.extend_from_slice(&2835i32.to_le_bytes());
+        out.extend_from_slice(&[0; 8]); // palette
+
+        self.packed_rgb_rows(true, 4, |row| out.extend_from_slice(row));
+        out
+    }
+
+    /// Saves the screenshot as a 24-bit BMP file.
+    pub fn save_bmp<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bmp())
+    }
+}
+
+#[cfg(test)]
+fn screenshot(width: usize, height: usize) -> Screenshot {
+    // pixel n is (b, g, r, a) = (n, n + 1, n + 2, 255)
+    let data = (0..width * height)
+        .flat_map(|n| {
+            let n = n as u8;
+            [n, n.wrapping_add(1), n.wrapping_add(2), 255]
+        })
+        .collect();
+    Screenshot::from_bgra(data, width, height, width * 4)
+}
+
+#[test]
+fn test_packed_rgb_rows() {
+    for &(width, padded) in &[(1, 4), (2, 8), (3, 12), (5, 16)] {
+        let s = screenshot(width, 2);
+        let mut rows = Vec::new();
+        s.packed_rgb_rows(true, 4, |row| rows.push(row.to_vec()));
+        assert_eq!(rows.len(), 2);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row.len(), padded, "width {}", width);
+            for x in 0..width {
+                let n = (y * width + x) as u8;
+                assert_eq!(&row[x * 3..x * 3 + 3], &[n, n + 1, n + 2]);
+            }
+            assert!(row[width * 3..].iter().all(|&b| b == 0));
+        }
+    }
+
+    let rgb = screenshot(3, 1).to_rgb_vec();
+    assert_eq!(rgb, [2, 1, 0, 3, 2, 1, 4, 3, 2]);
+}
+
+#[test]
+fn test_bmp() {
+    for &width in &[1, 2, 3, 5] {
+        let bmp = screenshot(width, 3).to_bmp();
+        let row_len = (width * 3).div_ceil(4) * 4;
+        assert_eq!(bmp.len(), 54 + row_len * 3);
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(
+            u32::from_le_bytes([bmp[2], bmp[3], bmp[4], bmp[5]]) as usize,
+            bmp.len()
+        );
+        // first pixel of the second row starts at a 4-byte boundary
+        let n = width as u8;
+        assert_eq!(&bmp[54 + row_len..54 + row_len + 3], &[n, n + 1, n + 2]);
+    }
+}
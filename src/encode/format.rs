@@ -0,0 +1,126 @@
+//! Encoding to a format picked at runtime, e.g. from a file name.
+
+use crate::Screenshot;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// An image file format screenshots can be encoded in. PNG needs the `png`
+/// feature and JPEG the `jpeg` feature; without them, encoding fails with
+/// `io::ErrorKind::Unsupported`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 8-bit RGBA, lossless.
+    Png,
+    /// 24-bit, uncompressed.
+    Bmp,
+    /// Lossy, without alpha, at a quality from 1 to 100.
+    Jpeg { quality: u8 },
+}
+
+impl ImageFormat {
+    /// The format whose usual extension `path` has, ignoring case. JPEG is
+    /// picked at quality 90.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match &extension[..] {
+            "png" => Some(ImageFormat::Png),
+            "bmp" => Some(ImageFormat::Bmp),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg { quality: 90 }),
+            _ => None,
+        }
+    }
+
+    /// File extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Jpeg { .. } => "jpg",
+        }
+    }
+}
+
+impl Screenshot {
+    /// Encodes the screenshot as `format`, see `to_png`, `to_bmp` and
+    /// `to_jpeg`.
+    pub fn encode(&self, format: ImageFormat) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write_encoded(&mut out, format)?;
+        Ok(out)
+    }
+
+    /// Encodes the screenshot as `format` into `w`.
+    pub fn write_encoded<W: Write>(&self, mut w: W, format: ImageFormat) -> io::Result<()> {
+        match format {
+            #[cfg(feature = "png")]
+            ImageFormat::Png => self.write_png(w),
+            #[cfg(not(feature = "png"))]
+            ImageFormat::Png => Err(disabled("png")),
+            ImageFormat::Bmp => w.write_all(&self.to_bmp()),
+            #[cfg(feature = "jpeg")]
+            ImageFormat::Jpeg { quality } => self.write_jpeg(w, quality),
+            #[cfg(not(feature = "jpeg"))]
+            ImageFormat::Jpeg { .. } => Err(disabled("jpeg")),
+        }
+    }
+
+    /// Saves the screenshot as a `format` file. The file is only created
+    /// if the format's feature is enabled.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: ImageFormat) -> io::Result<()> {
+        match format {
+            ImageFormat::Png if !cfg!(feature = "png") => return Err(disabled("png")),
+            ImageFormat::Jpeg { .. } if !cfg!(feature = "jpeg") => return Err(disabled("jpeg")),
+            _ => {}
+        }
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        self.write_encoded(&mut file, format)?;
+        file.flush()
+    }
+}
+
+/// The error for encoding to a format whose `feature` is disabled.
+fn disabled(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("the `{}` feature is disabled", feature),
+    )
+}
+
+#[test]
+fn test_image_format() {
+    assert_eq!(ImageFormat::from_path("a/b.PNG"), Some(ImageFormat::Png));
+    assert_eq!(
+        ImageFormat::from_path("shot.jpeg"),
+        Some(ImageFormat::Jpeg { quality: 90 })
+    );
+    assert_eq!(ImageFormat::from_path("shot"), None);
+    assert_eq!(ImageFormat::from_path("shot.gif"), None);
+    for format in [
+        ImageFormat::Png,
+        ImageFormat::Bmp,
+        ImageFormat::Jpeg { quality: 1 },
+    ] {
+        assert_eq!(
+            ImageFormat::from_path(format!("x.{}", format.extension())).map(|f| f.extension()),
+            Some(format.extension())
+        );
+    }
+
+    let s = Screenshot::from_raw(vec![1, 2, 3, 255], 1, 1, 4).unwrap();
+    assert_eq!(s.encode(ImageFormat::Bmp).unwrap(), s.to_bmp());
+    let png = s.encode(ImageFormat::Png);
+    let jpeg = s.encode(ImageFormat::Jpeg { quality: 90 });
+    assert_eq!(png.is_ok(), cfg!(feature = "png"));
+    assert_eq!(jpeg.is_ok(), cfg!(feature = "jpeg"));
+    if let Err(e) = jpeg {
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        // nothing is left behind
+        let path = std::env::temp_dir().join("screenshot-test-disabled.jpg");
+        assert!(s.save(&path, ImageFormat::Jpeg { quality: 90 }).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,63 @@
+//! Writing screenshots as JPEG files, with the `jpeg` feature.
+
+use crate::Screenshot;
+
+use jpeg_encoder::{ColorType, Encoder};
+
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+impl Screenshot {
+    /// Encodes the screenshot as a JPEG image of the given `quality`, from
+    /// 1 to 100. JPEG has no alpha channel, so alpha is dropped. Fails with
+    /// `InvalidInput` for images over 65535 pixels wide or high.
+    pub fn to_jpeg(&self, quality: u8) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write_jpeg(&mut out, quality)?;
+        Ok(out)
+    }
+
+    /// Encodes the screenshot as a JPEG image into `w`, see `to_jpeg`.
+    pub fn write_jpeg<W: Write>(&self, w: W, quality: u8) -> io::Result<()> {
+        let (width, height) = match (u16::try_from(self.width), u16::try_from(self.height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "JPEG images are at most 65535 pixels wide and high",
+                ))
+            }
+        };
+        Encoder::new(w, quality.clamp(1, 100))
+            .encode(&self.to_rgb_vec(), width, height, ColorType::Rgb)
+            .map_err(io::Error::other)
+    }
+
+    /// Saves the screenshot as a JPEG file, see `to_jpeg`.
+    pub fn save_jpeg<P: AsRef<Path>>(&self, path: P, quality: u8) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        self.write_jpeg(&mut file, quality)?;
+        file.flush()
+    }
+}
+
+#[test]
+fn test_jpeg() {
+    let s = Screenshot::from_raw(vec![0, 0, 255, 255].repeat(16 * 8), 16, 8, 16 * 4).unwrap();
+    let jpeg = s.to_jpeg(90).unwrap();
+    let img = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+    assert_eq!(img.dimensions(), (16, 8));
+    // lossy, but a solid colour comes out close
+    let px = img.get_pixel(3, 3).0;
+    assert!(px[0] > 240 && px[1] < 15 && px[2] < 15, "{:?}", px);
+
+    let wide = Screenshot::from_raw(vec![0; 65536 * 4], 65536, 1, 65536 * 4).unwrap();
+    assert_eq!(
+        wide.to_jpeg(90).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+}
@@ -0,0 +1,14 @@
+//! Turning screenshots into image files: BMP always, PNG with the `png`
+//! feature and JPEG with the `jpeg` feature. Encoding can run in the
+//! background, see `Job` and `EncodePool`.
+
+mod bmp;
+mod format;
+#[cfg(feature = "jpeg")]
+mod jpeg;
+#[cfg(feature = "png")]
+mod png_encoder;
+
+pub use format::ImageFormat;
+#[cfg(feature = "png")]
+pub use png_encoder::PngJob;
@@ -0,0 +1,100 @@
+//! Writing screenshots as PNG files, with the `png` feature.
+
+use crate::{job::Job, EncodePool, Screenshot};
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// A PNG being encoded in the background.
+pub type PngJob = Job<Vec<u8>>;
+
+impl Screenshot {
+    /// Encodes the screenshot as an 8-bit RGBA PNG image. Rows are
+    /// converted and compressed one at a time, so apart from the output no
+    /// buffer of the image's size is allocated.
+    pub fn to_png(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write_png(&mut out)?;
+        Ok(out)
+    }
+
+    /// Encodes the screenshot as an 8-bit RGBA PNG image into `w`, one row
+    /// at a time.
+    pub fn write_png<W: Write>(&self, w: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut stream = encoder
+            .write_header()
+            .and_then(|writer| writer.into_stream_writer())
+            .map_err(io::Error::other)?;
+        self.try_packed_rgba_rows(|row| stream.write_all(row))?;
+        stream.finish().map_err(io::Error::other)
+    }
+
+    /// Saves the screenshot as an 8-bit RGBA PNG file.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        self.write_png(&mut file)?;
+        file.flush()
+    }
+
+    /// Encodes the screenshot as a PNG on a thread of its own, which takes
+    /// tens of milliseconds for a full screen. Errors are returned by
+    /// `PngJob::join`. Use `EncodePool::encode_png` to bound the number of
+    /// threads.
+    pub fn encode_png_async(self) -> PngJob {
+        Job::spawn(move || self.to_png())
+    }
+
+    /// Like `save_png`, but on a thread of its own.
+    pub fn save_png_async<P: AsRef<Path>>(self, path: P) -> Job<()> {
+        let path = path.as_ref().to_owned();
+        Job::spawn(move || self.save_png(path))
+    }
+}
+
+impl EncodePool {
+    /// Encodes `screenshot` as a PNG on the next free worker.
+    pub fn encode_png(&self, screenshot: Screenshot) -> PngJob {
+        self.spawn(move || screenshot.to_png())
+    }
+
+    /// Saves `screenshot` as a PNG file on the next free worker.
+    pub fn save_png<P: AsRef<Path>>(&self, screenshot: Screenshot, path: P) -> Job<()> {
+        let path = path.as_ref().to_owned();
+        self.spawn(move || screenshot.save_png(path))
+    }
+}
+
+#[test]
+fn test_png() {
+    // 2x2 pixels, BGRA, rows padded to 12 bytes
+    let data = vec![
+        0, 0, 255, 255, 0, 255, 0, 255, 0, 0, 0, 0, //
+        255, 0, 0, 255, 1, 2, 3, 4, 0, 0, 0, 0,
+    ];
+    let s = Screenshot::from_raw(data, 2, 2, 12).unwrap();
+    let check = |png: Vec<u8>| {
+        let img = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (2, 2));
+        assert_eq!(
+            img.into_raw(),
+            [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 3, 2, 1, 4]
+        );
+    };
+    check(s.to_png().unwrap());
+
+    let pool = EncodePool::new(1).unwrap();
+    let job = pool.encode_png(Screenshot::from_raw(s.data().to_vec(), 2, 2, 12).unwrap());
+    check(job.join().unwrap());
+    check(s.encode_png_async().join().unwrap());
+
+    // write errors come back through `join`
+    let s = Screenshot::from_raw(vec![0; 4], 1, 1, 4).unwrap();
+    let missing_dir = std::env::temp_dir().join("screenshot-missing-dir/out.png");
+    assert!(s.save_png_async(missing_dir).join().is_err());
+}
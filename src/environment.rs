@@ -0,0 +1,201 @@
+//! Detection of sessions in which captures come out wrong rather than
+//! failing, e.g. black frames from a minimized RDP client. Only Windows is
+//! queried; the types build everywhere.
+
+#[cfg(windows)]
+use windows::{
+    core::PWSTR,
+    Win32::Foundation::HANDLE,
+    Win32::System::RemoteDesktop::*,
+    Win32::System::StationsAndDesktops::{
+        CloseDesktop, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS,
+        DESKTOP_READOBJECTS, UOI_NAME,
+    },
+    Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_CMONITORS, SM_REMOTESESSION},
+};
+
+#[cfg(windows)]
+use core::ffi::c_void;
+#[cfg(windows)]
+use std::ptr;
+
+/// Connection state of the current session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+    /// A user is logged on and connected.
+    Active,
+    /// The session has no client attached, e.g. a closed RDP window.
+    Disconnected,
+    /// Any other state, e.g. while connecting or shadowing.
+    Other,
+    /// The state couldn't be queried.
+    Unknown,
+}
+
+/// The conditions captures are taken in, as returned by
+/// `capture_environment`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureEnvironment {
+    /// The process runs in a remote desktop session. Captures then have the
+    /// client's resolution.
+    pub remote_session: bool,
+    pub session_state: SessionState,
+    /// Whether the session is locked, if Windows tells us.
+    pub locked: Option<bool>,
+    /// Number of monitors attached to the desktop.
+    pub monitor_count: i32,
+}
+
+impl CaptureEnvironment {
+    /// Whether captures would likely be black or otherwise not show what a
+    /// user would see: the session is disconnected or locked, or there's no
+    /// monitor to capture.
+    pub fn is_degraded(&self) -> bool {
+        self.session_state == SessionState::Disconnected
+            || self.locked == Some(true)
+            || self.monitor_count <= 0
+    }
+}
+
+/// Reports whether the current session is remote, disconnected or locked.
+#[cfg(windows)]
+pub fn capture_environment() -> CaptureEnvironment {
+    unsafe {
+        CaptureEnvironment {
+            remote_session: GetSystemMetrics(SM_REMOTESESSION) != 0,
+            session_state: session_state(),
+            locked: session_locked(),
+            monitor_count: GetSystemMetrics(SM_CMONITORS),
+        }
+    }
+}
+
+/// Whether input currently goes to a desktop other than the user's, i.e.
+/// the secure desktop of a UAC prompt or the lock screen. Captures then fail
+/// or come out black.
+#[cfg(windows)]
+pub fn secure_desktop_active() -> bool {
+    unsafe {
+        // The secure desktop can't be opened by user processes at all.
+        let desktop = match OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS) {
+            Ok(desktop) => desktop,
+            Err(_) => return true,
+        };
+        let mut name = [0u16; 64];
+        let mut needed = 0;
+        let ok = GetUserObjectInformationW(
+            HANDLE(desktop.0),
+            UOI_NAME,
+            Some(name.as_mut_ptr() as *mut c_void),
+            (name.len() * 2) as u32,
+            Some(&mut needed),
+        );
+        CloseDesktop(desktop);
+        ok.as_bool() && !is_default_desktop(&name)
+    }
+}
+
+/// Whether the NUL-terminated desktop `name` is the interactive user's.
+#[cfg(windows)]
+fn is_default_desktop(name: &[u16]) -> bool {
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    String::from_utf16_lossy(&name[..len]).eq_ignore_ascii_case("Default")
+}
+
+/// Queries `class` about the current session, handing the returned buffer
+/// to `read` before freeing it.
+#[cfg(windows)]
+unsafe fn query_session<T>(
+    class: WTS_INFO_CLASS,
+    read: impl FnOnce(*const c_void, u32) -> Option<T>,
+) -> Option<T> {
+    let mut buffer = PWSTR(ptr::null_mut());
+    let mut len = 0;
+    let ok = WTSQuerySessionInformationW(
+        WTS_CURRENT_SERVER_HANDLE,
+        WTS_CURRENT_SESSION,
+        class,
+        &mut buffer,
+        &mut len,
+    );
+    if !ok.as_bool() || buffer.0.is_null() {
+        return None;
+    }
+    let res = read(buffer.0 as *const c_void, len);
+    WTSFreeMemory(buffer.0 as *mut c_void);
+    res
+}
+
+#[cfg(windows)]
+unsafe fn session_state() -> SessionState {
+    query_session(WTSConnectState, |buffer, len| {
+        if (len as usize) < std::mem::size_of::<WTS_CONNECTSTATE_CLASS>() {
+            return None;
+        }
+        let state = *(buffer as *const WTS_CONNECTSTATE_CLASS);
+        Some(if state == WTSActive {
+            SessionState::Active
+        } else if state == WTSDisconnected {
+            SessionState::Disconnected
+        } else {
+            SessionState::Other
+        })
+    })
+    .unwrap_or(SessionState::Unknown)
+}
+
+#[cfg(windows)]
+unsafe fn session_locked() -> Option<bool> {
+    query_session(WTSSessionInfoEx, |buffer, len| {
+        if (len as usize) < std::mem::size_of::<WTSINFOEXW>() {
+            return None;
+        }
+        let info = &*(buffer as *const WTSINFOEXW);
+        if info.Level != 1 {
+            return None;
+        }
+        // Windows 7 reports these two the wrong way round; it's out of
+        // support, so we don't try to detect it.
+        match info.Data.WTSInfoExLevel1.SessionFlags as u32 {
+            WTS_SESSIONSTATE_LOCK => Some(true),
+            WTS_SESSIONSTATE_UNLOCK => Some(false),
+            _ => None,
+        }
+    })
+}
+
+#[test]
+fn test_is_degraded() {
+    let env = CaptureEnvironment {
+        remote_session: true,
+        session_state: SessionState::Active,
+        locked: None,
+        monitor_count: 1,
+    };
+    // a remote session on its own captures fine
+    assert!(!env.is_degraded());
+    assert!(CaptureEnvironment {
+        session_state: SessionState::Disconnected,
+        ..env.clone()
+    }
+    .is_degraded());
+    assert!(CaptureEnvironment {
+        locked: Some(true),
+        ..env.clone()
+    }
+    .is_degraded());
+    assert!(CaptureEnvironment {
+        monitor_count: 0,
+        ..env
+    }
+    .is_degraded());
+}
+
+#[test]
+#[cfg(windows)]
+fn test_is_default_desktop() {
+    let wide = |s: &str| s.encode_utf16().chain([0, 0]).collect::<Vec<_>>();
+    assert!(is_default_desktop(&wide("Default")));
+    assert!(!is_default_desktop(&wide("Winlogon")));
+    assert!(!is_default_desktop(&wide("")));
+}
@@ -0,0 +1,287 @@
+//! Errors returned by captures, whichever backend they come from.
+
+use crate::{Backend, CaptureEnvironment, Point, Rect, Size, WindowId};
+
+use std::{error::Error, fmt, path::PathBuf, time::Duration};
+
+/// Errors returned when capturing a screenshot.
+#[derive(Debug)]
+pub enum ScreenshotError {
+    /// The display reported a zero or negative size, e.g. a disconnected RDP
+    /// session or a headless VM.
+    EmptyDisplay { width: i32, height: i32 },
+    /// `BitBlt` failed to copy the screen into the memory bitmap.
+    BitBltFailed,
+    /// A UAC prompt or the lock screen has the input desktop, so the user's
+    /// desktop can't be captured. Retrying shortly after usually works.
+    SecureDesktopActive,
+    /// The display resolution changed while the screenshot was being taken,
+    /// so the copied pixels don't match the recorded size.
+    DisplayChanged {
+        before: (i32, i32),
+        after: (i32, i32),
+    },
+    /// A buffer passed to `Screenshot::from_raw` doesn't match the given
+    /// dimensions.
+    InvalidBuffer {
+        width: usize,
+        height: usize,
+        row_len: usize,
+        len: usize,
+    },
+    /// The system metrics and the screen DC disagree about the resolution.
+    InconsistentDisplayMetrics {
+        /// Size from `GetSystemMetrics`.
+        metrics: (i32, i32),
+        /// Size from `GetDeviceCaps(HORZRES/VERTRES)`.
+        device_caps: (i32, i32),
+    },
+    /// The display is larger than `CaptureOptions::max_dimension`, or too
+    /// large for its bitmap to be addressed on this target.
+    DimensionsTooLarge { width: usize, height: usize },
+    /// Another GDI call failed; the name of the call is attached.
+    GdiFailed(&'static str),
+    /// `GetDIBits` failed, or wrote pixels in a layout we didn't ask for.
+    GetDIBitsFailed,
+    /// A CoreGraphics call failed; the name of the call is attached.
+    CoreGraphicsFailed(&'static str),
+    /// The user hasn't allowed the app to record the screen, see
+    /// `backend::macos::MacBackend::request_access`.
+    ScreenRecordingDenied,
+    /// There's no default backend for this platform, see
+    /// `backend::Unsupported`.
+    UnsupportedPlatform,
+    /// The backend asked for in `CaptureOptions::backend` can't capture
+    /// here, or for `Backend::Auto` none can; the probe's reason is
+    /// attached.
+    BackendUnavailable { backend: Backend, reason: String },
+    /// `CaptureOptions` asks for things that don't go together, e.g. a
+    /// window and a monitor; what's wrong is attached.
+    InvalidOptions(&'static str),
+    /// The requested region is empty or lies outside the virtual screen,
+    /// or, for `Screenshot::view`, outside the screenshot.
+    InvalidRegion(Rect),
+    /// No monitor shows the point passed to `get_color_at`.
+    PointOffScreen(Point),
+    /// No monitors could be enumerated.
+    NoMonitors,
+    /// There's no monitor at this index of `monitors()`.
+    NoSuchMonitor(usize),
+    /// The window to capture was closed.
+    NoSuchWindow(WindowId),
+    /// Talking to the X server failed, e.g. because `$DISPLAY` isn't set;
+    /// the message of the underlying error is attached.
+    X11Failed(String),
+    /// Setting up or reading a DXGI desktop duplication failed; the call
+    /// and the error are attached.
+    DxgiFailed(String),
+    /// Setting up or reading a Windows.Graphics.Capture session failed; the
+    /// call and the error are attached.
+    GraphicsCaptureFailed(String),
+    /// The display's pixels are in a format that can't be converted to BGRA.
+    UnsupportedPixelFormat { depth: u32, bits_per_pixel: u32 },
+    /// Opening, querying or mapping a framebuffer device failed; the device
+    /// and the error are attached.
+    FramebufferFailed(String),
+    /// We may not read the framebuffer device at this path, usually because
+    /// the user isn't in the `video` group.
+    FramebufferAccessDenied(PathBuf),
+    /// There's no xdg-desktop-portal with screencasts on the session bus,
+    /// or no session bus at all; the message of the underlying error is
+    /// attached.
+    NoPortal(String),
+    /// The user declined to share the screen in the portal's dialog.
+    PortalDenied,
+    /// A request to the portal failed.
+    PortalFailed(String),
+    /// The PipeWire stream of a screencast couldn't be read, or ended, e.g.
+    /// because the user stopped sharing.
+    PipeWireFailed(String),
+    /// The session is in a state where captures would come out black, see
+    /// `CaptureOptions::fail_on_degraded`.
+    DegradedEnvironment(CaptureEnvironment),
+    /// The capture didn't finish within the given time.
+    Timeout(Duration),
+    /// The thread running the capture couldn't be started or panicked.
+    CaptureThreadFailed,
+    /// The callback of `Capturer::on_frame` panicked, with this message.
+    CallbackPanicked(String),
+    /// The hotkey passed to `HotkeyCapture::register` is registered by
+    /// this or another app already.
+    HotkeyInUse,
+    /// Setting up a `HotkeyCapture` failed; the name of the call is
+    /// attached.
+    HotkeyFailed(&'static str),
+    /// Consecutive captures of a scrolling view had too little in common
+    /// to be stitched, e.g. because it scrolled by more than its height.
+    NoScrollOverlap,
+    /// The screenshot carries no capture metadata, e.g. because it was made
+    /// with `Screenshot::from_raw`.
+    NoMetadata,
+    /// A `CaptureProfile` couldn't be read or written; the message names
+    /// the offending field or position in the file.
+    InvalidConfig(String),
+    /// The operation was cancelled through its `CancelToken`.
+    Cancelled,
+    /// Two screenshots that must be of the same size aren't, e.g. in
+    /// `Screenshot::blend`.
+    SizeMismatch { expected: Size, actual: Size },
+    /// Scrolling a window for `capture_scrolling` failed; the name of the
+    /// call is attached.
+    ScrollFailed(&'static str),
+    /// Every attempt allowed by the `RetryPolicy` failed.
+    RetriesExhausted {
+        attempts: u32,
+        last: Box<ScreenshotError>,
+    },
+}
+
+impl ScreenshotError {
+    /// Whether trying again shortly after may succeed, e.g. during secure
+    /// desktop transitions, fullscreen mode switches and driver resets.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ScreenshotError::BitBltFailed
+                | ScreenshotError::DisplayChanged { .. }
+                | ScreenshotError::SecureDesktopActive
+        )
+    }
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::EmptyDisplay { width, height } => {
+                write!(f, "Display has no pixels ({} x {})", width, height)
+            }
+            ScreenshotError::BitBltFailed => write!(f, "Failed to copy screen to Windows buffer"),
+            ScreenshotError::SecureDesktopActive => {
+                write!(f, "The secure desktop (UAC or lock screen) is active")
+            }
+            ScreenshotError::DisplayChanged { before, after } => write!(
+                f,
+                "Display changed from {} x {} to {} x {} during capture",
+                before.0, before.1, after.0, after.1
+            ),
+            ScreenshotError::InvalidBuffer {
+                width,
+                height,
+                row_len,
+                len,
+            } => write!(
+                f,
+                "Buffer of {} bytes doesn't hold {} x {} pixels with {} bytes per row",
+                len, width, height, row_len
+            ),
+            ScreenshotError::InconsistentDisplayMetrics {
+                metrics,
+                device_caps,
+            } => write!(
+                f,
+                "Display metrics report {} x {} but the screen DC reports {} x {}",
+                metrics.0, metrics.1, device_caps.0, device_caps.1
+            ),
+            ScreenshotError::DimensionsTooLarge { width, height } => {
+                write!(
+                    f,
+                    "Display of {} x {} is too large to capture",
+                    width, height
+                )
+            }
+            ScreenshotError::GdiFailed(call) => write!(f, "{} failed", call),
+            ScreenshotError::GetDIBitsFailed => write!(f, "Failed to read the Windows bitmap"),
+            ScreenshotError::CoreGraphicsFailed(call) => write!(f, "{} failed", call),
+            ScreenshotError::ScreenRecordingDenied => {
+                write!(f, "Screen recording isn't allowed for this app")
+            }
+            ScreenshotError::UnsupportedPlatform => {
+                write!(f, "Screen capture isn't supported on this platform")
+            }
+            ScreenshotError::BackendUnavailable { backend, reason } => {
+                write!(
+                    f,
+                    "The {} backend is unavailable: {}",
+                    backend.name(),
+                    reason
+                )
+            }
+            ScreenshotError::InvalidOptions(msg) => write!(f, "Invalid capture options: {}", msg),
+            ScreenshotError::InvalidRegion(r) => write!(
+                f,
+                "Region {} x {} at ({}, {}) is outside the screen",
+                r.width, r.height, r.x, r.y
+            ),
+            ScreenshotError::PointOffScreen(p) => {
+                write!(f, "Point ({}, {}) is outside every monitor", p.x, p.y)
+            }
+            ScreenshotError::NoMonitors => write!(f, "No monitors found"),
+            ScreenshotError::NoSuchMonitor(index) => write!(f, "No monitor at index {}", index),
+            ScreenshotError::NoSuchWindow(window) => write!(f, "No window {:?}", window),
+            ScreenshotError::X11Failed(msg) => write!(f, "X11 request failed: {}", msg),
+            ScreenshotError::DxgiFailed(msg) => write!(f, "Desktop duplication failed: {}", msg),
+            ScreenshotError::GraphicsCaptureFailed(msg) => {
+                write!(f, "Windows.Graphics.Capture failed: {}", msg)
+            }
+            ScreenshotError::UnsupportedPixelFormat {
+                depth,
+                bits_per_pixel,
+            } => write!(
+                f,
+                "Unsupported pixel format (depth {}, {} bits per pixel)",
+                depth, bits_per_pixel
+            ),
+            ScreenshotError::FramebufferFailed(msg) => write!(f, "Framebuffer failed: {}", msg),
+            ScreenshotError::FramebufferAccessDenied(path) => {
+                write!(f, "No permission to read {}", path.display())
+            }
+            ScreenshotError::NoPortal(msg) => write!(f, "No screencast portal available: {}", msg),
+            ScreenshotError::PortalDenied => write!(f, "Screen sharing was declined"),
+            ScreenshotError::PortalFailed(msg) => write!(f, "Portal request failed: {}", msg),
+            ScreenshotError::PipeWireFailed(msg) => write!(f, "PipeWire stream failed: {}", msg),
+            ScreenshotError::DegradedEnvironment(env) => write!(
+                f,
+                "Session can't be captured ({:?}, locked: {:?}, monitors: {})",
+                env.session_state, env.locked, env.monitor_count
+            ),
+            ScreenshotError::Timeout(timeout) => {
+                write!(f, "Capture didn't finish within {:?}", timeout)
+            }
+            ScreenshotError::CaptureThreadFailed => write!(f, "Capture thread failed"),
+            ScreenshotError::CallbackPanicked(msg) => write!(f, "Frame callback panicked: {}", msg),
+            ScreenshotError::HotkeyInUse => write!(f, "The hotkey is registered already"),
+            ScreenshotError::HotkeyFailed(call) => {
+                write!(f, "Registering the hotkey failed: {} failed", call)
+            }
+            ScreenshotError::NoScrollOverlap => {
+                write!(
+                    f,
+                    "Consecutive captures of the scrolling view don't overlap"
+                )
+            }
+            ScreenshotError::NoMetadata => write!(f, "The screenshot has no capture metadata"),
+            ScreenshotError::InvalidConfig(msg) => write!(f, "Invalid capture profile: {}", msg),
+            ScreenshotError::Cancelled => write!(f, "The operation was cancelled"),
+            ScreenshotError::SizeMismatch { expected, actual } => write!(
+                f,
+                "Expected a {} x {} screenshot, got {} x {}",
+                expected.width, expected.height, actual.width, actual.height
+            ),
+            ScreenshotError::ScrollFailed(call) => {
+                write!(f, "Scrolling the window failed: {} failed", call)
+            }
+            ScreenshotError::RetriesExhausted { attempts, last } => {
+                write!(f, "Capture failed after {} attempts: {}", attempts, last)
+            }
+        }
+    }
+}
+
+impl Error for ScreenshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScreenshotError::RetriesExhausted { last, .. } => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
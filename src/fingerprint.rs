@@ -0,0 +1,122 @@
+//! Cheap hashes of the pixels, to tell unchanged frames apart without
+//! comparing them byte by byte.
+
+use crate::{Screenshot, PIXEL_WIDTH};
+
+use std::convert::TryInto;
+
+/// Multiplier of the Fx hash used by rustc, which mixes well enough for
+/// telling frames apart and handles 8 bytes per step.
+const K: u64 = 0x517c_c1b7_2722_0a95;
+
+impl Screenshot {
+    /// A 64-bit hash of the pixels and dimensions. Equal screenshots always
+    /// have the same fingerprint, so it can be compared against the previous
+    /// frame's to skip unchanged ones. Row padding is ignored. Not suitable
+    /// against deliberate collisions.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint_sampled(1)
+    }
+
+    /// Like `fingerprint`, but hashes only every `row_step`th row, starting
+    /// with the first, which is about `row_step` times faster. A change
+    /// confined to the skipped rows, e.g. a blinking cursor, goes unnoticed:
+    /// the frames hash the same although they differ. Only use it where
+    /// missing such small changes is acceptable.
+    pub fn fingerprint_sampled(&self, row_step: usize) -> u64 {
+        let len = self.width * PIXEL_WIDTH;
+        let rows = self
+            .data
+            .chunks(self.row_len.max(1))
+            .take(self.height)
+            .step_by(row_step.max(1))
+            .map(|row| &row[..len.min(row.len())]);
+        fingerprint_rows(self.width, self.height, rows)
+    }
+}
+
+/// Hashes the dimensions and `rows`, each the pixels of a row without
+/// padding.
+pub(crate) fn fingerprint_rows<'a>(
+    width: usize,
+    height: usize,
+    rows: impl Iterator<Item = &'a [u8]>,
+) -> u64 {
+    let mut h = hash_word(0, width as u64);
+    h = hash_word(h, height as u64);
+    for row in rows {
+        h = hash_bytes(h, row);
+    }
+    finish(h)
+}
+
+/// Hash of a single row of pixels, or any other bytes.
+pub(crate) fn hash_row(row: &[u8]) -> u64 {
+    finish(hash_bytes(0, row))
+}
+
+fn hash_word(h: u64, word: u64) -> u64 {
+    (h.rotate_left(5) ^ word).wrapping_mul(K)
+}
+
+fn hash_bytes(mut h: u64, bytes: &[u8]) -> u64 {
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+        h = hash_word(h, u64::from_le_bytes(word.try_into().unwrap()));
+    }
+    let rest = words.remainder();
+    if !rest.is_empty() {
+        let mut word = [0; 8];
+        word[..rest.len()].copy_from_slice(rest);
+        h = hash_word(h, u64::from_le_bytes(word));
+    }
+    h
+}
+
+/// Spreads every input bit over the whole hash, as the Fx steps alone
+/// leave the low bits weak.
+fn finish(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+#[test]
+fn test_fingerprint() {
+    // 3x4 pixels, rows padded to 16 bytes
+    let data: Vec<u8> = (0..64).collect();
+    let s = Screenshot::from_raw(data.clone(), 3, 4, 16).unwrap();
+    let same = Screenshot::from_raw(data.clone(), 3, 4, 16).unwrap();
+    assert_eq!(s.fingerprint(), s.fingerprint());
+    assert_eq!(s.fingerprint(), same.fingerprint());
+
+    // padding is ignored
+    let mut padded = data.clone();
+    padded[15] = 0xff;
+    let padded = Screenshot::from_raw(padded, 3, 4, 16).unwrap();
+    assert_eq!(s.fingerprint(), padded.fingerprint());
+
+    // any pixel byte counts
+    for i in [0, 7, 11, 16 + 5, 48 + 11] {
+        let mut changed = data.clone();
+        changed[i] ^= 1;
+        let changed = Screenshot::from_raw(changed, 3, 4, 16).unwrap();
+        assert_ne!(s.fingerprint(), changed.fingerprint(), "byte {}", i);
+    }
+
+    // so do the dimensions
+    let reshaped = Screenshot::from_raw(data.clone(), 4, 4, 16).unwrap();
+    assert_ne!(s.fingerprint(), reshaped.fingerprint());
+
+    // sampling misses changes in skipped rows only
+    let mut odd_row = data.clone();
+    odd_row[16] ^= 1;
+    let odd_row = Screenshot::from_raw(odd_row, 3, 4, 16).unwrap();
+    assert_eq!(s.fingerprint_sampled(2), odd_row.fingerprint_sampled(2));
+    let mut even_row = data;
+    even_row[32] ^= 1;
+    let even_row = Screenshot::from_raw(even_row, 3, 4, 16).unwrap();
+    assert_ne!(s.fingerprint_sampled(2), even_row.fingerprint_sampled(2));
+}
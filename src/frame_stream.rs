@@ -0,0 +1,142 @@
+//! Frames as an async `Stream`, captured on tokio's blocking thread pool.
+
+use crate::{
+    change::ChangeDetector, stop::StopCheck, CaptureOptions, Capturer, Screenshot, ScreenshotError,
+};
+
+use futures_core::Stream;
+use tokio::{
+    task::{self, JoinHandle},
+    time::{self, Interval, MissedTickBehavior},
+};
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+/// What a capture on the pool needs, and gives back with the frame for the
+/// next one.
+struct Worker {
+    capturer: Capturer,
+    changes: Option<ChangeDetector>,
+    until: StopCheck,
+}
+
+impl Worker {
+    /// Captures a frame. None if it's unchanged, and not to be delivered.
+    fn capture(&mut self) -> Option<Result<Screenshot, ScreenshotError>> {
+        let res = self.capturer.capture_swap(None);
+        if let Ok(frame) = &res {
+            if let Some(changes) = &mut self.changes {
+                if !changes.should_deliver_frame(frame) {
+                    return None;
+                }
+            }
+            self.until.delivered(frame.metadata());
+        }
+        Some(res)
+    }
+}
+
+type Captured = (Worker, Option<Result<Screenshot, ScreenshotError>>);
+
+/// Captures at a fixed interval, yielding every frame as a screenshot of
+/// its own. Returned by `frame_stream` and `Capturer::stream`.
+///
+/// Frames are only captured when the stream is polled, so a slow consumer
+/// makes the stream skip frames rather than queue them up: a frame that's
+/// overdue is taken right away and the cadence restarts from there, as with
+/// `Frames`. Each frame's `captured_at` shows the gaps. With
+/// `CaptureOptions::only_on_change`, unchanged frames are skipped as well.
+///
+/// Failed captures are yielded as errors and the stream goes on. It ends
+/// when `CaptureOptions::stop_when` says so, or if a capture panicked,
+/// after yielding `ScreenshotError::CaptureThreadFailed`. Dropping the
+/// stream during a capture lets that capture finish on the pool, then
+/// releases the capturer.
+pub struct FrameStream {
+    /// None while a capture runs, and for good once the stream ended.
+    worker: Option<Worker>,
+    interval: Duration,
+    /// Created on the first poll, as it needs the runtime.
+    timer: Option<Interval>,
+    capture: Option<JoinHandle<Captured>>,
+}
+
+impl Stream for FrameStream {
+    type Item = Result<Screenshot, ScreenshotError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(capture) = &mut this.capture {
+                let res = ready!(Pin::new(capture).poll(cx));
+                this.capture = None;
+                match res {
+                    Ok((worker, res)) => {
+                        this.worker = Some(worker);
+                        if let Some(res) = res {
+                            return Poll::Ready(Some(res));
+                        }
+                    }
+                    Err(_) => return Poll::Ready(Some(Err(ScreenshotError::CaptureThreadFailed))),
+                }
+            }
+            let done = match &mut this.worker {
+                Some(worker) => worker.until.is_done(),
+                None => true,
+            };
+            if done {
+                this.worker = None;
+                return Poll::Ready(None);
+            }
+            let interval = this.interval;
+            let timer = this.timer.get_or_insert_with(|| {
+                // tokio rejects a zero period
+                let mut timer = time::interval(interval.max(Duration::from_nanos(1)));
+                timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                timer
+            });
+            ready!(timer.poll_tick(cx));
+            if let Some(mut worker) = this.worker.take() {
+                // the time may be up by the tick
+                if worker.until.is_done() {
+                    return Poll::Ready(None);
+                }
+                this.capture = Some(task::spawn_blocking(move || {
+                    let res = worker.capture();
+                    (worker, res)
+                }));
+            }
+        }
+    }
+}
+
+impl Capturer {
+    /// Captures a frame every `interval` on tokio's blocking thread pool,
+    /// as a `Stream`. See `FrameStream`. The stream must be polled within a
+    /// tokio runtime.
+    pub fn stream(self, interval: Duration) -> FrameStream {
+        FrameStream {
+            worker: Some(Worker {
+                changes: self.options().change_detector(),
+                until: self.options().stop_check(),
+                capturer: self,
+            }),
+            interval,
+            timer: None,
+            capture: None,
+        }
+    }
+}
+
+/// Captures the primary display every `interval`, as a `Stream`. See
+/// `Capturer::stream`.
+pub fn frame_stream(options: CaptureOptions, interval: Duration) -> FrameStream {
+    let mut capturer = Capturer::new();
+    capturer.set_options(options);
+    capturer.stream(interval)
+}
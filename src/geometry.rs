@@ -0,0 +1,448 @@
+//! Points, sizes, rectangles and monitors in virtual-screen coordinates.
+//!
+//! Coordinates are always `(x, y)`, i.e. column before row, except for
+//! `Screenshot::get_pixel`.
+
+use crate::ScreenshotError;
+
+#[cfg(windows)]
+use windows::Win32::Foundation::{POINT, RECT};
+
+/// A point in virtual-screen coordinates, or in pixels of a screenshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(deny_unknown_fields))]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// The size of a rectangle, screenshot or monitor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(deny_unknown_fields))]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Size {
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The number of pixels, or square units.
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+}
+
+/// A rectangle in virtual-screen coordinates. The origin is the top left
+/// corner of the primary monitor, so monitors left of or above it have
+/// negative coordinates. On X11 the origin is the top left corner of the
+/// root window instead, and on macOS coordinates are in points.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(deny_unknown_fields))]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// The rectangle at `origin`, its top left corner, of `size`.
+    pub fn new(origin: Point, size: Size) -> Self {
+        Rect {
+            x: origin.x,
+            y: origin.y,
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    /// The top left corner.
+    pub fn origin(&self) -> Point {
+        Point {
+            x: self.x,
+            y: self.y,
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        Size {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn area(&self) -> u64 {
+        self.size().area()
+    }
+
+    /// Whether `point` is inside, counting the top and left edges but not
+    /// the bottom and right ones, as for pixels.
+    pub fn contains(&self, point: Point) -> bool {
+        let (x, y) = (i64::from(point.x), i64::from(point.y));
+        let (left, top) = (i64::from(self.x), i64::from(self.y));
+        x >= left
+            && y >= top
+            && x < left + i64::from(self.width)
+            && y < top + i64::from(self.height)
+    }
+
+    /// The smallest rectangle covering both. An empty rectangle adds
+    /// nothing, wherever it is.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if other.is_empty() {
+            return *self;
+        }
+        if self.is_empty() {
+            return *other;
+        }
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+        let right = (self.x as i64 + self.width as i64).max(other.x as i64 + other.width as i64);
+        let bottom = (self.y as i64 + self.height as i64).max(other.y as i64 + other.height as i64);
+        Rect {
+            x: left,
+            y: top,
+            width: (right - left as i64).min(u32::MAX as i64) as u32,
+            height: (bottom - top as i64).min(u32::MAX as i64) as u32,
+        }
+    }
+
+    /// The overlap of both rectangles, if any.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        let right = (self.x as i64 + self.width as i64).min(other.x as i64 + other.width as i64);
+        let bottom = (self.y as i64 + self.height as i64).min(other.y as i64 + other.height as i64);
+        if right <= left as i64 || bottom <= top as i64 {
+            return None;
+        }
+        Some(Rect {
+            x: left,
+            y: top,
+            width: (right - left as i64) as u32,
+            height: (bottom - top as i64) as u32,
+        })
+    }
+}
+
+#[cfg(windows)]
+impl From<RECT> for Rect {
+    fn from(r: RECT) -> Self {
+        Rect {
+            x: r.left,
+            y: r.top,
+            width: (r.right as i64 - r.left as i64).max(0) as u32,
+            height: (r.bottom as i64 - r.top as i64).max(0) as u32,
+        }
+    }
+}
+
+/// Saturates at the edge of the coordinate space, which no real rectangle
+/// reaches.
+#[cfg(windows)]
+impl From<Rect> for RECT {
+    fn from(r: Rect) -> Self {
+        RECT {
+            left: r.x,
+            top: r.y,
+            right: r.x.saturating_add(r.width.min(i32::MAX as u32) as i32),
+            bottom: r.y.saturating_add(r.height.min(i32::MAX as u32) as i32),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl From<POINT> for Point {
+    fn from(p: POINT) -> Self {
+        Point { x: p.x, y: p.y }
+    }
+}
+
+#[cfg(windows)]
+impl From<Point> for POINT {
+    fn from(p: Point) -> Self {
+        POINT { x: p.x, y: p.y }
+    }
+}
+
+/// A monitor, as part of the virtual screen.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(deny_unknown_fields))]
+pub struct Monitor {
+    /// Bounds in virtual-screen coordinates.
+    pub rect: Rect,
+    /// Bounds without the taskbar and docked toolbars.
+    pub work_area: Rect,
+    pub primary: bool,
+    /// Physical pixels per unit of `rect`, e.g. 2.0 on a Retina display,
+    /// where coordinates are in points. On Windows it's the monitor's DPI
+    /// over 96, e.g. 1.5 at 150%, which only relates `rect` to pixels for
+    /// threads that aren't DPI aware.
+    pub scale_factor: f64,
+    /// What users know the monitor as, e.g. "DELL U2720Q". On Windows, the
+    /// GDI device name such as `\\.\DISPLAY2` if the monitor doesn't report
+    /// one; on X11 the output, e.g. "DP-1".
+    pub name: Option<String>,
+    /// What the OS calls the monitor, which stays the same when `name` is a
+    /// friendly one: the GDI device name such as `\\.\DISPLAY2` on Windows,
+    /// the output such as "DP-1" on X11, the display ID on macOS and the
+    /// device path on Linux framebuffers.
+    pub device_name: Option<String>,
+    /// In Hz.
+    pub refresh_rate: Option<f64>,
+    pub bits_per_pixel: Option<u32>,
+    pub orientation: Option<Orientation>,
+    /// Width and height of the visible area in millimeters, as the monitor
+    /// reports them in its EDID. None for projectors and other displays
+    /// without a fixed size.
+    pub physical_size_mm: Option<Size>,
+}
+
+/// Another name for `Monitor`, as returned by `enumerate_displays`.
+pub type Display = Monitor;
+
+/// How far a monitor's picture is rotated clockwise, e.g. `Rotated90` for
+/// a landscape monitor turned to portrait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum Orientation {
+    Default,
+    Rotated90,
+    Rotated180,
+    Rotated270,
+}
+
+/// A monitor to capture, see `CaptureOptions::monitor` and
+/// `spawn_multi_capture`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum MonitorSelector {
+    /// The primary monitor.
+    Primary,
+    /// The monitor at this index of `monitors()`.
+    Index(usize),
+    /// This monitor, e.g. one of `monitors()`.
+    Monitor(Monitor),
+}
+
+impl MonitorSelector {
+    pub(crate) fn resolve(&self, monitors: &[Monitor]) -> Result<Monitor, ScreenshotError> {
+        match self {
+            MonitorSelector::Primary => monitors
+                .iter()
+                .find(|m| m.primary)
+                .cloned()
+                .ok_or(ScreenshotError::NoMonitors),
+            MonitorSelector::Index(i) => monitors
+                .get(*i)
+                .cloned()
+                .ok_or(ScreenshotError::NoSuchMonitor(*i)),
+            MonitorSelector::Monitor(monitor) => Ok(monitor.clone()),
+        }
+    }
+}
+
+/// Checks that `region` is non-empty and lies within `bounds`. Regions are
+/// never clamped, so a negative origin is passed through unchanged.
+pub(crate) fn validate_region(region: Rect, bounds: Rect) -> Result<Rect, ScreenshotError> {
+    if region.is_empty() || region.intersect(&bounds) != Some(region) {
+        return Err(ScreenshotError::InvalidRegion(region));
+    }
+    Ok(region)
+}
+
+#[test]
+fn test_validate_region() {
+    let primary = Rect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+    let left = Rect {
+        x: -1920,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+    let above = Rect {
+        x: 0,
+        y: -1440,
+        width: 2560,
+        height: 1440,
+    };
+    // virtual screen for each layout, as reported by SM_*VIRTUALSCREEN
+    let left_layout = Rect {
+        x: -1920,
+        y: 0,
+        width: 3840,
+        height: 1080,
+    };
+    let above_layout = Rect {
+        x: 0,
+        y: -1440,
+        width: 2560,
+        height: 2520,
+    };
+
+    // regions on the secondary monitors keep their negative origin
+    assert_eq!(validate_region(left, left_layout).unwrap(), left);
+    assert_eq!(validate_region(above, above_layout).unwrap(), above);
+
+    // a region straddling the primary origin
+    let straddling = Rect {
+        x: -100,
+        y: 100,
+        width: 200,
+        height: 200,
+    };
+    assert_eq!(
+        validate_region(straddling, left_layout).unwrap(),
+        straddling
+    );
+    assert!(validate_region(straddling, above_layout).is_err());
+
+    assert!(validate_region(primary, left_layout).is_ok());
+    assert!(validate_region(
+        Rect {
+            width: 0,
+            ..primary
+        },
+        left_layout
+    )
+    .is_err());
+    assert!(validate_region(Rect { x: -1921, ..left }, left_layout).is_err());
+}
+
+#[test]
+fn test_rect_intersect() {
+    let a = Rect {
+        x: -10,
+        y: -10,
+        width: 20,
+        height: 20,
+    };
+    let b = Rect {
+        x: 0,
+        y: 0,
+        width: 20,
+        height: 20,
+    };
+    assert_eq!(
+        a.intersect(&b),
+        Some(Rect {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10
+        })
+    );
+    assert_eq!(
+        a.intersect(&Rect {
+            x: 10,
+            y: 0,
+            width: 5,
+            height: 5
+        }),
+        None
+    );
+}
+
+#[test]
+fn test_rect_contains_union() {
+    let a = Rect {
+        x: -10,
+        y: -10,
+        width: 20,
+        height: 20,
+    };
+    assert!(a.contains(Point { x: -10, y: -10 }));
+    assert!(a.contains(Point { x: 9, y: 9 }));
+    assert!(!a.contains(Point { x: 10, y: 0 }));
+    assert!(!a.contains(Point { x: 0, y: -11 }));
+    assert!(!Rect::default().contains(Point::default()));
+    assert_eq!(a.area(), 400);
+    assert_eq!(
+        Rect::new(a.origin(), a.size()),
+        a,
+        "origin and size are the rect's parts"
+    );
+
+    let b = Rect {
+        x: 5,
+        y: 20,
+        width: 10,
+        height: 5,
+    };
+    let both = Rect {
+        x: -10,
+        y: -10,
+        width: 25,
+        height: 35,
+    };
+    assert_eq!(a.union(&b), both);
+    assert_eq!(b.union(&a), both);
+    let nowhere = Rect {
+        x: 1000,
+        y: 1000,
+        width: 0,
+        height: 0,
+    };
+    assert_eq!(a.union(&nowhere), a);
+    assert_eq!(nowhere.union(&a), a);
+    // the union covers what either contains
+    for point in [a.origin(), b.origin(), Point { x: 14, y: 24 }] {
+        assert!(both.contains(point));
+    }
+}
+
+#[test]
+#[cfg(windows)]
+fn test_win32_conversions() {
+    let r: Rect = RECT {
+        left: -1920,
+        top: -200,
+        right: 0,
+        bottom: 880,
+    }
+    .into();
+    assert_eq!(
+        r,
+        Rect {
+            x: -1920,
+            y: -200,
+            width: 1920,
+            height: 1080
+        }
+    );
+    assert_eq!(Rect::from(RECT::from(r)), r);
+    assert_eq!(
+        Rect::from(RECT {
+            left: 10,
+            top: 10,
+            right: 0,
+            bottom: 0
+        }),
+        Rect {
+            x: 10,
+            y: 10,
+            width: 0,
+            height: 0
+        }
+    );
+    let p = Point { x: -5, y: 7 };
+    assert_eq!(Point::from(POINT::from(p)), p);
+}
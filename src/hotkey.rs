@@ -0,0 +1,220 @@
+//! Capturing when a global hotkey is pressed, like PrintScreen, with the
+//! `hotkey` feature. Each registration has a thread of its own with a
+//! message-only window that receives the hotkey.
+
+use crate::{CaptureOptions, Screenshot, ScreenshotError};
+
+use std::{ops::BitOr, sync::mpsc, thread};
+
+use windows::{
+    w,
+    Win32::Foundation::{
+        GetLastError, ERROR_HOTKEY_ALREADY_REGISTERED, HINSTANCE, HWND, LPARAM, WPARAM,
+    },
+    Win32::UI::Input::KeyboardAndMouse::*,
+    Win32::UI::WindowsAndMessaging::*,
+};
+
+/// Modifier keys that must be held with the key of a hotkey, combined with
+/// `|`, e.g. `Modifiers::CONTROL | Modifiers::SHIFT`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers(u32);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const ALT: Modifiers = Modifiers(MOD_ALT.0);
+    pub const CONTROL: Modifiers = Modifiers(MOD_CONTROL.0);
+    pub const SHIFT: Modifiers = Modifiers(MOD_SHIFT.0);
+    /// The Windows key. Most combinations with it are reserved by Windows.
+    pub const WIN: Modifiers = Modifiers(MOD_WIN.0);
+
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+/// A Windows virtual-key code. Letters and digits are their uppercase
+/// ASCII codes, e.g. `Key(u16::from(b'S'))`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key(pub u16);
+
+impl Key {
+    pub const PRINT_SCREEN: Key = Key(0x2c);
+
+    /// The function key `F<n>`, from F1 to F24.
+    pub fn function(n: u8) -> Option<Key> {
+        (1..=24).contains(&n).then(|| Key(0x70 + u16::from(n) - 1))
+    }
+}
+
+/// Posted to the window to end its message loop.
+const WM_STOP: u32 = WM_APP;
+
+/// A registered hotkey that captures a screenshot whenever it's pressed.
+/// Dropping it unregisters the hotkey and waits for its thread to end, so
+/// also for a capture in progress.
+pub struct HotkeyCapture {
+    window: HWND,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HotkeyCapture {
+    /// Registers `modifiers` with `key` as a global hotkey. Each time it's
+    /// pressed, what `options` select is captured and handed to `callback`
+    /// on the hotkey's thread; failed captures are handed over as errors.
+    /// Presses while the callback runs are queued. Holding the keys down
+    /// doesn't repeat.
+    ///
+    /// Fails with `ScreenshotError::HotkeyInUse` if this or another app
+    /// has registered the hotkey already. Any number of different hotkeys
+    /// can be registered at once.
+    pub fn register<F>(
+        modifiers: Modifiers,
+        key: Key,
+        options: CaptureOptions,
+        mut callback: F,
+    ) -> Result<HotkeyCapture, ScreenshotError>
+    where
+        F: FnMut(Result<Screenshot, ScreenshotError>) + Send + 'static,
+    {
+        options.validate()?;
+        let (tx, rx) = mpsc::sync_channel(1);
+        let thread = thread::Builder::new()
+            .name("screenshot-hotkey".into())
+            .spawn(move || {
+                let window = match HotkeyWindow::register(modifiers, key) {
+                    Ok(window) => window,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = tx.send(Ok(window.0));
+                let mut msg = MSG::default();
+                // SAFETY: msg is a valid MSG to fill in. -1 means an error.
+                while unsafe { GetMessageW(&mut msg, HWND(0), 0, 0) }.0 > 0 {
+                    match msg.message {
+                        WM_HOTKEY if msg.hwnd == window.0 => callback(options.capture()),
+                        WM_STOP if msg.hwnd == window.0 => break,
+                        _ => {}
+                    }
+                }
+            })
+            .map_err(|_| ScreenshotError::CaptureThreadFailed)?;
+        match rx.recv() {
+            Ok(Ok(window)) => Ok(HotkeyCapture {
+                window,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => Err(ScreenshotError::CaptureThreadFailed),
+        }
+    }
+}
+
+impl Drop for HotkeyCapture {
+    fn drop(&mut self) {
+        // the window is gone already if the callback panicked
+        unsafe { PostMessageW(self.window, WM_STOP, WPARAM(0), LPARAM(0)) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A message-only window with the hotkey registered to it, both of which
+/// are released on drop. Must stay on the thread that registered it.
+struct HotkeyWindow(HWND);
+
+impl HotkeyWindow {
+    /// The hotkey's id; ids only need to be unique per window.
+    const ID: i32 = 1;
+
+    fn register(modifiers: Modifiers, key: Key) -> Result<HotkeyWindow, ScreenshotError> {
+        // SAFETY: STATIC is a system class, so no window procedure of ours
+        // is involved.
+        let hwnd = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                w!("STATIC"),
+                w!("screenshot hotkey"),
+                WINDOW_STYLE::default(),
+                0,
+                0,
+                0,
+                0,
+                HWND_MESSAGE,
+                HMENU::default(),
+                HINSTANCE::default(),
+                None,
+            )
+        };
+        if hwnd.0 == 0 {
+            return Err(ScreenshotError::HotkeyFailed("CreateWindowExW"));
+        }
+        let window = HotkeyWindow(hwnd);
+        let modifiers = HOT_KEY_MODIFIERS(modifiers.0) | MOD_NOREPEAT;
+        if !unsafe { RegisterHotKey(hwnd, Self::ID, modifiers, u32::from(key.0)) }.as_bool() {
+            return Err(match unsafe { GetLastError() } {
+                ERROR_HOTKEY_ALREADY_REGISTERED => ScreenshotError::HotkeyInUse,
+                _ => ScreenshotError::HotkeyFailed("RegisterHotKey"),
+            });
+        }
+        Ok(window)
+    }
+}
+
+impl Drop for HotkeyWindow {
+    fn drop(&mut self) {
+        unsafe {
+            // fails if the hotkey never got registered, which is fine
+            UnregisterHotKey(self.0, Self::ID);
+            DestroyWindow(self.0);
+        }
+    }
+}
+
+#[test]
+fn test_keys() {
+    let modifiers = Modifiers::CONTROL | Modifiers::SHIFT;
+    assert!(modifiers.contains(Modifiers::SHIFT));
+    assert!(!modifiers.contains(Modifiers::ALT));
+    assert!(modifiers.contains(Modifiers::NONE));
+    assert_eq!(Key::function(1), Some(Key(0x70)));
+    assert_eq!(Key::function(24), Some(Key(0x87)));
+    assert_eq!(Key::function(0), None);
+    assert_eq!(Key::function(25), None);
+}
+
+#[test]
+fn test_hotkey_in_use() {
+    // a combination nobody uses
+    let (modifiers, key) = (
+        Modifiers::CONTROL | Modifiers::ALT,
+        Key::function(24).unwrap(),
+    );
+    let first = HotkeyCapture::register(modifiers, key, CaptureOptions::default(), |_| {}).unwrap();
+    let second = HotkeyCapture::register(modifiers, key, CaptureOptions::default(), |_| {});
+    assert!(matches!(second, Err(ScreenshotError::HotkeyInUse)));
+    let other = HotkeyCapture::register(
+        modifiers,
+        Key::function(23).unwrap(),
+        CaptureOptions::default(),
+        |_| {},
+    );
+    assert!(other.is_ok());
+    drop(first);
+    // unregistered on drop, so it can be taken again
+    assert!(HotkeyCapture::register(modifiers, key, CaptureOptions::default(), |_| {}).is_ok());
+}
@@ -0,0 +1,57 @@
+//! Conversions into the `image` crate's buffers, with the `image` feature.
+
+use crate::{Screenshot, ScreenshotError};
+
+use image::{DynamicImage, RgbaImage};
+
+use std::convert::TryFrom;
+
+/// Copies the pixels into an `RgbaImage`, switching red and blue for BGRA
+/// screenshots and dropping row padding in the same pass. This always
+/// copies: the screenshot's own buffer is aligned in a way `image` can't
+/// own, so only borrowed screenshots convert.
+/// Fails with `ScreenshotError::DimensionsTooLarge` for a screenshot over
+/// `u32::MAX` pixels wide or high.
+impl TryFrom<&Screenshot> for RgbaImage {
+    type Error = ScreenshotError;
+
+    fn try_from(shot: &Screenshot) -> Result<Self, ScreenshotError> {
+        let too_large = ScreenshotError::DimensionsTooLarge {
+            width: shot.width,
+            height: shot.height,
+        };
+        let (width, height) = match (u32::try_from(shot.width), u32::try_from(shot.height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(too_large),
+        };
+        RgbaImage::from_raw(width, height, shot.to_rgba_vec()).ok_or(too_large)
+    }
+}
+
+/// An `ImageRgba8`, see the `RgbaImage` conversion.
+impl TryFrom<&Screenshot> for DynamicImage {
+    type Error = ScreenshotError;
+
+    fn try_from(shot: &Screenshot) -> Result<Self, ScreenshotError> {
+        RgbaImage::try_from(shot).map(DynamicImage::ImageRgba8)
+    }
+}
+
+#[test]
+fn test_into_image() {
+    // 2x2 pixels, BGRA, rows padded to 12 bytes
+    let data = vec![
+        0, 0, 255, 255, 0, 255, 0, 255, 0, 0, 0, 0, //
+        255, 0, 0, 255, 1, 2, 3, 4, 0, 0, 0, 0,
+    ];
+    let rgba = [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 3, 2, 1, 4];
+    let mut s = Screenshot::from_raw(data, 2, 2, 12).unwrap();
+    let img = RgbaImage::try_from(&s).unwrap();
+    assert_eq!(img.dimensions(), (2, 2));
+    assert_eq!(img.into_raw(), rgba);
+
+    // the channel order is followed
+    s.swap_r_b_in_place();
+    let img = DynamicImage::try_from(&s).unwrap();
+    assert_eq!(img.into_rgba8().into_raw(), rgba);
+}
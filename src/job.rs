@@ -0,0 +1,178 @@
+//! Running slow work such as encoding off the capturing thread.
+
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+};
+
+/// The result of work running in the background, e.g. from
+/// `Screenshot::encode_png_async`.
+pub struct Job<T> {
+    slot: Arc<Slot<T>>,
+}
+
+/// Where a worker leaves the result of a job.
+struct Slot<T> {
+    result: Mutex<Option<io::Result<T>>>,
+    done: Condvar,
+}
+
+impl<T> Slot<T> {
+    fn lock(&self) -> MutexGuard<'_, Option<io::Result<T>>> {
+        // the result is set in a single statement, so it's never half-done
+        self.result.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Send + 'static> Job<T> {
+    /// Runs `f` on a thread of its own.
+    #[cfg(feature = "png")]
+    pub(crate) fn spawn(f: impl FnOnce() -> io::Result<T> + Send + 'static) -> Self {
+        let (job, task) = Job::new(f);
+        if let Err(e) = thread::Builder::new()
+            .name("screenshot-job".into())
+            .spawn(task)
+        {
+            *job.slot.lock() = Some(Err(e));
+        }
+        job
+    }
+
+    /// A job and the task that completes it.
+    fn new(f: impl FnOnce() -> io::Result<T> + Send + 'static) -> (Self, Task) {
+        let slot = Arc::new(Slot {
+            result: Mutex::new(None),
+            done: Condvar::new(),
+        });
+        let job = Job { slot: slot.clone() };
+        let task: Task = Box::new(move || {
+            // A panic must reach `join` rather than leave it waiting.
+            let res = panic::catch_unwind(AssertUnwindSafe(f))
+                .unwrap_or_else(|_| Err(io::Error::other("job panicked")));
+            *slot.lock() = Some(res);
+            slot.done.notify_all();
+        });
+        (job, task)
+    }
+}
+
+impl<T> Job<T> {
+    /// Whether the job has finished, successfully or not.
+    pub fn is_done(&self) -> bool {
+        self.slot.lock().is_some()
+    }
+
+    /// Waits for the job to finish and returns its result, including any
+    /// error, or a panic turned into an error.
+    pub fn join(self) -> io::Result<T> {
+        let mut result = self.slot.lock();
+        loop {
+            if let Some(res) = result.take() {
+                return res;
+            }
+            result = self
+                .slot
+                .done
+                .wait(result)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of worker threads running jobs in the order they were
+/// submitted, so encoding many frames doesn't start a thread for each.
+///
+/// Dropping the pool waits for the jobs already submitted to finish.
+pub struct EncodePool {
+    tasks: Option<mpsc::Sender<Task>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl EncodePool {
+    /// Starts a pool of `threads` workers, at least one.
+    pub fn new(threads: usize) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel::<Task>();
+        let rx = Arc::new(Mutex::new(rx));
+        let mut pool = EncodePool {
+            tasks: Some(tx),
+            workers: Vec::new(),
+        };
+        for i in 0..threads.max(1) {
+            let rx = rx.clone();
+            let worker = thread::Builder::new()
+                .name(format!("screenshot-encode-{}", i))
+                .spawn(move || loop {
+                    // Tasks catch their own panics, so the lock can't be
+                    // poisoned by one.
+                    let task = rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    match task {
+                        Ok(task) => task(),
+                        // the pool was dropped
+                        Err(_) => break,
+                    }
+                })?;
+            pool.workers.push(worker);
+        }
+        Ok(pool)
+    }
+
+    /// Number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `f` on one of the workers once it's free.
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> io::Result<T> + Send + 'static,
+    ) -> Job<T> {
+        let (job, task) = Job::new(f);
+        // The workers only stop once `tasks` is dropped.
+        if let Some(tasks) = &self.tasks {
+            let _ = tasks.send(task);
+        }
+        job
+    }
+}
+
+impl Drop for EncodePool {
+    fn drop(&mut self) {
+        self.tasks = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[test]
+fn test_jobs() {
+    #[cfg(feature = "png")]
+    assert_eq!(Job::spawn(|| Ok(1)).join().unwrap(), 1);
+
+    let pool = EncodePool::new(2).unwrap();
+    assert_eq!(pool.threads(), 2);
+    let jobs: Vec<_> = (0..10).map(|i| pool.spawn(move || Ok(i * i))).collect();
+    let results: Vec<_> = jobs.into_iter().map(|job| job.join().unwrap()).collect();
+    assert_eq!(results, (0..10).map(|i| i * i).collect::<Vec<_>>());
+
+    // errors and panics reach the caller
+    let failed = pool.spawn::<()>(|| Err(io::Error::other("full disk")));
+    assert_eq!(failed.join().unwrap_err().to_string(), "full disk");
+    let panicked = pool.spawn::<()>(|| panic!("boom"));
+    assert!(panicked.join().is_err());
+    // and the workers survive them
+    let job = pool.spawn(|| Ok("still working"));
+    while !job.is_done() {
+        thread::yield_now();
+    }
+    assert_eq!(job.join().unwrap(), "still working");
+
+    // dropping the pool finishes what's queued
+    let job = pool.spawn(|| Ok(()));
+    drop(pool);
+    assert!(job.is_done());
+}
@@ -1,163 +1,540 @@
 //! Capture a bitmap image of a display. The resulting screenshot is stored in
-//! the `Screenshot` type, which varies per platform.
+//! the `Screenshot` type, which is the same whichever backend took it.
+//! Everything public is re-exported at the crate root, and
+//! `use screenshot::prelude::*` imports the types most captures need.
 //!
-//! The Windows GDI bitmap has its coordinate origin at the bottom left. We
-//! attempt to undo this by reordering the rows. Windows also uses ARGB pixels.
+//! # Backends
+//!
+//! Captures go through a `CaptureBackend`, see the `backend` module. The
+//! free functions such as `get_screenshot` use `backend::DefaultBackend`,
+//! which is GDI on Windows, CoreGraphics on macOS, and on Linux whichever
+//! of the `x11`, `wayland` and `fbdev` backends works; `Screenshot`, `Rect`,
+//! `ScreenshotError` and `CaptureOptions` don't depend on the backend.
+//!
+//! The crate builds on every platform. Where there's no default backend,
+//! the free functions fail with `ScreenshotError::UnsupportedPlatform`.
+//! `Capturer`, `LiveCapture` and `spawn_multi_capture` are built on GDI and
+//! only exist on Windows with the `gdi` feature; `spawn_backend_capture`
+//! streams from any backend.
+//!
+//! On Windows, `CaptureOptions::backend` picks the capture method at
+//! runtime. By default it's `Backend::Auto`, which probes for the fastest
+//! available one; `CaptureMetadata::backend` says which captured a frame.
+//!
+//! What to capture can be given in the options as well, e.g.
+//! `CaptureOptions::new().monitor(MonitorSelector::Index(1)).capture()`, or
+//! `Capturer::with_options` for repeated captures.
+//! `get_virtual_screen_screenshot` takes all monitors at once, with where
+//! each one is within the image.
+//!
+//! # Threads
+//!
+//! Capturing is safe from any thread, including several at once: every call
+//! acquires and releases its own device contexts, and nothing is shared
+//! between calls. `Screenshot` is plain owned data and can be sent freely.
+//!
+//! The reported size depends on the DPI awareness of the *calling thread*.
+//! A thread that isn't DPI aware on a scaled display sees the logical
+//! resolution and gets a downscaled image. Worker threads (e.g. from rayon)
+//! inherit the process default, so set the awareness in the manifest or at
+//! startup rather than on the main thread only, or set
+//! `CaptureOptions::dpi_aware` to capture at the physical resolution from
+//! any thread. `Screenshot::logical_size` and `physical_size` convert
+//! between the two.
+//!
+//! Operations that take several captures, like `capture_scrolling` and
+//! `get_screenshot_averaged`, have `_cancellable` variants that a
+//! `CancelToken` stops from another thread, and that report their
+//! `Progress` as they go.
+//!
+//! # Features
+//!
+//! `gdi` (on by default), `dxgi` and `winrt-capture`: the Windows backends
+//! `CaptureOptions::backend` can pick from. Disabled ones are compiled out
+//! and reported as unavailable by `Backend::probe`. `Capturer`,
+//! `LiveCapture`, `spawn_multi_capture` and `capture_scrolling` need
+//! `gdi`, which the other two turn on as well; they capture through desktop
+//! duplication or Windows.Graphics.Capture whenever the options resolve to
+//! it, and with DXGI `Capturer::last_dirty_rects` says what changed between
+//! frames. With any backend, `Screenshot::diff` finds it by comparing them.
+//!
+//! `rayon`: per-pixel conversions such as `to_rgba_vec` and
+//! `swap_r_b_in_place`, `Screenshot::diff` and `find_color` split large
+//! frames into rows handled in parallel. Small frames are still handled on
+//! the calling thread.
+//!
+//! `png`: `Screenshot::to_png` and friends, including encoding on a
+//! background thread with `encode_png_async` or an `EncodePool`.
+//!
+//! `jpeg`: `Screenshot::to_jpeg` and friends, through jpeg-encoder.
+//! `Screenshot::encode` and `save` pick PNG, BMP or JPEG at runtime.
+//!
+//! `image`: `TryFrom<&Screenshot>` for the `image` crate's `RgbaImage` and
+//! `DynamicImage`, converting to RGBA in a single copy.
+//!
+//! `lz4`: `RawRecorder::set_lz4`, compressing raw recordings frame by
+//! frame.
+//!
+//! `x11`: `backend::x11::X11Backend`, capturing from an X server on Linux
+//! through x11rb.
+//!
+//! `wayland`: `backend::wayland::WaylandBackend`, capturing through
+//! xdg-desktop-portal and PipeWire on Linux.
+//!
+//! `fbdev`: `backend::fbdev::FbdevBackend`, capturing from a Linux
+//! framebuffer device such as `/dev/fb0`.
+//!
+//! `tracing`: logs the capture path through the `tracing` crate, with
+//! debug-level spans `capture`, `bitblt`, `get_dibits` and `convert`, and
+//! events for retries, backend probing, degraded environments and failed GDI
+//! calls with their Win32 error codes. Without it nothing is logged.
+//!
+//! `hotkey`: `HotkeyCapture`, capturing whenever a global hotkey is pressed,
+//! on Windows.
+//!
+//! `config`: `CaptureProfile`, reading what to capture, how often, what to
+//! redact and where to save from TOML or JSON, and serde support for
+//! `CaptureOptions` and the types it's made of.
+//!
+//! `tokio`: `get_screenshot_async`, `Capturer::capture_async` and
+//! `frame_stream`, which run the blocking capture on tokio's blocking
+//! thread pool.
+
+mod average;
+pub mod backend;
+mod blend;
+mod buffer;
+#[cfg(all(windows, feature = "gdi"))]
+mod cache;
+mod cancel;
+#[cfg(all(windows, feature = "gdi"))]
+mod capturer;
+mod change;
+#[cfg(feature = "config")]
+mod config;
+mod convert;
+mod diff;
+mod encode;
+mod environment;
+mod error;
+mod fingerprint;
+#[cfg(all(windows, feature = "gdi", feature = "tokio"))]
+mod frame_stream;
+mod geometry;
+#[cfg(all(windows, feature = "hotkey"))]
+mod hotkey;
+#[cfg(feature = "image")]
+mod image_conv;
+mod job;
+#[cfg(all(windows, feature = "gdi"))]
+mod live;
+#[cfg(all(windows, feature = "gdi"))]
+mod multi;
+mod options;
+mod pacing;
+mod perceptual;
+mod pixel;
+mod pool;
+pub mod prelude;
+mod preview;
+mod raw;
+mod redact;
+mod resize;
+mod screenshot;
+#[cfg(all(windows, feature = "gdi"))]
+mod scroll;
+mod search;
+mod stitch;
+mod stop;
+mod stream;
+pub mod testing;
+mod text;
+mod trace;
+mod view;
+mod virtual_screen;
+mod watcher;
+
+#[cfg(windows)]
+pub use environment::{capture_environment, secure_desktop_active};
+pub use environment::{CaptureEnvironment, SessionState};
 
-use windows::{Win32::Graphics::Gdi::*, Win32::UI::WindowsAndMessaging::*};
+pub use average::{
+    capture_averaged, get_screenshot_averaged, get_screenshot_averaged_cancellable,
+    get_screenshot_averaged_with, Averaging,
+};
+pub use backend::{Backend, CaptureBackend, CaptureTarget, Window, WindowId};
+pub use blend::BlendMode;
+pub use cancel::{CancelToken, Progress};
+#[cfg(all(windows, feature = "gdi"))]
+pub use capturer::{Capturer, Frames};
+pub use change::ChangeFilter;
+#[cfg(feature = "config")]
+pub use config::{CaptureProfile, OutputFormat, OutputProfile, RedactStyle, Redaction, Retention};
+pub use diff::{DiffOptions, DiffResult};
+pub use encode::ImageFormat;
+#[cfg(feature = "png")]
+pub use encode::PngJob;
+pub use error::ScreenshotError;
+#[cfg(all(windows, feature = "gdi", feature = "tokio"))]
+pub use frame_stream::{frame_stream, FrameStream};
+pub use geometry::{Display, Monitor, MonitorSelector, Orientation, Point, Rect, Size};
+#[cfg(all(windows, feature = "hotkey"))]
+pub use hotkey::{HotkeyCapture, Key, Modifiers};
+pub use job::{EncodePool, Job};
+#[cfg(all(windows, feature = "gdi"))]
+pub use live::{FrameGuard, LiveCapture};
+#[cfg(all(windows, feature = "gdi"))]
+pub use multi::{
+    spawn_multi_capture, MergedReceiver, MonitorFrame, MultiCaptureHandle, MultiChannels,
+    MultiReceiver,
+};
+pub use options::{
+    CaptureOptions, FaultPoint, RetryPolicy, DEFAULT_MAX_DIMENSION, MAX_RETRY_DELAY,
+};
+pub use pacing::{Pacing, PacingStats};
+pub use perceptual::hash_distance;
+pub use pixel::{Pixel, PixelFormat};
+pub use pool::{FramePool, PooledFrame};
+pub use raw::{RawEntry, RawPlayer, RawRecorder};
+pub use resize::ResizeFilter;
+pub use screenshot::{CaptureMetadata, CaptureMetrics, FrameInfo, Screenshot};
+#[cfg(all(windows, feature = "gdi"))]
+pub use scroll::{
+    capture_scrolling, capture_scrolling_cancellable, capture_scrolling_with, ScrollCaptureOptions,
+    ScrollMethod,
+};
+pub use stitch::{ScrollStitcher, StitchOptions, StitchProgress};
+pub use stop::StopCondition;
+pub use stream::{
+    spawn_backend_capture, spawn_capture, CaptureHandle, CaptureSummary, FrameReceiver,
+    QueuePolicy, QueueStats,
+};
+pub use text::{Corner, TextOptions};
+pub use view::ScreenshotView;
+pub use virtual_screen::{
+    get_virtual_screen_screenshot, get_virtual_screen_screenshot_with, VirtualScreenshot,
+};
+pub use watcher::{RegionMatch, RegionWatcher};
 
-use core::ffi::c_void;
-use std::{error::Error, mem::size_of};
+#[cfg(all(windows, feature = "gdi"))]
+use backend::gdi::State;
+use backend::DefaultBackend;
+use geometry::validate_region;
+use pixel::PIXEL_WIDTH;
+use screenshot::buffer_len;
 
-// 4 as 32 bit colour
-const PIXEL_WIDTH: usize = 4;
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
 
-#[derive(Clone, Copy)]
-pub struct Pixel {
-    pub a: u8,
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
+/// Lists the monitors making up the virtual screen, as the default backend
+/// sees them.
+pub fn monitors() -> Result<Vec<Monitor>, ScreenshotError> {
+    DefaultBackend::default().monitors()
 }
 
-/// An image buffer containing the screenshot.
-/// Pixels are stored as [ARGB](https://en.wikipedia.org/wiki/ARGB).
-pub struct Screenshot {
-    pub data: Vec<u8>,
-    pub data_r_and_b_switched: Vec<u8>,
-    /// Height of image in pixels
-    pub height: usize,
-    /// Width of image in pixels.
-    pub width: usize,
-    /// Number of bytes in one row of bitmap.
-    pub row_len: usize, // Might be superfluous
+/// Gets a screenshot of the primary display.
+pub fn get_screenshot() -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_with(&CaptureOptions::default())
 }
 
-impl Screenshot {
-    /// Number of bytes in bitmap
-    pub fn len(&self) -> usize {
-        self.data.len()
-    }
+/// Like `get_screenshot`, with explicit options.
+pub fn get_screenshot_with(options: &CaptureOptions) -> Result<Screenshot, ScreenshotError> {
+    options
+        .backend
+        .open()?
+        .capture_target(CaptureTarget::Primary, options)
+}
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
+/// Captures the primary display into `buf`, reusing its allocation instead
+/// of creating a `Screenshot`. The buffer grows as needed but is never
+/// shrunk, so passing the same one every frame avoids allocating. Only on
+/// Windows.
+#[cfg(all(windows, feature = "gdi"))]
+pub fn get_screenshot_into(buf: &mut Vec<u8>) -> Result<FrameInfo, ScreenshotError> {
+    State::default().capture_into(CaptureTarget::Primary, &CaptureOptions::default(), buf)
+}
 
-    /// Gets pixel at (row, col)
-    pub fn get_pixel(&self, row: usize, col: usize) -> Pixel {
-        let idx = row * self.row_len + col * PIXEL_WIDTH;
-        if idx > self.len() {
-            panic!("Bounds overflow");
-        }
-
-        Pixel {
-            a: self.data[idx + 3],
-            r: self.data[idx + 2],
-            g: self.data[idx + 1],
-            b: self.data[idx],
-        }
-    }
+/// The colour of the pixel at `x`, `y` in virtual-screen coordinates,
+/// which are negative left of and above the primary monitor. Much cheaper
+/// than a capture, but each call acquires the screen DC, so use
+/// `get_colors_at` for several pixels. Fails with
+/// `ScreenshotError::PointOffScreen` where no monitor is. Only on Windows.
+#[cfg(all(windows, feature = "gdi"))]
+pub fn get_color_at(x: i32, y: i32) -> Result<Pixel, ScreenshotError> {
+    backend::gdi::colors_at(&[Point { x, y }]).map(|colors| colors[0])
 }
 
-// TODO: Support multiple screens
-// gets a screenshot from a default screen
-pub fn get_screenshot() -> Result<Screenshot, Box<dyn Error>> {
-    unsafe {
-        // Enumerate monitors, getting a handle and DC for requested monitor.
-        // loljk, because doing that on Windows is worse than death
-        let h_wnd_screen = GetDesktopWindow();
-        let h_dc_screen = GetDC(h_wnd_screen);
-        let width = GetSystemMetrics(SM_CXSCREEN);
-        let height = GetSystemMetrics(SM_CYSCREEN);
-
-        // Create a Windows Bitmap, and copy the bits into it
-        let h_dc = CreateCompatibleDC(h_dc_screen);
-        let h_bmp = CreateCompatibleBitmap(h_dc_screen, width, height);
-        let _ = SelectObject(h_dc, h_bmp);
-
-        let res = BitBlt(
-            h_dc,
-            0,
-            0,
-            width,
-            height,
-            h_dc_screen,
-            0,
-            0,
-            ROP_CODE(SRCCOPY.0),
-        );
+/// Like `get_color_at` for each of `points`, reading them all through one
+/// screen DC. Fails if any point is off screen. Only on Windows.
+#[cfg(all(windows, feature = "gdi"))]
+pub fn get_colors_at(points: &[Point]) -> Result<Vec<Pixel>, ScreenshotError> {
+    backend::gdi::colors_at(points)
+}
 
-        if !res.as_bool() {
-            return Err("Failed to copy screen to Windows buffer".into());
-        }
-
-        // Get image info
-        let mut bmi = BITMAPINFO {
-            bmiHeader: BITMAPINFOHEADER {
-                biSize: size_of::<BITMAPINFOHEADER>() as u32,
-                biWidth: width,
-                biHeight: -height, // having this reverted by -1 causes the image to be flipped to save a additional flipping step later
-                biPlanes: 1,
-                biBitCount: 32,
-                biCompression: BI_RGB,
-                biSizeImage: 0, // as compression is set to RGB, this may be set to zero (width * height * (pixel_width as i32)) as u32,
-                biXPelsPerMeter: 0,
-                biYPelsPerMeter: 0,
-                biClrUsed: 0,
-                biClrImportant: 0,
-            },
-            bmiColors: [RGBQUAD {
-                rgbBlue: 0,
-                rgbGreen: 0,
-                rgbRed: 0,
-                rgbReserved: 0,
-            }],
-        };
-
-        // Create a Vec for image
-        let size: usize = (width * height) as usize * PIXEL_WIDTH;
-        let mut data: Vec<u8> = vec![0; size];
-
-        // copy bits into Vec
-        GetDIBits(
-            h_dc,
-            h_bmp,
-            0,
-            height as u32,
-            Some(&mut data[0] as *mut _ as *mut c_void),
-            &mut bmi as *mut BITMAPINFO,
-            DIB_RGB_COLORS,
-        );
+/// Like `get_screenshot_with`, but gives up with `ScreenshotError::Timeout`
+/// if the capture takes longer than `timeout`, e.g. while a display driver
+/// resets.
+///
+/// The capture runs on a thread of its own. A timed out capture can't be
+/// interrupted, so that thread keeps running in the background until the
+/// OS call returns, then cleans up and exits; if the call never returns, the
+/// thread is leaked.
+pub fn capture_with_timeout(
+    options: &CaptureOptions,
+    timeout: Duration,
+) -> Result<Screenshot, ScreenshotError> {
+    let options = options.clone();
+    run_with_timeout(timeout, move || get_screenshot_with(&options))
+}
+
+/// Like `get_screenshot_with`, but runs the capture on tokio's blocking
+/// thread pool instead of stalling the runtime. Must be awaited within a
+/// tokio runtime.
+///
+/// Dropping the future doesn't cancel the capture: it runs to completion,
+/// releasing its device contexts and bitmap as usual, and only the result
+/// is discarded.
+#[cfg(feature = "tokio")]
+pub async fn get_screenshot_async(options: &CaptureOptions) -> Result<Screenshot, ScreenshotError> {
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || get_screenshot_with(&options))
+        .await
+        .map_err(|_| ScreenshotError::CaptureThreadFailed)?
+}
 
-        // create a colour inverted version, switch r and b
-        let mut data_color_invert = data.clone();
-        let l = data_color_invert.len();
-        for i in (0..l).into_iter().step_by(4) {
-            data_color_invert.swap(i, i + 2);
-        }
-
-        // Release native image buffers
-        ReleaseDC(h_wnd_screen, h_dc_screen); // don't need screen anymore
-        DeleteDC(h_dc);
-        DeleteObject(h_bmp);
-
-        Ok(Screenshot {
-            data,
-            data_r_and_b_switched: data_color_invert,
-            height: height as usize,
-            width: width as usize,
-            row_len: width as usize * PIXEL_WIDTH,
+/// Runs `f` on a new thread, waiting at most `timeout` for its result.
+pub(crate) fn run_with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    f: impl FnOnce() -> Result<T, ScreenshotError> + Send + 'static,
+) -> Result<T, ScreenshotError> {
+    let (tx, rx) = mpsc::sync_channel(1);
+    thread::Builder::new()
+        .name("screenshot-capture".into())
+        .spawn(move || {
+            // the receiver is gone if we timed out
+            let _ = tx.send(f());
         })
+        .map_err(|_| ScreenshotError::CaptureThreadFailed)?;
+    match rx.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(RecvTimeoutError::Timeout) => Err(ScreenshotError::Timeout(timeout)),
+        Err(RecvTimeoutError::Disconnected) => Err(ScreenshotError::CaptureThreadFailed),
     }
 }
 
+/// Gets a screenshot of `region`, given in virtual-screen coordinates.
+/// The region may extend onto monitors left of or above the primary one,
+/// i.e. have a negative origin, but must lie within the virtual screen.
+///
+/// Only the region is copied: the bitmap is the size of the region, so
+/// `BitBlt` and `GetDIBits` never transfer rows or columns outside it. A
+/// small region is thus much cheaper than cropping a full capture.
+pub fn get_screenshot_region(region: Rect) -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_region_with(region, &CaptureOptions::default())
+}
+
+/// The same as `get_screenshot_region` with a `Rect` at `x`, `y`.
+pub fn get_screenshot_area(
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_region(Rect {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+/// Like `get_screenshot_region`, with explicit options.
+pub fn get_screenshot_region_with(
+    region: Rect,
+    options: &CaptureOptions,
+) -> Result<Screenshot, ScreenshotError> {
+    options
+        .backend
+        .open()?
+        .capture_target(CaptureTarget::Region(region), options)
+}
+
+/// Gets a screenshot of a single monitor.
+pub fn get_monitor_screenshot(monitor: &Monitor) -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_region(monitor.rect)
+}
+
+/// Like `get_monitor_screenshot`, with explicit options.
+pub fn get_monitor_screenshot_with(
+    monitor: &Monitor,
+    options: &CaptureOptions,
+) -> Result<Screenshot, ScreenshotError> {
+    get_screenshot_region_with(monitor.rect, options)
+}
+
+/// The same as `monitors`.
+pub fn enumerate_displays() -> Result<Vec<Display>, ScreenshotError> {
+    monitors()
+}
+
+/// The same as `get_monitor_screenshot`.
+pub fn get_screenshot_from(display: &Display) -> Result<Screenshot, ScreenshotError> {
+    get_monitor_screenshot(display)
+}
+
+/// Lists the visible top-level windows, topmost first, as the default
+/// backend sees them.
+pub fn windows() -> Result<Vec<Window>, ScreenshotError> {
+    DefaultBackend::default().windows()
+}
+
+/// The topmost of `windows()` whose title contains `title`.
+pub fn find_window_by_title(title: &str) -> Result<Option<Window>, ScreenshotError> {
+    Ok(windows()?
+        .into_iter()
+        .find(|window| window.title.contains(title)))
+}
+
+/// Gets a screenshot of `window`, wherever it is at the time. Windows on
+/// top of it show up as well, unless `CaptureOptions::print_window` is set.
+pub fn get_window_screenshot(window: WindowId) -> Result<Screenshot, ScreenshotError> {
+    get_window_screenshot_with(window, &CaptureOptions::default())
+}
+
+/// Like `get_window_screenshot`, with explicit options.
+pub fn get_window_screenshot_with(
+    window: WindowId,
+    options: &CaptureOptions,
+) -> Result<Screenshot, ScreenshotError> {
+    options
+        .backend
+        .open()?
+        .capture_target(CaptureTarget::Window(window), options)
+}
+
 #[test]
+#[cfg(any(all(windows, feature = "gdi"), target_os = "macos"))]
 fn test_get_screenshot() {
     let s: Screenshot = get_screenshot().unwrap();
     println!(
         "width: {}\nheight: {}\nbytes: {}",
-        s.width,
-        s.height,
+        s.width(),
+        s.height(),
         s.len()
     );
 }
+
+#[test]
+#[cfg(any(all(windows, feature = "gdi"), target_os = "macos"))]
+fn test_concurrent_screenshots() {
+    let handles: Vec<_> = (0..8)
+        .map(|_| thread::spawn(|| get_screenshot().map(|s| (s.width(), s.height()))))
+        .collect();
+    let sizes: Vec<_> = handles
+        .into_iter()
+        .map(|h| h.join().unwrap().unwrap())
+        .collect();
+    assert!(sizes.windows(2).all(|w| w[0] == w[1]));
+}
+
+#[test]
+#[cfg(windows)]
+fn test_region_capture() {
+    let region = Rect {
+        x: 10,
+        y: 20,
+        width: 100,
+        height: 50,
+    };
+    let s = get_screenshot_region(region).unwrap();
+    assert_eq!((s.width(), s.height()), (100, 50));
+    // nothing beyond the region was read
+    assert_eq!((s.row_len(), s.len()), (400, 400 * 50));
+}
+
+#[test]
+#[cfg(windows)]
+fn test_area_capture() {
+    let s = get_screenshot_area(10, 20, 100, 50).unwrap();
+    assert_eq!((s.width(), s.height()), (100, 50));
+}
+
+#[test]
+#[cfg(any(all(windows, feature = "gdi"), target_os = "macos"))]
+fn test_displays() {
+    let displays = enumerate_displays().unwrap();
+    assert_eq!(displays, monitors().unwrap());
+    for display in &displays {
+        let s = get_screenshot_from(display).unwrap();
+        assert_eq!(
+            (s.width(), s.height()),
+            (display.rect.width as usize, display.rect.height as usize)
+        );
+    }
+}
+
+#[test]
+#[cfg(all(windows, feature = "gdi"))]
+fn test_color_at() {
+    let points = [Point { x: 10, y: 20 }, Point { x: 11, y: 20 }];
+    let colors = get_colors_at(&points).unwrap();
+    let s = get_screenshot_region(Rect {
+        x: 10,
+        y: 20,
+        width: 2,
+        height: 1,
+    })
+    .unwrap();
+    // alpha is undefined in captures
+    let opaque = |p: Pixel| Pixel { a: 255, ..p };
+    assert_eq!(
+        colors,
+        [opaque(s.get_pixel(0, 0)), opaque(s.get_pixel(0, 1))]
+    );
+    assert_eq!(get_color_at(11, 20).unwrap(), colors[1]);
+
+    let screen = backend::gdi::virtual_screen();
+    let off_screen = Point {
+        x: screen.x + screen.width as i32,
+        y: screen.y,
+    };
+    assert!(matches!(
+        get_colors_at(&[points[0], off_screen]),
+        Err(ScreenshotError::PointOffScreen(p)) if p == off_screen
+    ));
+}
+
+#[test]
+fn test_run_with_timeout() {
+    assert_eq!(
+        run_with_timeout(Duration::from_secs(5), || Ok(1)).unwrap(),
+        1
+    );
+    assert!(matches!(
+        run_with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        }),
+        Err(ScreenshotError::Timeout(_))
+    ));
+    assert!(matches!(
+        run_with_timeout::<()>(Duration::from_secs(5), || panic!("boom")),
+        Err(ScreenshotError::CaptureThreadFailed)
+    ));
+}
+
+#[test]
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+fn test_unsupported_platform() {
+    assert!(matches!(
+        get_screenshot(),
+        Err(ScreenshotError::UnsupportedPlatform)
+    ));
+    assert!(matches!(
+        monitors(),
+        Err(ScreenshotError::UnsupportedPlatform)
+    ));
+}
@@ -0,0 +1,163 @@
+//! Continuous capture on a background thread, for previews that only ever
+//! want the newest frame.
+
+use crate::{Capturer, Screenshot, ScreenshotError};
+
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Captures frames on a thread of its own, alternating between two
+/// buffers: one being captured into, the other holding the latest complete
+/// frame. Only complete frames are ever published, so readers can't see a
+/// frame being overwritten.
+///
+/// Dropping it stops the thread and waits for it to finish.
+pub struct LiveCapture {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    latest: Mutex<Option<Arc<Screenshot>>>,
+    stop: AtomicBool,
+}
+
+/// The latest frame of a `LiveCapture`. It stays valid, and unchanged, for
+/// as long as it's held; the capture thread uses another buffer meanwhile.
+#[derive(Clone)]
+pub struct FrameGuard(Arc<Screenshot>);
+
+impl Deref for FrameGuard {
+    type Target = Screenshot;
+
+    fn deref(&self) -> &Screenshot {
+        &self.0
+    }
+}
+
+impl LiveCapture {
+    /// Starts capturing with `capturer`, at most once per `interval`.
+    /// Failed captures are retried on the next tick; meanwhile `latest`
+    /// keeps returning the last good frame. So do unchanged frames if
+    /// `CaptureOptions::only_on_change` or `skip_duplicate_frames` is set.
+    pub fn start(mut capturer: Capturer, interval: Duration) -> Result<Self, ScreenshotError> {
+        let shared = Arc::new(Shared {
+            latest: Mutex::new(None),
+            stop: AtomicBool::new(false),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("screenshot-live".into())
+                .spawn(move || shared.run(&mut capturer, interval))
+                .map_err(|_| ScreenshotError::CaptureThreadFailed)?
+        };
+        Ok(LiveCapture {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// The most recently completed frame, or `None` before the first one.
+    /// Never waits for a capture in progress.
+    pub fn latest(&self) -> Option<FrameGuard> {
+        self.shared.latest().clone().map(FrameGuard)
+    }
+
+    /// Stops capturing and waits for the thread to finish.
+    pub fn stop(mut self) -> Result<(), ScreenshotError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), ScreenshotError> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => {
+                // wake it if it's waiting for the next tick
+                thread.thread().unpark();
+                thread
+                    .join()
+                    .map_err(|_| ScreenshotError::CaptureThreadFailed)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LiveCapture {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+impl Shared {
+    fn latest(&self) -> MutexGuard<'_, Option<Arc<Screenshot>>> {
+        // only ever holds a complete frame, even if a reader panicked
+        self.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&self, capturer: &mut Capturer, interval: Duration) {
+        let mut changes = capturer.options().change_detector();
+        let mut spare = None;
+        while !self.stop.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            if let Ok(frame) = capturer.capture_swap(spare.take()) {
+                let changed = match &mut changes {
+                    Some(changes) => changes.should_deliver_frame(&frame),
+                    None => true,
+                };
+                if changed {
+                    let previous = self.latest().replace(Arc::new(frame));
+                    // Capture into the previous frame next, unless a reader
+                    // still holds it.
+                    spare = previous.and_then(|frame| Arc::try_unwrap(frame).ok());
+                } else {
+                    // nothing changed, so keep the published frame
+                    spare = Some(frame);
+                }
+            }
+            if let Some(wait) = interval.checked_sub(started.elapsed()) {
+                thread::park_timeout(wait);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_live_capture() {
+    let live = LiveCapture::start(Capturer::new(), Duration::from_millis(10)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let frame = loop {
+        if let Some(frame) = live.latest() {
+            break frame;
+        }
+        assert!(Instant::now() < deadline, "no frame captured");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(!frame.is_empty());
+    let data = frame.data().to_vec();
+    thread::sleep(Duration::from_millis(50));
+    // a held frame isn't reused for later captures
+    assert_eq!(frame.data(), &data[..]);
+    live.stop().unwrap();
+    assert_eq!(frame.len(), data.len());
+
+    // an unchanged screen keeps the first frame published
+    let mut capturer = Capturer::new();
+    capturer.set_options(crate::CaptureOptions {
+        skip_duplicate_frames: true,
+        ..Default::default()
+    });
+    let live = LiveCapture::start(capturer, Duration::from_millis(10)).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    if let Some(frame) = live.latest() {
+        assert_eq!(frame.fingerprint(), live.latest().unwrap().fingerprint());
+    }
+}
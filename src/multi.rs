@@ -0,0 +1,374 @@
+//! Streaming captures of several monitors at once, one thread per monitor.
+
+use crate::{
+    stream::{Queue, Shared},
+    CaptureHandle, CaptureOptions, CaptureSummary, Capturer, FrameReceiver, Monitor,
+    MonitorSelector, PooledFrame, QueuePolicy, QueueStats, Screenshot, ScreenshotError,
+};
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How `spawn_multi_capture` delivers frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultiChannels {
+    /// All frames over one queue, tagged with their monitor. The policy's
+    /// capacity is shared by all monitors.
+    Merged(QueuePolicy),
+    /// A queue per monitor, each with this policy.
+    PerMonitor(QueuePolicy),
+}
+
+/// The receiving end of `spawn_multi_capture`, as its `MultiChannels` say.
+pub enum MultiReceiver {
+    /// One receiver for all monitors.
+    Merged(MergedReceiver),
+    /// A receiver per monitor, in the order they were selected.
+    PerMonitor(Vec<FrameReceiver>),
+}
+
+/// A frame of one of the monitors of `spawn_multi_capture`.
+pub struct MonitorFrame {
+    /// Index of the monitor in the selection, and in
+    /// `MultiCaptureHandle::monitors`.
+    pub monitor: usize,
+    /// The frame, or why its capture failed.
+    pub frame: Result<PooledFrame, ScreenshotError>,
+}
+
+struct Merged {
+    queue: Queue<MonitorFrame>,
+    /// Threads still capturing; the last one closes the queue.
+    producers: AtomicUsize,
+}
+
+/// A thread adding to the merged queue, counted out when dropped, even
+/// by a panic.
+struct Producer(Arc<Merged>);
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        if self.0.producers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.queue.close();
+        }
+    }
+}
+
+/// Receives the frames of all monitors of `spawn_multi_capture`, oldest
+/// first, like a `FrameReceiver`. Ends once every monitor's capture
+/// stopped and the queue is drained.
+///
+/// Dropping the receiver stops the capture threads.
+pub struct MergedReceiver {
+    merged: Arc<Merged>,
+}
+
+impl MergedReceiver {
+    /// Waits for the next frame. None once the captures stopped and the
+    /// queue is drained.
+    pub fn recv(&self) -> Option<MonitorFrame> {
+        self.merged.queue.pop(true)
+    }
+
+    /// The next frame if one is queued, without waiting.
+    pub fn try_recv(&self) -> Option<MonitorFrame> {
+        self.merged.queue.pop(false)
+    }
+
+    /// Frame counts of the merged queue.
+    pub fn stats(&self) -> QueueStats {
+        self.merged.queue.stats()
+    }
+}
+
+impl Iterator for MergedReceiver {
+    type Item = MonitorFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+impl Drop for MergedReceiver {
+    fn drop(&mut self) {
+        self.merged.queue.abandon();
+    }
+}
+
+/// Controls the threads started by `spawn_multi_capture`. Dropping it
+/// stops them and waits for them to finish.
+pub struct MultiCaptureHandle {
+    monitors: Vec<Monitor>,
+    options: CaptureOptions,
+    workers: Vec<CaptureHandle>,
+    merged: Option<Arc<Merged>>,
+}
+
+impl MultiCaptureHandle {
+    /// The captured monitors, indexed by `MonitorFrame::monitor`.
+    pub fn monitors(&self) -> &[Monitor] {
+        &self.monitors
+    }
+
+    /// Changes the time between frames of every monitor.
+    pub fn set_interval(&self, interval: Duration) {
+        self.workers.iter().for_each(|w| w.set_interval(interval));
+    }
+
+    /// Pauses every monitor's capture, see `CaptureHandle::pause`.
+    pub fn pause(&self) {
+        self.workers.iter().for_each(CaptureHandle::pause);
+    }
+
+    pub fn resume(&self) {
+        self.workers.iter().for_each(CaptureHandle::resume);
+    }
+
+    /// Stops capturing and waits for the threads to finish, failing like
+    /// `CaptureHandle::stop` if any thread failed. Frames already queued
+    /// can still be received.
+    pub fn stop(&self) -> Result<(), ScreenshotError> {
+        // wakes threads blocked on a full merged queue
+        if let Some(merged) = &self.merged {
+            merged.queue.close();
+        }
+        self.workers
+            .iter()
+            .map(CaptureHandle::stop)
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Waits for every monitor's capture to end by itself, see
+    /// `CaptureHandle::join`, and sums each up, by monitor. Frames dropped
+    /// from a merged queue are counted by `MergedReceiver::stats` instead.
+    pub fn join(&self) -> Result<Vec<CaptureSummary>, ScreenshotError> {
+        self.workers.iter().map(CaptureHandle::join).collect()
+    }
+
+    /// Captures every monitor once, on threads of their own that start
+    /// their blits together, and returns the frames by monitor. The
+    /// streaming captures go on meanwhile.
+    ///
+    /// The blits start within tens of microseconds of each other, but each
+    /// copies what its monitor shows at that moment, and monitors refresh
+    /// independently of each other. So the frames can be up to a refresh
+    /// interval apart in content (16.7 ms at 60 Hz), plus the difference in
+    /// how long the blits take, a few milliseconds for large monitors.
+    /// `CaptureMetadata::captured_at` shows when each blit completed.
+    pub fn capture_all_now(&self) -> Vec<Result<Screenshot, ScreenshotError>> {
+        let line = StartLine::default();
+        thread::scope(|scope| {
+            let captures: Vec<_> = self
+                .monitors
+                .iter()
+                .map(|monitor| {
+                    let mut capturer = Capturer::for_monitor(monitor);
+                    capturer.set_options(self.options.clone());
+                    let line = &line;
+                    thread::Builder::new()
+                        .name("screenshot-snapshot".into())
+                        .spawn_scoped(scope, move || {
+                            line.wait();
+                            capturer.capture_swap(None)
+                        })
+                })
+                .collect();
+            line.release(captures.iter().filter(|c| c.is_ok()).count());
+            captures
+                .into_iter()
+                .map(|capture| match capture {
+                    Ok(capture) => capture
+                        .join()
+                        .unwrap_or(Err(ScreenshotError::CaptureThreadFailed)),
+                    Err(_) => Err(ScreenshotError::CaptureThreadFailed),
+                })
+                .collect()
+        })
+    }
+}
+
+impl Drop for MultiCaptureHandle {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Holds threads back until all of them are ready, then lets them go at
+/// once.
+#[derive(Default)]
+struct StartLine {
+    /// Threads waiting, and whether they may go.
+    state: Mutex<(usize, bool)>,
+    changed: Condvar,
+}
+
+impl StartLine {
+    fn lock(&self) -> MutexGuard<'_, (usize, bool)> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait(&self) {
+        let mut state = self.lock();
+        state.0 += 1;
+        self.changed.notify_all();
+        while !state.1 {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Waits for `threads` threads to arrive, then lets them go.
+    fn release(&self, threads: usize) {
+        let mut state = self.lock();
+        while state.0 < threads {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.1 = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Captures each of `monitors` every `interval` on a thread of its own, as
+/// `Capturer::spawn` does, with its own device contexts and buffers.
+/// Frames are delivered as `channels` say. Stop conditions in `options`
+/// apply to each monitor separately.
+pub fn spawn_multi_capture(
+    monitors: Vec<MonitorSelector>,
+    options: CaptureOptions,
+    interval: Duration,
+    channels: MultiChannels,
+) -> Result<(MultiReceiver, MultiCaptureHandle), ScreenshotError> {
+    let all = crate::monitors()?;
+    let monitors = monitors
+        .iter()
+        .map(|selector| selector.resolve(&all))
+        .collect::<Result<Vec<_>, _>>()?;
+    let capturer = |monitor: &Monitor| {
+        let mut capturer = Capturer::for_monitor(monitor);
+        capturer.set_options(options.clone());
+        capturer
+    };
+
+    let (receiver, workers, merged) = match channels {
+        MultiChannels::PerMonitor(queue) => {
+            let (receivers, workers): (Vec<_>, Vec<_>) = monitors
+                .iter()
+                .map(|monitor| capturer(monitor).spawn(interval, queue))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .unzip();
+            (MultiReceiver::PerMonitor(receivers), workers, None)
+        }
+        MultiChannels::Merged(queue) => {
+            let merged = Arc::new(Merged {
+                queue: Queue::new(queue),
+                producers: AtomicUsize::new(monitors.len()),
+            });
+            let workers = monitors
+                .iter()
+                .enumerate()
+                .map(|(i, monitor)| {
+                    let mut capturer = capturer(monitor);
+                    let producer = Producer(merged.clone());
+                    // its own queue stays empty
+                    let shared = Arc::new(Shared::new(interval, QueuePolicy::default()));
+                    CaptureHandle::spawn(shared, move |shared| {
+                        let merged = &producer.0;
+                        let options = capturer.options().clone();
+                        shared.run_into(
+                            &options,
+                            queue.capacity(),
+                            |pool| capturer.capture_pooled(pool),
+                            |frame| merged.queue.push(MonitorFrame { monitor: i, frame }),
+                        );
+                        Ok(())
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let receiver = MergedReceiver {
+                merged: merged.clone(),
+            };
+            (MultiReceiver::Merged(receiver), workers, Some(merged))
+        }
+    };
+    Ok((
+        receiver,
+        MultiCaptureHandle {
+            monitors,
+            options,
+            workers,
+            merged,
+        },
+    ))
+}
+
+#[test]
+fn test_multi_capture() {
+    let count = crate::monitors().unwrap().len();
+    let selectors: Vec<_> = (0..count).map(MonitorSelector::Index).collect();
+    assert!(matches!(
+        spawn_multi_capture(
+            vec![MonitorSelector::Index(count)],
+            CaptureOptions::default(),
+            Duration::from_millis(10),
+            MultiChannels::Merged(QueuePolicy::default()),
+        ),
+        Err(ScreenshotError::NoSuchMonitor(i)) if i == count
+    ));
+
+    let (frames, handle) = spawn_multi_capture(
+        selectors.clone(),
+        CaptureOptions::default(),
+        Duration::from_millis(10),
+        MultiChannels::Merged(QueuePolicy::Unbounded),
+    )
+    .unwrap();
+    let frames = match frames {
+        MultiReceiver::Merged(frames) => frames,
+        MultiReceiver::PerMonitor(_) => unreachable!(),
+    };
+    // every monitor shows up, tagged
+    let mut seen = vec![false; count];
+    while seen.contains(&false) {
+        let frame = frames.recv().unwrap();
+        let info = frame.frame.unwrap().info();
+        let rect = handle.monitors()[frame.monitor].rect;
+        assert_eq!(
+            (info.width, info.height),
+            (rect.width as usize, rect.height as usize)
+        );
+        seen[frame.monitor] = true;
+    }
+
+    let snapshot = handle.capture_all_now();
+    assert_eq!(snapshot.len(), count);
+    for (frame, monitor) in snapshot.into_iter().zip(handle.monitors()) {
+        let frame = frame.unwrap();
+        assert_eq!(frame.width(), monitor.rect.width as usize);
+        assert_eq!(frame.metadata().unwrap().source, monitor.rect);
+    }
+    handle.stop().unwrap();
+    // ends once the captures stopped
+    assert!(frames.count() < 1000);
+
+    let (frames, handle) = spawn_multi_capture(
+        selectors,
+        CaptureOptions::default(),
+        Duration::from_millis(10),
+        MultiChannels::PerMonitor(QueuePolicy::default()),
+    )
+    .unwrap();
+    match frames {
+        MultiReceiver::PerMonitor(frames) => {
+            assert_eq!(frames.len(), count);
+            assert!(frames.iter().all(|frames| frames.recv().unwrap().is_ok()));
+        }
+        MultiReceiver::Merged(_) => unreachable!(),
+    }
+    drop(handle);
+}
@@ -0,0 +1,570 @@
+//! Knobs shared by every kind of capture.
+
+#[cfg(all(windows, feature = "gdi"))]
+use crate::capture_environment;
+use crate::{
+    change::ChangeDetector, stop::StopCheck, trace::trace_event, validate_region, Backend,
+    CaptureBackend, CaptureTarget, ChangeFilter, Monitor, MonitorSelector, Pacing, Rect,
+    Screenshot, ScreenshotError, StopCondition, WindowId,
+};
+
+use std::{thread, time::Duration};
+
+/// How often, and how patiently, a failed capture is retried.
+/// Only transient errors (see `ScreenshotError::is_transient`) are retried.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(default, deny_unknown_fields))]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    #[cfg_attr(feature = "config", serde(with = "crate::config::duration"))]
+    pub delay: Duration,
+    /// Factor the delay is multiplied by after every retry. The delay never
+    /// grows past `MAX_RETRY_DELAY`, or `delay` if that's longer.
+    pub backoff: f32,
+}
+
+impl Default for RetryPolicy {
+    /// A single attempt, i.e. no retries.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            delay: Duration::from_millis(50),
+            backoff: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `f` until it succeeds, fails with a permanent error, or the
+    /// attempts are used up.
+    pub fn run<T>(
+        &self,
+        mut f: impl FnMut() -> Result<T, ScreenshotError>,
+    ) -> Result<T, ScreenshotError> {
+        let max_attempts = self.max_attempts.max(1);
+        let mut delay = self.delay;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match f() {
+                Ok(v) => return Ok(v),
+                Err(e) if !e.is_transient() => return Err(e),
+                Err(e) => {
+                    if attempts >= max_attempts {
+                        trace_event!(warn, attempts, error = %e, "giving up");
+                        return Err(if attempts == 1 {
+                            e
+                        } else {
+                            ScreenshotError::RetriesExhausted {
+                                attempts,
+                                last: Box::new(e),
+                            }
+                        });
+                    }
+                    trace_event!(warn, attempt = attempts, error = %e, "retrying in {:?}", delay);
+                    thread::sleep(delay);
+                    delay = self.next_delay(delay);
+                }
+            }
+        }
+    }
+
+    /// The delay after `delay`, capped rather than overflowing.
+    fn next_delay(&self, delay: Duration) -> Duration {
+        let max = MAX_RETRY_DELAY.max(self.delay);
+        let next = delay.as_secs_f64() * f64::from(self.backoff.max(0.0));
+        Duration::try_from_secs_f64(next).map_or(max, |next| next.min(max))
+    }
+}
+
+/// Longest delay `RetryPolicy::backoff` grows the delay between retries to.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Why `include_cursor` is rejected with a forced DXGI backend.
+pub(crate) const NO_DUPLICATED_CURSOR: &str = "desktop duplication can't draw the cursor";
+
+/// Largest width or height accepted by default. Larger values come from
+/// broken mirror or virtual display drivers rather than real displays.
+pub const DEFAULT_MAX_DIMENSION: u32 = 32768;
+
+/// Knobs for a capture.
+///
+/// Besides setting the fields, options can be built up with chained calls
+/// and then captured with, or handed to a `Capturer`:
+///
+/// ```no_run
+/// # use screenshot::{Backend, CaptureOptions, MonitorSelector, Rect};
+/// let region = Rect { x: 0, y: 0, width: 640, height: 480 };
+/// let shot = CaptureOptions::new()
+///     .monitor(MonitorSelector::Index(1))
+///     .region(region)
+///     .backend(Backend::Auto)
+///     .capture()?;
+/// # Ok::<(), screenshot::ScreenshotError>(())
+/// ```
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(default, deny_unknown_fields))]
+pub struct CaptureOptions {
+    /// How `Capturer` and the free functions capture on Windows. `Auto` by
+    /// default; a forced backend that's unavailable fails every capture
+    /// with `ScreenshotError::BackendUnavailable`.
+    pub backend: Backend,
+    /// The monitor `capture`, `Capturer::with_options` and `spawn_capture`
+    /// capture. The primary one if None, unless there's a `region` or
+    /// `window`.
+    pub monitor: Option<MonitorSelector>,
+    /// The area to capture instead of a whole monitor: relative to the
+    /// monitor's top left corner if there's a `monitor`, in virtual-screen
+    /// coordinates otherwise. Read by the same functions as `monitor`.
+    pub region: Option<Rect>,
+    /// A window to capture instead of a monitor. Can't be combined with
+    /// `monitor` or `region`. Window handles only live as long as the
+    /// window, so a `CaptureProfile` can't set it.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub window: Option<WindowId>,
+    /// Have the `window` draw itself with `PrintWindow` instead of copying
+    /// its area of the screen, so windows on top of it don't show and
+    /// parts off screen are included. Some hardware-accelerated windows
+    /// come out black this way. Only used by the GDI backend.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub print_window: bool,
+    /// Draw the mouse cursor into the frame where it is; it's left out by
+    /// default. Desktop duplication can't draw it, so with this set frames
+    /// are taken with GDI instead when `Auto` picked DXGI, and asking for
+    /// `Backend::DxgiDuplication` fails with
+    /// `ScreenshotError::InvalidOptions`.
+    pub include_cursor: bool,
+    /// Capture as a per-monitor DPI aware thread, for the duration of the
+    /// capture only, so scaled monitors come out at their physical
+    /// resolution whatever the calling thread's awareness. `region` and
+    /// window rects are then in physical pixels too. Only used on Windows.
+    pub dpi_aware: bool,
+    pub retry: RetryPolicy,
+    /// Largest width or height to capture; anything above is reported as
+    /// `ScreenshotError::DimensionsTooLarge` before allocating.
+    pub max_dimension: u32,
+    /// Fail with `ScreenshotError::DegradedEnvironment` instead of returning
+    /// a likely black frame when the session is disconnected or locked.
+    /// Only checked by the GDI backend.
+    pub fail_on_degraded: bool,
+    /// Time each step of the capture, see `Capturer::last_metrics`. Off by
+    /// default, in which case the clock isn't read at all.
+    pub collect_metrics: bool,
+    /// In streaming captures such as `LiveCapture`, drop frames whose
+    /// `Screenshot::fingerprint` matches the previous frame's. Same as
+    /// `only_on_change` with the default filter.
+    pub skip_duplicate_frames: bool,
+    /// In streaming captures (`LiveCapture`, `Capturer::frames`,
+    /// `Capturer::spawn`, `Capturer::on_frame` and `frame_stream`), drop
+    /// frames that don't differ from the last delivered one as the filter
+    /// says. The first frame is always delivered.
+    pub only_on_change: Option<ChangeFilter>,
+    /// How `Capturer::frames`, `Capturer::spawn` and `Capturer::on_frame`
+    /// wait for their next frame.
+    pub pacing: Pacing,
+    /// When `Capturer::frames`, `Capturer::spawn`, `Capturer::on_frame` and
+    /// `frame_stream` end by themselves. Never, by default.
+    pub stop_when: StopCondition,
+    /// Forces the capture to fail at the given point, to test that error
+    /// paths release all OS handles. Not part of the stable API.
+    #[doc(hidden)]
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub inject_fault: Option<FaultPoint>,
+}
+
+/// Points at which `CaptureOptions::inject_fault` makes a capture fail.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultPoint {
+    /// After the bitmap is created, as if `BitBlt` failed.
+    BitBlt,
+    /// After the blit, as if `GetDIBits` copied nothing.
+    GetDIBits,
+    /// Before a DXGI or Windows.Graphics.Capture frame is taken, as if the
+    /// session broke.
+    Session,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            backend: Backend::Auto,
+            monitor: None,
+            region: None,
+            window: None,
+            print_window: false,
+            include_cursor: false,
+            dpi_aware: false,
+            retry: RetryPolicy::default(),
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            fail_on_degraded: false,
+            collect_metrics: false,
+            skip_duplicate_frames: false,
+            only_on_change: None,
+            pacing: Pacing::default(),
+            stop_when: StopCondition::Manual,
+            inject_fault: None,
+        }
+    }
+}
+
+impl CaptureOptions {
+    /// The default options, to build on with the methods below.
+    pub fn new() -> Self {
+        CaptureOptions::default()
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn monitor(mut self, monitor: MonitorSelector) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    pub fn region(mut self, region: Rect) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn window(mut self, window: WindowId) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn print_window(mut self, print_window: bool) -> Self {
+        self.print_window = print_window;
+        self
+    }
+
+    pub fn include_cursor(mut self, include_cursor: bool) -> Self {
+        self.include_cursor = include_cursor;
+        self
+    }
+
+    pub fn dpi_aware(mut self, dpi_aware: bool) -> Self {
+        self.dpi_aware = dpi_aware;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn max_dimension(mut self, max_dimension: u32) -> Self {
+        self.max_dimension = max_dimension;
+        self
+    }
+
+    pub fn fail_on_degraded(mut self, fail_on_degraded: bool) -> Self {
+        self.fail_on_degraded = fail_on_degraded;
+        self
+    }
+
+    pub fn collect_metrics(mut self, collect_metrics: bool) -> Self {
+        self.collect_metrics = collect_metrics;
+        self
+    }
+
+    pub fn only_on_change(mut self, filter: ChangeFilter) -> Self {
+        self.only_on_change = Some(filter);
+        self
+    }
+
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    pub fn stop_when(mut self, stop_when: StopCondition) -> Self {
+        self.stop_when = stop_when;
+        self
+    }
+
+    /// Checks that the options go together, failing with
+    /// `ScreenshotError::InvalidOptions` or, for an empty region,
+    /// `ScreenshotError::InvalidRegion`. Done by `capture` and
+    /// `Capturer::with_options` before anything is captured.
+    pub fn validate(&self) -> Result<(), ScreenshotError> {
+        if self.window.is_some() && self.monitor.is_some() {
+            return Err(ScreenshotError::InvalidOptions(
+                "a window can't be combined with a monitor",
+            ));
+        }
+        if self.window.is_some() && self.region.is_some() {
+            return Err(ScreenshotError::InvalidOptions(
+                "a window can't be combined with a region",
+            ));
+        }
+        if self.include_cursor && self.backend == Backend::DxgiDuplication {
+            return Err(ScreenshotError::InvalidOptions(NO_DUPLICATED_CURSOR));
+        }
+        if !(self.retry.backoff.is_finite() && self.retry.backoff >= 0.0) {
+            return Err(ScreenshotError::InvalidOptions(
+                "the retry backoff must be a finite number, not negative",
+            ));
+        }
+        match self.region {
+            Some(region) if region.is_empty() => Err(ScreenshotError::InvalidRegion(region)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Captures what the options select with the backend they ask for,
+    /// e.g. `CaptureOptions::new().region(rect).capture()`.
+    pub fn capture(&self) -> Result<Screenshot, ScreenshotError> {
+        self.validate()?;
+        let mut backend = self.backend.open()?;
+        let target = self.target(|| backend.monitors())?;
+        backend.capture_target(target, self)
+    }
+
+    /// What `monitor`, `region` and `window` select, with `monitors` asked
+    /// for the monitors if a monitor is selected.
+    pub(crate) fn target(
+        &self,
+        monitors: impl FnOnce() -> Result<Vec<Monitor>, ScreenshotError>,
+    ) -> Result<CaptureTarget, ScreenshotError> {
+        self.validate()?;
+        // monitor rects must be in the same pixels as the capture
+        #[cfg(all(windows, feature = "gdi"))]
+        let _dpi = crate::backend::gdi::DpiAwareScope::enter(self.dpi_aware);
+        Ok(match (self.window, &self.monitor, self.region) {
+            (Some(window), _, _) => CaptureTarget::Window(window),
+            (None, None, None) => CaptureTarget::Primary,
+            (None, None, Some(region)) => CaptureTarget::Region(region),
+            (None, Some(selector), region) => {
+                let monitor = selector.resolve(&monitors()?)?;
+                match region {
+                    None => CaptureTarget::Region(monitor.rect),
+                    Some(region) => {
+                        let region = Rect {
+                            x: monitor.rect.x.saturating_add(region.x),
+                            y: monitor.rect.y.saturating_add(region.y),
+                            ..region
+                        };
+                        CaptureTarget::Region(validate_region(region, monitor.rect)?)
+                    }
+                }
+            }
+        })
+    }
+
+    /// What streaming captures use to drop unchanged frames, if anything.
+    pub(crate) fn change_detector(&self) -> Option<ChangeDetector> {
+        match &self.only_on_change {
+            Some(filter) => Some(ChangeDetector::new(filter.clone())),
+            None if self.skip_duplicate_frames => {
+                Some(ChangeDetector::new(ChangeFilter::default()))
+            }
+            None => None,
+        }
+    }
+
+    /// What streaming captures use to tell when to end, starting now.
+    pub(crate) fn stop_check(&self) -> StopCheck {
+        StopCheck::new(self.stop_when.clone())
+    }
+
+    #[cfg(all(windows, feature = "gdi"))]
+    pub(crate) fn check_environment(&self) -> Result<(), ScreenshotError> {
+        if self.fail_on_degraded {
+            let env = capture_environment();
+            if env.is_degraded() {
+                trace_event!(warn, ?env, "degraded environment");
+                return Err(ScreenshotError::DegradedEnvironment(env));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        delay: Duration::ZERO,
+        backoff: 1.0,
+    };
+
+    let mut calls = 0;
+    let res: Result<(), _> = policy.run(|| {
+        calls += 1;
+        Err(ScreenshotError::BitBltFailed)
+    });
+    assert_eq!(calls, 3);
+    assert!(matches!(
+        res,
+        Err(ScreenshotError::RetriesExhausted { attempts: 3, .. })
+    ));
+
+    let mut calls = 0;
+    let res = policy.run(|| {
+        calls += 1;
+        if calls < 2 {
+            Err(ScreenshotError::BitBltFailed)
+        } else {
+            Ok(calls)
+        }
+    });
+    assert_eq!(res.unwrap(), 2);
+
+    // permanent errors are not retried
+    let mut calls = 0;
+    let res: Result<(), _> = policy.run(|| {
+        calls += 1;
+        Err(ScreenshotError::EmptyDisplay {
+            width: 0,
+            height: 0,
+        })
+    });
+    assert_eq!(calls, 1);
+    assert!(matches!(res, Err(ScreenshotError::EmptyDisplay { .. })));
+
+    // the default policy doesn't retry or wrap the error
+    let res: Result<(), _> = RetryPolicy::default().run(|| Err(ScreenshotError::BitBltFailed));
+    assert!(matches!(res, Err(ScreenshotError::BitBltFailed)));
+
+    // the delay grows up to a cap instead of overflowing
+    let policy = RetryPolicy {
+        max_attempts: 100,
+        delay: Duration::from_millis(50),
+        backoff: f32::INFINITY,
+    };
+    assert_eq!(policy.next_delay(policy.delay), MAX_RETRY_DELAY);
+    assert_eq!(policy.next_delay(Duration::MAX), MAX_RETRY_DELAY);
+    let policy = RetryPolicy {
+        backoff: 2.0,
+        ..policy
+    };
+    assert_eq!(policy.next_delay(policy.delay), Duration::from_millis(100));
+    assert_eq!(policy.next_delay(Duration::from_secs(40)), MAX_RETRY_DELAY);
+    let policy = RetryPolicy {
+        delay: Duration::from_secs(90),
+        backoff: f32::NAN,
+        ..policy
+    };
+    assert_eq!(policy.next_delay(policy.delay), Duration::ZERO);
+    for backoff in [f32::INFINITY, f32::NAN, -1.0] {
+        let options = CaptureOptions::new().retry(RetryPolicy { backoff, ..policy });
+        assert!(matches!(
+            options.validate(),
+            Err(ScreenshotError::InvalidOptions(_))
+        ));
+    }
+}
+
+#[test]
+fn test_options_builder() {
+    let monitor = |x, width, primary| Monitor {
+        rect: Rect {
+            x,
+            y: 0,
+            width,
+            height: 1080,
+        },
+        work_area: Rect::default(),
+        primary,
+        scale_factor: 1.0,
+        name: None,
+        device_name: None,
+        refresh_rate: None,
+        bits_per_pixel: None,
+        orientation: None,
+        physical_size_mm: None,
+    };
+    let monitors = || Ok(vec![monitor(0, 1920, true), monitor(-1280, 1280, false)]);
+    let region = Rect {
+        x: 10,
+        y: 20,
+        width: 300,
+        height: 200,
+    };
+
+    let options = CaptureOptions::new()
+        .backend(Backend::Gdi)
+        .monitor(MonitorSelector::Index(1))
+        .region(region)
+        .collect_metrics(true);
+    assert_eq!(options.backend, Backend::Gdi);
+    assert!(options.collect_metrics);
+    // the region is relative to the monitor, and must lie within it
+    assert_eq!(
+        options.target(monitors).unwrap(),
+        CaptureTarget::Region(Rect { x: -1270, ..region })
+    );
+    let too_wide = options.clone().region(Rect {
+        width: 1280,
+        ..region
+    });
+    assert!(matches!(
+        too_wide.target(monitors),
+        Err(ScreenshotError::InvalidRegion(_))
+    ));
+    assert!(matches!(
+        options
+            .clone()
+            .monitor(MonitorSelector::Index(2))
+            .target(monitors),
+        Err(ScreenshotError::NoSuchMonitor(2))
+    ));
+
+    // without a monitor, regions are in virtual-screen coordinates, and
+    // the monitors aren't even listed
+    let no_monitors = || -> Result<Vec<Monitor>, _> { panic!("listed the monitors") };
+    assert_eq!(
+        CaptureOptions::new()
+            .region(region)
+            .target(no_monitors)
+            .unwrap(),
+        CaptureTarget::Region(region)
+    );
+    assert_eq!(
+        CaptureOptions::new().target(no_monitors).unwrap(),
+        CaptureTarget::Primary
+    );
+    assert_eq!(
+        CaptureOptions::new()
+            .monitor(MonitorSelector::Primary)
+            .target(monitors)
+            .unwrap(),
+        CaptureTarget::Region(monitor(0, 1920, true).rect)
+    );
+
+    // conflicting targets fail before anything is looked up
+    let window = CaptureOptions::new().window(WindowId(42));
+    assert_eq!(
+        window.target(no_monitors).unwrap(),
+        CaptureTarget::Window(WindowId(42))
+    );
+    for conflicting in [
+        window.clone().monitor(MonitorSelector::Primary),
+        window.region(region),
+    ] {
+        assert!(matches!(
+            conflicting.validate(),
+            Err(ScreenshotError::InvalidOptions(_))
+        ));
+        assert!(conflicting.capture().is_err());
+    }
+    // duplicated frames can't have the cursor; Auto takes them with GDI
+    let cursor = CaptureOptions::new().include_cursor(true);
+    assert!(cursor.validate().is_ok());
+    assert!(matches!(
+        cursor.backend(Backend::DxgiDuplication).validate(),
+        Err(ScreenshotError::InvalidOptions(_))
+    ));
+    assert!(matches!(
+        CaptureOptions::new()
+            .region(Rect::default())
+            .target(no_monitors),
+        Err(ScreenshotError::InvalidRegion(_))
+    ));
+}
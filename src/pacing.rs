@@ -0,0 +1,263 @@
+//! Waiting for the next frame of a streaming capture.
+
+#[cfg(windows)]
+use windows::Win32::Media::{timeBeginPeriod, timeEndPeriod, TIMERR_NOERROR};
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How streaming captures wait for their next frame, see
+/// `CaptureOptions::pacing`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum Pacing {
+    /// Sleep until the frame is due. Windows wakes sleepers up to 15.6 ms
+    /// late by default, so frames are late by as much, but as the next one
+    /// is due an interval after this one was due, the lateness doesn't add
+    /// up. An overdue frame is taken right away and the cadence restarts
+    /// from there.
+    #[default]
+    Sleep,
+    /// Keep to deadlines a whole number of intervals after the first frame.
+    /// Raises the timer resolution to 1 ms while capturing, sleeps until
+    /// shortly before a frame is due and spins for the rest, which costs
+    /// some CPU. A late frame is taken right away without moving later
+    /// deadlines; deadlines that passed meanwhile are skipped rather than
+    /// caught up with.
+    Precise,
+}
+
+/// How well a streaming capture kept to its interval, see e.g.
+/// `CaptureHandle::pacing_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacingStats {
+    /// Frames taken, including unchanged ones and failed captures.
+    pub frames: u64,
+    /// Deadlines skipped because the capture fell behind by more than an
+    /// interval.
+    pub skipped: u64,
+    /// Time from the first frame to the latest, leaving out pauses.
+    pub elapsed: Duration,
+    /// Stretches of frames without a pause in between.
+    runs: u64,
+}
+
+impl PacingStats {
+    /// Frames per second achieved, zero before the second frame.
+    pub fn fps(&self) -> f64 {
+        let intervals = self.frames - self.runs;
+        if intervals == 0 || self.elapsed.is_zero() {
+            return 0.0;
+        }
+        intervals as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Average time between frames, zero before the second frame.
+    pub fn average_interval(&self) -> Duration {
+        match self.frames - self.runs {
+            0 => Duration::ZERO,
+            intervals => {
+                Duration::from_nanos((self.elapsed.as_nanos() / u128::from(intervals)) as u64)
+            }
+        }
+    }
+}
+
+/// Time before a deadline that `Pacing::Precise` spins through rather than
+/// sleeps, covering how late the timer wakes up at 1 ms resolution.
+const SPIN: Duration = Duration::from_millis(2);
+
+/// Schedules the frames of a streaming capture as its `Pacing` says.
+pub(crate) struct Pacer {
+    pacing: Pacing,
+    interval: Duration,
+    /// When the previous frame was due, once there is one.
+    previous: Option<Instant>,
+    /// When the scheduled frame is due; None before the first.
+    due: Option<Instant>,
+    /// When the latest frame was taken, None at the start of a run.
+    taken: Option<Instant>,
+    stats: PacingStats,
+    _resolution: Option<TimerResolution>,
+}
+
+impl Pacer {
+    pub(crate) fn new(pacing: Pacing, interval: Duration) -> Self {
+        Pacer {
+            pacing,
+            interval,
+            previous: None,
+            due: None,
+            taken: None,
+            stats: PacingStats::default(),
+            _resolution: match pacing {
+                Pacing::Sleep => None,
+                Pacing::Precise => TimerResolution::raise(),
+            },
+        }
+    }
+
+    /// Changes the interval, including for the scheduled frame unless it's
+    /// overdue anyway.
+    pub(crate) fn set_interval(&mut self, interval: Duration) {
+        if let (Some(previous), Some(due)) = (self.previous, self.due) {
+            if due == previous + self.interval {
+                self.due = Some(previous + interval);
+            }
+        }
+        self.interval = interval;
+    }
+
+    /// Starts over after a pause, taking the next frame right away.
+    pub(crate) fn restart(&mut self) {
+        self.previous = None;
+        self.due = None;
+        self.taken = None;
+    }
+
+    /// Schedules the next frame, if it's `now`, and returns when it's due.
+    pub(crate) fn schedule(&mut self, now: Instant) -> Instant {
+        let due = match self.due {
+            None => now,
+            Some(last) => {
+                let next = last + self.interval;
+                if next > now {
+                    next
+                } else {
+                    let missed = (now - next)
+                        .as_nanos()
+                        .checked_div(self.interval.as_nanos())
+                        .unwrap_or(0);
+                    self.stats.skipped += missed as u64;
+                    match self.pacing {
+                        Pacing::Sleep => now,
+                        Pacing::Precise => {
+                            next + Duration::from_nanos((self.interval.as_nanos() * missed) as u64)
+                        }
+                    }
+                }
+            }
+        };
+        self.previous = self.due;
+        self.due = Some(due);
+        due
+    }
+
+    /// How long to sleep before the scheduled frame is due, if it's `now`.
+    /// `Pacing::Precise` stops short, leaving the rest to `arrive`.
+    pub(crate) fn sleep_time(&self, now: Instant) -> Duration {
+        let left = self
+            .due
+            .map_or(Duration::ZERO, |due| due.saturating_duration_since(now));
+        match self.pacing {
+            Pacing::Sleep => left,
+            Pacing::Precise => left.saturating_sub(SPIN),
+        }
+    }
+
+    /// Spins until the scheduled frame is due if `Pacing::Precise`, and
+    /// counts it as taken.
+    pub(crate) fn arrive(&mut self) {
+        if let (Pacing::Precise, Some(due)) = (self.pacing, self.due) {
+            while Instant::now() < due {
+                thread::yield_now();
+            }
+        }
+        self.record(Instant::now());
+    }
+
+    /// Waits for the next frame, for captures that can't be interrupted.
+    #[cfg(all(windows, feature = "gdi"))]
+    pub(crate) fn wait(&mut self) {
+        let now = Instant::now();
+        self.schedule(now);
+        let sleep = self.sleep_time(now);
+        if !sleep.is_zero() {
+            thread::sleep(sleep);
+        }
+        self.arrive();
+    }
+
+    pub(crate) fn stats(&self) -> PacingStats {
+        self.stats
+    }
+
+    fn record(&mut self, at: Instant) {
+        match self.taken {
+            Some(taken) => self.stats.elapsed += at.saturating_duration_since(taken),
+            None => self.stats.runs += 1,
+        }
+        self.taken = Some(at);
+        self.stats.frames += 1;
+    }
+}
+
+/// Windows' timer resolution raised to 1 ms, restored on drop. A no-op
+/// elsewhere, where sleeps are precise enough already.
+struct TimerResolution;
+
+impl TimerResolution {
+    #[cfg(windows)]
+    fn raise() -> Option<Self> {
+        (unsafe { timeBeginPeriod(1) } == TIMERR_NOERROR).then_some(TimerResolution)
+    }
+
+    #[cfg(not(windows))]
+    fn raise() -> Option<Self> {
+        Some(TimerResolution)
+    }
+}
+
+#[cfg(windows)]
+impl Drop for TimerResolution {
+    fn drop(&mut self) {
+        unsafe {
+            timeEndPeriod(1);
+        }
+    }
+}
+
+#[test]
+fn test_deadlines_dont_drift() {
+    use crate::testing::{MockCapturer, MockFrame};
+
+    // a simulated 30 fps capture of a thousand frames, whose waits end up
+    // to 15 ms late and whose captures take 5 ms
+    let interval = Duration::from_nanos(1_000_000_000 / 30);
+    let mut mock = MockCapturer::new(64, 64, MockFrame::Gradient);
+    let start = Instant::now();
+    for pacing in [Pacing::Sleep, Pacing::Precise] {
+        let mut pacer = Pacer::new(pacing, interval);
+        let mut now = start;
+        let mut due = start;
+        for i in 0..1000u32 {
+            due = pacer.schedule(now);
+            assert!(due >= now || pacing == Pacing::Precise);
+            now = due.max(now) + Duration::from_millis(u64::from(i * 7 % 16));
+            pacer.record(now);
+            mock.capture().unwrap();
+            now += Duration::from_millis(5);
+        }
+        assert_eq!(due, start + interval * 999);
+        let stats = pacer.stats();
+        assert_eq!((stats.frames, stats.skipped), (1000, 0));
+        assert!((stats.fps() - 30.0).abs() < 0.1, "{}", stats.fps());
+    }
+
+    // a stall skips deadlines without moving later ones
+    let mut pacer = Pacer::new(Pacing::Precise, interval);
+    assert_eq!(pacer.schedule(start), start);
+    let stalled = start + interval * 3 + interval / 2;
+    assert_eq!(pacer.schedule(stalled), start + interval * 3);
+    assert_eq!(pacer.schedule(stalled), start + interval * 4);
+    assert_eq!(pacer.stats().skipped, 2);
+
+    // while sleeping starts over from the late frame
+    let mut pacer = Pacer::new(Pacing::Sleep, interval);
+    pacer.schedule(start);
+    assert_eq!(pacer.schedule(stalled), stalled);
+    assert_eq!(pacer.schedule(stalled), stalled + interval);
+}
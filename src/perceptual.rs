@@ -0,0 +1,127 @@
+//! Perceptual hashes, which stay close for images that look alike, e.g.
+//! captures of the same UI with different antialiasing, unlike the exact
+//! `Screenshot::fingerprint`.
+
+use crate::{ResizeFilter, Screenshot};
+
+use std::f32::consts::PI;
+
+/// Side of the square `phash` takes the DCT of.
+const DCT_SIZE: usize = 32;
+/// Side of the square of lowest frequencies `phash` keeps.
+const LOW_SIZE: usize = 8;
+
+impl Screenshot {
+    /// A 64-bit difference hash: whether brightness increases from each
+    /// pixel to the next of a 9x8 thumbnail. Cheap, and robust against
+    /// scaling, antialiasing and small colour shifts. Compare hashes with
+    /// `hash_distance`; 0 for a screenshot without pixels.
+    pub fn dhash(&self) -> u64 {
+        let luma = self.thumbnail_luma(LOW_SIZE + 1, LOW_SIZE);
+        luma.chunks_exact(LOW_SIZE + 1)
+            .flat_map(|row| row.windows(2).map(|pair| pair[0] < pair[1]))
+            .fold(0, |hash, bit| hash << 1 | u64::from(bit))
+    }
+
+    /// A 64-bit perceptual hash: which of the 8x8 lowest frequencies of the
+    /// DCT of a 32x32 thumbnail's brightness are above their median. Slower
+    /// than `dhash`, but also robust against gamma and contrast changes.
+    /// Compare hashes with `hash_distance`; 0 for a screenshot without
+    /// pixels.
+    pub fn phash(&self) -> u64 {
+        let luma = self.thumbnail_luma(DCT_SIZE, DCT_SIZE);
+        // DCT-II basis of the low frequencies, cos[u][x]
+        let cos: Vec<f32> = (0..LOW_SIZE)
+            .flat_map(|u| {
+                (0..DCT_SIZE).map(move |x| {
+                    ((2 * x + 1) as f32 * u as f32 * PI / (2 * DCT_SIZE) as f32).cos()
+                })
+            })
+            .collect();
+        let basis = |u: usize| &cos[u * DCT_SIZE..][..DCT_SIZE];
+        // along the rows, then down the columns
+        let rows: Vec<f32> = luma
+            .chunks_exact(DCT_SIZE)
+            .flat_map(|row| (0..LOW_SIZE).map(move |u| dot(basis(u), row.iter().copied())))
+            .collect();
+        let coefficients: Vec<f32> = (0..LOW_SIZE)
+            .flat_map(|v| {
+                let rows = &rows;
+                (0..LOW_SIZE)
+                    .map(move |u| dot(basis(v), rows[u..].iter().step_by(LOW_SIZE).copied()))
+            })
+            .collect();
+
+        let mut sorted = coefficients.clone();
+        sorted.sort_by(f32::total_cmp);
+        let median = (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0;
+        coefficients
+            .iter()
+            .fold(0, |hash, &c| hash << 1 | u64::from(c > median))
+    }
+
+    /// Brightness of a `width` x `height` thumbnail, row by row.
+    fn thumbnail_luma(&self, width: usize, height: usize) -> Vec<f32> {
+        match self.resize(width, height, ResizeFilter::Area) {
+            Ok(thumbnail) => thumbnail.to_luma_f32(),
+            // a thumbnail this small always fits
+            Err(_) => vec![0.0; width * height],
+        }
+    }
+}
+
+/// The sum of the products of `a` and `b`, pairwise.
+fn dot(a: &[f32], b: impl Iterator<Item = f32>) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Number of bits that differ between two hashes from `Screenshot::dhash`
+/// or `phash`, from 0 for images that look the same to 64. Up to about 10
+/// usually means the same picture.
+pub fn hash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[test]
+fn test_perceptual_hashes() {
+    // a 64x48 gradient with a dark block, rows padded
+    let image = |shift: u8| {
+        let row_len = 64 * 4 + 8;
+        let mut data = vec![0; row_len * 48];
+        for (y, row) in data.chunks_mut(row_len).enumerate() {
+            for (x, px) in row.chunks_exact_mut(4).take(64).enumerate() {
+                let v = if (16..32).contains(&x) && (8..24).contains(&y) {
+                    20
+                } else {
+                    (x * 3 + y) as u8
+                };
+                px.copy_from_slice(&[v, v.saturating_add(shift), v, 255]);
+            }
+        }
+        Screenshot::from_raw(data, 64, 48, row_len).unwrap()
+    };
+    let (original, shifted) = (image(0), image(4));
+    assert!(hash_distance(original.dhash(), shifted.dhash()) <= 4);
+    assert!(hash_distance(original.phash(), shifted.phash()) <= 4);
+    assert_ne!(original.dhash(), 0);
+    assert_ne!(original.phash(), 0);
+
+    // the same picture in another format hashes the same
+    let mut swapped = image(0);
+    swapped.swap_r_b_in_place();
+    assert_eq!(swapped.dhash(), original.dhash());
+    assert_eq!(swapped.phash(), original.phash());
+
+    // a different picture doesn't come close
+    let mut data = original.to_bgra_vec();
+    for px in data.chunks_exact_mut(4) {
+        px[..3].iter_mut().for_each(|v| *v = 255 - *v);
+    }
+    let inverted = Screenshot::from_raw(data, 64, 48, 64 * 4).unwrap();
+    assert!(hash_distance(original.dhash(), inverted.dhash()) > 32);
+    assert!(hash_distance(original.phash(), inverted.phash()) > 20);
+
+    let empty = Screenshot::from_raw(Vec::new(), 0, 0, 0).unwrap();
+    assert_eq!((empty.dhash(), empty.phash()), (0, 0));
+    assert_eq!(hash_distance(0b1011, 0b0110), 3);
+}
@@ -0,0 +1,21 @@
+//! Single pixels and the channel orders of pixel buffers.
+
+// 4 as 32 bit colour
+pub(crate) const PIXEL_WIDTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pixel {
+    pub a: u8,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Order of the bytes of each pixel in a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Blue, green, red, alpha; what Windows produces.
+    Bgra8,
+    /// Red, green, blue, alpha; what most image libraries expect.
+    Rgba8,
+}
@@ -0,0 +1,182 @@
+//! Reusable frame buffers, for captures handed from one thread to another.
+
+use crate::{CaptureMetadata, FrameInfo};
+
+use std::{
+    mem,
+    ops::Deref,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A stack of pixel buffers shared by everyone holding a clone. Frames
+/// captured with `Capturer::capture_pooled` borrow a buffer from it and put
+/// it back when they're dropped, so a capture thread and an encoder thread
+/// can pass frames around without allocating each time.
+///
+/// At most `capacity` idle buffers are kept; extra ones are freed. Buffers
+/// of a size other than the latest frame's, i.e. from before a resolution
+/// change, are freed rather than reused.
+#[derive(Clone)]
+pub struct FramePool {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    buffers: Vec<Vec<u8>>,
+    capacity: usize,
+    /// Size of the latest frame, which returned buffers must match.
+    frame_len: Option<usize>,
+}
+
+impl FramePool {
+    /// A pool keeping at most `capacity` idle buffers.
+    pub fn new(capacity: usize) -> Self {
+        FramePool {
+            inner: Arc::new(Mutex::new(Inner {
+                buffers: Vec::with_capacity(capacity),
+                capacity,
+                frame_len: None,
+            })),
+        }
+    }
+
+    /// Number of idle buffers in the pool.
+    pub fn available(&self) -> usize {
+        self.lock().buffers.len()
+    }
+
+    /// Takes an idle buffer, or an empty one if there's none.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.lock().buffers.pop().unwrap_or_default()
+    }
+
+    /// Returns a buffer to the pool, unless it's full or the buffer is of
+    /// another size than the latest frame.
+    pub(crate) fn put(&self, buf: Vec<u8>) {
+        let mut inner = self.lock();
+        let fits = !matches!(inner.frame_len, Some(len) if len != buf.len());
+        if fits && inner.buffers.len() < inner.capacity {
+            inner.buffers.push(buf);
+        }
+    }
+
+    /// Records the size of a new frame, freeing idle buffers of any other
+    /// size.
+    pub(crate) fn set_frame_len(&self, len: usize) {
+        let mut inner = self.lock();
+        if inner.frame_len != Some(len) {
+            inner.frame_len = Some(len);
+            inner.buffers.retain(|buf| buf.len() == len);
+        }
+    }
+
+    /// Wraps `buf` so it returns to the pool on drop.
+    pub(crate) fn wrap(
+        &self,
+        buf: Vec<u8>,
+        info: FrameInfo,
+        metadata: Option<CaptureMetadata>,
+    ) -> PooledFrame {
+        PooledFrame {
+            buf,
+            info,
+            metadata,
+            pool: self.clone(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The pool is consistent after every statement, so a panic while
+        // it was locked leaves nothing half-done.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A captured frame whose buffer goes back to its `FramePool` when dropped.
+/// Dereferences to the pixels, laid out as `info` says.
+pub struct PooledFrame {
+    buf: Vec<u8>,
+    info: FrameInfo,
+    metadata: Option<CaptureMetadata>,
+    pool: FramePool,
+}
+
+impl PooledFrame {
+    /// Layout of the pixels.
+    pub fn info(&self) -> FrameInfo {
+        self.info
+    }
+
+    /// When and where the frame was captured, see `Screenshot::metadata`.
+    pub fn metadata(&self) -> Option<CaptureMetadata> {
+        self.metadata
+    }
+
+    pub(crate) fn metadata_mut(&mut self) -> Option<&mut CaptureMetadata> {
+        self.metadata.as_mut()
+    }
+
+    /// Takes the buffer out for good; it won't go back to the pool.
+    pub fn into_inner(mut self) -> Vec<u8> {
+        mem::take(&mut self.buf)
+    }
+}
+
+impl Deref for PooledFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledFrame {
+    fn drop(&mut self) {
+        // empty after `into_inner`, and not worth keeping anyway
+        if self.buf.capacity() != 0 {
+            self.pool.put(mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[test]
+fn test_frame_pool() {
+    let info = |len| FrameInfo {
+        width: len / 4,
+        height: 1,
+        stride: len,
+        format: crate::PixelFormat::Bgra8,
+    };
+    let pool = FramePool::new(2);
+    pool.set_frame_len(8);
+    let frames: Vec<_> = (0..3)
+        .map(|_| pool.wrap(vec![0; 8], info(8), None))
+        .collect();
+    assert_eq!(frames[0].len(), 8);
+    drop(frames);
+    // only as many as the capacity are kept
+    assert_eq!(pool.available(), 2);
+
+    let buf = pool.take();
+    let ptr = buf.as_ptr();
+    pool.put(buf);
+    let buf = pool.take();
+    assert_eq!(buf.as_ptr(), ptr);
+    assert_eq!(pool.available(), 1);
+    pool.put(buf);
+    assert_eq!(pool.available(), 2);
+
+    // buffers detached with `into_inner` don't come back
+    drop(pool.take());
+    assert_eq!(pool.wrap(vec![1; 8], info(8), None).into_inner(), [1; 8]);
+    assert_eq!(pool.available(), 1);
+
+    // a resolution change retires buffers of the old size
+    let old = pool.wrap(vec![0; 8], info(8), None);
+    pool.set_frame_len(16);
+    assert_eq!(pool.available(), 0);
+    drop(old);
+    assert_eq!(pool.available(), 0);
+    drop(pool.wrap(vec![0; 16], info(16), None));
+    assert_eq!(pool.available(), 1);
+}
@@ -0,0 +1,15 @@
+//! The types most captures need, for a glob import:
+//!
+//! ```
+//! use screenshot::prelude::*;
+//!
+//! let options = CaptureOptions::new().monitor(MonitorSelector::Primary);
+//! # let _ = options;
+//! ```
+
+#[cfg(all(windows, feature = "gdi"))]
+pub use crate::Capturer;
+pub use crate::{
+    Backend, CaptureBackend, CaptureOptions, CaptureTarget, ImageFormat, Monitor, MonitorSelector,
+    Pixel, PixelFormat, Point, Rect, Screenshot, ScreenshotError, Size,
+};
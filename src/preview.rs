@@ -0,0 +1,160 @@
+//! Summaries of a screenshot for printing while debugging: `Display` and
+//! `Debug` without the pixels, and a tiny text rendering of the image.
+
+use crate::{PixelFormat, Screenshot, PIXEL_WIDTH};
+
+use std::fmt;
+
+/// Characters from dark to light.
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+impl Screenshot {
+    /// Renders the image `cols` characters wide, each standing for the
+    /// average luminance of its cell, e.g. to check over SSH that the right
+    /// monitor was captured. There are half as many lines per column as the
+    /// aspect ratio gives, as characters are about twice as tall as wide,
+    /// and never more columns or lines than pixels. Every line ends with a
+    /// newline; empty if the screenshot or `cols` is.
+    pub fn ascii_preview(&self, cols: usize) -> String {
+        if !self.has_pixels() || cols == 0 {
+            return String::new();
+        }
+        let cols = cols.min(self.width);
+        let rows = (cols * self.height / self.width / 2).clamp(1, self.height);
+        let mut out = String::with_capacity((cols + 1) * rows);
+        for line in self.cell_means(cols, rows).chunks(cols) {
+            for &[r, g, b] in line {
+                let luma = (299 * r + 587 * g + 114 * b) / 1000;
+                out.push(char::from(RAMP[luma as usize * RAMP.len() / 256]));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn has_pixels(&self) -> bool {
+        self.width != 0 && self.height != 0
+    }
+
+    /// Averages the RGB of the pixels in each of `cols` x `rows` equal
+    /// cells, which are returned row by row. Reads every pixel once.
+    fn cell_means(&self, cols: usize, rows: usize) -> Vec<[u32; 3]> {
+        let (r, b) = self.red_blue_offsets();
+        let col_of: Vec<usize> = (0..self.width).map(|x| x * cols / self.width).collect();
+        let mut sums = vec![[0u64; 4]; cols * rows];
+        for (y, src) in self
+            .data
+            .chunks(self.row_len.max(1))
+            .take(self.height)
+            .enumerate()
+        {
+            let cells = &mut sums[y * rows / self.height * cols..][..cols];
+            for (&col, px) in col_of.iter().zip(src.chunks_exact(PIXEL_WIDTH)) {
+                let sum = &mut cells[col];
+                sum[0] += u64::from(px[r]);
+                sum[1] += u64::from(px[1]);
+                sum[2] += u64::from(px[b]);
+                sum[3] += 1;
+            }
+        }
+        sums.iter()
+            .map(|&[r, g, b, n]| {
+                let n = n.max(1);
+                [(r / n) as u32, (g / n) as u32, (b / n) as u32]
+            })
+            .collect()
+    }
+}
+
+/// One line, e.g. `Screenshot 2560x1440 BGRA8 (14.7 MB), avg color
+/// #1E1E2E`. Computing the average reads every pixel.
+impl fmt::Display for Screenshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            PixelFormat::Bgra8 => "BGRA8",
+            PixelFormat::Rgba8 => "RGBA8",
+        };
+        write!(
+            f,
+            "Screenshot {}x{} {} ({:.1} MB)",
+            self.width,
+            self.height,
+            format,
+            self.len() as f64 / 1e6
+        )?;
+        if self.has_pixels() {
+            let [r, g, b] = self.cell_means(1, 1)[0];
+            write!(f, ", avg color #{:02X}{:02X}{:02X}", r, g, b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything but the pixels.
+impl fmt::Debug for Screenshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Screenshot")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("row_len", &self.row_len)
+            .field("format", &self.format)
+            .field("metadata", &self.metadata)
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn test_display() {
+    let px = [0x2e, 0x1e, 0x1e, 0xff];
+    let data = px.repeat(2560 * 1440);
+    let mut shot = Screenshot::from_raw(data, 2560, 1440, 2560 * 4).unwrap();
+    assert_eq!(
+        shot.to_string(),
+        "Screenshot 2560x1440 BGRA8 (14.7 MB), avg color #1E1E2E"
+    );
+    shot.swap_r_b_in_place();
+    assert_eq!(
+        shot.to_string(),
+        "Screenshot 2560x1440 RGBA8 (14.7 MB), avg color #1E1E2E"
+    );
+    assert_eq!(
+        format!("{:?}", shot),
+        "Screenshot { width: 2560, height: 1440, row_len: 10240, format: Rgba8, \
+         metadata: None, .. }"
+    );
+
+    let empty = Screenshot::from_raw(Vec::new(), 0, 0, 0).unwrap();
+    assert_eq!(empty.to_string(), "Screenshot 0x0 BGRA8 (0.0 MB)");
+    assert_eq!(empty.ascii_preview(80), "");
+}
+
+#[test]
+fn test_ascii_preview() {
+    // grey from black on the left to white on the right, with padded rows
+    let (width, height, row_len) = (10, 4, 48);
+    let mut data = vec![0; row_len * height];
+    for row in data.chunks_mut(row_len) {
+        for (x, px) in row.chunks_exact_mut(4).take(width).enumerate() {
+            let v = (x * 255 / (width - 1)) as u8;
+            px.copy_from_slice(&[v, v, v, 255]);
+        }
+    }
+    let shot = Screenshot::from_raw(data, width, height, row_len).unwrap();
+    assert_eq!(shot.ascii_preview(10), " .:-=+*#%@\n .:-=+*#%@\n");
+    // never wider than the image
+    assert_eq!(shot.ascii_preview(100), shot.ascii_preview(10));
+    assert_eq!(shot.ascii_preview(5), " :=#@\n");
+    assert_eq!(shot.ascii_preview(0), "");
+
+    // a white square in the middle of black
+    let mut data = vec![0; 8 * 8 * 4];
+    for y in 2..6 {
+        data[(y * 8 + 2) * 4..(y * 8 + 6) * 4].fill(255);
+    }
+    let shot = Screenshot::from_raw(data, 8, 8, 32).unwrap();
+    assert_eq!(
+        shot.ascii_preview(8),
+        "        \n  @@@@  \n  @@@@  \n        \n"
+    );
+    assert_eq!(shot.ascii_preview(4), " == \n == \n");
+}
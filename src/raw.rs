@@ -0,0 +1,419 @@
+//! Dumping frames to disk as they are, which is much cheaper than encoding
+//! them, and reading them back later.
+//!
+//! A recording is two files: the pixels of every frame back to back, and
+//! next to it an index with one fixed-size entry per frame. Frames are
+//! written before their entries, so a recording cut off by a crash ends in
+//! at most one incomplete frame, which readers ignore.
+
+use crate::{convert::swap_r_b, PixelFormat, PooledFrame, Screenshot};
+
+use std::{
+    borrow::Cow,
+    convert::{TryFrom, TryInto},
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Start of the index file, with the version of the format.
+const MAGIC: &[u8; 8] = b"SSRAWIX1";
+/// Size of an index entry: offset, length and timestamp as `u64`, the
+/// dimensions and flags as `u32`, all little endian.
+const ENTRY_LEN: usize = 40;
+/// The frame is LZ4-compressed.
+const FLAG_LZ4: u32 = 1;
+/// The pixels are RGBA rather than BGRA.
+const FLAG_RGBA: u32 = 2;
+
+/// Where the index of the recording at `path` goes: `path` plus `.idx`.
+fn index_path(path: &Path) -> PathBuf {
+    let mut index = OsString::from(path.as_os_str());
+    index.push(".idx");
+    index.into()
+}
+
+/// A frame of a raw recording, as listed in its index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawEntry {
+    /// Where the frame starts in the data file.
+    pub offset: u64,
+    /// Bytes it takes in the data file, i.e. after any compression.
+    pub len: u64,
+    /// When the frame was captured, or recorded if that's unknown.
+    pub timestamp: SystemTime,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row, including padding.
+    pub row_len: u32,
+    flags: u32,
+}
+
+impl RawEntry {
+    /// Whether the frame is LZ4-compressed, see `RawRecorder::set_lz4`.
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_LZ4 != 0
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_LEN] {
+        let nanos = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut bytes = [0; ENTRY_LEN];
+        bytes[0..8].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.len.to_le_bytes());
+        bytes[16..24].copy_from_slice(&nanos.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.width.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.height.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.row_len.to_le_bytes());
+        bytes[36..40].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        RawEntry {
+            offset: u64_at(0),
+            len: u64_at(8),
+            timestamp: UNIX_EPOCH + Duration::from_nanos(u64_at(16)),
+            width: u32_at(24),
+            height: u32_at(28),
+            row_len: u32_at(32),
+            flags: u32_at(36),
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.offset.saturating_add(self.len)
+    }
+}
+
+/// The complete frames of the recording at `path`, dropping an incomplete
+/// one at the end.
+fn read_index(path: &Path) -> io::Result<Vec<RawEntry>> {
+    let index = fs::read(index_path(path))?;
+    if !index.starts_with(MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not the index of a raw recording",
+        ));
+    }
+    let data_len = fs::metadata(path)?.len();
+    let mut end = 0;
+    // A partial entry is skipped by `chunks_exact`, and a frame whose data
+    // wasn't all written ends past the data.
+    Ok(index[MAGIC.len()..]
+        .chunks_exact(ENTRY_LEN)
+        .map(RawEntry::from_bytes)
+        .take_while(|entry| {
+            let complete = entry.offset == end && entry.end() <= data_len;
+            end = entry.end();
+            complete
+        })
+        .collect())
+}
+
+/// Appends frames to a raw recording, see the module docs.
+///
+/// Writes are buffered; call `flush` to make sure frames are on disk, e.g.
+/// before reading the recording while it's still going on. Dropping the
+/// recorder flushes too, ignoring errors.
+pub struct RawRecorder {
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    /// Where the next frame goes in the data file.
+    offset: u64,
+    frames: u64,
+    /// Set after a failed write, which leaves the files out of step.
+    failed: bool,
+    #[cfg(feature = "lz4")]
+    lz4: bool,
+}
+
+impl RawRecorder {
+    /// Starts a recording at `path`, with its index at `path` plus `.idx`.
+    /// Existing files are overwritten.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = File::create(path)?;
+        let mut index = File::create(index_path(path))?;
+        index.write_all(MAGIC)?;
+        Ok(RawRecorder::new(data, index, 0, 0))
+    }
+
+    /// Continues the recording at `path`, first cutting off a frame left
+    /// incomplete by a crash.
+    pub fn append<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let entries = read_index(path)?;
+        let end = entries.last().map_or(0, RawEntry::end);
+        let index_len = (MAGIC.len() + entries.len() * ENTRY_LEN) as u64;
+
+        let open = |path: &Path, len: u64| -> io::Result<File> {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(len)?;
+            file.seek(SeekFrom::End(0))?;
+            Ok(file)
+        };
+        let data = open(path, end)?;
+        let index = open(&index_path(path), index_len)?;
+        Ok(RawRecorder::new(data, index, end, entries.len() as u64))
+    }
+
+    fn new(data: File, index: File, offset: u64, frames: u64) -> Self {
+        RawRecorder {
+            data: BufWriter::new(data),
+            index: BufWriter::new(index),
+            offset,
+            frames,
+            failed: false,
+            #[cfg(feature = "lz4")]
+            lz4: false,
+        }
+    }
+
+    /// Compresses the frames recorded from now on with LZ4, which is fast
+    /// enough to keep up with capturing and shrinks typical desktops a lot.
+    #[cfg(feature = "lz4")]
+    pub fn set_lz4(&mut self, enabled: bool) {
+        self.lz4 = enabled;
+    }
+
+    /// Number of frames in the recording.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Appends a screenshot. Its capture time goes into the index.
+    pub fn record(&mut self, frame: &Screenshot) -> io::Result<()> {
+        let timestamp = frame.metadata().map(|metadata| metadata.wall_time);
+        self.write_frame(
+            frame.data(),
+            (frame.width(), frame.height(), frame.row_len()),
+            frame.format(),
+            timestamp,
+        )
+    }
+
+    /// Appends a frame from a `FramePool`.
+    pub fn record_pooled(&mut self, frame: &PooledFrame) -> io::Result<()> {
+        let info = frame.info();
+        let timestamp = frame.metadata().map(|metadata| metadata.wall_time);
+        self.write_frame(
+            frame,
+            (info.width, info.height, info.stride),
+            info.format,
+            timestamp,
+        )
+    }
+
+    fn write_frame(
+        &mut self,
+        pixels: &[u8],
+        (width, height, row_len): (usize, usize, usize),
+        format: PixelFormat,
+        timestamp: Option<SystemTime>,
+    ) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other("an earlier write to the recording failed"));
+        }
+        let dimension = |n: usize| {
+            u32::try_from(n)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))
+        };
+        let (pixels, compressed) = self.compress(pixels);
+        let mut flags = if compressed { FLAG_LZ4 } else { 0 };
+        if format == PixelFormat::Rgba8 {
+            flags |= FLAG_RGBA;
+        }
+        let entry = RawEntry {
+            offset: self.offset,
+            len: pixels.len() as u64,
+            timestamp: timestamp.unwrap_or_else(SystemTime::now),
+            width: dimension(width)?,
+            height: dimension(height)?,
+            row_len: dimension(row_len)?,
+            flags,
+        };
+
+        // the frame first, so an entry never points past the data
+        let res = self
+            .data
+            .write_all(&pixels)
+            .and_then(|()| self.index.write_all(&entry.to_bytes()));
+        if res.is_err() {
+            self.failed = true;
+        }
+        res?;
+        self.offset = entry.end();
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// The pixels as they're to be stored, and whether they're compressed.
+    #[cfg(feature = "lz4")]
+    fn compress<'a>(&self, pixels: &'a [u8]) -> (Cow<'a, [u8]>, bool) {
+        if self.lz4 {
+            (Cow::Owned(lz4_flex::compress_prepend_size(pixels)), true)
+        } else {
+            (Cow::Borrowed(pixels), false)
+        }
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    fn compress<'a>(&self, pixels: &'a [u8]) -> (Cow<'a, [u8]>, bool) {
+        (Cow::Borrowed(pixels), false)
+    }
+
+    /// Writes out the buffered frames, then their index entries.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.data.flush()?;
+        self.index.flush()
+    }
+}
+
+impl Drop for RawRecorder {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Reads a raw recording back, e.g. one still being written or cut off by
+/// a crash, up to its last complete frame. Iterating yields the frames in
+/// order, as BGRA screenshots; their timestamps are in `entries`.
+pub struct RawPlayer {
+    data: File,
+    entries: Vec<RawEntry>,
+    next: usize,
+}
+
+impl RawPlayer {
+    /// Opens the recording at `path`, with its index at `path` plus `.idx`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        Ok(RawPlayer {
+            entries: read_index(path)?,
+            data: File::open(path)?,
+            next: 0,
+        })
+    }
+
+    /// The complete frames of the recording.
+    pub fn entries(&self) -> &[RawEntry] {
+        &self.entries
+    }
+
+    /// Reads frame number `i`, which must be in `entries`.
+    pub fn frame(&mut self, i: usize) -> io::Result<Screenshot> {
+        let entry = *self.entries.get(i).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no such frame in the recording",
+            )
+        })?;
+        let mut data = vec![0; entry.len as usize];
+        self.data.seek(SeekFrom::Start(entry.offset))?;
+        self.data.read_exact(&mut data)?;
+        if entry.is_compressed() {
+            data = decompress(&data)?;
+        }
+        let (width, height, row_len) = (
+            entry.width as usize,
+            entry.height as usize,
+            entry.row_len as usize,
+        );
+        if entry.flags & FLAG_RGBA != 0 && data.len() >= row_len * height {
+            swap_r_b(&mut data, width, row_len);
+        }
+        Screenshot::from_raw(data, width, height, row_len)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(feature = "lz4")]
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    lz4_flex::decompress_size_prepended(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn decompress(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the recording is compressed; enable the lz4 feature to read it",
+    ))
+}
+
+impl Iterator for RawPlayer {
+    type Item = io::Result<Screenshot>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.entries.len() {
+            return None;
+        }
+        self.next += 1;
+        Some(self.frame(self.next - 1))
+    }
+}
+
+#[test]
+fn test_raw_recording() {
+    let path = std::env::temp_dir().join("screenshot_raw_recording.raw");
+    let gradient = |width: usize, height: usize, row_len: usize| {
+        let data = (0..row_len * height).map(|i| i as u8).collect();
+        Screenshot::from_raw(data, width, height, row_len).unwrap()
+    };
+    // the resolution changes, and one frame is RGBA
+    let small = gradient(2, 2, 12);
+    let large = gradient(3, 4, 12);
+    let mut rgba = gradient(2, 1, 8);
+    rgba.swap_r_b_in_place();
+
+    let mut recorder = RawRecorder::create(&path).unwrap();
+    for frame in [&small, &large, &rgba] {
+        recorder.record(frame).unwrap();
+    }
+    assert_eq!(recorder.frames(), 3);
+    drop(recorder);
+
+    let player = RawPlayer::open(&path).unwrap();
+    assert_eq!(player.entries().len(), 3);
+    assert_eq!(
+        (player.entries()[1].width, player.entries()[1].height),
+        (3, 4)
+    );
+    let frames: Vec<_> = player.map(Result::unwrap).collect();
+    assert_eq!(frames[0].data(), small.data());
+    assert_eq!(frames[1].data(), large.data());
+    // played back as BGRA
+    assert_eq!(frames[2].format(), PixelFormat::Bgra8);
+    assert_eq!(frames[2].data(), gradient(2, 1, 8).data());
+
+    // a crash mid-write leaves part of a frame and of its entry
+    let mut data = OpenOptions::new().append(true).open(&path).unwrap();
+    data.write_all(&[0; 10]).unwrap();
+    let mut index = OpenOptions::new()
+        .append(true)
+        .open(index_path(&path))
+        .unwrap();
+    index.write_all(&[1; ENTRY_LEN / 2]).unwrap();
+    assert_eq!(RawPlayer::open(&path).unwrap().count(), 3);
+
+    // appending cuts both off first
+    let mut recorder = RawRecorder::append(&path).unwrap();
+    assert_eq!(recorder.frames(), 3);
+    recorder.record(&small).unwrap();
+    drop(recorder);
+    let mut player = RawPlayer::open(&path).unwrap();
+    assert_eq!(player.entries().len(), 4);
+    assert_eq!(player.frame(3).unwrap().data(), small.data());
+    assert!(player.frame(4).is_err());
+
+    fs::remove_file(index_path(&path)).unwrap();
+    fs::remove_file(&path).unwrap();
+}
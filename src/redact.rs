@@ -0,0 +1,272 @@
+//! Blurring and pixelating parts of a screenshot, to hide what they show
+//! while keeping the layout recognisable.
+
+use crate::{Rect, Screenshot, PIXEL_WIDTH};
+
+type Px = [u8; PIXEL_WIDTH];
+
+/// Box blur passes; three come close to a Gaussian blur.
+const BLUR_PASSES: usize = 3;
+
+impl Screenshot {
+    /// Blurs the pixels within `rect`, in pixels from the top left corner,
+    /// leaving the rest alone. Each pass averages every pixel with those up
+    /// to `radius` away, first along the rows, then the columns, treating
+    /// pixels beyond the edges of `rect` as copies of the edge. The part of
+    /// `rect` outside the screenshot is ignored.
+    pub fn blur_rect(&mut self, rect: Rect, radius: u32) {
+        let (rect, radius) = match self.clip(rect) {
+            Some(rect) if radius > 0 => (rect, radius as usize),
+            _ => return,
+        };
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        let mut line = Vec::with_capacity(width.max(height));
+        let mut blurred = Vec::with_capacity(width.max(height));
+        for _ in 0..BLUR_PASSES {
+            for y in 0..height {
+                self.read_line(rect, (0, y), (1, 0), width, &mut line);
+                box_blur(&line, radius, &mut blurred);
+                self.write_line(rect, (0, y), (1, 0), &blurred);
+            }
+            for x in 0..width {
+                self.read_line(rect, (x, 0), (0, 1), height, &mut line);
+                box_blur(&line, radius, &mut blurred);
+                self.write_line(rect, (x, 0), (0, 1), &blurred);
+            }
+        }
+    }
+
+    /// Replaces the pixels within `rect` by squares of `block_size` pixels,
+    /// each the average of the pixels it covers. The squares start at the
+    /// top left corner of `rect`, and the last ones in each row and column
+    /// are cut off by its edges. Cheaper than `blur_rect`. The part of
+    /// `rect` outside the screenshot is ignored.
+    pub fn pixelate_rect(&mut self, rect: Rect, block_size: u32) {
+        let rect = match self.clip(rect) {
+            Some(rect) if block_size > 1 => rect,
+            _ => return,
+        };
+        let (left, top) = (rect.x as usize, rect.y as usize);
+        let (right, bottom) = (left + rect.width as usize, top + rect.height as usize);
+        let block = block_size as usize;
+        for block_top in (top..bottom).step_by(block) {
+            let rows = block_top..(block_top + block).min(bottom);
+            for block_left in (left..right).step_by(block) {
+                let columns =
+                    block_left * PIXEL_WIDTH..(block_left + block).min(right) * PIXEL_WIDTH;
+                let mut sums = [0u32; PIXEL_WIDTH];
+                for y in rows.clone() {
+                    let row = &self.data[y * self.row_len..][columns.clone()];
+                    for px in row.chunks_exact(PIXEL_WIDTH) {
+                        for (sum, &v) in sums.iter_mut().zip(px) {
+                            *sum += u32::from(v);
+                        }
+                    }
+                }
+                let count = (rows.len() * columns.len() / PIXEL_WIDTH) as u32;
+                let mean = sums.map(|sum| ((sum + count / 2) / count) as u8);
+                for y in rows.clone() {
+                    let row = &mut self.data[y * self.row_len..][columns.clone()];
+                    for px in row.chunks_exact_mut(PIXEL_WIDTH) {
+                        px.copy_from_slice(&mean);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The part of `rect` within the screenshot, if any.
+    fn clip(&self, rect: Rect) -> Option<Rect> {
+        rect.intersect(&Rect {
+            x: 0,
+            y: 0,
+            width: self.width as u32,
+            height: self.height as u32,
+        })
+    }
+
+    /// Reads `len` pixels into `line`, from `start` within `rect` on in
+    /// steps of `step`.
+    fn read_line(
+        &self,
+        rect: Rect,
+        start: (usize, usize),
+        step: (usize, usize),
+        len: usize,
+        line: &mut Vec<Px>,
+    ) {
+        line.clear();
+        line.extend((0..len).map(|i| {
+            let offset = self.offset_in(rect, start, step, i);
+            let mut px = [0; PIXEL_WIDTH];
+            px.copy_from_slice(&self.data[offset..offset + PIXEL_WIDTH]);
+            px
+        }));
+    }
+
+    /// Writes `line` back where `read_line` read it from.
+    fn write_line(&mut self, rect: Rect, start: (usize, usize), step: (usize, usize), line: &[Px]) {
+        for (i, px) in line.iter().enumerate() {
+            let offset = self.offset_in(rect, start, step, i);
+            self.data[offset..offset + PIXEL_WIDTH].copy_from_slice(px);
+        }
+    }
+
+    /// Offset in `data` of the `i`th pixel from `start` within `rect` in
+    /// steps of `step`.
+    fn offset_in(
+        &self,
+        rect: Rect,
+        start: (usize, usize),
+        step: (usize, usize),
+        i: usize,
+    ) -> usize {
+        let x = rect.x as usize + start.0 + step.0 * i;
+        let y = rect.y as usize + start.1 + step.1 * i;
+        y * self.row_len + x * PIXEL_WIDTH
+    }
+}
+
+/// Averages each pixel of `line` with those up to `radius` away into
+/// `blurred`, repeating the first and last pixels beyond the ends.
+fn box_blur(line: &[Px], radius: usize, blurred: &mut Vec<Px>) {
+    let last = line.len() - 1;
+    let window = (2 * radius + 1) as u32;
+    let mut sums = [0u32; PIXEL_WIDTH];
+    for i in 0..=2 * radius {
+        let px = line[i.saturating_sub(radius).min(last)];
+        for (sum, v) in sums.iter_mut().zip(px) {
+            *sum += u32::from(v);
+        }
+    }
+    blurred.clear();
+    for x in 0..line.len() {
+        blurred.push(sums.map(|sum| ((sum + window / 2) / window) as u8));
+        let (entering, leaving) = (
+            line[(x + radius + 1).min(last)],
+            line[x.saturating_sub(radius)],
+        );
+        for c in 0..PIXEL_WIDTH {
+            sums[c] = sums[c] + u32::from(entering[c]) - u32::from(leaving[c]);
+        }
+    }
+}
+
+/// 40x30 of noise with padded rows, in BGRA.
+#[cfg(test)]
+fn noise() -> Screenshot {
+    let mut state = 0x2545_f491_u32;
+    let data = (0..44 * 4 * 30)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    Screenshot::from_raw(data, 40, 30, 44 * 4).unwrap()
+}
+
+/// Variance of green within `rect`.
+#[cfg(test)]
+fn variance(shot: &Screenshot, rect: Rect) -> f64 {
+    let values: Vec<f64> = (rect.y..rect.y + rect.height as i32)
+        .flat_map(|y| (rect.x..rect.x + rect.width as i32).map(move |x| (x, y)))
+        .map(|(x, y)| f64::from(shot.get_pixel_xy(x as usize, y as usize).g))
+        .collect();
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+}
+
+/// Whether only the pixels of `shot` within `rect` differ from `noise()`,
+/// padding included.
+#[cfg(test)]
+fn changed_within(shot: &Screenshot, rect: Rect) -> bool {
+    let original = noise();
+    shot.data()
+        .iter()
+        .zip(original.data())
+        .enumerate()
+        .all(|(i, (a, b))| {
+            let (y, x) = (i / shot.row_len(), i % shot.row_len() / PIXEL_WIDTH);
+            let inside = x < shot.width()
+                && rect.contains(crate::Point {
+                    x: x as i32,
+                    y: y as i32,
+                });
+            inside || a == b
+        })
+}
+
+#[test]
+fn test_blur_rect() {
+    let rect = Rect {
+        x: 5,
+        y: 4,
+        width: 24,
+        height: 18,
+    };
+    let original = variance(&noise(), rect);
+    let mut last = original;
+    for radius in [1, 3, 8] {
+        let mut shot = noise();
+        shot.blur_rect(rect, radius);
+        assert!(changed_within(&shot, rect));
+        let blurred = variance(&shot, rect);
+        assert!(blurred < last / 2.0, "{} at radius {}", blurred, radius);
+        last = blurred;
+    }
+
+    // a uniform area stays as it is
+    let mut shot = Screenshot::from_raw([9, 8, 7, 255].repeat(12), 4, 3, 16).unwrap();
+    shot.blur_rect(
+        Rect {
+            x: 1,
+            y: -1,
+            width: 10,
+            height: 10,
+        },
+        2,
+    );
+    assert_eq!(shot.data(), &[9, 8, 7, 255].repeat(12)[..]);
+    let mut shot = noise();
+    shot.blur_rect(rect, 0);
+    assert_eq!(shot.data(), noise().data());
+}
+
+#[test]
+fn test_pixelate_rect() {
+    let rect = Rect {
+        x: 3,
+        y: 2,
+        width: 30,
+        height: 25,
+    };
+    let mut last = variance(&noise(), rect);
+    for block_size in [2, 5, 10] {
+        let mut shot = noise();
+        shot.pixelate_rect(rect, block_size);
+        assert!(changed_within(&shot, rect));
+        let pixelated = variance(&shot, rect);
+        assert!(pixelated < last, "{} at {}", pixelated, block_size);
+        last = pixelated;
+    }
+
+    // the blocks are the averages of what they cover, cut off at the edges
+    let mut data = Vec::new();
+    for v in [0, 10, 20, 30, 40, 50, 60, 70, 80] {
+        data.extend_from_slice(&[v, v, v, 255]);
+    }
+    let mut shot = Screenshot::from_raw(data, 3, 3, 12).unwrap();
+    shot.pixelate_rect(
+        Rect {
+            x: 0,
+            y: 0,
+            width: 3,
+            height: 3,
+        },
+        2,
+    );
+    let green: Vec<u8> = (0..9).map(|i| shot.get_pixel_xy(i % 3, i / 3).g).collect();
+    assert_eq!(green, [20, 20, 35, 20, 20, 35, 65, 65, 80]);
+}
@@ -0,0 +1,192 @@
+//! Scaling screenshots, e.g. to small previews for a dashboard instead of
+//! full frames.
+
+use crate::{buffer::AlignedBuf, buffer_len, Screenshot, ScreenshotError, PIXEL_WIDTH};
+
+/// How `Screenshot::resize` computes each pixel from the original.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// The pixel nearest the middle of each new one. The fastest, but
+    /// blocky when enlarging and grainy when shrinking.
+    Nearest,
+    /// Interpolates between the four pixels nearest the middle of each new
+    /// one. Smooth when enlarging, but shrinking to less than half skips
+    /// pixels.
+    Bilinear,
+    /// Averages the pixels each new one covers. The best for thumbnails,
+    /// at the cost of reading every pixel; like `Nearest` when enlarging.
+    Area,
+}
+
+impl Screenshot {
+    /// A copy scaled to `width` x `height` pixels with `filter`, in the same
+    /// pixel format and with the same metadata. All four channels are
+    /// filtered. The copy is black if the screenshot has no pixels, and
+    /// fails with `ScreenshotError::DimensionsTooLarge` if the new size
+    /// doesn't fit in memory.
+    pub fn resize(
+        &self,
+        width: usize,
+        height: usize,
+        filter: ResizeFilter,
+    ) -> Result<Screenshot, ScreenshotError> {
+        let mut data = AlignedBuf::default();
+        data.resize(buffer_len(width, height)?);
+        let row_len = width * PIXEL_WIDTH;
+        if self.width != 0 && self.height != 0 && row_len != 0 {
+            let src: Vec<&[u8]> = self.rows().collect();
+            let dst = (&mut data[..], width, height);
+            match filter {
+                ResizeFilter::Nearest => nearest(&src, self.width, dst),
+                ResizeFilter::Bilinear => bilinear(&src, self.width, dst),
+                ResizeFilter::Area => area(&src, self.width, dst),
+            }
+        }
+        Ok(Screenshot {
+            data,
+            format: self.format,
+            height,
+            width,
+            row_len,
+            metadata: self.metadata,
+        })
+    }
+}
+
+/// Index of the pixel of `src_len` nearest the middle of pixel `i` of
+/// `dst_len`.
+fn nearest_index(i: usize, src_len: usize, dst_len: usize) -> usize {
+    ((2 * i + 1) * src_len / (2 * dst_len)).min(src_len - 1)
+}
+
+/// Scales the rows `src`, `src_width` pixels wide, into the packed rows of
+/// `dst`, given with their width and height.
+fn nearest(src: &[&[u8]], src_width: usize, (dst, width, height): (&mut [u8], usize, usize)) {
+    let xs: Vec<usize> = (0..width)
+        .map(|x| nearest_index(x, src_width, width) * PIXEL_WIDTH)
+        .collect();
+    for (y, row) in dst.chunks_exact_mut(width * PIXEL_WIDTH).enumerate() {
+        let src = src[nearest_index(y, src.len(), height)];
+        for (px, &x) in row.chunks_exact_mut(PIXEL_WIDTH).zip(&xs) {
+            px.copy_from_slice(&src[x..][..PIXEL_WIDTH]);
+        }
+    }
+}
+
+/// The two pixels of `src_len` on either side of the middle of pixel `i` of
+/// `dst_len`, and how far it is from the first to the second.
+fn bilinear_weights(i: usize, src_len: usize, dst_len: usize) -> (usize, usize, f32) {
+    let at = ((i as f32 + 0.5) * src_len as f32 / dst_len as f32 - 0.5).max(0.0);
+    let first = (at as usize).min(src_len - 1);
+    (first, (first + 1).min(src_len - 1), at - first as f32)
+}
+
+fn bilinear(src: &[&[u8]], src_width: usize, (dst, width, height): (&mut [u8], usize, usize)) {
+    let xs: Vec<_> = (0..width)
+        .map(|x| bilinear_weights(x, src_width, width))
+        .collect();
+    for (y, row) in dst.chunks_exact_mut(width * PIXEL_WIDTH).enumerate() {
+        let (top, bottom, fy) = bilinear_weights(y, src.len(), height);
+        let (top, bottom) = (src[top], src[bottom]);
+        for (px, &(left, right, fx)) in row.chunks_exact_mut(PIXEL_WIDTH).zip(&xs) {
+            let (left, right) = (left * PIXEL_WIDTH, right * PIXEL_WIDTH);
+            for (c, v) in px.iter_mut().enumerate() {
+                let lerp = |a: u8, b: u8, f: f32| f32::from(a) + (f32::from(b) - f32::from(a)) * f;
+                let upper = lerp(top[left + c], top[right + c], fx);
+                let lower = lerp(bottom[left + c], bottom[right + c], fx);
+                *v = (upper + (lower - upper) * fy).round() as u8;
+            }
+        }
+    }
+}
+
+/// The pixels of `src_len` that pixel `i` of `dst_len` covers; at least one.
+fn area_span(i: usize, src_len: usize, dst_len: usize) -> (usize, usize) {
+    let start = (i * src_len / dst_len).min(src_len - 1);
+    (start, ((i + 1) * src_len / dst_len).max(start + 1))
+}
+
+fn area(src: &[&[u8]], src_width: usize, (dst, width, height): (&mut [u8], usize, usize)) {
+    let xs: Vec<_> = (0..width).map(|x| area_span(x, src_width, width)).collect();
+    let mut sums = vec![[0u64; PIXEL_WIDTH]; width];
+    for (y, row) in dst.chunks_exact_mut(width * PIXEL_WIDTH).enumerate() {
+        let (top, bottom) = area_span(y, src.len(), height);
+        sums.fill([0; PIXEL_WIDTH]);
+        for src in &src[top..bottom] {
+            for (sum, &(left, right)) in sums.iter_mut().zip(&xs) {
+                for px in src[left * PIXEL_WIDTH..right * PIXEL_WIDTH].chunks_exact(PIXEL_WIDTH) {
+                    for (sum, &v) in sum.iter_mut().zip(px) {
+                        *sum += u64::from(v);
+                    }
+                }
+            }
+        }
+        for ((px, sum), &(left, right)) in row.chunks_exact_mut(PIXEL_WIDTH).zip(&sums).zip(&xs) {
+            let count = ((right - left) * (bottom - top)) as u64;
+            for (v, &sum) in px.iter_mut().zip(sum) {
+                *v = ((sum + count / 2) / count) as u8;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_resize() {
+    // 4x2, left half black and right half white, rows padded to 20 bytes
+    let row = [[0, 0, 0, 255].repeat(2), [255; 8].to_vec(), vec![7; 4]].concat();
+    let s = Screenshot::from_raw(row.repeat(2), 4, 2, 20).unwrap();
+
+    for filter in [
+        ResizeFilter::Nearest,
+        ResizeFilter::Bilinear,
+        ResizeFilter::Area,
+    ] {
+        let half = s.resize(2, 1, filter).unwrap();
+        assert_eq!((half.width(), half.height(), half.row_len()), (2, 1, 8));
+        assert_eq!(
+            half.data(),
+            &[0, 0, 0, 255, 255, 255, 255, 255],
+            "{:?}",
+            filter
+        );
+        assert_eq!(
+            s.resize(4, 2, filter).unwrap().to_rgba_vec(),
+            s.to_rgba_vec()
+        );
+    }
+
+    // one pixel for the whole image
+    assert_eq!(
+        s.resize(1, 1, ResizeFilter::Area).unwrap().data(),
+        &[128, 128, 128, 255]
+    );
+    // enlarging blends the edge for bilinear only
+    let wide = |filter| {
+        s.resize(8, 1, filter)
+            .unwrap()
+            .pixels()
+            .map(|p| p.r)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        wide(ResizeFilter::Nearest),
+        [0, 0, 0, 0, 255, 255, 255, 255]
+    );
+    assert_eq!(wide(ResizeFilter::Area), [0, 0, 0, 0, 255, 255, 255, 255]);
+    assert_eq!(
+        wide(ResizeFilter::Bilinear),
+        [0, 0, 0, 64, 191, 255, 255, 255]
+    );
+
+    // nothing in, black out; nothing out
+    let empty = Screenshot::from_raw(Vec::new(), 0, 0, 0).unwrap();
+    assert_eq!(
+        empty.resize(2, 2, ResizeFilter::Area).unwrap().data(),
+        &[0; 16]
+    );
+    assert_eq!(s.resize(0, 5, ResizeFilter::Bilinear).unwrap().len(), 0);
+    assert!(matches!(
+        s.resize(usize::MAX, 2, ResizeFilter::Nearest),
+        Err(ScreenshotError::DimensionsTooLarge { .. })
+    ));
+}
@@ -0,0 +1,821 @@
+//! The captured image and what's known about how it was taken.
+
+use crate::{
+    buffer::AlignedBuf, convert, convert::swap_r_b, Monitor, Pixel, PixelFormat, Point, Rect,
+    ScreenshotError, Size, WindowId, PIXEL_WIDTH,
+};
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// Layout of a frame captured into a caller's buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Width in pixels.
+    pub width: usize,
+    /// Height in pixels, i.e. number of rows.
+    pub height: usize,
+    /// Number of bytes in one row, including any padding.
+    pub stride: usize,
+    pub format: PixelFormat,
+}
+
+/// Time spent in each step of a capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CaptureMetrics {
+    /// Acquiring the screen DC and, after a resolution change, creating the
+    /// bitmap.
+    pub acquire: Duration,
+    /// Copying the screen into the bitmap with `BitBlt`.
+    pub blit: Duration,
+    /// Reading the pixels out of the bitmap with `GetDIBits`.
+    pub read_dib: Duration,
+    /// The whole capture, including checks and retries.
+    pub total: Duration,
+    /// Size of the captured pixels.
+    pub frame_bytes: usize,
+}
+
+/// When and where a frame was captured, see `Screenshot::metadata`.
+///
+/// With the `config` feature it can be serialized, e.g. to log it next to
+/// saved frames, leaving out `captured_at`, which only means something
+/// within the process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Serialize))]
+pub struct CaptureMetadata {
+    /// Taken right after the blit, i.e. when the screen was copied.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub captured_at: Instant,
+    /// The wall-clock time along with `captured_at`, for lining frames up
+    /// with logs. Unlike `captured_at`, it jumps when the clock is set.
+    pub wall_time: SystemTime,
+    /// Numbers the frames of a `Capturer`, from 0 and up by one per
+    /// successful capture, so skipped frames leave gaps. Also counts on
+    /// after a timed out capture. One-shot captures are number 0.
+    pub sequence: u64,
+    /// The captured area, in virtual-screen coordinates. For a monitor,
+    /// its `Monitor::rect`, see `monitor`.
+    pub source: Rect,
+    /// The window captured, if it was one.
+    pub window: Option<WindowId>,
+    /// Name of the backend that captured the frame, e.g. `"gdi"`, see
+    /// `CaptureBackend::name`.
+    pub backend: &'static str,
+    /// DPI scale of the monitor showing the middle of `source`, as in
+    /// `Monitor::scale_factor`. Only filled in by the GDI backend.
+    pub scale_factor: Option<f64>,
+    /// Where the mouse cursor was when the frame was captured, in
+    /// virtual-screen coordinates. Only filled in by the GDI backend.
+    pub cursor: Option<Point>,
+    /// The frame is in physical pixels. On Windows, a thread that isn't
+    /// DPI aware gets scaled monitors downscaled to their logical size
+    /// instead, see `CaptureOptions::dpi_aware`.
+    pub physical_pixels: bool,
+    /// The first frame of a streaming capture after
+    /// `CaptureHandle::resume`, following a gap.
+    pub resumed: bool,
+}
+
+impl CaptureMetadata {
+    /// The monitor of `monitors`, e.g. from `monitors()`, showing the middle
+    /// of the captured area, e.g. to look up its name.
+    pub fn monitor<'a>(&self, monitors: &'a [Monitor]) -> Option<&'a Monitor> {
+        let middle = Point {
+            x: self.source.x + (self.source.width / 2) as i32,
+            y: self.source.y + (self.source.height / 2) as i32,
+        };
+        monitors.iter().find(|m| m.rect.contains(middle))
+    }
+}
+
+/// An image buffer containing the screenshot.
+/// Pixels are stored as four bytes each, BGRA by default; `Screenshot::format`
+/// tells the actual order.
+///
+/// The fields are private so the buffer always matches the dimensions;
+/// use the accessors to read them.
+pub struct Screenshot {
+    /// Aligned, so the pixels can be viewed as `u32`s.
+    pub(crate) data: AlignedBuf,
+    /// Channel order of `data`.
+    pub(crate) format: PixelFormat,
+    /// Height of image in pixels
+    pub(crate) height: usize,
+    /// Width of image in pixels.
+    pub(crate) width: usize,
+    /// Number of bytes in one row of bitmap.
+    pub(crate) row_len: usize,
+    /// None if the pixels weren't captured but passed to `from_raw`.
+    pub(crate) metadata: Option<CaptureMetadata>,
+}
+
+impl Screenshot {
+    /// Wraps a buffer of `height` rows of `row_len` bytes each, holding
+    /// `width` BGRA pixels per row. Rows may be padded, i.e. `row_len` may
+    /// exceed `width * 4`. The pixels are copied into an aligned buffer.
+    pub fn from_raw(
+        data: Vec<u8>,
+        width: usize,
+        height: usize,
+        row_len: usize,
+    ) -> Result<Self, ScreenshotError> {
+        let min_row_len = self::row_len(width);
+        let consistent = matches!(min_row_len, Some(min) if min <= row_len)
+            && row_len.checked_mul(height) == Some(data.len());
+        if !consistent {
+            return Err(ScreenshotError::InvalidBuffer {
+                width,
+                height,
+                row_len,
+                len: data.len(),
+            });
+        }
+        Ok(Screenshot::from_bgra(data, width, height, row_len))
+    }
+
+    /// Wraps a BGRA buffer of `height` rows of `row_len` bytes each.
+    pub(crate) fn from_bgra(data: Vec<u8>, width: usize, height: usize, row_len: usize) -> Self {
+        Screenshot {
+            data: AlignedBuf::from(&data[..]),
+            format: PixelFormat::Bgra8,
+            height,
+            width,
+            row_len,
+            metadata: None,
+        }
+    }
+
+    /// Switches the red and blue channels of every pixel without
+    /// allocating, turning BGRA into RGBA or back, and updates `format`.
+    /// Row padding and alpha are left alone.
+    pub fn swap_r_b_in_place(&mut self) {
+        swap_r_b(&mut self.data, self.width, self.row_len);
+        self.format = match self.format {
+            PixelFormat::Bgra8 => PixelFormat::Rgba8,
+            PixelFormat::Rgba8 => PixelFormat::Bgra8,
+        };
+    }
+
+    /// Sets the alpha of every pixel to 255. GDI doesn't define the alpha
+    /// channel of captured pixels and usually leaves it at 0, which image
+    /// viewers show as fully transparent.
+    pub fn set_opaque(&mut self) {
+        convert::set_opaque(&mut self.data, self.width, self.row_len);
+    }
+
+    /// The pixels, row by row, in the order given by `format`. That is
+    /// BGRA unless `swap_r_b_in_place` was called.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The pixels as native-endian `u32`s without copying, `row_len / 4`
+    /// per row including any padding. On little-endian targets a BGRA pixel
+    /// reads as `0xAARRGGBB`. `None` if the rows aren't a whole number of
+    /// `u32`s, which never happens for captured screenshots.
+    pub fn as_u32_slice(&self) -> Option<&[u32]> {
+        if !self.row_len.is_multiple_of(PIXEL_WIDTH) {
+            return None;
+        }
+        // SAFETY: any 4 bytes are a valid u32.
+        let (head, pixels, tail) = unsafe { self.data.align_to::<u32>() };
+        if head.is_empty() && tail.is_empty() {
+            Some(pixels)
+        } else {
+            None
+        }
+    }
+
+    /// Channel order of `data`.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Height of image in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Width of image in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Width and height in pixels.
+    pub fn size(&self) -> Size {
+        Size {
+            width: self.width as u32,
+            height: self.height as u32,
+        }
+    }
+
+    /// Size in logical pixels, i.e. as a thread that isn't DPI aware sees
+    /// it, from `CaptureMetadata::scale_factor`. None without one.
+    pub fn logical_size(&self) -> Option<Size> {
+        let metadata = self.metadata?;
+        let scale = if metadata.physical_pixels {
+            1.0 / metadata.scale_factor?
+        } else {
+            1.0
+        };
+        Some(self.scaled_size(scale))
+    }
+
+    /// Size in physical pixels, i.e. as the monitor shows it, from
+    /// `CaptureMetadata::scale_factor`. The same as `size` unless the frame
+    /// was downscaled for a thread that isn't DPI aware.
+    pub fn physical_size(&self) -> Option<Size> {
+        let metadata = self.metadata?;
+        let scale = if metadata.physical_pixels {
+            1.0
+        } else {
+            metadata.scale_factor?
+        };
+        Some(self.scaled_size(scale))
+    }
+
+    fn scaled_size(&self, scale: f64) -> Size {
+        let scaled = |len: usize| (len as f64 * scale).round() as u32;
+        Size {
+            width: scaled(self.width),
+            height: scaled(self.height),
+        }
+    }
+
+    /// Number of bytes in one row of bitmap, including any padding.
+    pub fn row_len(&self) -> usize {
+        self.row_len
+    }
+
+    /// When and where the frame was captured. None for screenshots made
+    /// with `from_raw`.
+    pub fn metadata(&self) -> Option<&CaptureMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// When the screen was copied, see `CaptureMetadata::captured_at`.
+    /// Comparing those of consecutive frames shows gaps in a stream.
+    pub fn captured_at(&self) -> Option<Instant> {
+        self.metadata.map(|metadata| metadata.captured_at)
+    }
+
+    /// Copies the pixel buffer out of the screenshot, in `format` order.
+    /// The screenshot's own buffer is aligned in a way a `Vec` can't own,
+    /// so use `data` or `as_u32_slice` to avoid the copy.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data.to_vec()
+    }
+
+    /// Number of bytes in bitmap
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the bitmap holds no pixels. Screenshots returned by
+    /// `get_screenshot` are never empty, as a display without pixels is
+    /// reported as `ScreenshotError::EmptyDisplay` instead.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets the pixel at `row`, counted from the top, and `col`, counted
+    /// from the left. Note the order: that's `(y, x)`, unlike `get_pixel_xy`
+    /// and the rest of the crate. Panics if it's out of bounds.
+    pub fn get_pixel(&self, row: usize, col: usize) -> Pixel {
+        self.try_get_pixel(row, col).expect("Bounds overflow")
+    }
+
+    /// Gets the pixel at `row` and `col` like `get_pixel`, or None if it's
+    /// out of bounds. A `col` past the width is out of bounds even where
+    /// the row is padded or followed by another one.
+    pub fn try_get_pixel(&self, row: usize, col: usize) -> Option<Pixel> {
+        if row >= self.height || col >= self.width {
+            return None;
+        }
+        let idx = pixel_offset(row, col, self.row_len)?;
+        let px = self.data.get(idx..idx.checked_add(PIXEL_WIDTH)?)?;
+        let (r, b) = self.red_blue_offsets();
+        Some(Pixel {
+            a: px[3],
+            r: px[r],
+            g: px[1],
+            b: px[b],
+        })
+    }
+
+    /// Gets the pixel at `x` from the left and `y` from the top, the same as
+    /// `get_pixel(y, x)`. Panics if it's out of bounds.
+    pub fn get_pixel_xy(&self, x: usize, y: usize) -> Pixel {
+        self.get_pixel(y, x)
+    }
+
+    /// Copies the pixels as packed RGB, 3 bytes per pixel without alpha.
+    pub fn to_rgb_vec(&self) -> Vec<u8> {
+        let (r, b) = self.red_blue_offsets();
+        let row_len = self.width * 3;
+        let mut out = vec![0; row_len * self.height];
+        convert::map_rows(
+            &self.data,
+            self.width,
+            self.row_len,
+            &mut out,
+            row_len,
+            |src, dst| {
+                for (dst, px) in dst.chunks_exact_mut(3).zip(src.chunks_exact(PIXEL_WIDTH)) {
+                    dst.copy_from_slice(&[px[r], px[1], px[b]]);
+                }
+            },
+        );
+        out
+    }
+
+    /// Converts the pixels to 8-bit luma, one byte per pixel without row
+    /// padding, weighing red, green and blue by Rec. 709 as they are, i.e.
+    /// gamma-encoded. Alpha is ignored.
+    pub fn to_grayscale(&self) -> Vec<u8> {
+        let (r, b) = self.red_blue_offsets();
+        let mut out = vec![0; self.width * self.height];
+        convert::map_rows(
+            &self.data,
+            self.width,
+            self.row_len,
+            &mut out,
+            self.width,
+            |src, dst| {
+                for (dst, px) in dst.iter_mut().zip(src.chunks_exact(PIXEL_WIDTH)) {
+                    let [r, g, b] = [px[r], px[1], px[b]].map(u32::from);
+                    *dst = ((LUMA_R * r + LUMA_G * g + LUMA_B * b + 5_000) / 10_000) as u8;
+                }
+            },
+        );
+        out
+    }
+
+    /// Converts the pixels to luma like `to_grayscale`, but from 0.0 to 1.0
+    /// and without rounding.
+    pub fn to_luma_f32(&self) -> Vec<f32> {
+        let (r, b) = self.red_blue_offsets();
+        let mut out = vec![0.0; self.width * self.height];
+        convert::map_rows(
+            &self.data,
+            self.width,
+            self.row_len,
+            &mut out,
+            self.width,
+            |src, dst| {
+                for (dst, px) in dst.iter_mut().zip(src.chunks_exact(PIXEL_WIDTH)) {
+                    let [r, g, b] = [px[r], px[1], px[b]].map(f32::from);
+                    let weighted = LUMA_R as f32 * r + LUMA_G as f32 * g + LUMA_B as f32 * b;
+                    *dst = weighted / (10_000.0 * 255.0);
+                }
+            },
+        );
+        out
+    }
+
+    /// Copies the pixels as packed RGBA without row padding, whatever
+    /// `format` is. Unlike `swap_r_b_in_place`, the screenshot is left as
+    /// it is.
+    pub fn to_rgba_vec(&self) -> Vec<u8> {
+        self.to_packed_vec(self.red_blue_offsets())
+    }
+
+    /// Copies the pixels as packed BGRA without row padding, whatever
+    /// `format` is.
+    pub fn to_bgra_vec(&self) -> Vec<u8> {
+        let (r, b) = self.red_blue_offsets();
+        self.to_packed_vec((b, r))
+    }
+
+    /// The rows, top to bottom, each `width * 4` bytes in `format` order
+    /// without the padding.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.data
+            .chunks(self.row_len.max(1))
+            .take(self.height)
+            .map(move |row| &row[..self.width * PIXEL_WIDTH])
+    }
+
+    /// The pixels, row by row, whatever `format` is.
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel> + '_ {
+        let (r, b) = self.red_blue_offsets();
+        self.rows()
+            .flat_map(|row| row.chunks_exact(PIXEL_WIDTH))
+            .map(move |px| Pixel {
+                a: px[3],
+                r: px[r],
+                g: px[1],
+                b: px[b],
+            })
+    }
+
+    /// The pixels like `pixels`, each with its `x` and `y`, as in
+    /// `get_pixel_xy`.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, Pixel)> + '_ {
+        let width = self.width.max(1);
+        self.pixels()
+            .enumerate()
+            .map(move |(i, pixel)| (i % width, i / width, pixel))
+    }
+
+    /// The pixels as RGBA, row by row without padding, converted as they're
+    /// read. Cheaper than `to_rgba_vec` when each pixel is only looked at
+    /// once.
+    pub fn rgba_iter(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+        self.pixels().map(|p| [p.r, p.g, p.b, p.a])
+    }
+
+    /// Copies the pixels without row padding, with the bytes at offsets
+    /// `(r, b)` of each pixel first and third.
+    fn to_packed_vec(&self, (r, b): (usize, usize)) -> Vec<u8> {
+        let row_len = self.width * PIXEL_WIDTH;
+        let mut out = vec![0; row_len * self.height];
+        convert::map_rows(
+            &self.data,
+            self.width,
+            self.row_len,
+            &mut out,
+            row_len,
+            |src, dst| {
+                for (dst, px) in dst
+                    .chunks_exact_mut(PIXEL_WIDTH)
+                    .zip(src.chunks_exact(PIXEL_WIDTH))
+                {
+                    dst.copy_from_slice(&[px[r], px[1], px[b], px[3]]);
+                }
+            },
+        );
+        out
+    }
+
+    /// Offsets of the red and blue bytes within a pixel of `data`.
+    pub(crate) fn red_blue_offsets(&self) -> (usize, usize) {
+        match self.format {
+            PixelFormat::Bgra8 => (2, 0),
+            PixelFormat::Rgba8 => (0, 2),
+        }
+    }
+
+    /// Converts each row to 24 bits per pixel, in RGB order or, if `bgr`,
+    /// BGR order, and pads it with zeros to a multiple of `align` bytes
+    /// before handing it to `f`. Rows are visited top to bottom.
+    pub(crate) fn packed_rgb_rows(&self, bgr: bool, align: usize, mut f: impl FnMut(&[u8])) {
+        let (r, b) = self.red_blue_offsets();
+        let (r, b) = if bgr { (b, r) } else { (r, b) };
+        let len = self.width * 3;
+        let mut row = vec![0; len.div_ceil(align) * align];
+        for src in self.data.chunks(self.row_len.max(1)).take(self.height) {
+            for (dst, px) in row[..len]
+                .chunks_exact_mut(3)
+                .zip(src.chunks_exact(PIXEL_WIDTH))
+            {
+                dst[0] = px[r];
+                dst[1] = px[1];
+                dst[2] = px[b];
+            }
+            f(&row);
+        }
+    }
+
+    /// Converts each row to packed RGBA in a scratch buffer of a single row
+    /// and hands it to `f`, top to bottom, stopping at the first error.
+    #[cfg(feature = "png")]
+    pub(crate) fn try_packed_rgba_rows<E>(
+        &self,
+        mut f: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let (r, b) = self.red_blue_offsets();
+        let mut row = vec![0; self.width * PIXEL_WIDTH];
+        for src in self.data.chunks(self.row_len.max(1)).take(self.height) {
+            for (dst, px) in row
+                .chunks_exact_mut(PIXEL_WIDTH)
+                .zip(src.chunks_exact(PIXEL_WIDTH))
+            {
+                dst.copy_from_slice(&[px[r], px[1], px[b], px[3]]);
+            }
+            f(&row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rec. 709 weights of red, green and blue in luma, in ten-thousandths.
+const LUMA_R: u32 = 2_126;
+const LUMA_G: u32 = 7_152;
+const LUMA_B: u32 = 722;
+
+/// Number of bytes in a row of `width` pixels.
+pub(crate) fn row_len(width: usize) -> Option<usize> {
+    width.checked_mul(PIXEL_WIDTH)
+}
+
+/// Number of bytes in a bitmap of `width` x `height` pixels.
+pub(crate) fn buffer_len(width: usize, height: usize) -> Result<usize, ScreenshotError> {
+    row_len(width)
+        .and_then(|row_len| row_len.checked_mul(height))
+        .ok_or(ScreenshotError::DimensionsTooLarge { width, height })
+}
+
+/// Byte offset of the pixel at (row, col).
+pub(crate) fn pixel_offset(row: usize, col: usize, row_len: usize) -> Option<usize> {
+    row.checked_mul(row_len)?
+        .checked_add(col.checked_mul(PIXEL_WIDTH)?)
+}
+
+#[test]
+fn test_is_empty() {
+    let s = Screenshot::from_raw(Vec::new(), 0, 0, 0).unwrap();
+    assert!(s.is_empty());
+}
+
+#[test]
+fn test_from_raw() {
+    let s = Screenshot::from_raw(vec![0; 2 * 12], 3, 2, 12).unwrap();
+    assert_eq!(
+        (s.width(), s.height(), s.row_len(), s.len()),
+        (3, 2, 12, 24)
+    );
+    assert!(s.metadata().is_none());
+    // padded rows
+    assert!(Screenshot::from_raw(vec![0; 2 * 16], 3, 2, 16).is_ok());
+    // rows too short for the width
+    assert!(matches!(
+        Screenshot::from_raw(vec![0; 2 * 8], 3, 2, 8),
+        Err(ScreenshotError::InvalidBuffer { .. })
+    ));
+    // buffer too short for the height
+    assert!(Screenshot::from_raw(vec![0; 12], 3, 2, 12).is_err());
+    assert!(Screenshot::from_raw(Vec::new(), usize::MAX, 1, 0).is_err());
+    assert_eq!(
+        Screenshot::from_raw(vec![1, 2, 3, 4], 1, 1, 4)
+            .unwrap()
+            .into_inner(),
+        [1, 2, 3, 4]
+    );
+}
+
+#[test]
+fn test_as_u32_slice() {
+    let s = Screenshot::from_raw(vec![1, 2, 3, 4, 5, 6, 7, 8], 2, 1, 8).unwrap();
+    assert_eq!(
+        s.as_u32_slice().unwrap(),
+        [
+            u32::from_ne_bytes([1, 2, 3, 4]),
+            u32::from_ne_bytes([5, 6, 7, 8])
+        ]
+    );
+    // rows of 6 bytes would put every other row off a u32 boundary
+    assert!(Screenshot::from_raw(vec![0; 12], 1, 2, 6)
+        .unwrap()
+        .as_u32_slice()
+        .is_none());
+    assert_eq!(
+        Screenshot::from_raw(Vec::new(), 0, 0, 0)
+            .unwrap()
+            .as_u32_slice(),
+        Some(&[][..])
+    );
+}
+
+#[test]
+fn test_try_get_pixel() {
+    // 2x2 pixels, rows padded to 12 bytes
+    let data: Vec<u8> = (0..24).collect();
+    let s = Screenshot::from_raw(data, 2, 2, 12).unwrap();
+    let pixel = |b, g, r, a| Some(Pixel { r, g, b, a });
+    assert_eq!(s.try_get_pixel(0, 0), pixel(0, 1, 2, 3));
+    assert_eq!(s.try_get_pixel(1, 1), pixel(16, 17, 18, 19));
+    // neither the padding nor the next row
+    assert_eq!(s.try_get_pixel(0, 2), None);
+    assert_eq!(s.try_get_pixel(0, 3), None);
+    assert_eq!(s.try_get_pixel(2, 0), None);
+    assert_eq!(s.try_get_pixel(usize::MAX, usize::MAX), None);
+}
+
+#[test]
+#[should_panic(expected = "Bounds overflow")]
+fn test_get_pixel_past_width() {
+    let s = Screenshot::from_raw(vec![0; 32], 2, 2, 16).unwrap();
+    s.get_pixel(0, 2);
+}
+
+#[test]
+fn test_pixel_iterators() {
+    // 2x2 pixels, rows padded to 12 bytes
+    let data: Vec<u8> = (0..24).collect();
+    let mut s = Screenshot::from_raw(data, 2, 2, 12).unwrap();
+    let rows: Vec<_> = s.rows().collect();
+    assert_eq!(
+        rows,
+        [
+            &[0, 1, 2, 3, 4, 5, 6, 7][..],
+            &[12, 13, 14, 15, 16, 17, 18, 19]
+        ]
+    );
+    let expected: Vec<_> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+        .iter()
+        .map(|&(x, y)| (x, y, s.get_pixel_xy(x, y)))
+        .collect();
+    assert_eq!(s.enumerate_pixels().collect::<Vec<_>>(), expected);
+    assert_eq!(
+        expected[3].2,
+        Pixel {
+            r: 18,
+            g: 17,
+            b: 16,
+            a: 19
+        }
+    );
+
+    // the same pixels in the other order
+    s.swap_r_b_in_place();
+    assert_eq!(s.rows().next(), Some(&[2, 1, 0, 3, 6, 5, 4, 7][..]));
+    let pixels: Vec<_> = s.pixels().collect();
+    assert_eq!(pixels, expected.iter().map(|e| e.2).collect::<Vec<_>>());
+
+    let empty = Screenshot::from_raw(Vec::new(), 0, 0, 0).unwrap();
+    assert_eq!(empty.rows().count(), 0);
+    assert_eq!(empty.enumerate_pixels().count(), 0);
+}
+
+#[test]
+fn test_grayscale() {
+    // black, white, red, green and blue, in a padded row
+    let data = [
+        [0, 0, 0, 255],
+        [255, 255, 255, 0],
+        [0, 0, 255, 255],
+        [0, 255, 0, 255],
+        [255, 0, 0, 255],
+        [9, 9, 9, 9],
+    ]
+    .concat();
+    let mut s = Screenshot::from_raw(data.repeat(2), 5, 2, 24).unwrap();
+    let gray = s.to_grayscale();
+    assert_eq!(gray[..5], [0, 255, 54, 182, 18]);
+    assert_eq!(gray[..5], gray[5..]);
+    let luma = s.to_luma_f32();
+    assert_eq!(luma.len(), 10);
+    assert_eq!(luma[..2], [0.0, 1.0]);
+    assert!((luma[2] - 0.2126).abs() < 1e-6, "{}", luma[2]);
+
+    // the same in the other order
+    s.swap_r_b_in_place();
+    assert_eq!(s.to_grayscale(), gray);
+}
+
+#[test]
+fn test_swap_r_b_in_place() {
+    // 2x2 pixels, rows padded to 12 bytes
+    let data: Vec<u8> = (0..24).collect();
+    let mut s = Screenshot::from_raw(data.clone(), 2, 2, 12).unwrap();
+    let rgba = s.to_rgba_vec();
+    let pixel = s.get_pixel(1, 1);
+
+    s.swap_r_b_in_place();
+    assert_eq!(s.format(), PixelFormat::Rgba8);
+    assert_eq!(&s.data()[..12], &[2, 1, 0, 3, 6, 5, 4, 7, 8, 9, 10, 11]);
+    assert_eq!(
+        s.to_bgra_vec(),
+        [0, 1, 2, 3, 4, 5, 6, 7, 12, 13, 14, 15, 16, 17, 18, 19]
+    );
+    assert_eq!(s.to_rgba_vec(), rgba);
+    assert_eq!(s.rgba_iter().flatten().collect::<Vec<_>>(), rgba);
+    assert_eq!(s.get_pixel(1, 1), pixel);
+
+    s.swap_r_b_in_place();
+    assert_eq!(s.format(), PixelFormat::Bgra8);
+    assert_eq!(s.data(), &data[..]);
+
+    s.set_opaque();
+    assert_eq!(s.get_pixel(1, 1).a, 255);
+    assert_eq!(s.rgba_iter().last(), Some([18, 17, 16, 255]));
+    // padding is left alone
+    assert_eq!(s.data()[11], 11);
+}
+
+#[test]
+fn test_size_arithmetic() {
+    assert_eq!(buffer_len(1920, 1080).unwrap(), 1920 * 1080 * 4);
+    assert!(matches!(
+        buffer_len(usize::MAX / 4 + 1, 1),
+        Err(ScreenshotError::DimensionsTooLarge { .. })
+    ));
+    assert!(buffer_len(usize::MAX / 8, 2).is_ok());
+    assert!(buffer_len(usize::MAX / 8, 3).is_err());
+    assert_eq!(row_len(usize::MAX / 4), Some(usize::MAX / 4 * 4));
+    assert_eq!(row_len(usize::MAX / 4 + 1), None);
+
+    assert_eq!(pixel_offset(2, 3, 40), Some(2 * 40 + 3 * 4));
+    assert_eq!(pixel_offset(usize::MAX, 0, 1), Some(usize::MAX));
+    assert_eq!(pixel_offset(usize::MAX, 1, 1), None);
+    assert_eq!(pixel_offset(2, 0, usize::MAX / 2 + 1), None);
+    assert_eq!(pixel_offset(0, usize::MAX / 4 + 1, 0), None);
+}
+
+#[test]
+fn test_thread_safety() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Screenshot>();
+    assert_send_sync::<ScreenshotError>();
+}
+
+#[test]
+fn test_get_pixel_xy() {
+    // blue runs along x, green along y
+    let (width, height) = (7, 3);
+    let data = (0..height)
+        .flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8, 0, 255]))
+        .collect();
+    let s = Screenshot::from_raw(data, width, height, width * PIXEL_WIDTH).unwrap();
+    assert_eq!(
+        s.size(),
+        Size {
+            width: 7,
+            height: 3
+        }
+    );
+    for y in 0..height {
+        for x in 0..width {
+            let p = s.get_pixel_xy(x, y);
+            assert_eq!((p.b as usize, p.g as usize), (x, y));
+            assert_eq!(p, s.get_pixel(y, x));
+        }
+    }
+}
+
+#[test]
+fn test_metadata_monitor() {
+    let monitor = |x, width| Monitor {
+        rect: Rect {
+            x,
+            y: 0,
+            width,
+            height: 1080,
+        },
+        work_area: Rect::default(),
+        primary: x == 0,
+        scale_factor: 1.0,
+        name: None,
+        device_name: None,
+        refresh_rate: None,
+        bits_per_pixel: None,
+        orientation: None,
+        physical_size_mm: None,
+    };
+    let monitors = [monitor(0, 1920), monitor(-1280, 1280)];
+    let metadata = |x, width| CaptureMetadata {
+        captured_at: Instant::now(),
+        wall_time: SystemTime::now(),
+        sequence: 0,
+        source: Rect {
+            x,
+            y: 100,
+            width,
+            height: 100,
+        },
+        window: None,
+        backend: "test",
+        scale_factor: None,
+        cursor: None,
+        physical_pixels: true,
+        resumed: false,
+    };
+    // mostly on the left monitor
+    assert_eq!(metadata(-400, 500).monitor(&monitors), Some(&monitors[1]));
+    assert_eq!(metadata(0, 1920).monitor(&monitors), Some(&monitors[0]));
+    assert_eq!(metadata(5000, 100).monitor(&monitors), None);
+}
+
+#[test]
+fn test_logical_size() {
+    let mut s = Screenshot::from_raw(vec![0; 300 * 4 * 200], 300, 200, 300 * 4).unwrap();
+    assert_eq!(s.logical_size(), None);
+    assert_eq!(s.physical_size(), None);
+    let metadata = |scale_factor, physical_pixels| CaptureMetadata {
+        captured_at: Instant::now(),
+        wall_time: SystemTime::now(),
+        sequence: 0,
+        source: Rect::default(),
+        window: None,
+        backend: "test",
+        scale_factor,
+        cursor: None,
+        physical_pixels,
+        resumed: false,
+    };
+    let size = |width, height| Some(Size { width, height });
+
+    // physical pixels at 150%
+    s.metadata = Some(metadata(Some(1.5), true));
+    assert_eq!(s.logical_size(), size(200, 133));
+    assert_eq!(s.physical_size(), size(300, 200));
+    // downscaled for a thread that isn't DPI aware
+    s.metadata = Some(metadata(Some(1.5), false));
+    assert_eq!(s.logical_size(), size(300, 200));
+    assert_eq!(s.physical_size(), size(450, 300));
+    // unknown scale
+    s.metadata = Some(metadata(None, true));
+    assert_eq!(s.logical_size(), None);
+    assert_eq!(s.physical_size(), size(300, 200));
+}
@@ -0,0 +1,176 @@
+//! Capturing all of a scrollable window, e.g. a long document or a chat, by
+//! scrolling it down step by step and stitching the captures together.
+
+use crate::{
+    backend::gdi::{client_rect, GdiBackend},
+    CancelToken, CaptureBackend, CaptureOptions, CaptureTarget, Progress, Rect, Screenshot,
+    ScreenshotError, ScrollStitcher, StitchOptions, StitchProgress, WindowId,
+};
+
+use windows::{
+    Win32::Foundation::{HWND, LPARAM, POINT, WPARAM},
+    Win32::UI::Input::KeyboardAndMouse::*,
+    Win32::UI::WindowsAndMessaging::*,
+};
+
+use std::{mem::size_of, time::Duration};
+
+/// How `capture_scrolling_with` scrolls the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrollMethod {
+    /// Turns the mouse wheel by this many notches with `SendInput`, with the
+    /// cursor over the window meanwhile. Works with most apps, including
+    /// browsers.
+    Wheel(u32),
+    /// Posts `WM_VSCROLL` with `SB_LINEDOWN` this many times. Leaves the
+    /// cursor alone, but only works with windows that have a standard
+    /// scroll bar.
+    ScrollBar(u32),
+}
+
+impl Default for ScrollMethod {
+    fn default() -> Self {
+        ScrollMethod::Wheel(3)
+    }
+}
+
+/// How `capture_scrolling_with` scrolls and stitches.
+#[derive(Clone, Debug)]
+pub struct ScrollCaptureOptions {
+    pub method: ScrollMethod,
+    /// How long to wait after each scroll for the window to repaint,
+    /// including any smooth scrolling. 200 ms by default.
+    pub settle: Duration,
+    pub stitch: StitchOptions,
+}
+
+impl Default for ScrollCaptureOptions {
+    fn default() -> Self {
+        ScrollCaptureOptions {
+            method: ScrollMethod::default(),
+            settle: Duration::from_millis(200),
+            stitch: StitchOptions::default(),
+        }
+    }
+}
+
+/// Captures the client area of `window` from where it's scrolled to now
+/// down to the end, scrolling with the mouse wheel, as one tall image.
+pub fn capture_scrolling(window: WindowId) -> Result<Screenshot, ScreenshotError> {
+    capture_scrolling_with(window, &ScrollCaptureOptions::default())
+}
+
+/// Like `capture_scrolling`, scrolling and stitching as `options` say.
+///
+/// The window is captured as it appears on screen, so it must stay
+/// unobstructed and in place until this returns. Scrolling stops once a
+/// scroll changes nothing or the image reaches
+/// `StitchOptions::max_height`. The window is left scrolled down.
+pub fn capture_scrolling_with(
+    window: WindowId,
+    options: &ScrollCaptureOptions,
+) -> Result<Screenshot, ScreenshotError> {
+    capture_scrolling_cancellable(window, options, &CancelToken::new(), |_| {})
+}
+
+/// Like `capture_scrolling_with`, failing with `ScreenshotError::Cancelled`
+/// once `cancel` is cancelled, also while waiting for the window to settle,
+/// and calling `progress` after every capture. The cursor is put back
+/// either way.
+pub fn capture_scrolling_cancellable(
+    window: WindowId,
+    options: &ScrollCaptureOptions,
+    cancel: &CancelToken,
+    mut progress: impl FnMut(Progress),
+) -> Result<Screenshot, ScreenshotError> {
+    cancel.check()?;
+    let client = client_rect(window)?;
+    let target = CaptureTarget::Region(client);
+    let capture_options = CaptureOptions::default();
+    let mut backend = GdiBackend::default();
+    let first = backend.capture_target(target, &capture_options)?;
+    let mut stitcher = ScrollStitcher::new(first, options.stitch.clone())?;
+    let mut captures = 1;
+    progress(Progress {
+        done: captures,
+        total: None,
+    });
+    let _cursor = match options.method {
+        ScrollMethod::Wheel(_) => Some(CursorGuard::save()?),
+        ScrollMethod::ScrollBar(_) => None,
+    };
+    loop {
+        cancel.check()?;
+        scroll(HWND(window.0), client, options.method)?;
+        cancel.sleep(options.settle)?;
+        let frame = backend.capture_target(target, &capture_options)?;
+        let pushed = stitcher.push(frame)?;
+        captures += 1;
+        progress(Progress {
+            done: captures,
+            total: None,
+        });
+        match pushed {
+            StitchProgress::Scrolled(_) => {}
+            StitchProgress::Unchanged | StitchProgress::Full => return Ok(stitcher.finish()),
+        }
+    }
+}
+
+/// Scrolls `hwnd`, whose client area is `client`, down by one step.
+fn scroll(hwnd: HWND, client: Rect, method: ScrollMethod) -> Result<(), ScreenshotError> {
+    match method {
+        ScrollMethod::Wheel(notches) => {
+            // the wheel turns whatever window is under the cursor
+            let (x, y) = (
+                client.x + (client.width / 2) as i32,
+                client.y + (client.height / 2) as i32,
+            );
+            if !unsafe { SetCursorPos(x, y) }.as_bool() {
+                return Err(ScreenshotError::ScrollFailed("SetCursorPos"));
+            }
+            let input = INPUT {
+                r#type: INPUT_MOUSE,
+                Anonymous: INPUT_0 {
+                    mi: MOUSEINPUT {
+                        // negative is towards the user, i.e. down
+                        mouseData: -((WHEEL_DELTA * notches) as i32),
+                        dwFlags: MOUSEEVENTF_WHEEL,
+                        ..Default::default()
+                    },
+                },
+            };
+            if unsafe { SendInput(&[input], size_of::<INPUT>() as i32) } != 1 {
+                return Err(ScreenshotError::ScrollFailed("SendInput"));
+            }
+        }
+        ScrollMethod::ScrollBar(lines) => {
+            let line_down = WPARAM(SB_LINEDOWN.0 as usize);
+            for _ in 0..lines {
+                if !unsafe { PostMessageW(hwnd, WM_VSCROLL, line_down, LPARAM(0)) }.as_bool() {
+                    return Err(ScreenshotError::ScrollFailed("PostMessageW"));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Puts the cursor back where it was on drop.
+struct CursorGuard(POINT);
+
+impl CursorGuard {
+    fn save() -> Result<CursorGuard, ScreenshotError> {
+        let mut pos = POINT::default();
+        if !unsafe { GetCursorPos(&mut pos) }.as_bool() {
+            return Err(ScreenshotError::ScrollFailed("GetCursorPos"));
+        }
+        Ok(CursorGuard(pos))
+    }
+}
+
+impl Drop for CursorGuard {
+    fn drop(&mut self) {
+        unsafe { SetCursorPos(self.0.x, self.0.y) };
+    }
+}
@@ -0,0 +1,116 @@
+//! Finding colours and smaller images within a screenshot. Only red, green
+//! and blue are compared, as alpha is undefined for GDI captures, so
+//! screenshots of either pixel format can be searched.
+
+use crate::{convert::find_map_range, Pixel, Point, Screenshot, PIXEL_WIDTH};
+
+impl Screenshot {
+    /// The first pixel, row by row from the top left, whose red, green and
+    /// blue each differ from `color`'s by at most `tolerance`, in pixels
+    /// from the top left corner.
+    pub fn find_color(&self, color: Pixel, tolerance: u8) -> Option<Point> {
+        let (r, b) = self.red_blue_offsets();
+        let close = |px: &[u8]| {
+            px[r].abs_diff(color.r) <= tolerance
+                && px[1].abs_diff(color.g) <= tolerance
+                && px[b].abs_diff(color.b) <= tolerance
+        };
+        let rows: Vec<&[u8]> = self.rows().collect();
+        let pixels = self.width.saturating_mul(self.height);
+        find_map_range(rows.len(), pixels, |y| {
+            let x = rows[y].chunks_exact(PIXEL_WIDTH).position(close)?;
+            Some(Point {
+                x: x as i32,
+                y: y as i32,
+            })
+        })
+    }
+
+    /// The top left corner of the first place, row by row, where `needle`
+    /// appears exactly, in pixels from the top left corner. A needle larger
+    /// than the screenshot or without pixels is never found.
+    pub fn find_subimage(&self, needle: &Screenshot) -> Option<Point> {
+        let (width, height) = (needle.width(), needle.height());
+        if width == 0 || height == 0 || width > self.width || height > self.height {
+            return None;
+        }
+        let offsets = (self.red_blue_offsets(), needle.red_blue_offsets());
+        let same = |a: &[u8], b: &[u8]| same_rgb(a, b, offsets);
+        let haystack: Vec<&[u8]> = self.rows().collect();
+        let needle: Vec<&[u8]> = needle.rows().collect();
+        for y in 0..=self.height - height {
+            for x in 0..=self.width - width {
+                let start = x * PIXEL_WIDTH;
+                let found = haystack[y..y + height]
+                    .iter()
+                    .zip(&needle)
+                    .all(|(row, needle_row)| same(&row[start..], needle_row));
+                if found {
+                    return Some(Point {
+                        x: x as i32,
+                        y: y as i32,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Whether the pixels at the start of `a` have the RGB of all pixels of
+/// `b`, given the red and blue offsets of each.
+fn same_rgb(a: &[u8], b: &[u8], ((ar, ab), (br, bb)): ((usize, usize), (usize, usize))) -> bool {
+    a.chunks_exact(PIXEL_WIDTH)
+        .zip(b.chunks_exact(PIXEL_WIDTH))
+        .all(|(a, b)| a[ar] == b[br] && a[1] == b[1] && a[ab] == b[bb])
+}
+
+#[test]
+fn test_find() {
+    // 4x3 with padded rows, distinct colours
+    let mut data = vec![0; 20 * 3];
+    for (y, row) in data.chunks_mut(20).enumerate() {
+        for (x, px) in row.chunks_exact_mut(4).take(4).enumerate() {
+            px.copy_from_slice(&[(x * 10) as u8, (y * 10) as u8, 100, 0]);
+        }
+    }
+    let shot = Screenshot::from_raw(data, 4, 3, 20).unwrap();
+    let color = Pixel {
+        a: 255,
+        r: 100,
+        g: 10,
+        b: 20,
+    };
+    assert_eq!(shot.find_color(color, 0), Some(Point { x: 2, y: 1 }));
+    assert_eq!(shot.find_color(Pixel { b: 22, ..color }, 1), None);
+    assert_eq!(
+        shot.find_color(Pixel { b: 22, ..color }, 2),
+        Some(Point { x: 2, y: 1 })
+    );
+
+    // the bottom right 2x2, in RGBA
+    let mut needle = Vec::new();
+    for y in 1..3 {
+        for x in 2..4 {
+            needle.extend_from_slice(&[x * 10, y * 10, 100, 255]);
+        }
+    }
+    let mut needle = Screenshot::from_raw(needle, 2, 2, 8).unwrap();
+    needle.swap_r_b_in_place();
+    assert_eq!(shot.find_subimage(&needle), Some(Point { x: 2, y: 1 }));
+    let needle = Screenshot::from_raw(vec![1, 2, 3, 4], 1, 1, 4).unwrap();
+    assert_eq!(shot.find_subimage(&needle), None);
+    assert_eq!(shot.find_subimage(&shot), Some(Point { x: 0, y: 0 }));
+    let wide = Screenshot::from_raw(vec![0; 5 * 4], 5, 1, 20).unwrap();
+    assert_eq!(shot.find_subimage(&wide), None);
+
+    // a frame large enough to be searched in parallel still gives the
+    // first match
+    let (width, height) = (1024, 512);
+    let mut data = vec![0; width * height * 4];
+    for (x, y) in [(900, 400), (5, 300), (700, 300)] {
+        data[(y * width + x) * 4..][..3].copy_from_slice(&[20, 10, 100]);
+    }
+    let large = Screenshot::from_raw(data, width, height, width * 4).unwrap();
+    assert_eq!(large.find_color(color, 0), Some(Point { x: 5, y: 300 }));
+}
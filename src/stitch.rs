@@ -0,0 +1,306 @@
+//! Stitching captures of a scrolling view into one tall image, by finding
+//! how far the content moved between consecutive captures.
+
+use crate::{
+    fingerprint::hash_row, CaptureMetadata, PixelFormat, Screenshot, ScreenshotError, PIXEL_WIDTH,
+};
+
+/// Which parts of the captures `ScrollStitcher` leaves out when matching
+/// them, and how tall the stitched image may get.
+#[derive(Clone, Debug)]
+pub struct StitchOptions {
+    /// Rows at the top that don't scroll, e.g. a toolbar or a fixed
+    /// header. They're kept once, from the first capture.
+    pub fixed_header: u32,
+    /// Columns at the right that are ignored when matching rows, e.g. a
+    /// scroll bar drawn within the captured area, whose thumb moves with
+    /// every scroll.
+    pub ignore_right: u32,
+    /// Height at which the stitched image is cut off, in pixels.
+    pub max_height: u32,
+}
+
+impl Default for StitchOptions {
+    fn default() -> Self {
+        StitchOptions {
+            fixed_header: 0,
+            ignore_right: 0,
+            max_height: 20_000,
+        }
+    }
+}
+
+/// What `ScrollStitcher::push` made of a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StitchProgress {
+    /// The content moved up by this many rows, which were appended.
+    Scrolled(u32),
+    /// The content didn't move, so the end is reached.
+    Unchanged,
+    /// The stitched image reached `StitchOptions::max_height` and was cut
+    /// off there.
+    Full,
+}
+
+/// A row of the scrolling part of a capture, as far as it's compared.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct RowKey {
+    hash: u64,
+    /// Whether it's a single colour, like the blank rows between lines of
+    /// text, which match anywhere and so say nothing about the scrolling.
+    plain: bool,
+}
+
+/// Matching rows needed, in tenths of the compared rows that aren't plain.
+const MIN_MATCH_TENTHS: usize = 9;
+
+/// Stitches captures of the same area, each scrolled further down than the
+/// one before, into one tall image.
+///
+/// The scrolling is found by comparing rows: consecutive captures must
+/// overlap by at least an eighth of their scrolling part, and content that
+/// changes by itself, like animations, should stay small. Horizontal
+/// scrolling isn't supported.
+pub struct ScrollStitcher {
+    options: StitchOptions,
+    width: usize,
+    /// Height of each capture.
+    frame_height: usize,
+    format: PixelFormat,
+    metadata: Option<CaptureMetadata>,
+    /// The stitched rows so far, without padding.
+    data: Vec<u8>,
+    /// The scrolling rows of the latest capture.
+    previous: Vec<RowKey>,
+    full: bool,
+}
+
+impl ScrollStitcher {
+    /// Starts with `first`, the capture scrolled furthest up. Fails with
+    /// `ScreenshotError::InvalidOptions` if the fixed header or the ignored
+    /// columns leave nothing of it to compare.
+    pub fn new(first: Screenshot, options: StitchOptions) -> Result<Self, ScreenshotError> {
+        if options.fixed_header as usize >= first.height() {
+            return Err(ScreenshotError::InvalidOptions(
+                "the fixed header must be lower than the captures",
+            ));
+        }
+        if options.ignore_right as usize >= first.width() {
+            return Err(ScreenshotError::InvalidOptions(
+                "the ignored columns must be narrower than the captures",
+            ));
+        }
+        let mut stitcher = ScrollStitcher {
+            width: first.width(),
+            frame_height: first.height(),
+            format: first.format(),
+            metadata: first.metadata,
+            data: Vec::new(),
+            previous: Vec::new(),
+            full: false,
+            options,
+        };
+        stitcher.previous = stitcher.keys(&first);
+        stitcher.append(&first, first.height());
+        Ok(stitcher)
+    }
+
+    /// Appends what `frame` shows below the captures so far. Fails with
+    /// `ScreenshotError::NoScrollOverlap` if it has too little in common
+    /// with the previous capture, e.g. because the view scrolled by more
+    /// than its height, and with `ScreenshotError::DisplayChanged` if it's
+    /// of a different size.
+    ///
+    /// Once `StitchProgress::Full` was returned, further frames are
+    /// ignored.
+    pub fn push(&mut self, mut frame: Screenshot) -> Result<StitchProgress, ScreenshotError> {
+        if self.full {
+            return Ok(StitchProgress::Full);
+        }
+        if (frame.width(), frame.height()) != (self.width, self.frame_height) {
+            return Err(ScreenshotError::DisplayChanged {
+                before: (self.width as i32, self.frame_height as i32),
+                after: (frame.width() as i32, frame.height() as i32),
+            });
+        }
+        if frame.format() != self.format {
+            frame.swap_r_b_in_place();
+        }
+        let keys = self.keys(&frame);
+        let scrolled =
+            find_scroll(&self.previous, &keys).ok_or(ScreenshotError::NoScrollOverlap)?;
+        self.previous = keys;
+        if scrolled == 0 {
+            return Ok(StitchProgress::Unchanged);
+        }
+        if self.append(&frame, scrolled) {
+            Ok(StitchProgress::Scrolled(scrolled as u32))
+        } else {
+            Ok(StitchProgress::Full)
+        }
+    }
+
+    /// Height of the stitched image so far.
+    pub fn height(&self) -> usize {
+        self.data.len() / (self.width * PIXEL_WIDTH)
+    }
+
+    /// The stitched image, in the pixel format of the first capture.
+    pub fn finish(self) -> Screenshot {
+        let (height, row_len) = (self.height(), self.width * PIXEL_WIDTH);
+        let mut stitched = Screenshot::from_bgra(self.data, self.width, height, row_len);
+        stitched.format = self.format;
+        stitched.metadata = self.metadata;
+        stitched
+    }
+
+    /// Appends the bottom `rows` rows of `frame`, as far as the maximum
+    /// height allows. Returns whether they all fit.
+    fn append(&mut self, frame: &Screenshot, rows: usize) -> bool {
+        let room = (self.options.max_height as usize).saturating_sub(self.height());
+        let skip = frame.height() - rows;
+        for row in frame.rows().skip(skip).take(room) {
+            self.data.extend_from_slice(row);
+        }
+        self.full = rows > room;
+        !self.full
+    }
+
+    /// The rows of `frame` below the fixed header, without the ignored
+    /// columns.
+    fn keys(&self, frame: &Screenshot) -> Vec<RowKey> {
+        let len = (self.width - self.options.ignore_right as usize) * PIXEL_WIDTH;
+        frame
+            .rows()
+            .skip(self.options.fixed_header as usize)
+            .map(|row| {
+                let row = &row[..len];
+                RowKey {
+                    hash: hash_row(row),
+                    plain: row
+                        .chunks_exact(PIXEL_WIDTH)
+                        .all(|px| px == &row[..PIXEL_WIDTH]),
+                }
+            })
+            .collect()
+    }
+}
+
+/// How many rows the content moved up from `previous` to `current`, if
+/// enough of the rows that aren't plain match for some distance. Prefers
+/// the distance where the largest share matches, then the smallest.
+fn find_scroll(previous: &[RowKey], current: &[RowKey]) -> Option<usize> {
+    if previous == current {
+        return Some(0);
+    }
+    let min_overlap = (current.len() / 8).max(1);
+    // (distance, matching, compared)
+    let mut best: Option<(usize, usize, usize)> = None;
+    for distance in 0..=current.len() - min_overlap {
+        let (mut matching, mut compared) = (0, 0);
+        for (prev, cur) in previous[distance..].iter().zip(current) {
+            if !cur.plain {
+                compared += 1;
+                matching += usize::from(prev.hash == cur.hash);
+            }
+        }
+        if compared == 0 || matching * 10 < compared * MIN_MATCH_TENTHS {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some((_, best_matching, best_compared)) => {
+                matching * best_compared > best_matching * compared
+            }
+        };
+        if better {
+            best = Some((distance, matching, compared));
+        }
+    }
+    best.map(|(distance, _, _)| distance)
+}
+
+/// A page of 4x100 pixels to scroll through, each row unique apart from
+/// every tenth, which is blank.
+#[cfg(test)]
+fn page_row(y: usize) -> [u8; 16] {
+    let mut row = [200; 16];
+    if y % 10 != 5 {
+        for (x, px) in row.chunks_exact_mut(PIXEL_WIDTH).enumerate() {
+            px.copy_from_slice(&[(x * 50 + y) as u8, y as u8, (y * y) as u8, 255]);
+        }
+    }
+    row
+}
+
+/// The page scrolled down by `offset` rows, seen through a view of 4x30
+/// pixels with a 5 rows high header and a scroll bar in the rightmost
+/// column.
+#[cfg(test)]
+fn view(offset: usize) -> Screenshot {
+    let mut data = Vec::new();
+    for y in 0..30 {
+        let mut row = if y < 5 {
+            [90; 16]
+        } else {
+            page_row(offset + y - 5)
+        };
+        row[12..].copy_from_slice(&[offset as u8, 0, 0, 255]);
+        data.extend_from_slice(&row);
+    }
+    Screenshot::from_raw(data, 4, 30, 16).unwrap()
+}
+
+#[test]
+fn test_stitching() {
+    let options = StitchOptions {
+        fixed_header: 5,
+        ignore_right: 1,
+        ..StitchOptions::default()
+    };
+    let mut stitcher = ScrollStitcher::new(view(0), options.clone()).unwrap();
+    for offset in [11, 22, 33, 44, 55, 66, 75] {
+        assert!(matches!(
+            stitcher.push(view(offset)),
+            Ok(StitchProgress::Scrolled(_))
+        ));
+    }
+    assert_eq!(stitcher.push(view(75)).unwrap(), StitchProgress::Unchanged);
+    let stitched = stitcher.finish();
+    assert_eq!((stitched.width(), stitched.height()), (4, 105));
+    for (y, row) in stitched.rows().enumerate() {
+        let mut expected = [90; 12];
+        if y >= 5 {
+            expected.copy_from_slice(&page_row(y - 5)[..12]);
+        }
+        assert_eq!(row[..12], expected, "row {}", y);
+    }
+
+    // cut off
+    let options = StitchOptions {
+        max_height: 40,
+        ..options
+    };
+    let mut stitcher = ScrollStitcher::new(view(0), options.clone()).unwrap();
+    assert_eq!(stitcher.push(view(8)).unwrap(), StitchProgress::Scrolled(8));
+    assert_eq!(stitcher.push(view(16)).unwrap(), StitchProgress::Full);
+    assert_eq!(stitcher.push(view(24)).unwrap(), StitchProgress::Full);
+    assert_eq!(stitcher.height(), 40);
+
+    // too far, or another size
+    let mut stitcher = ScrollStitcher::new(view(0), options.clone()).unwrap();
+    assert!(matches!(
+        stitcher.push(view(24)),
+        Err(ScreenshotError::NoScrollOverlap)
+    ));
+    let small = Screenshot::from_raw(vec![0; 4 * 10], 1, 10, 4).unwrap();
+    assert!(matches!(
+        stitcher.push(small),
+        Err(ScreenshotError::DisplayChanged { .. })
+    ));
+    let options = StitchOptions {
+        fixed_header: 30,
+        ..options
+    };
+    assert!(ScrollStitcher::new(view(0), options).is_err());
+}
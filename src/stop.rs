@@ -0,0 +1,171 @@
+//! When a streaming capture ends by itself.
+
+use crate::CaptureMetadata;
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// When a streaming capture ends by itself, see `CaptureOptions::stop_when`.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum StopCondition {
+    /// Only when stopped, e.g. with `CaptureHandle::stop`.
+    #[default]
+    Manual,
+    /// Once this long has passed since the capture started, pauses
+    /// included.
+    Duration(#[cfg_attr(feature = "config", serde(with = "crate::config::duration"))] Duration),
+    /// After delivering this many frames. Unchanged frames skipped by
+    /// `CaptureOptions::only_on_change` and failed captures don't count.
+    FrameCount(u64),
+    /// After delivering a frame for whose metadata this returns true.
+    /// Can't be read from or written to a `CaptureProfile`.
+    #[cfg_attr(feature = "config", serde(skip))]
+    When(Arc<dyn Fn(&CaptureMetadata) -> bool + Send + Sync>),
+    /// Whichever of these comes first.
+    Any(Vec<StopCondition>),
+}
+
+impl StopCondition {
+    /// `StopCondition::When`, for a closure.
+    pub fn when<F>(predicate: F) -> Self
+    where
+        F: Fn(&CaptureMetadata) -> bool + Send + Sync + 'static,
+    {
+        StopCondition::When(Arc::new(predicate))
+    }
+
+    /// Stops at this condition or `other`, whichever comes first.
+    pub fn or(self, other: StopCondition) -> Self {
+        match self {
+            StopCondition::Any(mut conditions) => {
+                conditions.push(other);
+                StopCondition::Any(conditions)
+            }
+            condition => StopCondition::Any(vec![condition, other]),
+        }
+    }
+
+    /// The earliest `StopCondition::Duration`, if any.
+    fn limit(&self) -> Option<Duration> {
+        match self {
+            StopCondition::Duration(limit) => Some(*limit),
+            StopCondition::Any(conditions) => conditions.iter().filter_map(|c| c.limit()).min(),
+            _ => None,
+        }
+    }
+
+    fn reached(&self, frames: u64, metadata: Option<&CaptureMetadata>) -> bool {
+        match self {
+            StopCondition::FrameCount(count) => frames >= *count,
+            StopCondition::When(predicate) => matches!(metadata, Some(m) if predicate(m)),
+            StopCondition::Any(conditions) => {
+                conditions.iter().any(|c| c.reached(frames, metadata))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopCondition::Manual => write!(f, "Manual"),
+            StopCondition::Duration(limit) => f.debug_tuple("Duration").field(limit).finish(),
+            StopCondition::FrameCount(count) => f.debug_tuple("FrameCount").field(count).finish(),
+            StopCondition::When(_) => write!(f, "When(..)"),
+            StopCondition::Any(conditions) => f.debug_tuple("Any").field(conditions).finish(),
+        }
+    }
+}
+
+/// Applies a `StopCondition` to the frames of a streaming capture.
+pub(crate) struct StopCheck {
+    condition: StopCondition,
+    started: Instant,
+    /// Frames delivered so far.
+    frames: u64,
+    done: bool,
+}
+
+impl StopCheck {
+    /// Starts counting time and frames from now.
+    pub(crate) fn new(condition: StopCondition) -> Self {
+        StopCheck {
+            condition,
+            started: Instant::now(),
+            frames: 0,
+            done: false,
+        }
+    }
+
+    /// Whether the capture should end before taking another frame.
+    pub(crate) fn is_done(&mut self) -> bool {
+        self.done = self.done || self.time_left() == Some(Duration::ZERO);
+        self.done
+    }
+
+    /// Time until a `StopCondition::Duration` is up, if there's one.
+    pub(crate) fn time_left(&self) -> Option<Duration> {
+        let limit = self.condition.limit()?;
+        Some(limit.saturating_sub(self.started.elapsed()))
+    }
+
+    /// Counts a delivered frame, and returns whether it's the last.
+    pub(crate) fn delivered(&mut self, metadata: Option<&CaptureMetadata>) -> bool {
+        self.frames += 1;
+        self.done = self.done || self.condition.reached(self.frames, metadata);
+        self.done
+    }
+
+    pub(crate) fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+#[test]
+fn test_stop_check() {
+    use crate::Rect;
+    use std::time::SystemTime;
+
+    let metadata = |sequence| CaptureMetadata {
+        captured_at: Instant::now(),
+        wall_time: SystemTime::now(),
+        sequence,
+        source: Rect::default(),
+        window: None,
+        backend: "mock",
+        scale_factor: None,
+        cursor: None,
+        physical_pixels: true,
+        resumed: false,
+    };
+
+    let mut manual = StopCheck::new(StopCondition::default());
+    assert!((0..100).all(|i| !manual.is_done() && !manual.delivered(Some(&metadata(i)))));
+
+    let mut count = StopCheck::new(StopCondition::FrameCount(2));
+    assert!(!count.delivered(Some(&metadata(0))));
+    assert!(count.delivered(Some(&metadata(5))));
+    // and stays done
+    assert!(count.is_done());
+    assert_eq!(count.frames(), 2);
+
+    // whichever comes first
+    let mut any = StopCheck::new(
+        StopCondition::FrameCount(100)
+            .or(StopCondition::when(|m| m.sequence == 2))
+            .or(StopCondition::Duration(Duration::from_secs(60))),
+    );
+    assert!(!any.delivered(Some(&metadata(1))));
+    assert!(any.delivered(Some(&metadata(2))));
+
+    let mut timed = StopCheck::new(StopCondition::Duration(Duration::from_millis(20)));
+    assert!(!timed.is_done());
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(timed.is_done());
+}
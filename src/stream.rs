@@ -0,0 +1,956 @@
+//! Capturing on a background thread, with the frames delivered over a
+//! bounded queue or to a callback.
+
+#[cfg(all(windows, feature = "gdi"))]
+use crate::Capturer;
+use crate::{
+    pacing::Pacer, stop::StopCheck, CaptureBackend, CaptureOptions, CaptureTarget, FrameInfo,
+    FramePool, Pacing, PacingStats, PooledFrame, Screenshot, ScreenshotError,
+};
+
+use std::{
+    any::Any,
+    collections::VecDeque,
+    ops::ControlFlow,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle, Thread},
+    time::{Duration, Instant},
+};
+
+/// What to do with a new frame when the queue is full, i.e. when the
+/// consumer doesn't keep up. The bounded variants hold the number of frames
+/// the queue holds, at least one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "config", serde(rename_all = "snake_case"))]
+pub enum QueuePolicy {
+    /// Discard the oldest queued frame, so the consumer sees the latest
+    /// frames, with gaps.
+    DropOldest { capacity: usize },
+    /// Discard the new frame, so the consumer sees the queued frames, and
+    /// then a gap.
+    DropNewest { capacity: usize },
+    /// Wait until the consumer takes a frame. Capturing stalls meanwhile,
+    /// so the interval isn't kept.
+    Block { capacity: usize },
+    /// Never discard or wait, so memory grows for as long as the consumer
+    /// falls behind.
+    Unbounded,
+}
+
+impl QueuePolicy {
+    /// None if unbounded.
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        match *self {
+            QueuePolicy::DropOldest { capacity }
+            | QueuePolicy::DropNewest { capacity }
+            | QueuePolicy::Block { capacity } => Some(capacity.max(1)),
+            QueuePolicy::Unbounded => None,
+        }
+    }
+}
+
+impl Default for QueuePolicy {
+    /// Keep the two latest frames.
+    fn default() -> Self {
+        QueuePolicy::DropOldest { capacity: 2 }
+    }
+}
+
+/// Frame counts of a streaming capture, see `FrameReceiver::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Frames offered to the queue. Frames skipped by
+    /// `CaptureOptions::only_on_change` and failed captures aren't frames.
+    pub produced: u64,
+    /// Frames taken by the receiver.
+    pub delivered: u64,
+    /// Frames discarded by the `QueuePolicy` because the queue was full,
+    /// since the capture started or `CaptureHandle::reset_dropped`.
+    pub dropped: u64,
+}
+
+/// A queue whose producers and consumer are told apart, so either side
+/// notices when the other is gone. Usually there's one producer.
+pub(crate) struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    changed: Condvar,
+    policy: QueuePolicy,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    /// The producer is done; nothing more will be pushed.
+    closed: bool,
+    /// The consumer is gone; nothing more will be popped.
+    abandoned: bool,
+    stats: QueueStats,
+}
+
+impl<T> Queue<T> {
+    pub(crate) fn new(policy: QueuePolicy) -> Self {
+        Queue {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(policy.capacity().unwrap_or(0)),
+                closed: false,
+                abandoned: false,
+                stats: QueueStats::default(),
+            }),
+            changed: Condvar::new(),
+            policy,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        // every change is a single step, so a panic can't leave it half-done
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, QueueState<T>>) -> MutexGuard<'a, QueueState<T>> {
+        self.changed.wait(state).unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds `item` as the policy says. Returns false if the consumer is
+    /// gone or the queue was closed, and the producer should stop.
+    pub(crate) fn push(&self, item: T) -> bool {
+        let capacity = self.policy.capacity().unwrap_or(usize::MAX);
+        let mut state = self.lock();
+        while state.items.len() >= capacity && !state.abandoned && !state.closed {
+            match self.policy {
+                QueuePolicy::DropOldest { .. } => {
+                    state.items.pop_front();
+                    state.stats.dropped += 1;
+                }
+                QueuePolicy::DropNewest { .. } => {
+                    state.stats.produced += 1;
+                    state.stats.dropped += 1;
+                    return true;
+                }
+                QueuePolicy::Block { .. } => state = self.wait(state),
+                // never full
+                QueuePolicy::Unbounded => break,
+            }
+        }
+        if state.abandoned || state.closed {
+            return false;
+        }
+        state.stats.produced += 1;
+        state.items.push_back(item);
+        self.changed.notify_all();
+        true
+    }
+
+    /// Takes the oldest item, waiting for one if `block`. None if there's
+    /// none and, when blocking, the queue is closed.
+    pub(crate) fn pop(&self, block: bool) -> Option<T> {
+        let mut state = self.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                state.stats.delivered += 1;
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if !block || state.closed {
+                return None;
+            }
+            state = self.wait(state);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        self.lock().stats
+    }
+
+    /// Zeroes the dropped count, returning what it was.
+    fn reset_dropped(&self) -> u64 {
+        std::mem::take(&mut self.lock().stats.dropped)
+    }
+
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn abandon(&self) {
+        let mut state = self.lock();
+        state.abandoned = true;
+        state.items.clear();
+        self.changed.notify_all();
+    }
+}
+
+pub(crate) type Item = Result<PooledFrame, ScreenshotError>;
+
+pub(crate) struct Shared {
+    queue: Queue<Item>,
+    interval: Mutex<Duration>,
+    stop: AtomicBool,
+    paused: AtomicBool,
+    /// Updated by the thread after every wait.
+    pacing: Mutex<PacingStats>,
+    /// Frames delivered to the queue or the callback.
+    delivered: AtomicU64,
+}
+
+impl Shared {
+    pub(crate) fn new(interval: Duration, queue: QueuePolicy) -> Self {
+        Shared {
+            queue: Queue::new(queue),
+            interval: Mutex::new(interval),
+            stop: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            pacing: Mutex::new(PacingStats::default()),
+            delivered: AtomicU64::new(0),
+        }
+    }
+
+    fn lock_interval(&self) -> MutexGuard<'_, Duration> {
+        self.interval.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_pacing(&self) -> MutexGuard<'_, PacingStats> {
+        self.pacing.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&self, options: &CaptureOptions, capture: impl FnMut(&FramePool) -> Item) {
+        let capacity = self.queue.policy.capacity();
+        self.run_into(options, capacity, capture, |frame| self.queue.push(frame));
+        self.queue.close();
+    }
+
+    /// Captures into pooled buffers with `capture` and hands the frames to
+    /// `push`, until it returns false or the capture stops or ends, as
+    /// `options` say. `capacity` is that of the queue `push` adds to, None
+    /// if unbounded.
+    pub(crate) fn run_into(
+        &self,
+        options: &CaptureOptions,
+        capacity: Option<usize>,
+        mut capture: impl FnMut(&FramePool) -> Item,
+        mut push: impl FnMut(Item) -> bool,
+    ) {
+        // Queued frames, plus one being processed and one being captured.
+        // An unbounded queue keeps as many idle buffers as the default one.
+        let pool = FramePool::new(capacity.unwrap_or(2) + 2);
+        let mut changes = options.change_detector();
+        let (pacing, until) = (options.pacing, options.stop_check());
+        self.pace(pacing, until, |resumed, until| {
+            let mut res = capture(&pool);
+            if let (Ok(frame), true) = (&mut res, *resumed) {
+                *resumed = false;
+                if let Some(metadata) = frame.metadata_mut() {
+                    metadata.resumed = true;
+                }
+                // delivered even if unchanged, to show the capture is back
+                if let Some(changes) = &mut changes {
+                    changes.reset();
+                }
+            }
+            let changed = match (&res, &mut changes) {
+                (Ok(frame), Some(changes)) => {
+                    let info = frame.info();
+                    changes.should_deliver(frame, info.width, info.height, info.stride)
+                }
+                _ => true,
+            };
+            if !changed {
+                return ControlFlow::Continue(());
+            }
+            let metadata = res.as_ref().ok().map(|frame| frame.metadata());
+            if !push(res) {
+                return ControlFlow::Break(());
+            }
+            match metadata {
+                Some(metadata) if until.delivered(metadata.as_ref()) => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+    }
+
+    /// Hands every frame `capture` takes to `callback`, see
+    /// `Capturer::on_frame`. `capture` is given the previous frame to reuse
+    /// its buffer. Transient failures are skipped, and others end the
+    /// capture with the error.
+    #[cfg_attr(not(all(windows, feature = "gdi")), allow(dead_code))]
+    fn run_callback<F>(
+        &self,
+        options: &CaptureOptions,
+        mut capture: impl FnMut(Option<Screenshot>) -> Result<Screenshot, ScreenshotError>,
+        mut callback: F,
+    ) -> Result<(), ScreenshotError>
+    where
+        F: FnMut(&Screenshot) -> ControlFlow<()>,
+    {
+        let mut changes = options.change_detector();
+        let mut spare = None;
+        let mut failed = None;
+        let (pacing, until) = (options.pacing, options.stop_check());
+        self.pace(pacing, until, |resumed, until| {
+            let mut frame = match capture(spare.take()) {
+                Ok(frame) => frame,
+                // the rest is up to `CaptureOptions::retry`
+                Err(e) if e.is_transient() => return ControlFlow::Continue(()),
+                Err(e) => {
+                    failed = Some(e);
+                    return ControlFlow::Break(());
+                }
+            };
+            if *resumed {
+                *resumed = false;
+                if let Some(metadata) = &mut frame.metadata {
+                    metadata.resumed = true;
+                }
+                if let Some(changes) = &mut changes {
+                    changes.reset();
+                }
+            }
+            if let Some(changes) = &mut changes {
+                if !changes.should_deliver_frame(&frame) {
+                    spare = Some(frame);
+                    return ControlFlow::Continue(());
+                }
+            }
+            let flow = match panic::catch_unwind(AssertUnwindSafe(|| callback(&frame))) {
+                Ok(flow) => {
+                    if until.delivered(frame.metadata.as_ref()) {
+                        ControlFlow::Break(())
+                    } else {
+                        flow
+                    }
+                }
+                Err(payload) => {
+                    failed = Some(ScreenshotError::CallbackPanicked(panic_message(payload)));
+                    ControlFlow::Break(())
+                }
+            };
+            spare = Some(frame);
+            flow
+        });
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls `frame` every interval until it breaks, the capture stops or
+    /// `until` says it's done, pausing while the handle says so. `resumed`
+    /// is set for the frames after a pause, until `frame` clears it; `frame`
+    /// tells `until` about the frames it delivers.
+    fn pace(
+        &self,
+        pacing: Pacing,
+        mut until: StopCheck,
+        mut frame: impl FnMut(&mut bool, &mut StopCheck) -> ControlFlow<()>,
+    ) {
+        let mut pacer = Pacer::new(pacing, *self.lock_interval());
+        let mut resumed = false;
+        while !self.stop.load(Ordering::Relaxed) && !until.is_done() {
+            if self.paused.load(Ordering::Relaxed) {
+                // woken by `resume` and `stop`, or when the time is up
+                match until.time_left() {
+                    Some(left) => thread::park_timeout(left),
+                    None => thread::park(),
+                }
+                pacer.restart();
+                resumed = true;
+                continue;
+            }
+            if !self.wait(&mut pacer, &mut until) {
+                continue;
+            }
+            let flow = frame(&mut resumed, &mut until);
+            self.delivered.store(until.frames(), Ordering::Relaxed);
+            if flow.is_break() {
+                break;
+            }
+        }
+    }
+
+    /// Waits until the next frame is due, as `Frames` does. The interval is
+    /// read again when woken, so a change applies to the current wait.
+    /// False if the capture was stopped, paused or done meanwhile.
+    fn wait(&self, pacer: &mut Pacer, until: &mut StopCheck) -> bool {
+        pacer.schedule(Instant::now());
+        loop {
+            if self.stop.load(Ordering::Relaxed)
+                || self.paused.load(Ordering::Relaxed)
+                || until.is_done()
+            {
+                return false;
+            }
+            pacer.set_interval(*self.lock_interval());
+            let sleep = pacer.sleep_time(Instant::now());
+            if sleep.is_zero() {
+                break;
+            }
+            thread::park_timeout(until.time_left().map_or(sleep, |left| left.min(sleep)));
+        }
+        pacer.arrive();
+        *self.lock_pacing() = pacer.stats();
+        true
+    }
+}
+
+/// The message a panic was started with, if it's a string.
+#[cfg_attr(not(all(windows, feature = "gdi")), allow(dead_code))]
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast_ref::<&str>() {
+            Some(msg) => msg.to_string(),
+            None => "Box<dyn Any>".to_string(),
+        },
+    }
+}
+
+/// Receives the frames of `spawn_capture` or `Capturer::spawn`, oldest
+/// first, leaving out unchanged ones if `CaptureOptions::only_on_change`
+/// is set. Errors are delivered in place of the frame that failed, and the
+/// capture goes on. Also an iterator, which ends once the capture stopped,
+/// e.g. as `CaptureOptions::stop_when` says, and the queue is drained.
+///
+/// Dropping the receiver stops the capture thread.
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+
+impl FrameReceiver {
+    /// Waits for the next frame. None once the capture stopped and every
+    /// queued frame was received.
+    pub fn recv(&self) -> Option<Result<PooledFrame, ScreenshotError>> {
+        self.shared.queue.pop(true)
+    }
+
+    /// The next frame if one is queued, without waiting.
+    pub fn try_recv(&self) -> Option<Result<PooledFrame, ScreenshotError>> {
+        self.shared.queue.pop(false)
+    }
+
+    /// How many frames were captured, received and dropped so far. Frames
+    /// still queued are neither delivered nor dropped.
+    pub fn stats(&self) -> QueueStats {
+        self.shared.queue.stats()
+    }
+}
+
+impl Iterator for FrameReceiver {
+    type Item = Result<PooledFrame, ScreenshotError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        self.shared.queue.abandon();
+    }
+}
+
+/// Sums up a streaming capture, see `CaptureHandle::join`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CaptureSummary {
+    /// Frames delivered to the queue or the callback.
+    pub frames: u64,
+    /// Frames the `QueuePolicy` dropped since the capture started or the
+    /// last `CaptureHandle::reset_dropped`.
+    pub dropped: u64,
+    /// Average time between frames taken, unchanged ones and failed
+    /// captures included. Zero before the second frame.
+    pub average_interval: Duration,
+}
+
+/// Controls the thread started by `spawn_capture`, `Capturer::spawn` or
+/// `Capturer::on_frame`. Dropping it stops the thread and waits for it to
+/// finish.
+///
+/// It can be shared with other threads, and with the frame callback, e.g.
+/// through an `Arc<OnceLock<CaptureHandle>>`.
+pub struct CaptureHandle {
+    shared: Arc<Shared>,
+    thread: Thread,
+    /// None once joined.
+    join: Mutex<Option<JoinHandle<Result<(), ScreenshotError>>>>,
+}
+
+impl CaptureHandle {
+    /// Changes the time between frames, including the wait for the next
+    /// one.
+    pub fn set_interval(&self, interval: Duration) {
+        *self.shared.lock_interval() = interval;
+        self.thread.unpark();
+    }
+
+    /// Stops capturing frames until `resume`, keeping the thread, the
+    /// queued frames and the capturer's resources. A capture in progress
+    /// still completes.
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+        self.thread.unpark();
+    }
+
+    /// Captures again, starting with a frame right away, which is marked
+    /// by `CaptureMetadata::resumed`. Sequence numbers go on from before the
+    /// pause.
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+        self.thread.unpark();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether the thread ended, e.g. because the frame callback broke off
+    /// or panicked.
+    pub fn is_finished(&self) -> bool {
+        match &*self.lock_join() {
+            Some(join) => join.is_finished(),
+            None => true,
+        }
+    }
+
+    /// How well the capture kept to the interval so far, see
+    /// `CaptureOptions::pacing`.
+    pub fn pacing_stats(&self) -> PacingStats {
+        *self.shared.lock_pacing()
+    }
+
+    /// Number of frames the `QueuePolicy` dropped since the capture
+    /// started or the last `reset_dropped`. Always zero for `on_frame`.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.stats().dropped
+    }
+
+    /// Starts counting dropped frames from zero again, e.g. once per
+    /// monitoring period, and returns the count so far.
+    pub fn reset_dropped(&self) -> u64 {
+        self.shared.queue.reset_dropped()
+    }
+
+    /// Stops capturing and waits for the thread to finish. Frames already
+    /// queued can still be received. Fails with
+    /// `ScreenshotError::CallbackPanicked` if the frame callback panicked,
+    /// or with the error that ended an `on_frame` capture.
+    ///
+    /// Called from the frame callback, it returns right away, and the
+    /// capture ends once the callback returns. Once the thread was waited
+    /// for, further calls return `Ok`.
+    pub fn stop(&self) -> Result<(), ScreenshotError> {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        // wakes the thread if it's blocked on a full queue
+        self.shared.queue.close();
+        self.thread.unpark();
+        self.wait()
+    }
+
+    /// Waits for the capture to end by itself, as `CaptureOptions::stop_when`
+    /// says, and sums it up. Fails like `stop`, which it also sums up after.
+    ///
+    /// With `StopCondition::Manual`, it only returns once the capture is
+    /// stopped from another thread or the receiver is dropped. Called from
+    /// the frame callback, it returns the summary so far.
+    pub fn join(&self) -> Result<CaptureSummary, ScreenshotError> {
+        self.wait()?;
+        Ok(CaptureSummary {
+            frames: self.shared.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped(),
+            average_interval: self.pacing_stats().average_interval(),
+        })
+    }
+
+    fn wait(&self) -> Result<(), ScreenshotError> {
+        if self.thread.id() == thread::current().id() {
+            return Ok(());
+        }
+        let join = self.lock_join().take();
+        match join {
+            Some(join) => join
+                .join()
+                .map_err(|_| ScreenshotError::CaptureThreadFailed)?,
+            None => Ok(()),
+        }
+    }
+
+    fn lock_join(&self) -> MutexGuard<'_, Option<JoinHandle<Result<(), ScreenshotError>>>> {
+        self.join.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts `run` on a thread of its own.
+    pub(crate) fn spawn<F>(shared: Arc<Shared>, run: F) -> Result<Self, ScreenshotError>
+    where
+        F: FnOnce(&Shared) -> Result<(), ScreenshotError> + Send + 'static,
+    {
+        let join = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("screenshot-stream".into())
+                .spawn(move || run(&shared))
+                .map_err(|_| ScreenshotError::CaptureThreadFailed)?
+        };
+        Ok(CaptureHandle {
+            shared,
+            thread: join.thread().clone(),
+            join: Mutex::new(Some(join)),
+        })
+    }
+}
+
+impl Drop for CaptureHandle {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(all(windows, feature = "gdi"))]
+impl Capturer {
+    /// Moves the capturer to a thread of its own that captures a frame
+    /// every `interval` into buffers of a `FramePool`, and queues them as
+    /// `queue` says.
+    pub fn spawn(
+        mut self,
+        interval: Duration,
+        queue: QueuePolicy,
+    ) -> Result<(FrameReceiver, CaptureHandle), ScreenshotError> {
+        let shared = Arc::new(Shared::new(interval, queue));
+        let handle = CaptureHandle::spawn(shared.clone(), move |shared| {
+            let options = self.options().clone();
+            shared.run(&options, |pool| self.capture_pooled(pool));
+            Ok(())
+        })?;
+        Ok((FrameReceiver { shared }, handle))
+    }
+
+    /// Moves the capturer to a thread of its own that captures a frame
+    /// every `interval` and hands it to `callback`, until that returns
+    /// `ControlFlow::Break` or the capture is stopped.
+    ///
+    /// Frames are captured into the capturer's own buffers and lent to the
+    /// callback, so capturing doesn't allocate. A slow callback delays the
+    /// next frame, as with `Frames`. Captures failing with a transient
+    /// error are skipped; any other error ends the capture, as does a panic
+    /// of the callback, and `CaptureHandle::stop` reports it.
+    pub fn on_frame<F>(
+        mut self,
+        interval: Duration,
+        callback: F,
+    ) -> Result<CaptureHandle, ScreenshotError>
+    where
+        F: FnMut(&Screenshot) -> ControlFlow<()> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::new(interval, QueuePolicy::default()));
+        CaptureHandle::spawn(shared, move |shared| {
+            let options = self.options().clone();
+            shared.run_callback(&options, |spare| self.capture_swap(spare), callback)
+        })
+    }
+}
+
+/// Captures what `options` select, by default the primary display, every
+/// `interval` on a thread of its own, see `Capturer::with_options` and
+/// `Capturer::spawn`. Off Windows, it streams from the default backend,
+/// see `spawn_backend_capture`.
+#[cfg(all(windows, feature = "gdi"))]
+pub fn spawn_capture(
+    options: CaptureOptions,
+    interval: Duration,
+    queue: QueuePolicy,
+) -> Result<(FrameReceiver, CaptureHandle), ScreenshotError> {
+    Capturer::with_options(options)?.spawn(interval, queue)
+}
+
+#[cfg(not(all(windows, feature = "gdi")))]
+pub fn spawn_capture(
+    options: CaptureOptions,
+    interval: Duration,
+    queue: QueuePolicy,
+) -> Result<(FrameReceiver, CaptureHandle), ScreenshotError> {
+    let backend = crate::backend::DefaultBackend::default();
+    let target = options.target(|| backend.monitors())?;
+    spawn_backend_capture(backend, target, options, interval, queue)
+}
+
+/// Captures `target` with `backend` every `interval` on a thread of its
+/// own, as `spawn_capture` does with the default backend. Every frame is
+/// copied out of the backend's screenshot into a pooled buffer.
+pub fn spawn_backend_capture<B>(
+    mut backend: B,
+    target: CaptureTarget,
+    options: CaptureOptions,
+    interval: Duration,
+    queue: QueuePolicy,
+) -> Result<(FrameReceiver, CaptureHandle), ScreenshotError>
+where
+    B: CaptureBackend + Send + 'static,
+{
+    let shared = Arc::new(Shared::new(interval, queue));
+    let handle = CaptureHandle::spawn(shared.clone(), move |shared| {
+        shared.run(&options, |pool| {
+            let frame = backend.capture_target(target, &options)?;
+            let mut buf = pool.take();
+            buf.clear();
+            buf.extend_from_slice(frame.data());
+            pool.set_frame_len(buf.len());
+            let info = FrameInfo {
+                width: frame.width(),
+                height: frame.height(),
+                stride: frame.row_len(),
+                format: frame.format(),
+            };
+            Ok(pool.wrap(buf, info, frame.metadata().copied()))
+        });
+        Ok(())
+    })?;
+    Ok((FrameReceiver { shared }, handle))
+}
+
+#[test]
+fn test_queue_policies() {
+    let stats = |produced, delivered, dropped| QueueStats {
+        produced,
+        delivered,
+        dropped,
+    };
+    let queue = Queue::new(QueuePolicy::DropOldest { capacity: 2 });
+    assert!((0..5).all(|i| queue.push(i)));
+    assert_eq!((queue.pop(false), queue.pop(false)), (Some(3), Some(4)));
+    assert_eq!(queue.pop(false), None);
+    assert_eq!(queue.stats(), stats(5, 2, 3));
+    assert_eq!(queue.reset_dropped(), 3);
+    assert_eq!(queue.stats(), stats(5, 2, 0));
+
+    let queue = Queue::new(QueuePolicy::DropNewest { capacity: 2 });
+    assert!((0..5).all(|i| queue.push(i)));
+    assert_eq!((queue.pop(false), queue.pop(false)), (Some(0), Some(1)));
+    assert_eq!(queue.stats(), stats(5, 2, 3));
+
+    let queue = Queue::new(QueuePolicy::Unbounded);
+    assert!((0..100).all(|i| queue.push(i)));
+    assert_eq!(queue.pop(false), Some(0));
+    assert_eq!(queue.stats(), stats(100, 1, 0));
+
+    // a full blocking queue waits for the consumer
+    let queue = Arc::new(Queue::new(QueuePolicy::Block { capacity: 1 }));
+    queue.push(0);
+    let producer = {
+        let queue = queue.clone();
+        thread::spawn(move || queue.push(1))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(queue.pop(false), Some(0));
+    assert!(producer.join().unwrap());
+    assert_eq!(queue.pop(true), Some(1));
+    assert_eq!(queue.stats(), stats(2, 2, 0));
+
+    // and gives up once the consumer is gone
+    queue.push(2);
+    let producer = {
+        let queue = queue.clone();
+        thread::spawn(move || queue.push(3))
+    };
+    thread::sleep(Duration::from_millis(50));
+    queue.abandon();
+    assert!(!producer.join().unwrap());
+
+    // closed queues are drained, then end
+    let queue = Queue::new(QueuePolicy::default());
+    queue.push(0);
+    queue.close();
+    assert!(!queue.push(1));
+    assert_eq!((queue.pop(true), queue.pop(true)), (Some(0), None));
+}
+
+#[test]
+#[cfg(any(all(windows, feature = "gdi"), target_os = "macos"))]
+fn test_spawn_capture() {
+    let (frames, handle) = spawn_capture(
+        CaptureOptions::default(),
+        Duration::from_millis(10),
+        QueuePolicy::DropOldest { capacity: 2 },
+    )
+    .unwrap();
+    let frame = frames.recv().unwrap().unwrap();
+    assert_eq!(frame.len(), frame.info().stride * frame.info().height);
+    let before = frame.metadata().unwrap();
+    assert!(!before.resumed);
+    drop(frame);
+
+    // nothing is captured while paused
+    handle.pause();
+    assert!(handle.is_paused());
+    thread::sleep(Duration::from_millis(50));
+    while frames.try_recv().is_some() {}
+    let produced = frames.stats().produced;
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(frames.stats().produced, produced);
+    // and the first frame after resuming says so, numbered on
+    handle.resume();
+    assert!(!handle.is_paused());
+    let after = frames.recv().unwrap().unwrap().metadata().unwrap();
+    assert!(after.resumed);
+    assert!(after.sequence > before.sequence);
+
+    // a slow consumer doesn't make the queue grow
+    handle.set_interval(Duration::from_millis(1));
+    thread::sleep(Duration::from_millis(200));
+    assert!(handle.dropped() > 0);
+    // frames are counted when due, and produced once captured
+    let produced = frames.stats().produced;
+    assert!(handle.pacing_stats().frames >= produced);
+    let dropped = handle.reset_dropped();
+    handle.stop().unwrap();
+    // what's neither delivered nor dropped is still queued
+    let stats = frames.stats();
+    assert!(stats.produced - stats.delivered - stats.dropped - dropped <= 2);
+    assert!(frames.count() <= 2);
+}
+
+#[test]
+fn test_spawn_backend_capture() {
+    use crate::testing::{MockCapturer, MockFrame};
+
+    let region = crate::Rect {
+        x: 2,
+        y: 1,
+        width: 4,
+        height: 3,
+    };
+    let (frames, handle) = spawn_backend_capture(
+        MockCapturer::new(8, 8, MockFrame::Gradient),
+        CaptureTarget::Region(region),
+        CaptureOptions::default(),
+        Duration::from_millis(1),
+        QueuePolicy::Block { capacity: 2 },
+    )
+    .unwrap();
+    for _ in 0..3 {
+        let frame = frames.recv().unwrap().unwrap();
+        assert_eq!((frame.info().width, frame.info().height), (4, 3));
+        assert_eq!(frame.len(), 4 * 4 * 3);
+    }
+    handle.stop().unwrap();
+}
+
+#[test]
+#[cfg(all(windows, feature = "gdi"))]
+fn test_stop_conditions() {
+    use crate::StopCondition;
+
+    let mut capturer = Capturer::new();
+    capturer.set_options(CaptureOptions {
+        stop_when: StopCondition::FrameCount(3),
+        ..CaptureOptions::default()
+    });
+    let (frames, handle) = capturer
+        .spawn(Duration::from_millis(1), QueuePolicy::Unbounded)
+        .unwrap();
+    // the receiver ends after the last frame
+    assert_eq!(frames.filter(Result::is_ok).count(), 3);
+    let summary = handle.join().unwrap();
+    assert_eq!((summary.frames, summary.dropped), (3, 0));
+    assert!(summary.average_interval > Duration::ZERO);
+
+    // whichever comes first
+    let mut capturer = Capturer::new();
+    capturer.set_options(CaptureOptions {
+        stop_when: StopCondition::FrameCount(1000)
+            .or(StopCondition::Duration(Duration::from_millis(100))),
+        ..CaptureOptions::default()
+    });
+    let started = Instant::now();
+    let handle = capturer
+        .on_frame(Duration::from_millis(20), |_| ControlFlow::Continue(()))
+        .unwrap();
+    let summary = handle.join().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert!(summary.frames > 0 && summary.frames <= 6);
+}
+
+#[test]
+#[cfg(all(windows, feature = "gdi"))]
+fn test_on_frame() {
+    use std::sync::{atomic::AtomicUsize, OnceLock};
+
+    let wait = |handle: &CaptureHandle| {
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    };
+    // breaking off ends the capture
+    let frames = Arc::new(AtomicUsize::new(0));
+    let handle = {
+        let frames = frames.clone();
+        Capturer::new().on_frame(Duration::from_millis(1), move |frame| {
+            assert!(!frame.is_empty());
+            match frames.fetch_add(1, Ordering::Relaxed) {
+                2 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
+    }
+    .unwrap();
+    wait(&handle);
+    handle.stop().unwrap();
+    assert_eq!(frames.load(Ordering::Relaxed), 3);
+
+    // so does a panic, which the handle reports
+    let handle = Capturer::new()
+        .on_frame(Duration::from_millis(1), |_| panic!("boom"))
+        .unwrap();
+    wait(&handle);
+    match handle.stop() {
+        Err(ScreenshotError::CallbackPanicked(msg)) => assert_eq!(msg, "boom"),
+        res => panic!("unexpected {:?}", res),
+    }
+
+    // and stopping from the callback
+    let cell = Arc::new(OnceLock::<CaptureHandle>::new());
+    let handle = {
+        let cell = cell.clone();
+        Capturer::new().on_frame(Duration::from_millis(1), move |_| {
+            if let Some(handle) = cell.get() {
+                handle.stop().unwrap();
+            }
+            ControlFlow::Continue(())
+        })
+    }
+    .unwrap();
+    assert!(cell.set(handle).is_ok());
+    wait(cell.get().unwrap());
+    cell.get().unwrap().stop().unwrap();
+}
+
+#[test]
+fn test_callback_errors() {
+    use crate::testing::{MockCapturer, MockFrame};
+
+    let shared = Shared::new(Duration::from_millis(1), QueuePolicy::default());
+    let options = CaptureOptions::default();
+    let mut mock = MockCapturer::new(4, 4, MockFrame::Gradient);
+    // transient failures are skipped
+    mock.fail_next(ScreenshotError::BitBltFailed);
+    let mut frames = 0;
+    let res = shared.run_callback(
+        &options,
+        |_| mock.capture(),
+        |_| {
+            frames += 1;
+            match frames {
+                2 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        },
+    );
+    assert!(res.is_ok());
+    assert_eq!((frames, mock.frames_captured()), (2, 2));
+
+    // others end the capture with the error
+    mock.fail_next(ScreenshotError::NoMonitors);
+    let res = shared.run_callback(&options, |_| mock.capture(), |_| unreachable!());
+    assert!(matches!(res, Err(ScreenshotError::NoMonitors)));
+    assert_eq!(mock.frames_captured(), 2);
+}
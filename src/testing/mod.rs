@@ -0,0 +1,494 @@
+//! Deterministic stand-ins for a real display, so code built on this crate
+//! can be tested on machines without one, e.g. in CI, and assertions
+//! comparing screenshots against goldens.
+
+use crate::{
+    buffer_len, validate_region, CaptureBackend, CaptureOptions, CaptureTarget, Monitor, Pixel,
+    Rect, Screenshot, ScreenshotError, Window, PIXEL_WIDTH,
+};
+
+use std::{collections::VecDeque, fs, io, path::Path};
+
+mod similarity;
+
+pub use similarity::{assert_screenshots_similar, SimilarityOptions};
+
+/// The picture a `MockCapturer` produces.
+#[derive(Clone, Debug)]
+pub enum MockFrame {
+    /// Every pixel has the same colour.
+    Solid(Pixel),
+    /// Red increases from left to right, green from top to bottom, blue is
+    /// zero and alpha is opaque.
+    Gradient,
+    /// Squares of `size` pixels alternating between two colours, starting
+    /// with `a` in the top left corner.
+    Checkerboard { size: usize, a: Pixel, b: Pixel },
+    /// Packed BGRA pixels, e.g. loaded from a dump of a real capture.
+    Raw(Vec<u8>),
+}
+
+/// Captures synthetic frames instead of the screen.
+///
+/// Failures can be queued with `fail_next`; each capture pops one off the
+/// queue before producing a frame, so error paths can be tested
+/// deterministically.
+///
+/// As a `CaptureBackend`, it has a single primary monitor the size of the
+/// frame and no windows. Regions are cut out of the frame, and the
+/// `RetryPolicy` of the options is applied, so retries see the queued
+/// failures too.
+pub struct MockCapturer {
+    width: usize,
+    height: usize,
+    frame: MockFrame,
+    failures: VecDeque<ScreenshotError>,
+    frames_captured: u64,
+}
+
+impl MockCapturer {
+    pub fn new(width: usize, height: usize, frame: MockFrame) -> Self {
+        MockCapturer {
+            width,
+            height,
+            frame,
+            failures: VecDeque::new(),
+            frames_captured: 0,
+        }
+    }
+
+    /// A capturer replaying a raw BGRA dump of `width` x `height` pixels.
+    pub fn from_raw_dump<P: AsRef<Path>>(path: P, width: usize, height: usize) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        if Some(data.len()) != buffer_len(width, height).ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dump size doesn't match the dimensions",
+            ));
+        }
+        Ok(MockCapturer::new(width, height, MockFrame::Raw(data)))
+    }
+
+    /// Makes the next capture fail with `error`. Queued failures are
+    /// returned in order.
+    pub fn fail_next(&mut self, error: ScreenshotError) -> &mut Self {
+        self.failures.push_back(error);
+        self
+    }
+
+    /// Changes the picture, and its size, of subsequent captures.
+    pub fn set_frame(&mut self, width: usize, height: usize, frame: MockFrame) {
+        self.width = width;
+        self.height = height;
+        self.frame = frame;
+    }
+
+    /// Number of frames successfully captured so far.
+    pub fn frames_captured(&self) -> u64 {
+        self.frames_captured
+    }
+
+    pub fn capture(&mut self) -> Result<Screenshot, ScreenshotError> {
+        if let Some(e) = self.failures.pop_front() {
+            return Err(e);
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(ScreenshotError::EmptyDisplay {
+                width: self.width as i32,
+                height: self.height as i32,
+            });
+        }
+        let data = render(&self.frame, self.width, self.height)?;
+        self.frames_captured += 1;
+        Ok(Screenshot::from_bgra(
+            data,
+            self.width,
+            self.height,
+            self.width * PIXEL_WIDTH,
+        ))
+    }
+}
+
+impl MockCapturer {
+    /// The only monitor, at the origin.
+    fn bounds(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.width as u32,
+            height: self.height as u32,
+        }
+    }
+}
+
+impl CaptureBackend for MockCapturer {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+        Ok(vec![Monitor {
+            rect: self.bounds(),
+            work_area: self.bounds(),
+            primary: true,
+            scale_factor: 1.0,
+            name: None,
+            device_name: None,
+            refresh_rate: None,
+            bits_per_pixel: None,
+            orientation: None,
+            physical_size_mm: None,
+        }])
+    }
+
+    fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+        Ok(Vec::new())
+    }
+
+    fn capture_target(
+        &mut self,
+        target: CaptureTarget,
+        options: &CaptureOptions,
+    ) -> Result<Screenshot, ScreenshotError> {
+        let region = match target {
+            CaptureTarget::Primary => return options.retry.run(|| self.capture()),
+            CaptureTarget::Region(region) => validate_region(region, self.bounds())?,
+            CaptureTarget::Window(window) => return Err(ScreenshotError::NoSuchWindow(window)),
+        };
+        let frame = options.retry.run(|| self.capture())?;
+        let (x, width, height) = (
+            region.x as usize * PIXEL_WIDTH,
+            region.width as usize,
+            region.height as usize,
+        );
+        let row_len = width * PIXEL_WIDTH;
+        let mut data = Vec::with_capacity(row_len * height);
+        for row in frame
+            .data()
+            .chunks(frame.row_len())
+            .skip(region.y as usize)
+            .take(height)
+        {
+            data.extend_from_slice(&row[x..x + row_len]);
+        }
+        Ok(Screenshot::from_bgra(data, width, height, row_len))
+    }
+}
+
+/// Checks what every backend must do alike, so code written against
+/// `CaptureBackend` works whichever backend it's given, and panics with the
+/// backend's name on the first check that fails:
+///
+/// - the primary monitor captures at its `Monitor::rect` size, or at that
+///   size times `Monitor::scale_factor` for backends whose coordinates
+///   aren't pixels;
+/// - consecutive captures have the same size and pixel format;
+/// - a region comes out the same as that area of a full capture;
+/// - regions that are empty or off screen fail with
+///   `ScreenshotError::InvalidRegion`.
+///
+/// Needs something on screen for a real backend. The screen may change
+/// between captures, e.g. a blinking cursor, so up to 10% of a region's
+/// pixels may differ from the full capture.
+pub fn assert_conformance<B: CaptureBackend + ?Sized>(backend: &mut B) {
+    let name = backend.name();
+    let options = CaptureOptions::default();
+    let monitors = backend.monitors().unwrap();
+    let primary = monitors
+        .iter()
+        .find(|m| m.primary)
+        .unwrap_or_else(|| panic!("{}: no primary monitor", name));
+    let full = backend
+        .capture_target(CaptureTarget::Primary, &options)
+        .unwrap();
+    let (width, height) = (primary.rect.width as usize, primary.rect.height as usize);
+    let scale = primary.scale_factor;
+    assert!(
+        (full.width(), full.height()) == (width, height)
+            || (full.width(), full.height()) == (scaled_by(width, scale), scaled_by(height, scale)),
+        "{}: captured {} x {} of a {} x {} monitor at scale {}",
+        name,
+        full.width(),
+        full.height(),
+        width,
+        height,
+        primary.scale_factor
+    );
+
+    let again = backend
+        .capture_target(CaptureTarget::Primary, &options)
+        .unwrap();
+    assert_eq!(
+        (again.width(), again.height(), again.format()),
+        (full.width(), full.height(), full.format()),
+        "{}: consecutive captures differ",
+        name
+    );
+    assert!(full.row_len() >= full.width() * PIXEL_WIDTH);
+    assert_eq!(full.len(), full.row_len() * full.height());
+
+    // a quarter of the monitor, away from the edges
+    let region = Rect {
+        x: primary.rect.x + (width / 4) as i32,
+        y: primary.rect.y + (height / 4) as i32,
+        width: (width / 4).max(1) as u32,
+        height: (height / 4).max(1) as u32,
+    };
+    let part = backend
+        .capture_target(CaptureTarget::Region(region), &options)
+        .unwrap();
+    let scale = full.width() as f64 / width as f64;
+    let (left, top) = (scaled_by(width / 4, scale), scaled_by(height / 4, scale));
+    assert_eq!(
+        (part.width(), part.height()),
+        (
+            scaled_by(region.width as usize, scale),
+            scaled_by(region.height as usize, scale)
+        ),
+        "{}: region {:?} has the wrong size",
+        name,
+        region
+    );
+    assert_eq!(part.format(), full.format(), "{}: region format", name);
+    let differing = (0..part.height())
+        .flat_map(|row| (0..part.width()).map(move |col| (row, col)))
+        .filter(|&(row, col)| part.get_pixel(row, col) != full.get_pixel(top + row, left + col))
+        .count();
+    assert!(
+        differing * 10 <= part.width() * part.height(),
+        "{}: {} pixels of region {:?} differ from the full capture",
+        name,
+        differing,
+        region
+    );
+
+    let right = monitors
+        .iter()
+        .map(|m| i64::from(m.rect.x) + i64::from(m.rect.width))
+        .max()
+        .unwrap_or(0);
+    let off_screen = Rect {
+        x: (right + 1) as i32,
+        ..region
+    };
+    let empty = Rect { width: 0, ..region };
+    for invalid in [off_screen, empty] {
+        let res = backend.capture_target(CaptureTarget::Region(invalid), &options);
+        assert!(
+            matches!(res, Err(ScreenshotError::InvalidRegion(_))),
+            "{}: region {:?} gave {:?}",
+            name,
+            invalid,
+            res.map(|s| (s.width(), s.height()))
+        );
+    }
+}
+
+fn scaled_by(v: usize, scale: f64) -> usize {
+    (v as f64 * scale).round() as usize
+}
+
+/// Renders `frame` into a packed BGRA buffer.
+fn render(frame: &MockFrame, width: usize, height: usize) -> Result<Vec<u8>, ScreenshotError> {
+    let len = buffer_len(width, height)?;
+    if let MockFrame::Raw(data) = frame {
+        if data.len() != len {
+            return Err(ScreenshotError::GetDIBitsFailed);
+        }
+        return Ok(data.clone());
+    }
+
+    let (last_col, last_row) = ((width - 1).max(1), (height - 1).max(1));
+    let mut data = Vec::with_capacity(len);
+    for row in 0..height {
+        for col in 0..width {
+            let p = match frame {
+                MockFrame::Solid(p) => *p,
+                MockFrame::Gradient => Pixel {
+                    a: 255,
+                    r: (col * 255 / last_col) as u8,
+                    g: (row * 255 / last_row) as u8,
+                    b: 0,
+                },
+                MockFrame::Checkerboard { size, a, b } => {
+                    let size = (*size).max(1);
+                    if (row / size + col / size) % 2 == 0 {
+                        *a
+                    } else {
+                        *b
+                    }
+                }
+                MockFrame::Raw(_) => unreachable!(),
+            };
+            data.extend_from_slice(&[p.b, p.g, p.r, p.a]);
+        }
+    }
+    Ok(data)
+}
+
+#[test]
+fn test_mock_frames() {
+    let red = Pixel {
+        a: 255,
+        r: 255,
+        g: 0,
+        b: 0,
+    };
+    let blue = Pixel {
+        a: 255,
+        r: 0,
+        g: 0,
+        b: 255,
+    };
+
+    let s = MockCapturer::new(4, 3, MockFrame::Solid(red))
+        .capture()
+        .unwrap();
+    assert_eq!((s.width(), s.height(), s.len()), (4, 3, 48));
+    assert_eq!(s.get_pixel(2, 3), red);
+    assert_eq!(s.rgba_iter().next(), Some([255, 0, 0, 255]));
+
+    let s = MockCapturer::new(
+        4,
+        4,
+        MockFrame::Checkerboard {
+            size: 2,
+            a: red,
+            b: blue,
+        },
+    )
+    .capture()
+    .unwrap();
+    assert_eq!(s.get_pixel(0, 0), red);
+    assert_eq!(s.get_pixel(1, 2), blue);
+    assert_eq!(s.get_pixel(3, 3), red);
+
+    let s = MockCapturer::new(256, 2, MockFrame::Gradient)
+        .capture()
+        .unwrap();
+    assert_eq!(s.get_pixel(0, 0).r, 0);
+    assert_eq!(s.get_pixel(0, 255).r, 255);
+    assert_eq!(s.get_pixel(1, 0).g, 255);
+}
+
+#[test]
+fn test_mock_failures() {
+    let mut mock = MockCapturer::new(2, 2, MockFrame::Gradient);
+    mock.fail_next(ScreenshotError::BitBltFailed)
+        .fail_next(ScreenshotError::NoMonitors);
+    assert!(matches!(mock.capture(), Err(ScreenshotError::BitBltFailed)));
+    assert!(matches!(mock.capture(), Err(ScreenshotError::NoMonitors)));
+    assert!(mock.capture().is_ok());
+    assert_eq!(mock.frames_captured(), 1);
+
+    mock.set_frame(0, 2, MockFrame::Gradient);
+    assert!(matches!(
+        mock.capture(),
+        Err(ScreenshotError::EmptyDisplay { .. })
+    ));
+
+    // retries see the same sequence of failures as a real display would
+    let mut mock = MockCapturer::new(2, 2, MockFrame::Gradient);
+    mock.fail_next(ScreenshotError::BitBltFailed);
+    let policy = crate::RetryPolicy {
+        max_attempts: 2,
+        delay: std::time::Duration::ZERO,
+        backoff: 1.0,
+    };
+    assert!(policy.run(|| mock.capture()).is_ok());
+}
+
+#[test]
+fn test_mock_raw_dump() {
+    let path = std::env::temp_dir().join("screenshot_mock_raw_dump.bgra");
+    fs::write(&path, [1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    assert!(MockCapturer::from_raw_dump(&path, 2, 2).is_err());
+    let s = MockCapturer::from_raw_dump(&path, 2, 1)
+        .unwrap()
+        .capture()
+        .unwrap();
+    assert_eq!(s.data(), [1, 2, 3, 4, 5, 6, 7, 8]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_mock_backend() {
+    fn capture_region(
+        backend: &mut dyn CaptureBackend,
+        region: Rect,
+    ) -> Result<Screenshot, ScreenshotError> {
+        backend.capture_target(CaptureTarget::Region(region), &CaptureOptions::default())
+    }
+
+    let mut mock = MockCapturer::new(16, 8, MockFrame::Gradient);
+    let monitors = mock.monitors().unwrap();
+    assert_eq!(monitors.len(), 1);
+    assert_eq!((monitors[0].rect.width, monitors[0].rect.height), (16, 8));
+    assert!(mock.windows().unwrap().is_empty());
+
+    let full = mock.capture().unwrap();
+    let region = Rect {
+        x: 3,
+        y: 2,
+        width: 5,
+        height: 4,
+    };
+    let s = capture_region(&mut mock, region).unwrap();
+    assert_eq!((s.width(), s.height(), s.row_len()), (5, 4, 20));
+    for row in 0..4 {
+        for col in 0..5 {
+            assert_eq!(s.get_pixel(row, col), full.get_pixel(row + 2, col + 3));
+        }
+    }
+    assert!(matches!(
+        capture_region(&mut mock, Rect { x: 12, ..region }),
+        Err(ScreenshotError::InvalidRegion(_))
+    ));
+    assert!(matches!(
+        mock.capture_target(
+            CaptureTarget::Window(crate::WindowId(1)),
+            &CaptureOptions::default()
+        ),
+        Err(ScreenshotError::NoSuchWindow(_))
+    ));
+
+    // the retry policy applies to queued failures
+    mock.fail_next(ScreenshotError::BitBltFailed);
+    let options = CaptureOptions {
+        retry: crate::RetryPolicy {
+            max_attempts: 2,
+            delay: std::time::Duration::ZERO,
+            backoff: 1.0,
+        },
+        ..CaptureOptions::default()
+    };
+    assert!(mock
+        .capture_target(CaptureTarget::Primary, &options)
+        .is_ok());
+    assert_eq!(mock.frames_captured(), 3);
+}
+
+#[test]
+fn test_mock_conformance() {
+    assert_conformance(&mut MockCapturer::new(64, 48, MockFrame::Gradient));
+    let mut boxed: Box<dyn CaptureBackend> = Box::new(MockCapturer::new(
+        7,
+        5,
+        MockFrame::Checkerboard {
+            size: 2,
+            a: Pixel {
+                a: 255,
+                r: 0,
+                g: 0,
+                b: 0,
+            },
+            b: Pixel {
+                a: 255,
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        },
+    ));
+    assert_conformance(&mut boxed);
+}
@@ -0,0 +1,274 @@
+//! Comparing screenshots against goldens in UI tests, leaving images to
+//! look at behind when they differ.
+
+use crate::{Point, Rect, Screenshot, PIXEL_WIDTH};
+
+use std::{
+    fs, io,
+    panic::Location,
+    path::{Path, PathBuf},
+};
+
+/// How much `assert_screenshots_similar` lets screenshots differ.
+#[derive(Clone, Debug)]
+pub struct SimilarityOptions {
+    /// How much each of red, green and blue may differ for pixels to still
+    /// count as equal. Alpha isn't compared, as GDI leaves it undefined.
+    pub tolerance: u8,
+    /// Fraction of the compared pixels, from 0 to 1, that may differ by
+    /// more than `tolerance`.
+    pub max_diff_fraction: f64,
+    /// Areas that aren't compared, e.g. a clock or the cursor, in pixels
+    /// from the top left corner of the screenshots.
+    pub ignore_regions: Vec<Rect>,
+    /// Where the images are written when the screenshots differ. By
+    /// default `screenshot-artifacts` in the temporary directory.
+    pub artifacts_dir: PathBuf,
+}
+
+impl Default for SimilarityOptions {
+    /// Exactly equal, apart from alpha.
+    fn default() -> Self {
+        SimilarityOptions {
+            tolerance: 0,
+            max_diff_fraction: 0.0,
+            ignore_regions: Vec::new(),
+            artifacts_dir: std::env::temp_dir().join("screenshot-artifacts"),
+        }
+    }
+}
+
+/// Differences between two screenshots of the same size.
+struct Comparison {
+    /// Pixels outside the ignored regions.
+    compared: usize,
+    /// Compared pixels differing by more than the tolerance.
+    differing: usize,
+    diff: Screenshot,
+}
+
+/// Panics unless `actual` matches `expected` within `options`, e.g. a
+/// capture and a golden made with `Screenshot::from_raw`. Screenshots of
+/// different sizes never match; their pixel formats may differ.
+///
+/// When they don't match, `actual`, `expected` and, if they're the same
+/// size, a diff are saved to `options.artifacts_dir` as BMP files named
+/// after the calling file and line, e.g. `login-42-actual.bmp`. The diff
+/// shows differing pixels in red over a faded grey copy of `expected`, and
+/// ignored regions in dark blue. The panic message gives the share of
+/// differing pixels and the paths of the files.
+#[track_caller]
+pub fn assert_screenshots_similar(
+    actual: &Screenshot,
+    expected: &Screenshot,
+    options: &SimilarityOptions,
+) {
+    let caller = Location::caller();
+    let (problem, diff) = if actual.size() != expected.size() {
+        let problem = format!(
+            "screenshots differ in size: {} x {}, expected {} x {}",
+            actual.width(),
+            actual.height(),
+            expected.width(),
+            expected.height()
+        );
+        (problem, None)
+    } else {
+        let comparison = compare(actual, expected, options);
+        if comparison.differing as f64 <= options.max_diff_fraction * comparison.compared as f64 {
+            return;
+        }
+        let problem = format!(
+            "screenshots differ in {:.2}% of the compared pixels ({} of {}), at most {:.2}% \
+             allowed with tolerance {}",
+            comparison.differing as f64 * 100.0 / comparison.compared as f64,
+            comparison.differing,
+            comparison.compared,
+            options.max_diff_fraction * 100.0,
+            options.tolerance
+        );
+        (problem, Some(comparison.diff))
+    };
+
+    let stem = Path::new(caller.file())
+        .file_stem()
+        .map_or("screenshot".into(), |stem| stem.to_string_lossy());
+    let prefix = format!("{}-{}", stem, caller.line());
+    let mut images = vec![("actual", actual), ("expected", expected)];
+    images.extend(diff.as_ref().map(|diff| ("diff", diff)));
+    match save_artifacts(&options.artifacts_dir, &prefix, &images) {
+        Ok(paths) => {
+            let paths: Vec<_> = images
+                .iter()
+                .zip(paths)
+                .map(|((kind, _), path)| format!("\n  {}: {}", kind, path.display()))
+                .collect();
+            panic!("{}{}", problem, paths.concat())
+        }
+        Err(e) => panic!(
+            "{}; the images couldn't be saved to {}: {}",
+            problem,
+            options.artifacts_dir.display(),
+            e
+        ),
+    }
+}
+
+/// Compares the RGB of the pixels of two screenshots of the same size and
+/// draws the diff image.
+fn compare(actual: &Screenshot, expected: &Screenshot, options: &SimilarityOptions) -> Comparison {
+    let (width, height) = (expected.width(), expected.height());
+    let (ar, ab) = actual.red_blue_offsets();
+    let (er, eb) = expected.red_blue_offsets();
+    let row_len = width * PIXEL_WIDTH;
+    let mut diff = Vec::with_capacity(row_len * height);
+    let (mut compared, mut differing) = (0, 0);
+    let rows = actual
+        .data()
+        .chunks(actual.row_len().max(1))
+        .zip(expected.data().chunks(expected.row_len().max(1)))
+        .take(height);
+    for (y, (a_row, e_row)) in rows.enumerate() {
+        let pixels = a_row
+            .chunks_exact(PIXEL_WIDTH)
+            .zip(e_row.chunks_exact(PIXEL_WIDTH))
+            .take(width);
+        for (x, (a, e)) in pixels.enumerate() {
+            let point = Point {
+                x: x as i32,
+                y: y as i32,
+            };
+            // BGRA
+            let px = if options.ignore_regions.iter().any(|r| r.contains(point)) {
+                [96, 0, 0, 255]
+            } else {
+                compared += 1;
+                let close = |i: usize, j: usize| a[i].abs_diff(e[j]) <= options.tolerance;
+                if close(ar, er) && close(1, 1) && close(ab, eb) {
+                    let luma =
+                        (299 * u32::from(e[er]) + 587 * u32::from(e[1]) + 114 * u32::from(e[eb]))
+                            / 1000;
+                    let grey = (160 + luma / 4) as u8;
+                    [grey, grey, grey, 255]
+                } else {
+                    differing += 1;
+                    [0, 0, 255, 255]
+                }
+            };
+            diff.extend_from_slice(&px);
+        }
+    }
+    Comparison {
+        compared,
+        differing,
+        diff: Screenshot::from_bgra(diff, width, height, row_len),
+    }
+}
+
+/// Saves each image to `dir` as `<prefix>-<kind>.bmp`, returning the paths.
+fn save_artifacts(
+    dir: &Path,
+    prefix: &str,
+    images: &[(&str, &Screenshot)],
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    images
+        .iter()
+        .map(|(kind, image)| {
+            let path = dir.join(format!("{}-{}.bmp", prefix, kind));
+            image.save_bmp(&path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn solid(width: usize, height: usize, bgra: [u8; 4]) -> Screenshot {
+    Screenshot::from_raw(bgra.repeat(width * height), width, height, width * 4).unwrap()
+}
+
+#[test]
+fn test_similar_screenshots() {
+    let expected = solid(4, 4, [10, 20, 30, 255]);
+    // alpha doesn't count, nor does the channel order
+    let mut actual = solid(4, 4, [10, 20, 30, 0]);
+    actual.swap_r_b_in_place();
+    assert_screenshots_similar(&actual, &expected, &SimilarityOptions::default());
+
+    let mut data = [12, 18, 30, 255].repeat(16);
+    // a "clock" in the top right corner
+    data[8..16].fill(255);
+    let actual = Screenshot::from_raw(data, 4, 4, 16).unwrap();
+    let options = SimilarityOptions {
+        tolerance: 2,
+        ignore_regions: vec![Rect {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 1,
+        }],
+        ..SimilarityOptions::default()
+    };
+    assert_screenshots_similar(&actual, &expected, &options);
+    // or 2 of 16 pixels differing
+    let options = SimilarityOptions {
+        tolerance: 2,
+        max_diff_fraction: 0.125,
+        ..SimilarityOptions::default()
+    };
+    assert_screenshots_similar(&actual, &expected, &options);
+}
+
+#[test]
+fn test_dissimilar_screenshots() {
+    let dir = std::env::temp_dir().join("screenshot_test_dissimilar");
+    let _ = fs::remove_dir_all(&dir);
+    let options = SimilarityOptions {
+        max_diff_fraction: 0.2,
+        ignore_regions: vec![Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 2,
+        }],
+        artifacts_dir: dir.clone(),
+        ..SimilarityOptions::default()
+    };
+    let expected = solid(4, 4, [0, 0, 0, 255]);
+    let mut data = [0, 0, 0, 255].repeat(16);
+    // 3 of the 8 compared pixels
+    data[32..44].fill(255);
+    let actual = Screenshot::from_raw(data, 4, 4, 16).unwrap();
+
+    let message = std::panic::catch_unwind(|| {
+        assert_screenshots_similar(&actual, &expected, &options);
+    })
+    .unwrap_err()
+    .downcast::<String>()
+    .unwrap();
+    assert!(
+        message.contains("37.50% of the compared pixels (3 of 8)"),
+        "{}",
+        message
+    );
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files.len(), 3);
+    for (file, kind) in files.iter().zip(["actual", "diff", "expected"]) {
+        assert!(file.starts_with("similarity-"), "{}", file);
+        assert!(file.ends_with(&format!("-{}.bmp", kind)), "{}", file);
+        assert!(message.contains(&*dir.join(file).to_string_lossy()));
+    }
+
+    let message = std::panic::catch_unwind(|| {
+        assert_screenshots_similar(&solid(4, 2, [0; 4]), &expected, &options);
+    })
+    .unwrap_err()
+    .downcast::<String>()
+    .unwrap();
+    assert!(message.starts_with("screenshots differ in size: 4 x 2, expected 4 x 4"));
+    fs::remove_dir_all(&dir).unwrap();
+}
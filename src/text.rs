@@ -0,0 +1,420 @@
+//! Drawing text onto screenshots, e.g. a timestamp for archiving, with a
+//! built-in 5x7 pixel font so the output is the same everywhere.
+
+use crate::{Pixel, Point, Screenshot, ScreenshotError, Size, PIXEL_WIDTH};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Columns of the glyphs of ASCII 0x20 to 0x7e, left to right, with the
+/// top row in the lowest bit.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x00, 0x07, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // backslash
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
+
+/// Each glyph takes a cell of this many pixels, including a column and a
+/// row of spacing, times the scale.
+const CELL: (u32, u32) = (6, 8);
+
+const WHITE: Pixel = Pixel {
+    a: 255,
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+const BLACK: Pixel = Pixel {
+    a: 255,
+    r: 0,
+    g: 0,
+    b: 0,
+};
+
+/// How `Screenshot::draw_text` draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Size of each font pixel in screenshot pixels, so glyphs are
+    /// `5 * scale` by `7 * scale`. At least 1.
+    pub scale: u32,
+    pub color: Pixel,
+    /// Fills the text's bounds, and a font pixel around them, before the
+    /// text is drawn.
+    pub background: Option<Pixel>,
+}
+
+impl Default for TextOptions {
+    /// White without a background, at scale 1.
+    fn default() -> Self {
+        TextOptions {
+            scale: 1,
+            color: WHITE,
+            background: None,
+        }
+    }
+}
+
+impl TextOptions {
+    /// The size `text` takes when drawn with these options, without the
+    /// background's margin.
+    pub fn text_size(&self, text: &str) -> Size {
+        let scale = self.scale.max(1);
+        let lines = text.split('\n');
+        let (count, longest) = lines.fold((0, 0), |(count, longest), line| {
+            (count + 1, longest.max(line.chars().count() as u32))
+        });
+        Size {
+            width: (longest * CELL.0 * scale).saturating_sub(scale),
+            height: count * CELL.1 * scale - scale,
+        }
+    }
+}
+
+/// A corner of a screenshot, for `Screenshot::stamp_text`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Screenshot {
+    /// Draws `text` with its top left corner at `position`, in pixels from
+    /// the top left corner of the screenshot. `\n` starts a new line;
+    /// characters other than printable ASCII are drawn as `?`. Whatever
+    /// falls outside the screenshot is left out.
+    pub fn draw_text(&mut self, text: &str, position: Point, options: &TextOptions) {
+        let scale = i64::from(options.scale.max(1));
+        let (x, y) = (i64::from(position.x), i64::from(position.y));
+        if let Some(background) = options.background {
+            let size = options.text_size(text);
+            let (width, height) = (i64::from(size.width), i64::from(size.height));
+            self.fill(
+                x - scale,
+                y - scale,
+                width + 2 * scale,
+                height + 2 * scale,
+                background,
+            );
+        }
+        for (line, text) in text.split('\n').enumerate() {
+            let top = y + line as i64 * i64::from(CELL.1) * scale;
+            for (column, c) in text.chars().enumerate() {
+                let left = x + column as i64 * i64::from(CELL.0) * scale;
+                let glyph = match c {
+                    ' '..='~' => &FONT[c as usize - 0x20],
+                    _ => &FONT[usize::from(b'?' - 0x20)],
+                };
+                for (gx, bits) in glyph.iter().enumerate() {
+                    for gy in (0..7).filter(|gy| bits >> gy & 1 == 1) {
+                        let (px, py) = (left + gx as i64 * scale, top + gy * scale);
+                        self.fill(px, py, scale, scale, options.color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws `text` white on black in `corner`, a little away from the
+    /// edges, scaled up on screenshots 1440 pixels high or more.
+    pub fn stamp_text(&mut self, text: &str, corner: Corner) {
+        let options = TextOptions {
+            scale: (self.height as u32 / 720).max(1),
+            color: WHITE,
+            background: Some(BLACK),
+        };
+        let size = options.text_size(text);
+        let margin = 4 * options.scale as i32;
+        let (right, bottom) = (
+            self.width as i32 - margin - size.width as i32,
+            self.height as i32 - margin - size.height as i32,
+        );
+        let position = match corner {
+            Corner::TopLeft => Point {
+                x: margin,
+                y: margin,
+            },
+            Corner::TopRight => Point {
+                x: right,
+                y: margin,
+            },
+            Corner::BottomLeft => Point {
+                x: margin,
+                y: bottom,
+            },
+            Corner::BottomRight => Point {
+                x: right,
+                y: bottom,
+            },
+        };
+        self.draw_text(text, position, &options);
+    }
+
+    /// Stamps when the screenshot was captured in `corner`, as UTC, e.g.
+    /// `2024-05-01 14:03:59 UTC`, see `stamp_text`. Fails with
+    /// `ScreenshotError::NoMetadata` for screenshots that weren't captured,
+    /// e.g. ones made with `from_raw`.
+    pub fn stamp_timestamp(&mut self, corner: Corner) -> Result<(), ScreenshotError> {
+        let captured = self.metadata.ok_or(ScreenshotError::NoMetadata)?.wall_time;
+        self.stamp_text(&format_utc(captured), corner);
+        Ok(())
+    }
+
+    /// Sets the pixels of the rectangle at `x`, `y` that are within the
+    /// screenshot to `color`.
+    pub(crate) fn fill(&mut self, x: i64, y: i64, width: i64, height: i64, color: Pixel) {
+        let clamp = |v: i64, max: usize| v.clamp(0, max as i64) as usize;
+        let (left, right) = (clamp(x, self.width), clamp(x + width, self.width));
+        let (top, bottom) = (clamp(y, self.height), clamp(y + height, self.height));
+        let (r, b) = self.red_blue_offsets();
+        let mut px = [0; PIXEL_WIDTH];
+        px[r] = color.r;
+        px[1] = color.g;
+        px[b] = color.b;
+        px[3] = color.a;
+        for row in top..bottom {
+            let start = row * self.row_len;
+            let row = &mut self.data[start + left * PIXEL_WIDTH..start + right * PIXEL_WIDTH];
+            for dst in row.chunks_exact_mut(PIXEL_WIDTH) {
+                dst.copy_from_slice(&px);
+            }
+        }
+    }
+}
+
+/// `time` as `YYYY-MM-DD hh:mm:ss UTC`, or the epoch for times before it.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // from days since 1970-01-01 to the civil date, after Howard Hinnant
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The pixels of `shot` as `#` where they're white and `.` elsewhere, row
+/// by row.
+#[cfg(test)]
+fn white_pixels(shot: &Screenshot) -> Vec<String> {
+    (0..shot.height())
+        .map(|y| {
+            (0..shot.width())
+                .map(|x| match shot.get_pixel_xy(x, y) {
+                    WHITE => '#',
+                    _ => '.',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_draw_text() {
+    let mut shot = Screenshot::from_raw(vec![0; 13 * 9 * 4], 13, 9, 13 * 4).unwrap();
+    shot.draw_text("Hi", Point { x: 1, y: 1 }, &TextOptions::default());
+    assert_eq!(
+        white_pixels(&shot),
+        [
+            ".............",
+            ".#...#...#...",
+            ".#...#.......",
+            ".#...#..##...",
+            ".#####...#...",
+            ".#...#...#...",
+            ".#...#...#...",
+            ".#...#..###..",
+            ".............",
+        ]
+    );
+
+    // clipped, scaled, on a background, in RGBA
+    let mut shot = Screenshot::from_raw(vec![0; 8 * 6 * 4], 8, 6, 8 * 4).unwrap();
+    shot.swap_r_b_in_place();
+    let red = Pixel {
+        a: 255,
+        r: 255,
+        g: 0,
+        b: 0,
+    };
+    let options = TextOptions {
+        scale: 2,
+        color: WHITE,
+        background: Some(red),
+    };
+    assert_eq!(
+        options.text_size("I\nI"),
+        Size {
+            width: 10,
+            height: 30
+        }
+    );
+    shot.draw_text("-", Point { x: -3, y: -5 }, &options);
+    assert_eq!(
+        white_pixels(&shot),
+        ["........", "#######.", "#######.", "........", "........", "........"]
+    );
+    assert_eq!(shot.get_pixel_xy(7, 5), red);
+    assert_eq!(&shot.data()[6 * 4 + 4 * 32..][..4], &[255, 0, 0, 255]);
+    assert_eq!(
+        &shot.to_bgra_vec()[6 * 4 + 4 * 32..][..4],
+        &[0, 0, 255, 255]
+    );
+
+    // anything but printable ASCII is a question mark
+    let black = || Screenshot::from_raw(vec![0; 6 * 8 * 4], 6, 8, 6 * 4).unwrap();
+    let (mut a, mut b) = (black(), black());
+    a.draw_text("\u{e9}", Point::default(), &TextOptions::default());
+    b.draw_text("?", Point::default(), &TextOptions::default());
+    assert_eq!(a.data(), b.data());
+    assert_ne!(a.data(), black().data());
+}
+
+#[test]
+fn test_stamp_timestamp() {
+    let mut shot = Screenshot::from_raw(vec![0; 200 * 20 * 4], 200, 20, 800).unwrap();
+    assert!(matches!(
+        shot.stamp_timestamp(Corner::BottomRight),
+        Err(ScreenshotError::NoMetadata)
+    ));
+    let wall_time = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    assert_eq!(format_utc(wall_time), "2023-11-14 22:13:20 UTC");
+    assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00 UTC");
+    assert_eq!(
+        format_utc(UNIX_EPOCH + std::time::Duration::from_secs(951_782_400)),
+        "2000-02-29 00:00:00 UTC"
+    );
+
+    shot.metadata = Some(crate::CaptureMetadata {
+        captured_at: std::time::Instant::now(),
+        wall_time,
+        sequence: 0,
+        source: crate::Rect::default(),
+        window: None,
+        backend: "test",
+        scale_factor: None,
+        cursor: None,
+        physical_pixels: true,
+        resumed: false,
+    });
+    shot.stamp_timestamp(Corner::BottomRight).unwrap();
+    // 23 characters, 137 pixels wide, 7 high, with a margin of 4 and a
+    // black box around
+    let mut expected = Screenshot::from_raw(vec![0; 200 * 20 * 4], 200, 20, 800).unwrap();
+    expected.draw_text(
+        "2023-11-14 22:13:20 UTC",
+        Point { x: 59, y: 9 },
+        &TextOptions::default(),
+    );
+    assert_eq!(white_pixels(&shot), white_pixels(&expected));
+    assert_eq!(shot.get_pixel_xy(58, 8), BLACK);
+    assert_eq!(shot.get_pixel_xy(57, 8).a, 0);
+}
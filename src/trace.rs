@@ -0,0 +1,95 @@
+//! Logging of the capture path through `tracing`, with the `tracing`
+//! feature. Without it the macros expand to nothing, so their arguments
+//! aren't even evaluated.
+//!
+//! Spans are at debug level: `capture` around a whole capture including
+//! retries, and `bitblt`, `get_dibits` and `convert` around its steps.
+//! Retries, backend probing, degraded environments and failed OS calls are
+//! events, at warn level if the capture may come out wrong or fail.
+
+/// Enters a debug-level span until the end of the enclosing block, e.g.
+/// `trace_span!("bitblt", x = rect.x)`.
+// unused in builds without a backend, e.g. on Linux without features
+#[allow(unused_macros)]
+macro_rules! trace_span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($args)*).entered();
+    };
+}
+
+/// Emits an event at `$level`, one of tracing's level macros, e.g.
+/// `trace_event!(warn, attempt, "retrying")`.
+macro_rules! trace_event {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($args)*);
+    };
+}
+
+#[allow(unused_imports)]
+pub(crate) use {trace_event, trace_span};
+
+/// Records span names and event messages, to check what gets logged.
+#[cfg(all(test, feature = "tracing"))]
+#[derive(Default)]
+struct Recorder(std::sync::Mutex<Vec<String>>);
+
+#[cfg(all(test, feature = "tracing"))]
+impl tracing::Subscriber for Recorder {
+    fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let mut log = self.0.lock().unwrap();
+        log.push(span.metadata().name().to_string());
+        tracing::span::Id::from_u64(log.len() as u64)
+    }
+
+    fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        struct Message<'a>(&'a mut Vec<String>);
+        impl tracing::field::Visit for Message<'_> {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0.push(format!("{:?}", value));
+                }
+            }
+        }
+        event.record(&mut Message(&mut self.0.lock().unwrap()));
+    }
+
+    fn enter(&self, _: &tracing::span::Id) {}
+
+    fn exit(&self, _: &tracing::span::Id) {}
+}
+
+#[test]
+#[cfg(feature = "tracing")]
+fn test_trace_retries() {
+    use crate::{RetryPolicy, ScreenshotError};
+    use std::{sync::Arc, time::Duration};
+
+    let recorder = Arc::new(Recorder::default());
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        delay: Duration::ZERO,
+        backoff: 1.0,
+    };
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let res: Result<(), _> = policy.run(|| Err(ScreenshotError::BitBltFailed));
+        assert!(res.is_err());
+    });
+    let log = recorder.0.lock().unwrap();
+    assert_eq!(
+        log.iter().filter(|m| m.starts_with("retrying")).count(),
+        2,
+        "{:?}",
+        log
+    );
+    assert!(log.iter().any(|m| m.starts_with("giving up")), "{:?}", log);
+}
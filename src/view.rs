@@ -0,0 +1,194 @@
+//! Borrowed views of part of a screenshot, for looking at several areas of
+//! one capture without copying it.
+
+use crate::{
+    buffer::AlignedBuf, Pixel, PixelFormat, Rect, Screenshot, ScreenshotError, Size, PIXEL_WIDTH,
+};
+
+/// A rectangle of a `Screenshot`, borrowed rather than copied, see
+/// `Screenshot::view`. Its pixels are in the screenshot's `format`.
+#[derive(Clone, Copy, Debug)]
+pub struct ScreenshotView<'a> {
+    screenshot: &'a Screenshot,
+    rect: Rect,
+}
+
+impl<'a> ScreenshotView<'a> {
+    /// The area of the screenshot this views, in pixels from its top left
+    /// corner.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.rect.width as usize
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.rect.height as usize
+    }
+
+    /// Width and height in pixels.
+    pub fn size(&self) -> Size {
+        self.rect.size()
+    }
+
+    /// Byte order of the pixels, that of the screenshot.
+    pub fn format(&self) -> PixelFormat {
+        self.screenshot.format()
+    }
+
+    /// Gets the pixel at `row` and `col` within the view, like
+    /// `Screenshot::get_pixel`. Panics if it's out of bounds.
+    pub fn get_pixel(&self, row: usize, col: usize) -> Pixel {
+        self.try_get_pixel(row, col).expect("Bounds overflow")
+    }
+
+    /// Gets the pixel at `row` and `col` within the view, or None if it's
+    /// outside the view.
+    pub fn try_get_pixel(&self, row: usize, col: usize) -> Option<Pixel> {
+        if row >= self.height() || col >= self.width() {
+            return None;
+        }
+        let (x, y) = (self.rect.x as usize, self.rect.y as usize);
+        self.screenshot.try_get_pixel(y + row, x + col)
+    }
+
+    /// The rows of the view, top to bottom, each `width * 4` bytes of the
+    /// screenshot's data.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let start = self.rect.x as usize * PIXEL_WIDTH;
+        let len = self.width() * PIXEL_WIDTH;
+        self.screenshot
+            .rows()
+            .skip(self.rect.y as usize)
+            .take(self.height())
+            .map(move |row| &row[start..][..len])
+    }
+
+    /// The pixels of the view, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel> + 'a {
+        let (r, b) = self.screenshot.red_blue_offsets();
+        self.rows()
+            .flat_map(|row| row.chunks_exact(PIXEL_WIDTH))
+            .map(move |px| Pixel {
+                a: px[3],
+                r: px[r],
+                g: px[1],
+                b: px[b],
+            })
+    }
+
+    /// Copies the view into a screenshot of its own, with the metadata of
+    /// the screenshot narrowed to the view.
+    pub fn to_screenshot(&self) -> Screenshot {
+        let row_len = self.width() * PIXEL_WIDTH;
+        let mut data = AlignedBuf::default();
+        data.resize(row_len * self.height());
+        for (dst, src) in data.chunks_exact_mut(row_len.max(1)).zip(self.rows()) {
+            dst.copy_from_slice(src);
+        }
+        let mut metadata = self.screenshot.metadata;
+        if let Some(metadata) = &mut metadata {
+            metadata.source = Rect {
+                x: metadata.source.x + self.rect.x,
+                y: metadata.source.y + self.rect.y,
+                ..self.rect
+            };
+        }
+        Screenshot {
+            data,
+            format: self.screenshot.format,
+            height: self.height(),
+            width: self.width(),
+            row_len,
+            metadata,
+        }
+    }
+}
+
+impl Screenshot {
+    /// A view of `rect`, in pixels from the top left corner, without
+    /// copying. Fails with `ScreenshotError::InvalidRegion` unless `rect`
+    /// lies within the screenshot.
+    pub fn view(&self, rect: Rect) -> Result<ScreenshotView<'_>, ScreenshotError> {
+        let within = rect.x >= 0
+            && rect.y >= 0
+            && rect.x as u64 + u64::from(rect.width) <= self.width as u64
+            && rect.y as u64 + u64::from(rect.height) <= self.height as u64;
+        if !within {
+            return Err(ScreenshotError::InvalidRegion(rect));
+        }
+        Ok(ScreenshotView {
+            screenshot: self,
+            rect,
+        })
+    }
+
+    /// Copies `rect`, in pixels from the top left corner, into a screenshot
+    /// of its own, see `view`.
+    pub fn crop(&self, rect: Rect) -> Result<Screenshot, ScreenshotError> {
+        Ok(self.view(rect)?.to_screenshot())
+    }
+}
+
+#[test]
+fn test_view() {
+    // 4x3 pixels, rows padded to 20 bytes, each pixel holding its x and y
+    let mut data = vec![0; 20 * 3];
+    for (y, row) in data.chunks_mut(20).enumerate() {
+        for (x, px) in row.chunks_exact_mut(PIXEL_WIDTH).take(4).enumerate() {
+            px.copy_from_slice(&[x as u8, y as u8, 0, 255]);
+        }
+    }
+    let s = Screenshot::from_raw(data, 4, 3, 20).unwrap();
+    let rect = |x, y, width, height| Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+
+    let view = s.view(rect(1, 1, 2, 2)).unwrap();
+    assert_eq!(
+        view.size(),
+        Size {
+            width: 2,
+            height: 2
+        }
+    );
+    let rows: Vec<_> = view.rows().collect();
+    assert_eq!(
+        rows,
+        [
+            &[1, 1, 0, 255, 2, 1, 0, 255][..],
+            &[1, 2, 0, 255, 2, 2, 0, 255]
+        ]
+    );
+    assert_eq!(view.get_pixel(1, 0), s.get_pixel(2, 1));
+    assert_eq!(view.try_get_pixel(0, 2), None);
+    assert_eq!(view.pixels().count(), 4);
+
+    let cropped = s.crop(rect(1, 1, 2, 2)).unwrap();
+    assert_eq!((cropped.width(), cropped.height()), (2, 2));
+    assert_eq!(cropped.row_len(), 8);
+    assert_eq!(
+        cropped.pixels().collect::<Vec<_>>(),
+        view.pixels().collect::<Vec<_>>()
+    );
+
+    // the whole screenshot and nothing at its edge are fine
+    assert_eq!(
+        s.crop(rect(0, 0, 4, 3)).unwrap().to_rgba_vec(),
+        s.to_rgba_vec()
+    );
+    assert_eq!(s.crop(rect(4, 3, 0, 0)).unwrap().len(), 0);
+    for outside in [rect(-1, 0, 2, 2), rect(3, 0, 2, 1), rect(0, 2, 1, 2)] {
+        assert!(matches!(
+            s.view(outside),
+            Err(ScreenshotError::InvalidRegion(r)) if r == outside
+        ));
+    }
+}
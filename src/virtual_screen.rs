@@ -0,0 +1,157 @@
+//! Capturing the whole virtual screen, spanning all monitors, as one
+//! image.
+
+use crate::{
+    CaptureBackend, CaptureOptions, CaptureTarget, Monitor, Rect, Screenshot, ScreenshotError,
+};
+
+/// A screenshot of the whole virtual screen along with the monitors it
+/// shows, see `get_virtual_screen_screenshot`.
+#[derive(Debug)]
+pub struct VirtualScreenshot {
+    /// The bounding box of all monitors. Areas that no monitor covers,
+    /// e.g. beside a smaller monitor, are black.
+    pub screenshot: Screenshot,
+    /// The monitors, in the order of `monitors()`, in virtual-screen
+    /// coordinates.
+    pub monitors: Vec<Monitor>,
+    /// The captured area in virtual-screen coordinates.
+    pub bounds: Rect,
+}
+
+impl VirtualScreenshot {
+    /// Where the monitor at `index` of `monitors` is within `screenshot`,
+    /// in pixels of the screenshot.
+    pub fn monitor_rect(&self, index: usize) -> Option<Rect> {
+        let rect = self.monitors.get(index)?.rect;
+        Some(Rect {
+            x: rect.x - self.bounds.x,
+            y: rect.y - self.bounds.y,
+            ..rect
+        })
+    }
+}
+
+/// Gets a screenshot of the whole virtual screen, i.e. of the bounding box
+/// of all monitors, in a single capture.
+pub fn get_virtual_screen_screenshot() -> Result<VirtualScreenshot, ScreenshotError> {
+    get_virtual_screen_screenshot_with(&CaptureOptions::default())
+}
+
+/// Like `get_virtual_screen_screenshot`, with explicit options. The
+/// options' monitor, region and window are ignored.
+pub fn get_virtual_screen_screenshot_with(
+    options: &CaptureOptions,
+) -> Result<VirtualScreenshot, ScreenshotError> {
+    capture_virtual_screen(&mut options.backend.open()?, options)
+}
+
+pub(crate) fn capture_virtual_screen<B: CaptureBackend + ?Sized>(
+    backend: &mut B,
+    options: &CaptureOptions,
+) -> Result<VirtualScreenshot, ScreenshotError> {
+    let monitors = backend.monitors()?;
+    let bounds = monitors.iter().fold(Rect::default(), |bounds, monitor| {
+        bounds.union(&monitor.rect)
+    });
+    if bounds.is_empty() {
+        return Err(ScreenshotError::NoMonitors);
+    }
+    let screenshot = backend.capture_target(CaptureTarget::Region(bounds), options)?;
+    Ok(VirtualScreenshot {
+        screenshot,
+        monitors,
+        bounds,
+    })
+}
+
+#[test]
+fn test_capture_virtual_screen() {
+    use crate::{testing::MockCapturer, testing::MockFrame, Window};
+
+    /// A 1920x1080 primary monitor with a 1280x1024 one left of it.
+    struct TwoMonitors(Vec<CaptureTarget>);
+
+    impl CaptureBackend for TwoMonitors {
+        fn name(&self) -> &'static str {
+            "two monitors"
+        }
+
+        fn monitors(&self) -> Result<Vec<Monitor>, ScreenshotError> {
+            let monitor = |x, width, height| Monitor {
+                rect: Rect {
+                    x,
+                    y: 0,
+                    width,
+                    height,
+                },
+                work_area: Rect::default(),
+                primary: x == 0,
+                scale_factor: 1.0,
+                name: None,
+                device_name: None,
+                refresh_rate: None,
+                bits_per_pixel: None,
+                orientation: None,
+                physical_size_mm: None,
+            };
+            Ok(vec![monitor(0, 1920, 1080), monitor(-1280, 1280, 1024)])
+        }
+
+        fn windows(&self) -> Result<Vec<Window>, ScreenshotError> {
+            Ok(Vec::new())
+        }
+
+        fn capture_target(
+            &mut self,
+            target: CaptureTarget,
+            _: &CaptureOptions,
+        ) -> Result<Screenshot, ScreenshotError> {
+            self.0.push(target);
+            let rect = match target {
+                CaptureTarget::Region(rect) => rect,
+                _ => unreachable!(),
+            };
+            MockCapturer::new(
+                rect.width as usize,
+                rect.height as usize,
+                MockFrame::Gradient,
+            )
+            .capture()
+        }
+    }
+
+    let mut backend = TwoMonitors(Vec::new());
+    let shot = capture_virtual_screen(&mut backend, &CaptureOptions::default()).unwrap();
+    let bounds = Rect {
+        x: -1280,
+        y: 0,
+        width: 3200,
+        height: 1080,
+    };
+    assert_eq!(backend.0, [CaptureTarget::Region(bounds)]);
+    assert_eq!(shot.bounds, bounds);
+    assert_eq!(
+        (shot.screenshot.width(), shot.screenshot.height()),
+        (3200, 1080)
+    );
+    assert_eq!(
+        shot.monitor_rect(0),
+        Some(Rect {
+            x: 1280,
+            y: 0,
+            width: 1920,
+            height: 1080
+        })
+    );
+    assert_eq!(
+        shot.monitor_rect(1),
+        Some(Rect {
+            x: 0,
+            y: 0,
+            width: 1280,
+            height: 1024
+        })
+    );
+    assert_eq!(shot.monitor_rect(2), None);
+}
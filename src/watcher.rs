@@ -0,0 +1,268 @@
+//! Polling a small area of the screen, e.g. a status indicator, for
+//! changes or for something to appear. Each poll captures only the area,
+//! so it moves a few kilobytes rather than a whole frame.
+
+use crate::{
+    backend::DefaultBackend, change::ChangeDetector, CaptureBackend, CaptureOptions, CaptureTarget,
+    ChangeFilter, Pixel, Point, Rect, Screenshot, ScreenshotError,
+};
+
+use std::{
+    ops::ControlFlow,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Where a `RegionWatcher` found what it waited for.
+#[derive(Debug)]
+pub struct RegionMatch {
+    /// In virtual-screen coordinates: the matching pixel, or the top left
+    /// corner of the subimage.
+    pub position: Point,
+    /// The poll that matched, showing the watched region.
+    pub frame: Screenshot,
+}
+
+/// Captures a region every `interval` until it changes or shows what's
+/// waited for. Waits block the calling thread.
+///
+/// Changes are relative to the previous poll that was reported as changed,
+/// or the first poll, so changes creeping in below the tolerance still add
+/// up. Polls of any of the waits count.
+pub struct RegionWatcher<B = DefaultBackend> {
+    backend: B,
+    region: Rect,
+    interval: Duration,
+    options: CaptureOptions,
+    detector: ChangeDetector,
+    /// Whether the detector has seen a first poll to compare against.
+    primed: bool,
+}
+
+impl RegionWatcher {
+    /// Watches `region`, in virtual-screen coordinates, with the default
+    /// backend. Pixels whose channels differ by at most `tolerance` count
+    /// as unchanged.
+    pub fn new(region: Rect, interval: Duration, tolerance: u8) -> Self {
+        RegionWatcher::with_backend(DefaultBackend::default(), region, interval, tolerance)
+    }
+}
+
+impl<B: CaptureBackend> RegionWatcher<B> {
+    /// Like `new`, capturing with `backend`.
+    pub fn with_backend(backend: B, region: Rect, interval: Duration, tolerance: u8) -> Self {
+        RegionWatcher {
+            backend,
+            region,
+            interval,
+            options: CaptureOptions::default(),
+            detector: ChangeDetector::new(ChangeFilter {
+                tolerance,
+                ..ChangeFilter::default()
+            }),
+            primed: false,
+        }
+    }
+
+    /// Waits until the region changes and returns it as it is then. Fails
+    /// with `ScreenshotError::Timeout` if it doesn't within `timeout`, and
+    /// with the capture's error if a poll fails.
+    ///
+    /// The first poll only sets what later ones are compared against,
+    /// unless an earlier wait polled already.
+    pub fn wait_for_change(&mut self, timeout: Duration) -> Result<Screenshot, ScreenshotError> {
+        self.poll(timeout, |_, frame, changed| changed.then_some(frame))
+    }
+
+    /// Calls `callback` with the region whenever it changes, until the
+    /// callback returns `ControlFlow::Break` or a poll fails.
+    pub fn watch<F>(&mut self, mut callback: F) -> Result<(), ScreenshotError>
+    where
+        F: FnMut(&Screenshot) -> ControlFlow<()>,
+    {
+        loop {
+            let frame = self.poll(Duration::MAX, |_, frame, changed| changed.then_some(frame))?;
+            if callback(&frame).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits until a pixel of the region has `color`, give or take
+    /// `tolerance` in each of red, green and blue, see
+    /// `Screenshot::find_color`.
+    pub fn wait_for_color(
+        &mut self,
+        color: Pixel,
+        tolerance: u8,
+        timeout: Duration,
+    ) -> Result<RegionMatch, ScreenshotError> {
+        self.poll(timeout, |region, frame, _| {
+            let found = frame.find_color(color, tolerance)?;
+            Some(region_match(region, found, frame))
+        })
+    }
+
+    /// Waits until `needle` appears within the region, see
+    /// `Screenshot::find_subimage`.
+    pub fn wait_for_subimage(
+        &mut self,
+        needle: &Screenshot,
+        timeout: Duration,
+    ) -> Result<RegionMatch, ScreenshotError> {
+        self.poll(timeout, |region, frame, _| {
+            let found = frame.find_subimage(needle)?;
+            Some(region_match(region, found, frame))
+        })
+    }
+
+    /// Captures the region until `found` returns something for a poll, the
+    /// region, whether the poll changed and the poll itself, or `timeout`
+    /// passes. The first poll is taken right away.
+    fn poll<T>(
+        &mut self,
+        timeout: Duration,
+        mut found: impl FnMut(Rect, Screenshot, bool) -> Option<T>,
+    ) -> Result<T, ScreenshotError> {
+        // None if it never passes
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let frame = self
+                .backend
+                .capture_target(CaptureTarget::Region(self.region), &self.options)?;
+            let changed = self.detector.should_deliver(
+                frame.data(),
+                frame.width(),
+                frame.height(),
+                frame.row_len(),
+            );
+            let changed = std::mem::replace(&mut self.primed, true) && changed;
+            if let Some(found) = found(self.region, frame, changed) {
+                return Ok(found);
+            }
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => self.interval.min(left),
+                    _ => return Err(ScreenshotError::Timeout(timeout)),
+                },
+                None => self.interval,
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// A match at `found`, in pixels of `frame`, a capture of `region`.
+fn region_match(region: Rect, found: Point, frame: Screenshot) -> RegionMatch {
+    RegionMatch {
+        position: Point {
+            x: region.x + found.x,
+            y: region.y + found.y,
+        },
+        frame,
+    }
+}
+
+#[cfg(test)]
+fn watch_mock(
+    frame: crate::testing::MockFrame,
+    tolerance: u8,
+) -> RegionWatcher<crate::testing::MockCapturer> {
+    let region = Rect {
+        x: 4,
+        y: 2,
+        width: 3,
+        height: 2,
+    };
+    let mock = crate::testing::MockCapturer::new(8, 8, frame);
+    RegionWatcher::with_backend(mock, region, Duration::from_millis(1), tolerance)
+}
+
+#[test]
+fn test_wait_for_change() {
+    use crate::testing::MockFrame;
+
+    let black = Pixel {
+        a: 255,
+        r: 0,
+        g: 0,
+        b: 0,
+    };
+    let mut watcher = watch_mock(MockFrame::Solid(black), 0);
+    assert!(matches!(
+        watcher.wait_for_change(Duration::from_millis(5)),
+        Err(ScreenshotError::Timeout(_))
+    ));
+    let polls = watcher.backend.frames_captured();
+    assert!(polls >= 2, "{}", polls);
+
+    watcher
+        .backend
+        .set_frame(8, 8, MockFrame::Solid(Pixel { r: 1, ..black }));
+    let frame = watcher.wait_for_change(Duration::from_secs(1)).unwrap();
+    assert_eq!((frame.width(), frame.height()), (3, 2));
+    assert_eq!(frame.get_pixel(0, 0).r, 1);
+    // the change is seen once
+    assert!(watcher.wait_for_change(Duration::ZERO).is_err());
+
+    // a tolerance ignores small changes
+    let mut watcher = watch_mock(MockFrame::Solid(black), 1);
+    assert!(watcher.wait_for_change(Duration::ZERO).is_err());
+    watcher
+        .backend
+        .set_frame(8, 8, MockFrame::Solid(Pixel { r: 1, ..black }));
+    assert!(watcher.wait_for_change(Duration::ZERO).is_err());
+
+    let mut watcher = watch_mock(MockFrame::Gradient, 0);
+    assert!(watcher.wait_for_change(Duration::ZERO).is_err());
+    watcher.backend.set_frame(8, 8, MockFrame::Solid(black));
+    let mut changes = 0;
+    watcher
+        .watch(|frame| {
+            changes += 1;
+            assert_eq!(frame.get_pixel(0, 0), black);
+            ControlFlow::Break(())
+        })
+        .unwrap();
+    assert_eq!(changes, 1);
+}
+
+#[test]
+fn test_wait_for_match() {
+    use crate::testing::MockFrame;
+
+    // red is x * 255 / 7 at x, green y * 255 / 7 at y
+    let mut watcher = watch_mock(MockFrame::Gradient, 0);
+    let color = Pixel {
+        a: 255,
+        r: 182,
+        g: 109,
+        b: 0,
+    };
+    let found = watcher
+        .wait_for_color(color, 0, Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(found.position, Point { x: 5, y: 3 });
+    assert_eq!(found.frame.get_pixel_xy(1, 1), color);
+    assert!(matches!(
+        watcher.wait_for_color(Pixel { b: 1, ..color }, 0, Duration::ZERO),
+        Err(ScreenshotError::Timeout(_))
+    ));
+
+    let mut mock = crate::testing::MockCapturer::new(8, 8, MockFrame::Gradient);
+    let needle = mock
+        .capture_target(
+            CaptureTarget::Region(Rect {
+                x: 5,
+                y: 2,
+                width: 2,
+                height: 2,
+            }),
+            &CaptureOptions::default(),
+        )
+        .unwrap();
+    let found = watcher
+        .wait_for_subimage(&needle, Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(found.position, Point { x: 5, y: 2 });
+}
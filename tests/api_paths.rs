@@ -0,0 +1,55 @@
+//! Checks that the public items stay importable from where downstream code
+//! imports them, whichever module they're defined in. Mostly a compile test.
+
+use screenshot::{
+    get_monitor_screenshot, get_screenshot, get_screenshot_region, monitors, CaptureMetadata,
+    FrameInfo, Monitor, Pixel, PixelFormat, Rect, Screenshot, ScreenshotError,
+};
+
+#[test]
+fn test_root_paths() {
+    let _: fn() -> Result<Screenshot, ScreenshotError> = get_screenshot;
+    let _: fn(Rect) -> Result<Screenshot, ScreenshotError> = get_screenshot_region;
+    let _: fn(&Monitor) -> Result<Screenshot, ScreenshotError> = get_monitor_screenshot;
+    let _: fn() -> Result<Vec<Monitor>, ScreenshotError> = monitors;
+    let _: Option<CaptureMetadata> = None;
+    let _: Option<FrameInfo> = None;
+
+    let shot = Screenshot::from_raw(vec![1, 2, 3, 4], 1, 1, 4).unwrap();
+    assert_eq!(shot.format(), PixelFormat::Bgra8);
+    assert_eq!(
+        shot.get_pixel(0, 0),
+        Pixel {
+            a: 4,
+            r: 3,
+            g: 2,
+            b: 1
+        }
+    );
+}
+
+#[test]
+fn test_prelude() {
+    use screenshot::prelude::*;
+
+    let options = CaptureOptions::new()
+        .monitor(MonitorSelector::Index(0))
+        .region(Rect::new(
+            Point { x: 0, y: 0 },
+            Size {
+                width: 1,
+                height: 1,
+            },
+        ));
+    assert!(options.validate().is_ok());
+    let _: Option<&dyn CaptureBackend> = None;
+    let _: Option<(
+        Backend,
+        CaptureTarget,
+        Monitor,
+        Pixel,
+        PixelFormat,
+        Screenshot,
+    )> = None;
+    let _: Option<ScreenshotError> = None;
+}
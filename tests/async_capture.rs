@@ -0,0 +1,91 @@
+//! Async captures on a tokio runtime: `cargo test --features tokio`.
+#![cfg(all(windows, feature = "gdi", feature = "tokio"))]
+
+use futures_util::StreamExt;
+use screenshot::{
+    frame_stream, get_screenshot_async, get_screenshot_with, CaptureOptions, Capturer,
+};
+use windows::Win32::System::Threading::{GetCurrentProcess, GetGuiResources, GR_GDIOBJECTS};
+
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_concurrent_async_captures() {
+    let expected = get_screenshot_with(&CaptureOptions::default()).unwrap();
+    let captures: Vec<_> = (0..8)
+        .map(|_| tokio::spawn(async { get_screenshot_async(&CaptureOptions::default()).await }))
+        .collect();
+    for capture in captures {
+        let s = capture.await.unwrap().unwrap();
+        assert_eq!(
+            (s.width(), s.height()),
+            (expected.width(), expected.height())
+        );
+    }
+
+    let mut capturer = Capturer::new();
+    for _ in 0..3 {
+        let s = capturer.capture_async().await.unwrap();
+        assert_eq!(s.width(), expected.width());
+    }
+}
+
+#[tokio::test]
+async fn test_frame_stream() {
+    let interval = Duration::from_millis(50);
+    let mut frames = frame_stream(CaptureOptions::default(), interval);
+    let first = frames.next().await.unwrap().unwrap();
+    assert!(!first.is_empty());
+
+    // a slow consumer skips frames instead of getting a backlog
+    tokio::time::sleep(interval * 5).await;
+    let second = frames.next().await.unwrap().unwrap();
+    let third = frames.next().await.unwrap().unwrap();
+    let (first, second, third) = (
+        first.captured_at().unwrap(),
+        second.captured_at().unwrap(),
+        third.captured_at().unwrap(),
+    );
+    assert!(second - first >= interval * 5);
+    assert!(third - second >= interval / 2);
+
+    let taken = frame_stream(CaptureOptions::default(), interval)
+        .take(3)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(taken.len(), 3);
+}
+
+/// Dropped futures must still release what their captures acquired. Needs
+/// an interactive desktop, like the `gdi_leaks` test.
+#[test]
+#[ignore]
+fn test_cancelled_captures_release_gdi_objects() {
+    let gdi_objects = || unsafe { GetGuiResources(GetCurrentProcess(), GR_GDIOBJECTS) };
+    // warm up, so lazily created objects don't count as leaks
+    get_screenshot_with(&CaptureOptions::default()).unwrap();
+    let before = gdi_objects();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        for _ in 0..100 {
+            let capture = get_screenshot_async(&CaptureOptions::default());
+            // gives up long before the capture is done
+            let _ = tokio::time::timeout(Duration::from_micros(1), capture).await;
+        }
+        let mut capturer = Capturer::new();
+        for _ in 0..100 {
+            let _ = tokio::time::timeout(Duration::from_micros(1), capturer.capture_async()).await;
+        }
+    });
+    // waits for the abandoned captures on the blocking pool
+    drop(runtime);
+
+    let after = gdi_objects();
+    assert!(
+        after <= before + 4,
+        "GDI objects grew from {} to {}",
+        before,
+        after
+    );
+}
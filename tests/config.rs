@@ -0,0 +1,26 @@
+//! Checks that `examples/profile.toml`, which documents the profile format,
+//! stays readable as the format changes.
+#![cfg(feature = "config")]
+
+use screenshot::{CaptureProfile, MonitorSelector, OutputFormat, RedactStyle};
+use std::time::Duration;
+
+#[test]
+fn test_example_profile() {
+    let profile = CaptureProfile::from_toml(include_str!("../examples/profile.toml")).unwrap();
+    assert_eq!(profile.capture.monitor, Some(MonitorSelector::Index(0)));
+    assert_eq!(profile.capture.retry.max_attempts, 3);
+    let filter = profile.capture.only_on_change.as_ref().unwrap();
+    assert_eq!(filter.max_quiet_duration, Some(Duration::from_secs(60)));
+    assert_eq!(profile.interval(), Some(Duration::from_millis(500)));
+    assert_eq!(profile.redact.len(), 2);
+    assert_eq!(profile.redact[1].style, RedactStyle::Blur(8));
+    assert_eq!(profile.output.format, OutputFormat::Png);
+    assert_eq!(profile.output.retention.max_files, Some(1000));
+
+    // the same profile in JSON
+    let json = CaptureProfile::from_json(&profile.to_json().unwrap()).unwrap();
+    assert_eq!(json.capture.region, profile.capture.region);
+    assert_eq!(json.redact, profile.redact);
+    assert_eq!(json.output, profile.output);
+}
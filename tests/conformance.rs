@@ -0,0 +1,41 @@
+//! Runs `testing::assert_conformance` against every backend built in, so
+//! they stay interchangeable. Needs a display, like the backends' own tests.
+
+#[allow(unused_imports)]
+use screenshot::testing::assert_conformance;
+
+#[test]
+#[cfg(all(windows, feature = "gdi"))]
+fn test_gdi_conformance() {
+    assert_conformance(&mut screenshot::backend::gdi::GdiBackend::default());
+}
+
+#[test]
+#[cfg(target_os = "macos")]
+fn test_macos_conformance() {
+    assert_conformance(&mut screenshot::backend::macos::MacBackend::new());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "x11"))]
+fn test_x11_conformance() {
+    assert_conformance(&mut screenshot::backend::x11::X11Backend::connect().unwrap());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "fbdev"))]
+fn test_fbdev_conformance() {
+    assert_conformance(&mut screenshot::backend::fbdev::FbdevBackend::open().unwrap());
+}
+
+/// Asks the user to share a monitor: `cargo test --features wayland --test
+/// conformance -- --ignored`
+#[test]
+#[ignore]
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+fn test_wayland_conformance() {
+    let mut backend = screenshot::backend::wayland::WaylandBackend::new();
+    // the monitor is only known once the session started
+    backend.start().unwrap();
+    assert_conformance(&mut backend);
+}
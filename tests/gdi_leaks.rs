@@ -0,0 +1,139 @@
+//! Checks that captures, including failed ones, don't leak GDI or USER
+//! objects. Needs an interactive desktop, so it's ignored by default:
+//! `cargo test --test gdi_leaks -- --ignored`
+#![cfg(all(windows, feature = "gdi"))]
+
+use screenshot::{
+    get_monitor_screenshot_with, get_screenshot_region_with, get_screenshot_with, monitors,
+    Backend, CaptureOptions, Capturer, FaultPoint, Monitor, Rect,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, GetGuiResources, GR_GDIOBJECTS, GR_USEROBJECTS,
+};
+
+const ITERATIONS: usize = 500;
+/// Objects Windows may create lazily on first use, e.g. for the desktop DC.
+const TOLERANCE: u32 = 4;
+
+fn gui_resources() -> (u32, u32) {
+    unsafe {
+        (
+            GetGuiResources(GetCurrentProcess(), GR_GDIOBJECTS),
+            GetGuiResources(GetCurrentProcess(), GR_USEROBJECTS),
+        )
+    }
+}
+
+#[test]
+#[ignore]
+fn test_no_gdi_leaks() {
+    let ok = CaptureOptions::default();
+    let fail_blit = CaptureOptions {
+        inject_fault: Some(FaultPoint::BitBlt),
+        ..Default::default()
+    };
+    let fail_dib = CaptureOptions {
+        inject_fault: Some(FaultPoint::GetDIBits),
+        ..Default::default()
+    };
+    let invalid_region = Rect {
+        x: i32::MAX - 10,
+        y: 0,
+        width: 10,
+        height: 10,
+    };
+    let invalid_monitor = Monitor {
+        rect: invalid_region,
+        work_area: invalid_region,
+        primary: false,
+        scale_factor: 1.0,
+        name: None,
+        device_name: None,
+        refresh_rate: None,
+        bits_per_pixel: None,
+        orientation: None,
+        physical_size_mm: None,
+    };
+    let monitor = monitors().unwrap().remove(0);
+
+    // warm up, so lazily created objects don't count as leaks
+    get_screenshot_with(&ok).unwrap();
+    let (gdi, user) = gui_resources();
+
+    for i in 0..ITERATIONS {
+        match i % 6 {
+            0 => drop(get_screenshot_with(&ok).unwrap()),
+            1 => assert!(get_screenshot_with(&fail_blit).is_err()),
+            2 => assert!(get_screenshot_with(&fail_dib).is_err()),
+            3 => assert!(get_screenshot_region_with(invalid_region, &ok).is_err()),
+            4 => assert!(get_monitor_screenshot_with(&invalid_monitor, &ok).is_err()),
+            _ => drop(get_monitor_screenshot_with(&monitor, &ok).unwrap()),
+        }
+    }
+
+    assert_no_growth((gdi, user));
+}
+
+#[test]
+#[ignore]
+fn test_no_gdi_leaks_capturer() {
+    // the cursor's icon bitmaps and the kept memory bitmap are ours to free
+    let cursor = CaptureOptions {
+        backend: Backend::Gdi,
+        include_cursor: true,
+        dpi_aware: true,
+        ..Default::default()
+    };
+    let mut capturer = Capturer::with_options(cursor.clone()).unwrap();
+    let mut buf = Vec::new();
+    let window = screenshot::windows()
+        .unwrap()
+        .into_iter()
+        .find(|w| !w.rect.is_empty());
+
+    capturer.capture().unwrap();
+    let before = gui_resources();
+
+    for i in 0..ITERATIONS {
+        let fault = match i % 3 {
+            0 => None,
+            1 => Some(FaultPoint::BitBlt),
+            _ => Some(FaultPoint::GetDIBits),
+        };
+        capturer.set_options(CaptureOptions {
+            inject_fault: fault,
+            ..cursor.clone()
+        });
+        assert_eq!(capturer.capture().is_ok(), fault.is_none());
+        assert_eq!(capturer.capture_into(&mut buf).is_ok(), fault.is_none());
+        if let Some(window) = &window {
+            let options = CaptureOptions {
+                print_window: i % 2 == 0,
+                ..CaptureOptions::new().window(window.id)
+            };
+            // the window may have closed meanwhile
+            let _ = Capturer::with_options(options).and_then(|mut c| c.capture().map(drop));
+        }
+    }
+    drop(capturer);
+
+    assert_no_growth(before);
+}
+
+/// Fails unless the GDI and USER object counts stayed within `TOLERANCE`
+/// of `before`.
+fn assert_no_growth((gdi, user): (u32, u32)) {
+    let (gdi_after, user_after) = gui_resources();
+    assert!(
+        gdi_after <= gdi + TOLERANCE,
+        "GDI objects grew from {} to {}",
+        gdi,
+        gdi_after
+    );
+    assert!(
+        user_after <= user + TOLERANCE,
+        "USER objects grew from {} to {}",
+        user,
+        user_after
+    );
+}
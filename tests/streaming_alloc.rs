@@ -0,0 +1,65 @@
+//! Checks that the encoders convert one row at a time instead of making a
+//! converted copy of the whole frame first, by recording the largest
+//! allocation made while encoding.
+
+use screenshot::testing::{MockCapturer, MockFrame};
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+struct Recording;
+
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Recording {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LARGEST.fetch_max(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Recording = Recording;
+
+/// Size of the largest allocation made by `f`.
+fn largest_allocation(f: impl FnOnce()) -> usize {
+    LARGEST.store(0, Ordering::Relaxed);
+    f();
+    LARGEST.load(Ordering::Relaxed)
+}
+
+// The only test in this binary, so nothing else allocates meanwhile.
+#[test]
+fn test_encoders_stream_rows() {
+    let (width, height) = (1920, 1080);
+    let frame_len = width * height * 4;
+    let s = MockCapturer::new(width, height, MockFrame::Gradient)
+        .capture()
+        .unwrap();
+
+    // the BMP itself is three quarters of the frame
+    let bmp = largest_allocation(|| drop(s.to_bmp()));
+    assert!(bmp < frame_len, "BMP allocated {} bytes", bmp);
+
+    #[cfg(feature = "png")]
+    {
+        let png = largest_allocation(|| drop(s.to_png().unwrap()));
+        assert!(png < frame_len / 2, "PNG allocated {} bytes", png);
+    }
+}